@@ -3,7 +3,8 @@
 //! Compares the allocating free-function path ("before" the zero-alloc
 //! refactor: fresh pyramids + fresh scratch every frame) against the
 //! buffer-reusing `TrackerContext` path ("after"), across 50/150/300 points and
-//! with/without an initial guess.
+//! with/without an initial guess, and the coarse-level early exit against
+//! refining every point on every level.
 
 use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
 use std::hint::black_box;
@@ -160,5 +161,30 @@ fn shift(src: &GrayImage, sx: f32, sy: f32) -> GrayImage {
     out
 }
 
-criterion_group!(benches, bench_tracking);
+fn bench_level_early_exit(c: &mut Criterion) {
+    let prev = textured(1);
+    let next = shift(&prev, 3.0, 2.0);
+
+    let mut group = c.benchmark_group("level_early_exit_640x480");
+
+    for &n in &[150usize, 300] {
+        let points = lattice(n);
+
+        for &(tag, fraction) in &[("none", None), ("0.8", Some(0.8))] {
+            let mut ctx = TrackerContext::new();
+            ctx.set_level_early_exit(fraction);
+            ctx.prepare(&prev, &next, LEVELS);
+            ctx.track(&points, None, WIN, ITERS, DEFAULT_MIN_EIGEN_THRESHOLD);
+            group.bench_with_input(BenchmarkId::new(tag, n), &n, |b, _| {
+                b.iter(|| {
+                    black_box(ctx.track(&points, None, WIN, ITERS, DEFAULT_MIN_EIGEN_THRESHOLD));
+                });
+            });
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_tracking, bench_level_early_exit);
 criterion_main!(benches);
@@ -128,10 +128,10 @@ fn main() {
             )
             .to_vec();
 
-        let mut counts = [0usize; 5];
+        let mut counts = [0usize; 6];
         let mut next_points = Vec::new();
         for r in &results {
-            counts[status_index(r.status)] += 1;
+            counts[r.status.code() as usize] += 1;
             if r.status == TrackStatus::Tracked {
                 next_points.push(r.pos);
             }
@@ -145,8 +145,8 @@ fn main() {
             motion.1
         );
         println!(
-            "  Tracked {}  OutOfBounds {}  Diverged {}  LowTexture {}  FbInconsistent {}",
-            counts[0], counts[1], counts[2], counts[3], counts[4]
+            "  Tracked {}  OutOfBounds {}  Diverged {}  LowTexture {}  FbInconsistent {}  Masked {}",
+            counts[0], counts[1], counts[2], counts[3], counts[4], counts[5]
         );
 
         // Show a few per-point diagnostics.
@@ -162,13 +162,3 @@ fn main() {
 
     println!("\n{} points survived the full sequence", points.len());
 }
-
-fn status_index(s: TrackStatus) -> usize {
-    match s {
-        TrackStatus::Tracked => 0,
-        TrackStatus::OutOfBounds => 1,
-        TrackStatus::Diverged => 2,
-        TrackStatus::LowTexture => 3,
-        TrackStatus::FbInconsistent => 4,
-    }
-}
@@ -0,0 +1,258 @@
+//! Per-frame motion activity and a hysteresis trigger on it.
+//!
+//! Motion-triggered recording only needs one number per frame: how much moves
+//! in the scene. [`motion_activity`] reduces a tracking step to that scalar,
+//! the mean track displacement left after removing the camera's own motion,
+//! and [`ActivityMonitor`] turns the sequence of levels into start and stop
+//! events that do not flicker around the threshold.
+
+use crate::lk::{TrackResult, TrackStatus};
+use crate::registration::{Match, RigidParams, fit_similarity_ransac};
+
+/// Settings of [`motion_activity`] and [`ActivityMonitor`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ActivityParams {
+    /// Remove the dominant similarity motion (pan, zoom, shake) before
+    /// measuring, so a moving camera over a static scene reads as inactive.
+    pub compensate_camera_motion: bool,
+    /// Fraction of the largest displacements dropped before averaging, so a
+    /// few mistracked points cannot fake activity.
+    pub trim: f32,
+    /// Level (pixels per frame) the activity must exceed to start.
+    pub start_level: f32,
+    /// Level the activity must fall below to stop; below `start_level`, so
+    /// levels in between keep the current state.
+    pub stop_level: f32,
+    /// Consecutive frames above `start_level` needed to start.
+    pub start_frames: usize,
+    /// Consecutive frames below `stop_level` needed to stop.
+    pub stop_frames: usize,
+}
+
+impl Default for ActivityParams {
+    fn default() -> Self {
+        ActivityParams {
+            compensate_camera_motion: true,
+            trim: 0.05,
+            start_level: 0.5,
+            stop_level: 0.25,
+            start_frames: 2,
+            stop_frames: 15,
+        }
+    }
+}
+
+/// Activity of one tracking step: the mean displacement in pixels of the
+/// [`TrackStatus::Tracked`] points, after dropping the largest
+/// [`trim`](ActivityParams::trim) fraction.
+///
+/// With [`compensate_camera_motion`](ActivityParams::compensate_camera_motion),
+/// displacements are measured against a RANSAC similarity fit over all tracks
+/// (thresholds of [`RigidParams::default`]) when at least half of them agree
+/// on it; otherwise no single camera motion dominates and the raw
+/// displacements are used.
+///
+/// # Returns
+/// The activity level, 0 when no point was tracked.
+///
+/// # Panics
+/// Panics if `results` does not have one entry per `prev_point`.
+pub fn motion_activity(
+    prev_points: &[(f32, f32)],
+    results: &[TrackResult],
+    params: &ActivityParams,
+) -> f32 {
+    assert_eq!(
+        prev_points.len(),
+        results.len(),
+        "results must have one entry per prev_point"
+    );
+    let matches: Vec<Match> = prev_points
+        .iter()
+        .zip(results)
+        .filter(|(_, r)| r.status == TrackStatus::Tracked)
+        .map(|(&p, r)| (p, r.pos))
+        .collect();
+    if matches.is_empty() {
+        return 0.0;
+    }
+
+    let camera = params
+        .compensate_camera_motion
+        .then(|| {
+            let rigid = RigidParams::default();
+            fit_similarity_ransac(
+                &matches,
+                None,
+                rigid.inlier_threshold,
+                rigid.ransac_iterations,
+            )
+        })
+        .flatten()
+        .filter(|transform| transform.inliers >= 3 && 2 * transform.inliers >= matches.len());
+
+    let mut magnitudes: Vec<f32> = matches
+        .iter()
+        .map(|&(from, to)| {
+            let expected = camera.map_or(from, |transform| transform.apply(from));
+            (to.0 - expected.0).hypot(to.1 - expected.1)
+        })
+        .collect();
+    magnitudes.sort_by(f32::total_cmp);
+    let dropped = (magnitudes.len() as f32 * params.trim.clamp(0.0, 1.0)) as usize;
+    let kept = &magnitudes[..(magnitudes.len() - dropped).max(1)];
+    kept.iter().sum::<f32>() / kept.len() as f32
+}
+
+/// Edge reported by [`ActivityMonitor::update`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ActivityEvent {
+    /// The activity stayed above the start level long enough.
+    Started,
+    /// The activity stayed below the stop level long enough.
+    Stopped,
+}
+
+/// Hysteresis trigger over per-frame activity levels, e.g. to start and stop
+/// recording when something moves.
+///
+/// Starting needs [`start_frames`](ActivityParams::start_frames) consecutive
+/// levels above [`start_level`](ActivityParams::start_level), stopping needs
+/// [`stop_frames`](ActivityParams::stop_frames) consecutive levels below
+/// [`stop_level`](ActivityParams::stop_level); a level in the other band
+/// resets the count.
+#[derive(Debug, Clone)]
+pub struct ActivityMonitor {
+    params: ActivityParams,
+    active: bool,
+    streak: usize,
+}
+
+impl ActivityMonitor {
+    /// Creates an inactive monitor.
+    ///
+    /// # Panics
+    /// Panics if `params.stop_level` exceeds `params.start_level`.
+    pub fn new(params: ActivityParams) -> Self {
+        assert!(
+            params.stop_level <= params.start_level,
+            "stop_level must not exceed start_level"
+        );
+        ActivityMonitor {
+            params,
+            active: false,
+            streak: 0,
+        }
+    }
+
+    /// Whether activity is currently on.
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// Feeds the level of one frame, see [`motion_activity`], and reports
+    /// whether activity started or stopped with it.
+    pub fn update(&mut self, level: f32) -> Option<ActivityEvent> {
+        let (crossing, needed) = if self.active {
+            (level < self.params.stop_level, self.params.stop_frames)
+        } else {
+            (level > self.params.start_level, self.params.start_frames)
+        };
+        if !crossing {
+            self.streak = 0;
+            return None;
+        }
+        self.streak += 1;
+        if self.streak < needed.max(1) {
+            return None;
+        }
+        self.streak = 0;
+        self.active = !self.active;
+        Some(if self.active {
+            ActivityEvent::Started
+        } else {
+            ActivityEvent::Stopped
+        })
+    }
+
+    /// [`motion_activity`] of one tracking step fed to
+    /// [`update`](Self::update).
+    ///
+    /// # Returns
+    /// The frame's activity level and the event it caused, if any.
+    pub fn update_tracks(
+        &mut self,
+        prev_points: &[(f32, f32)],
+        results: &[TrackResult],
+    ) -> (f32, Option<ActivityEvent>) {
+        let level = motion_activity(prev_points, results, &self.params);
+        (level, self.update(level))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step(prev: &[(f32, f32)], motion: impl Fn((f32, f32)) -> (f32, f32)) -> Vec<TrackResult> {
+        prev.iter()
+            .map(|&p| TrackResult {
+                pos: motion(p),
+                status: TrackStatus::Tracked,
+                error: 0.0,
+                aperture: None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn camera_pan_is_not_activity_but_a_moving_object_is() {
+        let prev: Vec<(f32, f32)> = (0..8)
+            .flat_map(|j| (0..8).map(move |i| (10.0 + 20.0 * i as f32, 10.0 + 15.0 * j as f32)))
+            .collect();
+        let params = ActivityParams::default();
+
+        let pan = step(&prev, |(x, y)| (x + 4.0, y - 1.0));
+        assert!(motion_activity(&prev, &pan, &params) < 1e-3);
+        let raw = ActivityParams {
+            compensate_camera_motion: false,
+            ..params
+        };
+        assert!((motion_activity(&prev, &pan, &raw) - 17f32.sqrt()).abs() < 1e-4);
+
+        // A quarter of the scene moves by 6 px on top of the pan.
+        let object = step(&prev, |(x, y)| {
+            let moved = if x < 90.0 && y < 60.0 { 6.0 } else { 0.0 };
+            (x + 4.0 + moved, y - 1.0)
+        });
+        let level = motion_activity(&prev, &object, &params);
+        assert!(level > 1.0, "{level}");
+
+        let mut monitor = ActivityMonitor::new(ActivityParams {
+            start_frames: 2,
+            stop_frames: 3,
+            ..params
+        });
+        let levels = [0.0, 2.0, 0.4, 2.0, 2.0, 0.4, 0.1, 0.1, 0.3, 0.1, 0.1, 0.1];
+        let events: Vec<_> = levels.iter().map(|&l| monitor.update(l)).collect();
+        use ActivityEvent::*;
+        assert_eq!(
+            events,
+            [
+                None,
+                None,
+                None,
+                None,
+                Some(Started),
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                Some(Stopped)
+            ]
+        );
+        assert!(!monitor.is_active());
+    }
+}
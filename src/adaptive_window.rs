@@ -0,0 +1,125 @@
+//! Tracking window sizes adapted to local texture.
+//!
+//! A weakly textured point needs a large window to collect enough gradient
+//! for a well-conditioned solve, while a strong corner tracks best in a small
+//! one that does not reach into neighbouring structure. The detectors already
+//! measure this: the minimum eigenvalue of the structure tensor they return
+//! with every feature. [`adaptive_windows`] turns those eigenvalues into one
+//! [`TrackWindow`] per point for
+//! [`calc_optical_flow_windows`](crate::calc_optical_flow_windows).
+
+use crate::lk::TrackWindow;
+
+/// Settings of [`adaptive_windows`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AdaptiveWindowParams {
+    /// Smallest window side (odd).
+    pub min_size: usize,
+    /// Largest window side (odd).
+    pub max_size: usize,
+    /// Window side of a point whose eigenvalue equals the reference.
+    pub reference_size: usize,
+    /// Eigenvalue that gets [`reference_size`](Self::reference_size), in the
+    /// detector's units; `None` takes the median of the given eigenvalues, so
+    /// a typical point keeps the reference size.
+    pub reference_eigenvalue: Option<f32>,
+}
+
+impl Default for AdaptiveWindowParams {
+    fn default() -> Self {
+        AdaptiveWindowParams {
+            min_size: 11,
+            max_size: 41,
+            reference_size: 21,
+            reference_eigenvalue: None,
+        }
+    }
+}
+
+/// Picks a square window for every point from the minimum structure-tensor
+/// eigenvalue its detector reported (the third element of the
+/// [`good_features_to_track`](crate::good_features_to_track) tuples).
+///
+/// The gradient energy a window collects grows with its area, so the side
+/// scales with `sqrt(reference / eigenvalue)`: a point with a quarter of the
+/// reference eigenvalue gets twice the reference side. Sizes are rounded to
+/// odd and clamped to `[min_size, max_size]`; a non-positive or non-finite
+/// eigenvalue gets `max_size`.
+///
+/// # Panics
+/// Panics if a size is even, if `min_size > max_size`, or if the reference
+/// eigenvalue is given and not positive.
+pub fn adaptive_windows(
+    eigenvalues: &[f32],
+    params: &AdaptiveWindowParams,
+) -> Vec<TrackWindow<'static>> {
+    let AdaptiveWindowParams {
+        min_size,
+        max_size,
+        reference_size,
+        reference_eigenvalue,
+    } = *params;
+    assert!(
+        [min_size, max_size, reference_size]
+            .iter()
+            .all(|s| s % 2 == 1),
+        "window sizes must be odd"
+    );
+    assert!(min_size <= max_size, "min_size must not exceed max_size");
+
+    let reference = match reference_eigenvalue {
+        Some(reference) => {
+            assert!(reference > 0.0, "reference_eigenvalue must be positive");
+            reference
+        }
+        None => {
+            let mut valid: Vec<f32> = eigenvalues
+                .iter()
+                .copied()
+                .filter(|e| e.is_finite() && *e > 0.0)
+                .collect();
+            if valid.is_empty() {
+                return vec![TrackWindow::new(max_size); eigenvalues.len()];
+            }
+            let middle = valid.len() / 2;
+            *valid.select_nth_unstable_by(middle, f32::total_cmp).1
+        }
+    };
+
+    eigenvalues
+        .iter()
+        .map(|&eigenvalue| {
+            if !(eigenvalue.is_finite() && eigenvalue > 0.0) {
+                return TrackWindow::new(max_size);
+            }
+            let side = reference_size as f32 * (reference / eigenvalue).sqrt();
+            // Nearest odd side, clamped.
+            let odd = 2 * ((side - 1.0) / 2.0).round().max(0.0) as usize + 1;
+            TrackWindow::new(odd.clamp(min_size, max_size))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn weak_points_get_larger_windows() {
+        let eigenvalues = [4.0, 1.0, 0.25, 0.0, 1000.0];
+        let params = AdaptiveWindowParams {
+            reference_eigenvalue: Some(1.0),
+            ..AdaptiveWindowParams::default()
+        };
+        let sizes: Vec<usize> = adaptive_windows(&eigenvalues, &params)
+            .iter()
+            .map(|w| w.width)
+            .collect();
+        assert_eq!(sizes, [11, 21, 41, 41, 11]);
+
+        // The median point keeps the reference size by default.
+        let windows = adaptive_windows(&[2.0, 8.0, 0.5], &AdaptiveWindowParams::default());
+        assert_eq!(windows[0], TrackWindow::new(21));
+        assert_eq!(windows[1].height, 11);
+    }
+}
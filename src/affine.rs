@@ -0,0 +1,349 @@
+//! Affine (6-DOF) Lucas-Kanade tracking.
+//!
+//! [`calc_optical_flow_similarity`](crate::calc_optical_flow_similarity)
+//! follows windows that turn and change size uniformly. Under perspective
+//! change the window content also shears and stretches differently along
+//! each axis, e.g. on a ground plane seen from a moving car.
+//! [`calc_optical_flow_affine`] estimates a full 2x3 affine warp per window
+//! for those cases; translation-only tracking stays the default everywhere
+//! else.
+
+use image::GrayImage;
+
+use crate::lk::{
+    TrackStatus, build_window_offsets_into, in_bounds, interpolate, interpolate_gradient,
+    min_eigenvalue,
+};
+use crate::pyramid::validate_pyramid_pair;
+use crate::similarity::solve_normal;
+use crate::utils::buffer_pool::{recycle_i16, take_i16};
+use crate::utils::gradient_tiles::TiledGradients;
+
+/// Per-point result of [`calc_optical_flow_affine`].
+///
+/// Positions follow the coordinate convention of
+/// [`TrackResult`](crate::TrackResult).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AffineResult {
+    /// Tracked position of the window center in the next frame.
+    pub pos: (f32, f32),
+    /// Linear part `[[a, b], [c, d]]` of the warp: an offset `(u, v)` from
+    /// the previous center lands at `pos + (a*u + b*v, c*u + d*v)`. The
+    /// identity when the window content did not deform.
+    pub matrix: [[f32; 2]; 2],
+    /// Why tracking ended the way it did.
+    pub status: TrackStatus,
+    /// Mean absolute photometric residual over the warped window at level 0,
+    /// in 8-bit intensity units; [`f32::INFINITY`] when it could not be
+    /// measured.
+    pub error: f32,
+}
+
+/// Warp `z -> a * z + t` of one window, with `z` relative to the previous
+/// center.
+#[derive(Clone, Copy)]
+struct Affine {
+    a: [[f32; 2]; 2],
+    t: (f32, f32),
+}
+
+impl Affine {
+    fn apply(&self, (u, v): (f32, f32)) -> (f32, f32) {
+        let a = &self.a;
+        (
+            a[0][0] * u + a[0][1] * v + self.t.0,
+            a[1][0] * u + a[1][1] * v + self.t.1,
+        )
+    }
+
+    /// Half extent of the warped square window of `radius`.
+    fn extent(&self, radius: usize) -> usize {
+        let a = &self.a;
+        let reach = (a[0][0].abs() + a[0][1].abs()).max(a[1][0].abs() + a[1][1].abs());
+        (radius as f32 * reach).ceil() as usize
+    }
+
+    /// `self ∘ step⁻¹`, the inverse-compositional update, or `None` when
+    /// `step` is not invertible.
+    fn compose_inverse(&self, step: &Affine) -> Option<Affine> {
+        let s = &step.a;
+        let det = s[0][0] * s[1][1] - s[0][1] * s[1][0];
+        if det.abs() < 1e-9 {
+            return None;
+        }
+        let inv = [
+            [s[1][1] / det, -s[0][1] / det],
+            [-s[1][0] / det, s[0][0] / det],
+        ];
+        let a = &self.a;
+        let m = [
+            [
+                a[0][0] * inv[0][0] + a[0][1] * inv[1][0],
+                a[0][0] * inv[0][1] + a[0][1] * inv[1][1],
+            ],
+            [
+                a[1][0] * inv[0][0] + a[1][1] * inv[1][0],
+                a[1][0] * inv[0][1] + a[1][1] * inv[1][1],
+            ],
+        ];
+        let shift = (
+            m[0][0] * step.t.0 + m[0][1] * step.t.1,
+            m[1][0] * step.t.0 + m[1][1] * step.t.1,
+        );
+        Some(Affine {
+            a: m,
+            t: (self.t.0 - shift.0, self.t.1 - shift.1),
+        })
+    }
+}
+
+/// Pyramidal Lucas-Kanade tracking of an affine warp (translation plus a
+/// full 2x2 linear part) per point.
+///
+/// Each window is registered with inverse-compositional Gauss-Newton on six
+/// parameters, coarse to fine: the translation doubles from level to level,
+/// the linear part carries over unchanged. The iteration starts from the
+/// identity (or the predicted translation) at the coarsest level.
+///
+/// Texture is judged as in [`calc_optical_flow_ex`](crate::calc_optical_flow_ex),
+/// by the normalized minimum eigenvalue of the translational gradient matrix;
+/// a window whose deformation is not observable (e.g. a single straight edge)
+/// is reported as [`TrackStatus::LowTexture`] as well. Six parameters need
+/// more texture than four, so prefer windows of 21 pixels or more.
+///
+/// # Arguments
+/// * `prev_pyramid` / `curr_pyramid` - frame pyramids, see
+///   [`build_pyramid`](crate::build_pyramid)
+/// * `prev_points` - points to track (level-0 coordinates)
+/// * `predicted` - optional predicted positions, see
+///   [`calc_optical_flow_ex`](crate::calc_optical_flow_ex)
+/// * `window_size` - side of the square window in the previous frame (odd)
+/// * `max_iterations` - max iterations per pyramid level
+/// * `min_eigen_threshold` - see
+///   [`DEFAULT_MIN_EIGEN_THRESHOLD`](crate::DEFAULT_MIN_EIGEN_THRESHOLD)
+///
+/// # Panics
+/// Panics if the pyramids fail [`validate_pyramid_pair`], or if `predicted`
+/// is `Some` but its length differs from `prev_points`.
+///
+/// # Returns
+/// One [`AffineResult`] per input point, in the same order.
+pub fn calc_optical_flow_affine(
+    prev_pyramid: &[GrayImage],
+    curr_pyramid: &[GrayImage],
+    prev_points: &[(f32, f32)],
+    predicted: Option<&[(f32, f32)]>,
+    window_size: usize,
+    max_iterations: usize,
+    min_eigen_threshold: f32,
+) -> Vec<AffineResult> {
+    if let Err(error) = validate_pyramid_pair(prev_pyramid, curr_pyramid) {
+        panic!("{error}");
+    }
+    if let Some(predicted) = predicted {
+        assert_eq!(
+            predicted.len(),
+            prev_points.len(),
+            "predicted must have one entry per prev_point"
+        );
+    }
+
+    let epsilon = 1e-3;
+    let radius = window_size / 2;
+    let mut offsets = Vec::new();
+    build_window_offsets_into(radius, &mut offsets);
+    let n_pixels = offsets.len();
+    let area = n_pixels as f32;
+
+    let n = (prev_pyramid[0].width() * prev_pyramid[0].height()) as usize;
+    let mut tiles = TiledGradients::with_planes(take_i16(n), take_i16(n));
+    let mut template = vec![0.0f32; n_pixels];
+    let mut steepest = vec![[0.0f32; 6]; n_pixels];
+
+    // Warps in level-0 units: the translation is the displacement of the
+    // window center.
+    let identity = [[1.0, 0.0], [0.0, 1.0]];
+    let mut warps: Vec<Affine> = prev_points
+        .iter()
+        .enumerate()
+        .map(|(i, &(px, py))| {
+            let t = predicted.map_or((0.0, 0.0), |p| (p[i].0 - px, p[i].1 - py));
+            Affine { a: identity, t }
+        })
+        .collect();
+    let mut out: Vec<AffineResult> = prev_points
+        .iter()
+        .map(|&pos| AffineResult {
+            pos,
+            matrix: identity,
+            status: TrackStatus::Tracked,
+            error: f32::INFINITY,
+        })
+        .collect();
+
+    for level in (0..prev_pyramid.len()).rev() {
+        let scale = 2f32.powi(level as i32);
+        let prev_img = &prev_pyramid[level];
+        let curr_img = &curr_pyramid[level];
+        let (w, h) = prev_img.dimensions();
+        tiles.reset(prev_img, prev_points.len(), window_size);
+
+        for (idx, &(prev_x, prev_y)) in prev_points.iter().enumerate() {
+            let (x, y) = (prev_x / scale, prev_y / scale);
+            let mut warp = Affine {
+                t: (warps[idx].t.0 / scale, warps[idx].t.1 / scale),
+                ..warps[idx]
+            };
+
+            if !in_bounds(prev_img, x, y, radius) {
+                out[idx].status = TrackStatus::OutOfBounds;
+                continue;
+            }
+
+            // Template and steepest-descent images at the identity warp, for
+            // the parameters (a00 - 1, a10, a01, a11 - 1, tx, ty).
+            let (x0, y0) = (x.floor() as i64, y.floor() as i64);
+            let r = radius as i64;
+            tiles.ensure(prev_img, x0 - r..x0 + r + 2, y0 - r..y0 + r + 2);
+            let mut hessian = [[0.0f32; 6]; 6];
+            for (i, &(u, v)) in offsets.iter().enumerate() {
+                let (ix, iy) = interpolate_gradient(&tiles.planes(), w, h, x + u, y + v);
+                let (ix, iy) = (ix / 32.0, iy / 32.0);
+                template[i] = interpolate(prev_img, x + u, y + v);
+                let sd = [ix * u, iy * u, ix * v, iy * v, ix, iy];
+                for (row, &a) in hessian.iter_mut().zip(&sd) {
+                    for (cell, &b) in row.iter_mut().zip(&sd) {
+                        *cell += a * b;
+                    }
+                }
+                steepest[i] = sd;
+            }
+
+            let min_eig = min_eigenvalue(hessian[4][4], hessian[4][5], hessian[5][5]);
+            if min_eig / area < min_eigen_threshold {
+                out[idx].status = TrackStatus::LowTexture;
+                continue;
+            }
+
+            // The deformation must be observable as well.
+            if solve_normal(hessian, [0.0; 6]).is_none() {
+                out[idx].status = TrackStatus::LowTexture;
+                continue;
+            }
+
+            let mut converged = false;
+            let mut out_of_bounds = false;
+            let mut diverged = false;
+            for _ in 0..max_iterations {
+                let (cx, cy) = (x + warp.t.0, y + warp.t.1);
+                if !in_bounds(curr_img, cx, cy, warp.extent(radius)) {
+                    out_of_bounds = true;
+                    break;
+                }
+
+                let mut b = [0.0f32; 6];
+                for (i, &offset) in offsets.iter().enumerate() {
+                    let (wx, wy) = warp.apply(offset);
+                    let error = interpolate(curr_img, x + wx, y + wy) - template[i];
+                    for (acc, &s) in b.iter_mut().zip(&steepest[i]) {
+                        *acc += s * error;
+                    }
+                }
+
+                let Some(dp) = solve_normal(hessian, b) else {
+                    diverged = true;
+                    break;
+                };
+                let step = Affine {
+                    a: [[1.0 + dp[0], dp[2]], [dp[1], 1.0 + dp[3]]],
+                    t: (dp[4], dp[5]),
+                };
+                let Some(next) = warp.compose_inverse(&step) else {
+                    diverged = true;
+                    break;
+                };
+                warp = next;
+
+                // Largest displacement of a window pixel caused by the step.
+                let motion = dp[4].abs().max(dp[5].abs())
+                    + radius as f32 * dp[..4].iter().map(|d| d.abs()).sum::<f32>();
+                if !motion.is_finite()
+                    || warp.a.iter().flatten().any(|a| !a.is_finite())
+                    || !warp.t.0.is_finite()
+                    || !warp.t.1.is_finite()
+                    || motion > window_size as f32
+                {
+                    diverged = true;
+                    break;
+                }
+                if motion < epsilon {
+                    converged = true;
+                    break;
+                }
+            }
+
+            out[idx].status = if out_of_bounds {
+                TrackStatus::OutOfBounds
+            } else if diverged || !converged {
+                TrackStatus::Diverged
+            } else {
+                TrackStatus::Tracked
+            };
+            warps[idx] = Affine {
+                t: (warp.t.0 * scale, warp.t.1 * scale),
+                ..warp
+            };
+
+            if level == 0 && !out_of_bounds && !diverged {
+                let (cx, cy) = (x + warp.t.0, y + warp.t.1);
+                if in_bounds(curr_img, cx, cy, warp.extent(radius)) {
+                    let sum: f32 = offsets
+                        .iter()
+                        .zip(&template)
+                        .map(|(&offset, &t)| {
+                            let (wx, wy) = warp.apply(offset);
+                            (interpolate(curr_img, x + wx, y + wy) - t).abs()
+                        })
+                        .sum();
+                    out[idx].error = sum / area;
+                }
+            }
+        }
+    }
+
+    for ((result, warp), &(x, y)) in out.iter_mut().zip(&warps).zip(prev_points) {
+        result.pos = (x + warp.t.0, y + warp.t.1);
+        result.matrix = warp.a;
+    }
+
+    let (grad_x, grad_y) = tiles.into_planes();
+    recycle_i16(grad_x);
+    recycle_i16(grad_y);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compose_inverse_undoes_step() {
+        let warp = Affine {
+            a: [[1.1, 0.2], [-0.1, 0.9]],
+            t: (3.0, -1.0),
+        };
+        let composed = warp.compose_inverse(&warp).unwrap();
+        for (row, expected) in composed.a.iter().zip([[1.0, 0.0], [0.0, 1.0]]) {
+            for (a, e) in row.iter().zip(expected) {
+                assert!((a - e).abs() < 1e-6, "{:?}", composed.a);
+            }
+        }
+        assert!(composed.t.0.abs() < 1e-6 && composed.t.1.abs() < 1e-6);
+
+        let singular = Affine {
+            a: [[1.0, 2.0], [0.5, 1.0]],
+            t: (0.0, 0.0),
+        };
+        assert!(warp.compose_inverse(&singular).is_none());
+    }
+}
@@ -0,0 +1,183 @@
+//! Segment-test corner detection for cheap re-detection.
+//!
+//! The Shi-Tomasi detector computes gradients, a structure tensor and an
+//! eigenvalue for every pixel. On low-power devices that re-detect often, the
+//! accelerated segment test is much cheaper: a pixel is a corner when a
+//! contiguous arc of at least 9 of the 16 pixels on a radius-3 circle around
+//! it are all brighter, or all darker, than the center by more than a
+//! threshold. [`agast_corners`] evaluates the circle in the order of a fixed
+//! decision tree, so most pixels are rejected after two or four comparisons,
+//! and returns the crate's usual `(x, y, score)` keypoints, thinned by the
+//! same 3x3 non-maximum suppression as the Shi-Tomasi detector.
+
+use std::cmp::Ordering;
+
+use image::GrayImage;
+
+use crate::features::non_maximum_suppression;
+use crate::utils::buffer_pool::{recycle_f32, take_f32};
+
+/// The 16 pixels of the radius-3 Bresenham circle, clockwise from the top.
+const CIRCLE: [(i32, i32); 16] = [
+    (0, -3),
+    (1, -3),
+    (2, -2),
+    (3, -1),
+    (3, 0),
+    (3, 1),
+    (2, 2),
+    (1, 3),
+    (0, 3),
+    (-1, 3),
+    (-2, 2),
+    (-3, 1),
+    (-3, 0),
+    (-3, -1),
+    (-2, -2),
+    (-1, -3),
+];
+
+/// Shortest arc of the segment test.
+const ARC: u32 = 9;
+
+/// Finds corners with the accelerated segment test (9 of 16).
+///
+/// A pixel is a corner when at least 9 contiguous circle pixels are brighter
+/// than `center + threshold` or darker than `center - threshold`. Its score
+/// is the summed excess `|p - center| - threshold` over the circle pixels of
+/// the winning side, so higher-contrast corners rank first. Pixels closer
+/// than 3 to the border are never corners.
+///
+/// With `nonmax_suppression`, only corners whose score no 8-neighbor exceeds
+/// are kept, like OpenCV's FAST option of the same name.
+///
+/// # Returns
+/// Vector of corners with their score, sorted in descending order of score
+pub fn agast_corners(
+    image: &GrayImage,
+    threshold: u8,
+    nonmax_suppression: bool,
+) -> Vec<(u32, u32, f32)> {
+    let (width, height) = image.dimensions();
+    let mut scores = take_f32((width * height) as usize);
+    let mut corners = Vec::new();
+
+    if width > 6 && height > 6 {
+        let data = image.as_raw();
+        let stride = width as usize;
+        let offsets = CIRCLE.map(|(dx, dy)| dy as isize * stride as isize + dx as isize);
+        for y in 3..height as usize - 3 {
+            for x in 3..width as usize - 3 {
+                let i = y * stride + x;
+                let circle = |k: usize| data[(i as isize + offsets[k]) as usize];
+                if let Some(score) = segment_test(data[i], threshold, circle) {
+                    scores[i] = score;
+                    if !nonmax_suppression {
+                        corners.push((x as u32, y as u32, score));
+                    }
+                }
+            }
+        }
+        if nonmax_suppression {
+            corners = non_maximum_suppression(&scores, width, height, f32::MIN_POSITIVE);
+        }
+    }
+    recycle_f32(scores);
+
+    corners.sort_by(|a, b| b.2.partial_cmp(&a.2).unwrap_or(Ordering::Equal));
+    corners
+}
+
+/// Segment test of one pixel, returning its score if it is a corner.
+///
+/// The circle is read in decision-tree order: any 9-pixel arc contains pixel
+/// 0 or 8 and at least two of 0, 4, 8 and 12, so those reject most pixels
+/// before the full circle is classified.
+#[inline(always)]
+fn segment_test(center: u8, threshold: u8, circle: impl Fn(usize) -> u8) -> Option<f32> {
+    let bright = center.saturating_add(threshold);
+    let dark = center.saturating_sub(threshold);
+    let class = |p: u8| ((p > bright) as u32, (p < dark) as u32);
+
+    let (b0, d0) = class(circle(0));
+    let (b8, d8) = class(circle(8));
+    if b0 | b8 | d0 | d8 == 0 {
+        return None;
+    }
+    let (b4, d4) = class(circle(4));
+    let (b12, d12) = class(circle(12));
+    if b0 + b4 + b8 + b12 < 2 && d0 + d4 + d8 + d12 < 2 {
+        return None;
+    }
+
+    let (mut brighter, mut darker) = (0u32, 0u32);
+    for k in 0..16 {
+        let (b, d) = class(circle(k));
+        brighter |= b << k;
+        darker |= d << k;
+    }
+    let mask = if has_arc(brighter) {
+        brighter
+    } else if has_arc(darker) {
+        darker
+    } else {
+        return None;
+    };
+
+    let score = (0..16)
+        .filter(|&k| mask >> k & 1 == 1)
+        .map(|k| (circle(k) as i32 - center as i32).abs() - threshold as i32)
+        .sum::<i32>();
+    Some(score as f32)
+}
+
+/// Whether the 16-bit circle mask holds a run of at least [`ARC`] set bits,
+/// wrapping around.
+fn has_arc(mask: u32) -> bool {
+    let wrapped = mask | mask << 16;
+    let mut run = wrapped;
+    for shift in 1..ARC {
+        run &= wrapped >> shift;
+    }
+    run != 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The segment test without the early rejections.
+    fn exhaustive(center: u8, threshold: u8, circle: [u8; 16]) -> bool {
+        let bright = circle.map(|p| p as i32 > center as i32 + threshold as i32);
+        let dark = circle.map(|p| (p as i32) < center as i32 - threshold as i32);
+        [bright, dark]
+            .iter()
+            .any(|side| (0..16).any(|start| (0..ARC as usize).all(|k| side[(start + k) % 16])))
+    }
+
+    #[test]
+    fn decision_order_never_rejects_a_corner() {
+        let mut state = 0x2545_f491u32;
+        let mut next = || {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state
+        };
+        let mut corners = 0;
+        for _ in 0..20_000 {
+            let center = (next() % 256) as u8;
+            // Two-level circles, so arcs of every length occur.
+            let (lo, hi) = ((next() % 256) as u8, (next() % 256) as u8);
+            let pattern = next();
+            let circle: [u8; 16] =
+                std::array::from_fn(|k| if pattern >> k & 1 == 1 { hi } else { lo });
+            let threshold = (next() % 40) as u8;
+
+            let fast = segment_test(center, threshold, |k| circle[k]).is_some();
+            assert_eq!(fast, exhaustive(center, threshold, circle), "{circle:?}");
+            corners += fast as usize;
+        }
+        assert!(corners > 1000, "{corners}");
+    }
+}
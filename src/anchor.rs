@@ -0,0 +1,223 @@
+//! First-frame template anchoring against tracking drift.
+//!
+//! Frame-to-frame tracking registers each window against the previous frame,
+//! so small per-frame errors add up over the life of a track. [`TrackAnchors`]
+//! keeps the window of every track as it looked in the frame where the track
+//! was born and periodically re-registers the tracked position against it,
+//! which pulls long-lived tracks back onto their original feature.
+
+use image::GrayImage;
+
+use crate::lk::{
+    ReferenceWindow, TrackResult, TrackStatus, build_window_offsets_into, in_bounds_rect,
+    invert_2x2,
+};
+use crate::utils::gradient_tiles::TiledGradients;
+
+/// Settings of [`TrackAnchors`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AnchorParams {
+    /// Side of the square template window (odd).
+    pub window_size: usize,
+    /// A track is re-registered every `interval` calls of
+    /// [`TrackAnchors::correct`], counted from its birth.
+    pub interval: u32,
+    /// Max iterations of the re-registration.
+    pub max_iterations: usize,
+    /// Largest correction, in pixels, that is applied. A template that
+    /// registers further away than this has most likely stopped matching the
+    /// feature (appearance change, occlusion) and is ignored.
+    pub max_correction: f32,
+}
+
+impl Default for AnchorParams {
+    fn default() -> Self {
+        AnchorParams {
+            window_size: 21,
+            interval: 10,
+            max_iterations: 20,
+            max_correction: 2.0,
+        }
+    }
+}
+
+/// Template of one track from its birth frame.
+struct Anchor {
+    template: ReferenceWindow,
+    /// Inverse spatial gradient matrix, `None` when the template cannot be
+    /// registered (out of bounds or flat).
+    inverse: Option<(f32, f32, f32)>,
+    age: u32,
+}
+
+/// Birth-frame templates of a set of tracks, used to correct drift.
+///
+/// Tracks are identified by their index, like the points of
+/// [`TrackerContext::track`](crate::TrackerContext::track): keep the anchors
+/// in step with the point list by calling [`push`](Self::push) for every new
+/// track and [`retain`](Self::retain) when tracks are dropped. After each
+/// tracking step, [`correct`](Self::correct) refines the results of the
+/// tracks that are due.
+pub struct TrackAnchors {
+    params: AnchorParams,
+    anchors: Vec<Anchor>,
+    offsets: Vec<(f32, f32)>,
+    gradients: TiledGradients,
+}
+
+impl TrackAnchors {
+    /// Creates an empty set of anchors.
+    ///
+    /// # Panics
+    /// Panics if `params.window_size` is even or `params.interval` is zero.
+    pub fn new(params: AnchorParams) -> Self {
+        assert!(params.window_size % 2 == 1, "window_size must be odd");
+        assert!(params.interval > 0, "interval must be non-zero");
+        let mut offsets = Vec::new();
+        build_window_offsets_into(params.window_size / 2, &mut offsets);
+        TrackAnchors {
+            params,
+            anchors: Vec::new(),
+            offsets,
+            gradients: TiledGradients::default(),
+        }
+    }
+
+    /// Number of anchored tracks.
+    pub fn len(&self) -> usize {
+        self.anchors.len()
+    }
+
+    /// Whether no track is anchored.
+    pub fn is_empty(&self) -> bool {
+        self.anchors.is_empty()
+    }
+
+    /// Adds a track born at `pos` (level-0 coordinates) in `image`, storing
+    /// its template.
+    pub fn push(&mut self, image: &GrayImage, pos: (f32, f32)) {
+        let anchor = self.capture(image, pos);
+        self.anchors.push(anchor);
+    }
+
+    /// Replaces the template of track `index`, e.g. after a deliberate
+    /// re-detection, and restarts its interval.
+    ///
+    /// # Panics
+    /// Panics if `index` is out of range.
+    pub fn reanchor(&mut self, index: usize, image: &GrayImage, pos: (f32, f32)) {
+        self.anchors[index] = self.capture(image, pos);
+    }
+
+    /// Keeps only the tracks for which `keep(index)` returns `true`,
+    /// preserving their order.
+    pub fn retain(&mut self, mut keep: impl FnMut(usize) -> bool) {
+        let mut index = 0;
+        self.anchors.retain(|_| {
+            index += 1;
+            keep(index - 1)
+        });
+    }
+
+    /// Advances every track by one frame and re-registers the tracks that are
+    /// due against their birth template in `image`, the frame the results
+    /// were tracked into.
+    ///
+    /// Translation-only Lucas-Kanade runs at full resolution, starting from
+    /// the tracked position. Only [`TrackStatus::Tracked`] results are
+    /// touched; their position is moved when the registration converges
+    /// within [`AnchorParams::max_correction`] pixels, and left alone
+    /// otherwise.
+    ///
+    /// # Returns
+    /// The number of corrected tracks.
+    ///
+    /// # Panics
+    /// Panics if `results` does not have one entry per anchored track.
+    pub fn correct(&mut self, image: &GrayImage, results: &mut [TrackResult]) -> usize {
+        assert_eq!(
+            results.len(),
+            self.anchors.len(),
+            "results must have one entry per anchored track"
+        );
+        let AnchorParams {
+            window_size,
+            interval,
+            max_iterations,
+            max_correction,
+        } = self.params;
+        let radius = (window_size / 2, window_size / 2);
+        let epsilon = 1e-3;
+
+        let mut corrected = 0;
+        for (anchor, result) in self.anchors.iter_mut().zip(results.iter_mut()) {
+            anchor.age += 1;
+            if anchor.age % interval != 0 || result.status != TrackStatus::Tracked {
+                continue;
+            }
+            let Some((inv_h00, inv_h01, inv_h11)) = anchor.inverse else {
+                continue;
+            };
+
+            let (x, y) = result.pos;
+            let (mut dx, mut dy) = (0.0f32, 0.0f32);
+            let mut converged = false;
+            for _ in 0..max_iterations {
+                if !in_bounds_rect(image, x + dx, y + dy, radius) {
+                    break;
+                }
+                let mismatch =
+                    anchor
+                        .template
+                        .mismatch(image, x + dx, y + dy, radius, &self.offsets);
+                let ddx = inv_h00 * mismatch.bx + inv_h01 * mismatch.by;
+                let ddy = inv_h01 * mismatch.bx + inv_h11 * mismatch.by;
+                dx += ddx;
+                dy += ddy;
+                if !dx.is_finite() || !dy.is_finite() || dx.hypot(dy) > max_correction {
+                    break;
+                }
+                if ddx.abs() < epsilon && ddy.abs() < epsilon {
+                    converged = true;
+                    break;
+                }
+            }
+
+            if converged {
+                result.pos = (x + dx, y + dy);
+                result.error =
+                    anchor
+                        .template
+                        .mean_error(image, x + dx, y + dy, radius, &self.offsets);
+                corrected += 1;
+            }
+        }
+        corrected
+    }
+
+    fn capture(&mut self, image: &GrayImage, (x, y): (f32, f32)) -> Anchor {
+        let radius = (self.params.window_size / 2, self.params.window_size / 2);
+        let mut template = ReferenceWindow::default();
+        template.resize(self.offsets.len());
+        if !in_bounds_rect(image, x, y, radius) {
+            return Anchor {
+                template,
+                inverse: None,
+                age: 0,
+            };
+        }
+
+        let (x0, y0) = (x.floor() as i64, y.floor() as i64);
+        let r = radius.0 as i64;
+        self.gradients.reset(image, 1, self.params.window_size);
+        self.gradients
+            .ensure(image, x0 - r..x0 + r + 2, y0 - r..y0 + r + 2);
+        let (gxx, gxy, gyy) =
+            template.fill(image, &self.gradients.planes(), x, y, radius, &self.offsets);
+        Anchor {
+            template,
+            inverse: invert_2x2(gxx, gxy, gyy, 1e-6),
+            age: 0,
+        }
+    }
+}
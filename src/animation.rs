@@ -0,0 +1,144 @@
+//! Animated GIF and APNG output of annotated frame sequences (`animation`
+//! feature).
+//!
+//! Drawing overlays with [`draw_tracks`](crate::draw_tracks) and its
+//! siblings produces one image per frame; [`write_gif`] and [`write_apng`]
+//! turn such a sequence into a single looping file that CLI tools and examples can share without an external
+//! encoder. GIF is smaller and plays everywhere but quantizes every frame to
+//! 256 colors; APNG keeps the frames lossless.
+
+use std::io::Write;
+
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::error::{
+    EncodingError, ImageFormatHint, ParameterError, ParameterErrorKind, UnsupportedError,
+    UnsupportedErrorKind,
+};
+use image::{Delay, DynamicImage, Frame, ImageError, ImageFormat, ImageResult, RgbImage};
+
+/// Writes `frames` as an endlessly looping GIF, showing each for `delay_ms`
+/// milliseconds.
+///
+/// # Errors
+/// Fails if `frames` is empty or differs in size, or when encoding or
+/// writing fails.
+pub fn write_gif<W: Write>(writer: W, frames: &[RgbImage], delay_ms: u32) -> ImageResult<()> {
+    check_frames(frames)?;
+    let mut encoder = GifEncoder::new_with_speed(writer, 10);
+    encoder.set_repeat(Repeat::Infinite)?;
+    let delay = Delay::from_numer_denom_ms(delay_ms, 1);
+    encoder.encode_frames(frames.iter().map(|frame| {
+        let rgba = DynamicImage::ImageRgb8(frame.clone()).into_rgba8();
+        Frame::from_parts(rgba, 0, 0, delay)
+    }))
+}
+
+/// Writes `frames` as an endlessly looping APNG, showing each for `delay_ms`
+/// milliseconds. Viewers without APNG support show the first frame.
+///
+/// # Errors
+/// Fails if `frames` is empty or differs in size, if `delay_ms` exceeds
+/// 65535, or when encoding or writing fails.
+pub fn write_apng<W: Write>(writer: W, frames: &[RgbImage], delay_ms: u32) -> ImageResult<()> {
+    check_frames(frames)?;
+    let delay_ms = u16::try_from(delay_ms).map_err(|_| {
+        ImageError::Unsupported(UnsupportedError::from_format_and_kind(
+            ImageFormat::Png.into(),
+            UnsupportedErrorKind::GenericFeature(format!("APNG frame delay of {delay_ms} ms")),
+        ))
+    })?;
+    let (width, height) = frames[0].dimensions();
+
+    let mut encoder = png::Encoder::new(writer, width, height);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .set_animated(frames.len() as u32, 0)
+        .map_err(png_error)?;
+    encoder.set_frame_delay(delay_ms, 1000).map_err(png_error)?;
+    let mut writer = encoder.write_header().map_err(png_error)?;
+    for frame in frames {
+        writer.write_image_data(frame.as_raw()).map_err(png_error)?;
+    }
+    writer.finish().map_err(png_error)
+}
+
+/// Rejects empty sequences and frames of differing size.
+fn check_frames(frames: &[RgbImage]) -> ImageResult<()> {
+    let Some(first) = frames.first() else {
+        return Err(ImageError::Parameter(ParameterError::from_kind(
+            ParameterErrorKind::Generic("no frames to write".into()),
+        )));
+    };
+    if frames
+        .iter()
+        .any(|frame| frame.dimensions() != first.dimensions())
+    {
+        return Err(ImageError::Parameter(ParameterError::from_kind(
+            ParameterErrorKind::DimensionMismatch,
+        )));
+    }
+    Ok(())
+}
+
+fn png_error(err: png::EncodingError) -> ImageError {
+    match err {
+        png::EncodingError::IoError(err) => ImageError::IoError(err),
+        err => ImageError::Encoding(EncodingError::new(
+            ImageFormatHint::Exact(ImageFormat::Png),
+            err,
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::AnimationDecoder;
+    use image::codecs::gif::GifDecoder;
+    use image::codecs::png::PngDecoder;
+    use std::io::Cursor;
+
+    fn frames() -> Vec<RgbImage> {
+        (0..3u8)
+            .map(|i| RgbImage::from_fn(16, 8, |x, _| image::Rgb([x as u8 * 16, 0, 100 * i])))
+            .collect()
+    }
+
+    #[test]
+    fn sequences_round_trip_through_gif_and_apng() {
+        let frames = frames();
+
+        let mut gif = Vec::new();
+        write_gif(&mut gif, &frames, 40).unwrap();
+        let decoded = GifDecoder::new(Cursor::new(&gif))
+            .unwrap()
+            .into_frames()
+            .collect_frames()
+            .unwrap();
+        assert_eq!(decoded.len(), 3);
+        assert_eq!(decoded[1].delay().numer_denom_ms(), (40, 1));
+        assert_eq!(decoded[2].buffer().get_pixel(0, 0)[2], 200);
+
+        let mut apng = Vec::new();
+        write_apng(&mut apng, &frames, 40).unwrap();
+        let decoded = PngDecoder::new(Cursor::new(&apng))
+            .unwrap()
+            .apng()
+            .unwrap()
+            .into_frames()
+            .collect_frames()
+            .unwrap();
+        assert_eq!(decoded.len(), 3);
+        assert_eq!(
+            decoded[2].buffer().get_pixel(5, 3).0,
+            [80, 0, 200, 255],
+            "APNG frames are lossless"
+        );
+
+        assert!(write_gif(Vec::new(), &[], 40).is_err());
+        let mut mixed = frames.clone();
+        mixed.push(RgbImage::new(8, 8));
+        assert!(write_apng(Vec::new(), &mixed, 40).is_err());
+    }
+}
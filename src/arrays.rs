@@ -0,0 +1,121 @@
+//! Struct-of-arrays layout of tracking results.
+//!
+//! Foreign callers, JavaScript over Wasm memory in particular, read results
+//! fastest as flat typed arrays: one `Float32Array` of x coordinates, one of
+//! y coordinates and so on, viewed in place rather than converted point by
+//! point. [`FlowArrays`] splits a slice of [`TrackResult`]s into such
+//! contiguous columns.
+
+use crate::lk::TrackResult;
+
+/// Tracking results as separate contiguous columns, one entry per point.
+///
+/// [`fill`](Self::fill) reuses the columns' storage, so converting every
+/// frame's results is allocation-free once the point count stops growing.
+/// Each column is exposed as a slice whose pointer and length can be handed
+/// across an FFI boundary.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FlowArrays {
+    xs: Vec<f32>,
+    ys: Vec<f32>,
+    statuses: Vec<u8>,
+    errors: Vec<f32>,
+}
+
+impl FlowArrays {
+    /// Creates empty columns.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Columns holding `results`.
+    pub fn from_results(results: &[TrackResult]) -> Self {
+        let mut arrays = Self::new();
+        arrays.fill(results);
+        arrays
+    }
+
+    /// Replaces the contents of the columns with `results`.
+    pub fn fill(&mut self, results: &[TrackResult]) {
+        self.xs.clear();
+        self.ys.clear();
+        self.statuses.clear();
+        self.errors.clear();
+        for result in results {
+            self.xs.push(result.pos.0);
+            self.ys.push(result.pos.1);
+            self.statuses.push(result.status.code());
+            self.errors.push(result.error);
+        }
+    }
+
+    /// Number of points.
+    pub fn len(&self) -> usize {
+        self.xs.len()
+    }
+
+    /// Whether there are no points.
+    pub fn is_empty(&self) -> bool {
+        self.xs.is_empty()
+    }
+
+    /// Tracked x coordinates.
+    pub fn xs(&self) -> &[f32] {
+        &self.xs
+    }
+
+    /// Tracked y coordinates.
+    pub fn ys(&self) -> &[f32] {
+        &self.ys
+    }
+
+    /// Statuses as [`TrackStatus::code`](crate::TrackStatus::code)s.
+    pub fn statuses(&self) -> &[u8] {
+        &self.statuses
+    }
+
+    /// Tracking errors, see [`TrackResult::error`].
+    pub fn errors(&self) -> &[f32] {
+        &self.errors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lk::TrackStatus;
+
+    #[test]
+    fn columns_follow_the_results() {
+        let results = [
+            TrackResult {
+                pos: (1.5, 2.5),
+                status: TrackStatus::Tracked,
+                error: 0.25,
+                aperture: None,
+            },
+            TrackResult {
+                pos: (-3.0, 40.0),
+                status: TrackStatus::FbInconsistent,
+                error: 7.0,
+                aperture: None,
+            },
+        ];
+        let mut arrays = FlowArrays::from_results(&results);
+        assert_eq!(arrays.len(), 2);
+        assert_eq!(arrays.xs(), [1.5, -3.0]);
+        assert_eq!(arrays.ys(), [2.5, 40.0]);
+        assert_eq!(arrays.statuses(), [0, 4]);
+        assert_eq!(arrays.errors(), [0.25, 7.0]);
+        assert_eq!(
+            TrackStatus::from_code(arrays.statuses()[1]),
+            Some(TrackStatus::FbInconsistent)
+        );
+        assert_eq!(TrackStatus::from_code(6), None);
+
+        arrays.fill(&results[1..]);
+        assert_eq!((arrays.xs(), arrays.statuses()), (&[-3.0][..], &[4][..]));
+        arrays.fill(&[]);
+        assert!(arrays.is_empty());
+    }
+}
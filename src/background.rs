@@ -0,0 +1,192 @@
+//! Running-average background subtraction for static cameras.
+//!
+//! A much cheaper complement to the flow-based motion tools: the background is
+//! an exponential moving average of past frames, and pixels that differ from
+//! it are foreground.
+
+use image::{GrayImage, Luma};
+
+use crate::utils::morphology::{dilate_in_place, erode_in_place};
+
+/// Mask value of foreground pixels in [`BackgroundModel::apply`].
+pub const FOREGROUND: u8 = 255;
+
+/// Mask value of shadow pixels in [`BackgroundModel::apply`], as in OpenCV's
+/// background subtractors.
+pub const SHADOW: u8 = 127;
+
+/// Settings of a [`BackgroundModel`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BackgroundParams {
+    /// Weight `α` of the newest frame in `bg = (1 - α) * bg + α * frame`.
+    /// Larger values adapt faster to scene changes but absorb slow movers.
+    pub learning_rate: f32,
+    /// Minimum absolute difference from the background, in 8-bit intensity
+    /// units, for a pixel to be foreground.
+    pub threshold: f32,
+    /// Range of `frame / background` brightness ratios treated as a cast
+    /// shadow rather than foreground, or `None` to disable shadow detection.
+    /// A shadow darkens the background without changing its texture.
+    pub shadow_ratio: Option<(f32, f32)>,
+    /// Radius of the square morphological opening (removing speckles) and
+    /// closing (filling holes) applied to the foreground, 0 to disable.
+    pub morphology_radius: u32,
+}
+
+impl Default for BackgroundParams {
+    fn default() -> Self {
+        BackgroundParams {
+            learning_rate: 0.05,
+            threshold: 25.0,
+            shadow_ratio: Some((0.5, 0.9)),
+            morphology_radius: 1,
+        }
+    }
+}
+
+/// Exponential running-average background model.
+///
+/// The first frame (and the first frame after a size change) initializes the
+/// background and yields an empty mask.
+#[derive(Debug, Clone, Default)]
+pub struct BackgroundModel {
+    params: BackgroundParams,
+    width: u32,
+    height: u32,
+    background: Vec<f32>,
+}
+
+impl BackgroundModel {
+    /// Creates an empty model with the given settings.
+    pub fn new(params: BackgroundParams) -> Self {
+        BackgroundModel {
+            params,
+            ..Self::default()
+        }
+    }
+
+    /// Classifies `frame` against the current background, then blends it in.
+    ///
+    /// # Returns
+    /// Mask of the frame's size: [`FOREGROUND`] for foreground, [`SHADOW`] for
+    /// shadow and 0 for background pixels
+    pub fn apply(&mut self, frame: &GrayImage) -> GrayImage {
+        let (width, height) = frame.dimensions();
+        let mut mask = GrayImage::new(width, height);
+        if (width, height) != (self.width, self.height) || self.background.is_empty() {
+            (self.width, self.height) = (width, height);
+            self.background.clear();
+            self.background
+                .extend(frame.as_raw().iter().map(|&v| v as f32));
+            return mask;
+        }
+
+        let BackgroundParams {
+            learning_rate,
+            threshold,
+            shadow_ratio,
+            morphology_radius,
+        } = self.params;
+
+        let mut shadows = Vec::new();
+        for (i, (&value, bg)) in frame.as_raw().iter().zip(&mut self.background).enumerate() {
+            let value = value as f32;
+            if (value - *bg).abs() > threshold {
+                let is_shadow = shadow_ratio
+                    .is_some_and(|(low, high)| (low * *bg..=high * *bg).contains(&value));
+                if is_shadow {
+                    shadows.push(i);
+                } else {
+                    mask.as_mut()[i] = FOREGROUND;
+                }
+            }
+            *bg += learning_rate * (value - *bg);
+        }
+
+        if morphology_radius > 0 {
+            // Opening, then closing.
+            erode_in_place(&mut mask, morphology_radius);
+            dilate_in_place(&mut mask, morphology_radius);
+            dilate_in_place(&mut mask, morphology_radius);
+            erode_in_place(&mut mask, morphology_radius);
+        }
+
+        let data: &mut [u8] = &mut mask;
+        for i in shadows {
+            if data[i] == 0 {
+                data[i] = SHADOW;
+            }
+        }
+        mask
+    }
+
+    /// The current background estimate, rounded to 8 bits.
+    pub fn background(&self) -> GrayImage {
+        GrayImage::from_fn(self.width, self.height, |x, y| {
+            let v = self.background[(y * self.width + x) as usize];
+            Luma([v.round().clamp(0.0, 255.0) as u8])
+        })
+    }
+
+    /// Forgets the background; the next frame re-initializes it.
+    pub fn reset(&mut self) {
+        self.background.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scene(w: u32, h: u32) -> GrayImage {
+        GrayImage::from_fn(w, h, |x, y| Luma([(100 + (x * 3 + y * 5) % 60) as u8]))
+    }
+
+    fn count(mask: &GrayImage, value: u8) -> usize {
+        mask.pixels().filter(|p| p[0] == value).count()
+    }
+
+    #[test]
+    fn detects_object_and_shadow() {
+        let background = scene(64, 48);
+        let mut model = BackgroundModel::new(BackgroundParams::default());
+        assert_eq!(count(&model.apply(&background), 0), 64 * 48);
+        for _ in 0..5 {
+            assert_eq!(count(&model.apply(&background), FOREGROUND), 0);
+        }
+
+        // A bright object, a darkened (shadowed) patch and one noisy pixel.
+        let mut frame = background.clone();
+        for y in 10..20 {
+            for x in 10..20 {
+                frame.put_pixel(x, y, Luma([250]));
+            }
+            for x in 40..50 {
+                let v = background.get_pixel(x, y)[0] as f32 * 0.7;
+                frame.put_pixel(x, y, Luma([v as u8]));
+            }
+        }
+        frame.put_pixel(30, 40, Luma([255]));
+
+        let mask = model.apply(&frame);
+        assert_eq!(count(&mask, FOREGROUND), 100);
+        assert!((10..20).all(|v| mask.get_pixel(v, v)[0] == FOREGROUND));
+        assert_eq!(mask.get_pixel(30, 40)[0], 0, "speckle removed by opening");
+        assert_eq!(count(&mask, SHADOW), 100);
+        assert_eq!(mask.get_pixel(45, 15)[0], SHADOW);
+
+        // The background slowly absorbs the object.
+        let before = model.background().get_pixel(15, 15)[0];
+        assert!(before > background.get_pixel(15, 15)[0]);
+    }
+
+    #[test]
+    fn size_change_reinitializes() {
+        let mut model = BackgroundModel::new(BackgroundParams::default());
+        model.apply(&scene(32, 24));
+        let mask = model.apply(&GrayImage::from_pixel(16, 16, Luma([0])));
+        assert_eq!(mask.dimensions(), (16, 16));
+        assert_eq!(count(&mask, 0), 256);
+        assert_eq!(model.background().dimensions(), (16, 16));
+    }
+}
@@ -0,0 +1,117 @@
+//! Tracking several independent frame pairs in one call.
+//!
+//! A multi-camera rig produces one frame pair per camera per step. Looping
+//! over single calls serializes the cameras and, with the free functions,
+//! rebuilds every workspace each time. [`BatchTracker`] keeps one
+//! [`TrackerContext`] per pair slot, reused from step to step, and with the
+//! `rayon` feature tracks the pairs in parallel on the thread pool.
+
+use image::GrayImage;
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+
+use crate::lk::{TrackResult, TrackerContext};
+
+/// One frame pair of a [`BatchTracker::track`] call.
+#[derive(Debug, Clone, Copy)]
+pub struct FramePair<'a> {
+    /// Previous frame.
+    pub prev: &'a GrayImage,
+    /// Next frame.
+    pub next: &'a GrayImage,
+    /// Points to track (previous-frame, level-0 coordinates).
+    pub points: &'a [(f32, f32)],
+    /// Optional predicted next-frame positions, see
+    /// [`calc_optical_flow_ex`](crate::calc_optical_flow_ex).
+    pub predicted: Option<&'a [(f32, f32)]>,
+}
+
+/// Tracks a batch of independent frame pairs, one [`TrackerContext`] per pair.
+///
+/// Pair `i` of every call uses context `i`, so a rig that always passes its
+/// cameras in the same order reuses each camera's buffers: with fixed image
+/// sizes, level count, window size and point counts, steady-state calls
+/// perform no heap allocation.
+#[derive(Default)]
+pub struct BatchTracker {
+    contexts: Vec<TrackerContext>,
+    len: usize,
+}
+
+impl BatchTracker {
+    /// Creates a tracker without contexts; they are created on the first
+    /// [`track`](Self::track) call.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Prepares and tracks every pair. Results are read back with
+    /// [`results`](Self::results).
+    ///
+    /// See [`TrackerContext::prepare`] and [`TrackerContext::track`] for the
+    /// arguments.
+    ///
+    /// # Panics
+    /// Panics if a pair's frames differ in size, or if its `predicted` does
+    /// not have one entry per point.
+    pub fn track(
+        &mut self,
+        pairs: &[FramePair],
+        levels: usize,
+        window_size: usize,
+        max_iterations: usize,
+        min_eigen_threshold: f32,
+    ) {
+        if self.contexts.len() < pairs.len() {
+            self.contexts.resize_with(pairs.len(), TrackerContext::new);
+        }
+        self.len = pairs.len();
+        let track_pair = |context: &mut TrackerContext, pair: &FramePair| {
+            context.prepare(pair.prev, pair.next, levels);
+            context.track(
+                pair.points,
+                pair.predicted,
+                window_size,
+                max_iterations,
+                min_eigen_threshold,
+            );
+        };
+
+        #[cfg(feature = "rayon")]
+        self.contexts[..pairs.len()]
+            .par_iter_mut()
+            .zip(pairs.par_iter())
+            .for_each(|(context, pair)| track_pair(context, pair));
+        #[cfg(not(feature = "rayon"))]
+        for (context, pair) in self.contexts.iter_mut().zip(pairs) {
+            track_pair(context, pair);
+        }
+    }
+
+    /// Number of pairs tracked by the last [`track`](Self::track) call.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether the last [`track`](Self::track) call had no pairs.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Results of pair `index` from the last [`track`](Self::track) call, one
+    /// per point.
+    ///
+    /// # Panics
+    /// Panics if `index` is not below [`len`](Self::len).
+    pub fn results(&self, index: usize) -> &[TrackResult] {
+        self.contexts[..self.len][index].results()
+    }
+
+    /// The context of pair `index`, e.g. to read its pyramids.
+    ///
+    /// # Panics
+    /// Panics if `index` is not below [`len`](Self::len).
+    pub fn context(&self, index: usize) -> &TrackerContext {
+        &self.contexts[..self.len][index]
+    }
+}
@@ -0,0 +1,278 @@
+//! Hierarchical block-matching motion estimation on a macroblock grid.
+//!
+//! Instead of per-point or per-pixel flow, [`block_motion`] returns one integer
+//! motion vector and its sum of absolute differences (SAD) per fixed-size
+//! block, the representation video encoders and cheap motion detectors work
+//! with.
+
+use image::GrayImage;
+
+use crate::pyramid::validate_pyramid_pair;
+
+/// Motion of one macroblock, see [`BlockMotionField`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BlockMotion {
+    /// Horizontal displacement in level-0 pixels: the block at `(x, y)` in the
+    /// previous frame best matches `(x + dx, y + dy)` in the next frame.
+    pub dx: i32,
+    /// Vertical displacement in level-0 pixels.
+    pub dy: i32,
+    /// Sum of absolute differences of the match at full resolution.
+    pub sad: u32,
+}
+
+/// Per-block motion vectors over a grid of `block_size` x `block_size`
+/// macroblocks covering the previous frame.
+///
+/// Blocks are laid out from the top-left corner; the last column and row are
+/// clipped to the frame when its size is not a multiple of `block_size`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockMotionField {
+    /// Side of a macroblock in level-0 pixels.
+    pub block_size: u32,
+    /// Number of block columns.
+    pub cols: u32,
+    /// Number of block rows.
+    pub rows: u32,
+    /// Row-major motion of every block, `cols * rows` entries.
+    pub vectors: Vec<BlockMotion>,
+}
+
+impl BlockMotionField {
+    /// Motion of the block in column `col` and row `row`.
+    pub fn get(&self, col: u32, row: u32) -> BlockMotion {
+        self.vectors[(row * self.cols + col) as usize]
+    }
+}
+
+/// Estimates the motion of every `block_size` x `block_size` macroblock from
+/// the previous to the next frame by pyramidal block matching.
+///
+/// At the coarsest level every block is matched by exhaustive search within
+/// `search_range` pixels; each finer level doubles the vector found above and
+/// refines it within `search_range` again, so the reachable displacement grows
+/// with the level count while the cost per level stays fixed. The block
+/// shrinks with the level (down to one pixel) and always covers the same image
+/// area. Among equal costs the shorter vector wins, so flat blocks report no
+/// motion.
+///
+/// # Arguments
+/// * `prev_pyramid` / `next_pyramid` - frame pyramids, see
+///   [`build_pyramid`](crate::build_pyramid)
+/// * `block_size` - macroblock side in level-0 pixels, typically 8 or 16
+/// * `search_range` - search radius per level, in that level's pixels
+///
+/// # Panics
+/// Panics if the pyramids fail [`validate_pyramid_pair`], or if `block_size`
+/// is zero.
+pub fn block_motion(
+    prev_pyramid: &[GrayImage],
+    next_pyramid: &[GrayImage],
+    block_size: u32,
+    search_range: u32,
+) -> BlockMotionField {
+    if let Err(error) = validate_pyramid_pair(prev_pyramid, next_pyramid) {
+        panic!("{error}");
+    }
+    assert!(block_size > 0, "block_size must be non-zero");
+
+    let (width, height) = prev_pyramid[0].dimensions();
+    let cols = width.div_ceil(block_size);
+    let rows = height.div_ceil(block_size);
+    let mut vectors = vec![BlockMotion::default(); (cols * rows) as usize];
+    let range = search_range as i32;
+
+    for level in (0..prev_pyramid.len()).rev() {
+        let (prev, next) = (&prev_pyramid[level], &next_pyramid[level]);
+        let is_coarsest = level + 1 == prev_pyramid.len();
+
+        for row in 0..rows {
+            for col in 0..cols {
+                let block = level_block(prev, col, row, block_size, level);
+                let motion = &mut vectors[(row * cols + col) as usize];
+                let (cx, cy) = if is_coarsest {
+                    (0, 0)
+                } else {
+                    (motion.dx * 2, motion.dy * 2)
+                };
+
+                let Some(block) = block else {
+                    // Too small to exist at this level: carry the vector on.
+                    (motion.dx, motion.dy) = (cx, cy);
+                    continue;
+                };
+
+                // No candidate kept the block inside the next frame.
+                let (sad, dx, dy) =
+                    best_match(prev, next, block, (cx, cy), range).unwrap_or((u32::MAX, cx, cy));
+                *motion = BlockMotion { dx, dy, sad };
+            }
+        }
+    }
+
+    BlockMotionField {
+        block_size,
+        cols,
+        rows,
+        vectors,
+    }
+}
+
+/// Rectangle `(x, y, width, height)` of a block at a pyramid level.
+pub(crate) type Block = (u32, u32, u32, u32);
+
+/// Exhaustive search for the displacement of `block` within `range` pixels
+/// of `(cx, cy)`, as `(sad, dx, dy)`. Among equal costs the shorter vector
+/// wins. `None` if every candidate moves the block out of `next`.
+pub(crate) fn best_match(
+    prev: &GrayImage,
+    next: &GrayImage,
+    block: Block,
+    (cx, cy): (i32, i32),
+    range: i32,
+) -> Option<(u32, i32, i32)> {
+    let mut best: Option<(u32, i32, i32)> = None;
+    for dy in cy - range..=cy + range {
+        for dx in cx - range..=cx + range {
+            let Some(sad) = block_sad(prev, next, block, dx, dy) else {
+                continue;
+            };
+            let better = match best {
+                None => true,
+                Some((best_sad, bx, by)) => {
+                    sad < best_sad || (sad == best_sad && dx.abs() + dy.abs() < bx.abs() + by.abs())
+                }
+            };
+            if better {
+                best = Some((sad, dx, dy));
+            }
+        }
+    }
+    best
+}
+
+/// Area of block (`col`, `row`) at `level`, clipped to the level image, or
+/// `None` when nothing of it is left.
+fn level_block(
+    image: &GrayImage,
+    col: u32,
+    row: u32,
+    block_size: u32,
+    level: usize,
+) -> Option<Block> {
+    let (width, height) = image.dimensions();
+    let x0 = (col * block_size) >> level;
+    let y0 = (row * block_size) >> level;
+    let x1 = (((col + 1) * block_size) >> level).max(x0 + 1).min(width);
+    let y1 = (((row + 1) * block_size) >> level).max(y0 + 1).min(height);
+    (x0 < x1 && y0 < y1).then_some((x0, y0, x1 - x0, y1 - y0))
+}
+
+/// SAD between `block` of `prev` and the same block moved by `(dx, dy)` in
+/// `next`, or `None` if the moved block leaves `next`.
+fn block_sad(prev: &GrayImage, next: &GrayImage, block: Block, dx: i32, dy: i32) -> Option<u32> {
+    let (x, y, w, h) = block;
+    let (nx, ny) = (x as i64 + dx as i64, y as i64 + dy as i64);
+    if nx < 0
+        || ny < 0
+        || nx + w as i64 > next.width() as i64
+        || ny + h as i64 > next.height() as i64
+    {
+        return None;
+    }
+
+    let (prev_stride, next_stride) = (prev.width() as usize, next.width() as usize);
+    let (prev_data, next_data) = (prev.as_raw(), next.as_raw());
+    let mut sad = 0u32;
+    for row in 0..h as usize {
+        let p = (y as usize + row) * prev_stride + x as usize;
+        let n = (ny as usize + row) * next_stride + nx as usize;
+        sad += prev_data[p..p + w as usize]
+            .iter()
+            .zip(&next_data[n..n + w as usize])
+            .map(|(&a, &b)| a.abs_diff(b) as u32)
+            .sum::<u32>();
+    }
+    Some(sad)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pyramid::build_pyramid;
+    use image::Luma;
+
+    fn textured(w: u32, h: u32) -> GrayImage {
+        GrayImage::from_fn(w, h, |x, y| {
+            let mut s = (y * w + x).wrapping_mul(2654435761) ^ 0x9e3779b9;
+            s ^= s >> 15;
+            s = s.wrapping_mul(0x85ebca6b);
+            s ^= s >> 13;
+            Luma([(s & 0xff) as u8])
+        })
+    }
+
+    /// Smooth blobs that survive downsampling, so coarse levels stay
+    /// informative, plus a little noise that makes the full-resolution match
+    /// unique. `(ox, oy)` shifts the content.
+    fn smooth(w: u32, h: u32, ox: i32, oy: i32) -> GrayImage {
+        let noise = textured(w + 64, h + 64);
+        GrayImage::from_fn(w, h, |x, y| {
+            let (fx, fy) = ((x as i32 - ox) as f32, (y as i32 - oy) as f32);
+            let v = 128.0
+                + 60.0 * (fx * 0.11).sin() * (fy * 0.07).cos()
+                + 40.0 * (fx * 0.031 + fy * 0.043).sin();
+            let n = noise.get_pixel((x as i32 - ox + 32) as u32, (y as i32 - oy + 32) as u32)[0];
+            Luma([(v * 0.85 + n as f32 * 0.15) as u8])
+        })
+    }
+
+    #[test]
+    fn single_level_finds_integer_shift() {
+        let prev = textured(64, 48);
+        let next = GrayImage::from_fn(64, 48, |x, y| {
+            *prev.get_pixel((x + 64 - 3) % 64, (y + 48 + 2) % 48)
+        });
+
+        let field = block_motion(&[prev], &[next], 8, 4);
+        assert_eq!((field.cols, field.rows), (8, 6));
+        // Interior blocks are unaffected by the wrap-around at the borders.
+        for row in 1..5 {
+            for col in 1..7 {
+                assert_eq!(
+                    field.get(col, row),
+                    BlockMotion {
+                        dx: 3,
+                        dy: -2,
+                        sad: 0
+                    }
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn pyramid_reaches_beyond_the_search_range() {
+        let (w, h) = (160, 128);
+        let prev = smooth(w, h, 0, 0);
+        let next = smooth(w, h, 19, -13);
+        let field = block_motion(&build_pyramid(&prev, 3), &build_pyramid(&next, 3), 16, 6);
+
+        for row in 2..field.rows - 2 {
+            for col in 2..field.cols - 2 {
+                let m = field.get(col, row);
+                assert_eq!((m.dx, m.dy), (19, -13), "block ({col}, {row})");
+                assert_eq!(m.sad, 0);
+            }
+        }
+    }
+
+    #[test]
+    fn flat_blocks_report_no_motion_and_edges_are_clipped() {
+        let flat = GrayImage::from_pixel(37, 21, Luma([90]));
+        let pyramid = [flat];
+        let field = block_motion(&pyramid, &pyramid, 16, 3);
+        assert_eq!((field.cols, field.rows), (3, 2));
+        assert!(field.vectors.iter().all(|m| *m == BlockMotion::default()));
+    }
+}
@@ -0,0 +1,132 @@
+//! Image border extension.
+//!
+//! Window-based processing reads pixels past the edges of the image. Rather
+//! than special-casing every read, the input can be padded once:
+//! [`extend_border`] is the equivalent of OpenCV's `copyMakeBorder`, with the
+//! same [`BorderMode`]s.
+
+use image::{GrayImage, Luma};
+
+/// How pixels beyond the image edges are synthesized; named after OpenCV's
+/// `BORDER_*` constants. The examples pad the row `abcdefgh` by three pixels
+/// on each side.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BorderMode {
+    /// A fixed value: `iii|abcdefgh|iii`.
+    Constant(u8),
+    /// The edge pixel repeated: `aaa|abcdefgh|hhh`.
+    #[default]
+    Replicate,
+    /// Mirrored, repeating the edge pixel: `cba|abcdefgh|hgf`.
+    Reflect,
+    /// Mirrored about the edge pixel: `dcb|abcdefgh|gfe`.
+    Reflect101,
+    /// The opposite side of the image: `fgh|abcdefgh|abc`.
+    Wrap,
+}
+
+impl BorderMode {
+    /// Index into a row or column of `len` pixels that supplies position `i`,
+    /// or `None` where a [`Constant`](Self::Constant) border supplies it.
+    pub(crate) fn source_index(self, i: i64, len: usize) -> Option<usize> {
+        let len = len as i64;
+        if (0..len).contains(&i) {
+            return Some(i as usize);
+        }
+        let index = match self {
+            BorderMode::Constant(_) => return None,
+            BorderMode::Replicate => i.clamp(0, len - 1),
+            BorderMode::Reflect => {
+                let m = i.rem_euclid(2 * len);
+                if m < len { m } else { 2 * len - 1 - m }
+            }
+            BorderMode::Reflect101 if len == 1 => 0,
+            BorderMode::Reflect101 => {
+                let m = i.rem_euclid(2 * len - 2);
+                if m < len { m } else { 2 * len - 2 - m }
+            }
+            BorderMode::Wrap => i.rem_euclid(len),
+        };
+        Some(index as usize)
+    }
+}
+
+/// Copies `image` into a larger image with `top`, `bottom`, `left` and
+/// `right` pixels of border filled according to `mode`.
+///
+/// Borders wider than the image are filled by applying the mode repeatedly,
+/// e.g. mirroring back and forth for [`BorderMode::Reflect`].
+///
+/// # Panics
+/// Panics if `image` is empty and `mode` is not [`BorderMode::Constant`].
+pub fn extend_border(
+    image: &GrayImage,
+    top: u32,
+    bottom: u32,
+    left: u32,
+    right: u32,
+    mode: BorderMode,
+) -> GrayImage {
+    let (width, height) = image.dimensions();
+    assert!(
+        (width > 0 && height > 0) || matches!(mode, BorderMode::Constant(_)),
+        "only a constant border can extend an empty image"
+    );
+    let fill = match mode {
+        BorderMode::Constant(value) => value,
+        _ => 0,
+    };
+    let columns: Vec<Option<usize>> = (0..(left + width + right) as i64)
+        .map(|x| mode.source_index(x - left as i64, width as usize))
+        .collect();
+    let mut out = GrayImage::from_pixel(left + width + right, top + height + bottom, Luma([fill]));
+    let src = image.as_raw();
+    for (y, row) in out.chunks_exact_mut(columns.len().max(1)).enumerate() {
+        let Some(sy) = mode.source_index(y as i64 - top as i64, height as usize) else {
+            continue;
+        };
+        let src_row = &src[sy * width as usize..][..width as usize];
+        for (value, column) in row.iter_mut().zip(&columns) {
+            if let Some(sx) = column {
+                *value = src_row[*sx];
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn padded_row(mode: BorderMode) -> Vec<u8> {
+        let row = GrayImage::from_fn(8, 1, |x, _| Luma([b'a' + x as u8]));
+        extend_border(&row, 0, 0, 3, 3, mode).into_raw()
+    }
+
+    #[test]
+    fn modes_match_opencv() {
+        assert_eq!(padded_row(BorderMode::Constant(b'i')), b"iiiabcdefghiii");
+        assert_eq!(padded_row(BorderMode::Replicate), b"aaaabcdefghhhh");
+        assert_eq!(padded_row(BorderMode::Reflect), b"cbaabcdefghhgf");
+        assert_eq!(padded_row(BorderMode::Reflect101), b"dcbabcdefghgfe");
+        assert_eq!(padded_row(BorderMode::Wrap), b"fghabcdefghabc");
+    }
+
+    #[test]
+    fn wide_borders_and_both_axes() {
+        let image = GrayImage::from_raw(2, 2, vec![1, 2, 3, 4]).unwrap();
+        let padded = extend_border(&image, 1, 2, 3, 0, BorderMode::Reflect101);
+        assert_eq!(padded.dimensions(), (5, 5));
+        assert_eq!(
+            padded.into_raw(),
+            [
+                4, 3, 4, 3, 4, //
+                2, 1, 2, 1, 2, //
+                4, 3, 4, 3, 4, //
+                2, 1, 2, 1, 2, //
+                4, 3, 4, 3, 4,
+            ]
+        );
+    }
+}
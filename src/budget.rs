@@ -0,0 +1,258 @@
+//! Processing oversized frames at a reduced resolution.
+//!
+//! Phones deliver 4K frames, but tracking at native resolution costs many
+//! times more than it gains. [`BudgetScale`] picks a processing resolution
+//! from a pixel budget, downscales frames to it, and maps point coordinates
+//! and flow vectors between the two resolutions;
+//! [`calc_optical_flow_budget`] wraps a whole tracking call that way.
+
+use std::borrow::Cow;
+
+use image::GrayImage;
+
+use crate::lk::{TrackResult, calc_optical_flow_ex};
+use crate::pyramid::build_pyramid;
+
+/// Mapping between an original resolution and a processing resolution of at
+/// most a given number of pixels, with the same aspect ratio.
+///
+/// Coordinates follow the crate's pixel-center convention (see
+/// [`TrackResult`]), so the mapping is exact for the downscaled image and not
+/// just for the pixel grid corners.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BudgetScale {
+    original: (u32, u32),
+    processing: (u32, u32),
+}
+
+impl BudgetScale {
+    /// Chooses the processing resolution for `width` x `height` frames: the
+    /// original if it fits in `max_pixels`, otherwise the largest uniformly
+    /// scaled size that does.
+    ///
+    /// # Panics
+    /// Panics if a dimension or `max_pixels` is zero.
+    pub fn new(width: u32, height: u32, max_pixels: u32) -> Self {
+        assert!(width > 0 && height > 0, "frame must be non-empty");
+        assert!(max_pixels > 0, "max_pixels must be non-zero");
+        let pixels = width as f64 * height as f64;
+        if pixels <= max_pixels as f64 {
+            return BudgetScale {
+                original: (width, height),
+                processing: (width, height),
+            };
+        }
+        let scale = (max_pixels as f64 / pixels).sqrt();
+        let processing = (
+            ((width as f64 * scale) as u32).max(1),
+            ((height as f64 * scale) as u32).max(1),
+        );
+        BudgetScale {
+            original: (width, height),
+            processing,
+        }
+    }
+
+    /// Processes `width` x `height` frames at `1 / divisor` of their size
+    /// (rounded down, at least one pixel), e.g. `2` for half resolution.
+    ///
+    /// # Panics
+    /// Panics if a dimension or `divisor` is zero.
+    pub fn with_divisor(width: u32, height: u32, divisor: u32) -> Self {
+        assert!(width > 0 && height > 0, "frame must be non-empty");
+        assert!(divisor > 0, "divisor must be non-zero");
+        BudgetScale {
+            original: (width, height),
+            processing: ((width / divisor).max(1), (height / divisor).max(1)),
+        }
+    }
+
+    /// Size of the original frames.
+    pub fn original_size(&self) -> (u32, u32) {
+        self.original
+    }
+
+    /// Size frames are processed at.
+    pub fn processing_size(&self) -> (u32, u32) {
+        self.processing
+    }
+
+    /// Whether the frames are processed at their original size.
+    pub fn is_identity(&self) -> bool {
+        self.original == self.processing
+    }
+
+    /// Per-axis factor from original to processing coordinates (at most 1).
+    pub fn factor(&self) -> (f32, f32) {
+        (
+            self.processing.0 as f32 / self.original.0 as f32,
+            self.processing.1 as f32 / self.original.1 as f32,
+        )
+    }
+
+    /// Downscales `image` to the processing size by area averaging, or
+    /// borrows it when no scaling is needed.
+    ///
+    /// # Panics
+    /// Panics if `image` is not of the original size.
+    pub fn downscale<'a>(&self, image: &'a GrayImage) -> Cow<'a, GrayImage> {
+        assert_eq!(
+            image.dimensions(),
+            self.original,
+            "image must have the original size"
+        );
+        if self.is_identity() {
+            Cow::Borrowed(image)
+        } else {
+            Cow::Owned(resize_area(image, self.processing))
+        }
+    }
+
+    /// Maps an original-resolution point to processing coordinates.
+    pub fn to_processing(&self, (x, y): (f32, f32)) -> (f32, f32) {
+        let (fx, fy) = self.factor();
+        ((x + 0.5) * fx - 0.5, (y + 0.5) * fy - 0.5)
+    }
+
+    /// Maps a processing-resolution point back to original coordinates.
+    pub fn to_original(&self, (x, y): (f32, f32)) -> (f32, f32) {
+        let (fx, fy) = self.factor();
+        ((x + 0.5) / fx - 0.5, (y + 0.5) / fy - 0.5)
+    }
+
+    /// Maps a processing-resolution displacement back to original pixels.
+    pub fn flow_to_original(&self, (dx, dy): (f32, f32)) -> (f32, f32) {
+        let (fx, fy) = self.factor();
+        (dx / fx, dy / fy)
+    }
+
+    /// Maps the positions of tracking results, computed at the processing
+    /// resolution, back to original coordinates in place.
+    pub fn results_to_original(&self, results: &mut [TrackResult]) {
+        for result in results {
+            result.pos = self.to_original(result.pos);
+        }
+    }
+}
+
+/// [`calc_optical_flow_ex`] on frames downscaled to at most `max_pixels`
+/// pixels (see [`BudgetScale`]).
+///
+/// `prev_points` and the returned positions are in original coordinates. The
+/// window size is in processing pixels, so it covers a larger part of an
+/// original frame the more it is downscaled; the photometric error is in
+/// intensity units and unaffected.
+///
+/// # Panics
+/// Panics if the frames differ in size.
+#[allow(clippy::too_many_arguments)]
+pub fn calc_optical_flow_budget(
+    prev: &GrayImage,
+    next: &GrayImage,
+    prev_points: &[(f32, f32)],
+    max_pixels: u32,
+    levels: usize,
+    window_size: usize,
+    max_iterations: usize,
+    min_eigen_threshold: f32,
+) -> Vec<TrackResult> {
+    assert_eq!(
+        prev.dimensions(),
+        next.dimensions(),
+        "frames must have the same size"
+    );
+    let budget = BudgetScale::new(prev.width(), prev.height(), max_pixels);
+    let points: Vec<(f32, f32)> = prev_points
+        .iter()
+        .map(|&p| budget.to_processing(p))
+        .collect();
+    let mut results = calc_optical_flow_ex(
+        &build_pyramid(&budget.downscale(prev), levels),
+        &build_pyramid(&budget.downscale(next), levels),
+        &points,
+        None,
+        window_size,
+        max_iterations,
+        min_eigen_threshold,
+    );
+    budget.results_to_original(&mut results);
+    results
+}
+
+/// Resizes `image` down to `size` by averaging the source area under every
+/// output pixel, with fractional coverage at the edges (OpenCV's
+/// `INTER_AREA`).
+fn resize_area(image: &GrayImage, size: (u32, u32)) -> GrayImage {
+    let (width, height) = image.dimensions();
+    let columns = area_weights(width, size.0);
+    let rows = area_weights(height, size.1);
+
+    // Horizontal pass into floats, then vertical pass into the output.
+    let mut horizontal = vec![0.0f32; (size.0 * height) as usize];
+    for (y, row) in image.as_raw().chunks_exact(width as usize).enumerate() {
+        for (x, taps) in columns.iter().enumerate() {
+            horizontal[y * size.0 as usize + x] =
+                taps.iter().map(|&(i, w)| w * row[i] as f32).sum();
+        }
+    }
+    GrayImage::from_fn(size.0, size.1, |x, y| {
+        let value: f32 = rows[y as usize]
+            .iter()
+            .map(|&(i, w)| w * horizontal[i * size.0 as usize + x as usize])
+            .sum();
+        image::Luma([value.round() as u8])
+    })
+}
+
+/// For every output cell of a `from` -> `to` shrink along one axis, the source
+/// cells it covers with their normalized overlap.
+fn area_weights(from: u32, to: u32) -> Vec<Vec<(usize, f32)>> {
+    let ratio = from as f64 / to as f64;
+    (0..to)
+        .map(|i| {
+            let (start, end) = (i as f64 * ratio, ((i + 1) as f64 * ratio).min(from as f64));
+            let mut taps = Vec::new();
+            let mut j = start.floor() as usize;
+            while (j as f64) < end {
+                let overlap = (end.min(j as f64 + 1.0) - start.max(j as f64)) / ratio;
+                if overlap > 1e-9 {
+                    taps.push((j, overlap as f32));
+                }
+                j += 1;
+            }
+            taps
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scale_fits_budget_and_round_trips_coordinates() {
+        let budget = BudgetScale::new(3840, 2160, 1_000_000);
+        let (w, h) = budget.processing_size();
+        assert!(w * h <= 1_000_000 && w * h > 950_000, "{w}x{h}");
+        assert!(((w as f32 / h as f32) - 16.0 / 9.0).abs() < 0.01);
+
+        let p = (1234.5, 987.25);
+        let back = budget.to_original(budget.to_processing(p));
+        assert!((back.0 - p.0).abs() < 1e-2 && (back.1 - p.1).abs() < 1e-2);
+        // The first pixel's center maps to where its area sits after the
+        // shrink, not to the origin.
+        assert!(budget.to_processing((0.0, 0.0)).0 < 0.0);
+
+        assert!(BudgetScale::new(640, 480, 1_000_000).is_identity());
+    }
+
+    #[test]
+    fn area_resize_averages_whole_blocks() {
+        let image = GrayImage::from_fn(6, 4, |x, y| image::Luma([(x % 2 * 100 + y * 10) as u8]));
+        let small = resize_area(&image, (3, 2));
+        // Each output pixel averages a 2x2 block: columns 0 and 100, rows
+        // 2y and 2y + 1.
+        assert_eq!(small.get_pixel(0, 0)[0], 55);
+        assert_eq!(small.get_pixel(2, 1)[0], 75);
+    }
+}
@@ -0,0 +1,119 @@
+//! Pinhole camera intrinsics with Brown-Conrady lens distortion.
+//!
+//! Wide-angle and cheap lenses bend straight lines, so geometry downstream of
+//! the tracker (pose estimation, triangulation) wants undistorted
+//! coordinates. Undistorting every frame is a full-image warp; undistorting
+//! only the tracked points is a handful of arithmetic per point.
+//! [`CameraIntrinsics`] maps points both ways, and
+//! [`TrackerContext::set_intrinsics`](crate::TrackerContext::set_intrinsics)
+//! applies it around tracking, which itself stays in the distorted image.
+
+/// Focal lengths, principal point and lens distortion of a camera, in the
+/// model and parameter order of OpenCV (`k1, k2, p1, p2, k3`).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CameraIntrinsics {
+    /// Focal length in pixels along x.
+    pub fx: f32,
+    /// Focal length in pixels along y.
+    pub fy: f32,
+    /// Principal point x in pixels.
+    pub cx: f32,
+    /// Principal point y in pixels.
+    pub cy: f32,
+    /// Radial distortion coefficients.
+    pub k1: f32,
+    pub k2: f32,
+    pub k3: f32,
+    /// Tangential distortion coefficients.
+    pub p1: f32,
+    pub p2: f32,
+}
+
+/// Fixed-point iterations of [`CameraIntrinsics::undistort`].
+const UNDISTORT_ITERATIONS: usize = 10;
+
+impl CameraIntrinsics {
+    /// Intrinsics of a distortion-free camera.
+    pub fn new(fx: f32, fy: f32, cx: f32, cy: f32) -> Self {
+        CameraIntrinsics {
+            fx,
+            fy,
+            cx,
+            cy,
+            k1: 0.0,
+            k2: 0.0,
+            k3: 0.0,
+            p1: 0.0,
+            p2: 0.0,
+        }
+    }
+
+    /// Maps an undistorted pixel position to where the lens images it.
+    pub fn distort(&self, (u, v): (f32, f32)) -> (f32, f32) {
+        let (x, y) = ((u - self.cx) / self.fx, (v - self.cy) / self.fy);
+        let (dx, dy) = self.distort_normalized(x, y);
+        (dx * self.fx + self.cx, dy * self.fy + self.cy)
+    }
+
+    /// Maps a pixel position of the distorted image to its undistorted
+    /// position, with the same focal lengths and principal point.
+    ///
+    /// Inverts [`distort`](Self::distort) by fixed-point iteration like
+    /// OpenCV's `undistortPoints`, accurate for the distortion of real
+    /// lenses within their field of view.
+    pub fn undistort(&self, (u, v): (f32, f32)) -> (f32, f32) {
+        let (xd, yd) = ((u - self.cx) / self.fx, (v - self.cy) / self.fy);
+        let (mut x, mut y) = (xd, yd);
+        for _ in 0..UNDISTORT_ITERATIONS {
+            let r2 = x * x + y * y;
+            let radial = 1.0 + r2 * (self.k1 + r2 * (self.k2 + r2 * self.k3));
+            let (tx, ty) = self.tangential(x, y, r2);
+            x = (xd - tx) / radial;
+            y = (yd - ty) / radial;
+        }
+        (x * self.fx + self.cx, y * self.fy + self.cy)
+    }
+
+    fn distort_normalized(&self, x: f32, y: f32) -> (f32, f32) {
+        let r2 = x * x + y * y;
+        let radial = 1.0 + r2 * (self.k1 + r2 * (self.k2 + r2 * self.k3));
+        let (tx, ty) = self.tangential(x, y, r2);
+        (x * radial + tx, y * radial + ty)
+    }
+
+    fn tangential(&self, x: f32, y: f32, r2: f32) -> (f32, f32) {
+        (
+            2.0 * self.p1 * x * y + self.p2 * (r2 + 2.0 * x * x),
+            self.p1 * (r2 + 2.0 * y * y) + 2.0 * self.p2 * x * y,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn undistort_inverts_distort() {
+        let camera = CameraIntrinsics {
+            k1: -0.28,
+            k2: 0.07,
+            k3: 0.0,
+            p1: 0.0008,
+            p2: -0.0004,
+            ..CameraIntrinsics::new(500.0, 505.0, 320.0, 240.0)
+        };
+        for &p in &[(320.0, 240.0), (10.0, 15.0), (600.0, 400.0), (100.0, 450.0)] {
+            let distorted = camera.distort(p);
+            let back = camera.undistort(distorted);
+            assert!(
+                (back.0 - p.0).abs() < 0.01 && (back.1 - p.1).abs() < 0.01,
+                "{p:?} -> {back:?}"
+            );
+        }
+        // Barrel distortion pulls the corners towards the center.
+        let corner = camera.distort((10.0, 15.0));
+        assert!(corner.0 > 10.0 && corner.1 > 15.0);
+        assert_eq!(camera.distort((320.0, 240.0)), (320.0, 240.0));
+    }
+}
@@ -0,0 +1,71 @@
+//! Pixel coordinate conventions.
+//!
+//! Libraries disagree on where a pixel's coordinates sit. This crate puts
+//! integer coordinates at pixel centers (like OpenCV), while others put them
+//! at the top-left corner of the pixel, so that its center is at `+0.5`.
+//! Passing points between the two without converting shifts them silently by
+//! half a pixel. [`CoordinateConvention`] names the convention of a caller
+//! and converts to and from the crate's.
+
+/// Where integer coordinates sit within a pixel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CoordinateConvention {
+    /// `(0, 0)` is the center of the top-left pixel, the crate's own
+    /// convention (see [`TrackResult`](crate::TrackResult)). Used by OpenCV.
+    #[default]
+    PixelCenter,
+    /// `(0, 0)` is the top-left corner of the image, so the top-left pixel's
+    /// center is `(0.5, 0.5)`. Used by e.g. OpenGL texture coordinates and
+    /// many photogrammetry tools.
+    PixelCorner,
+}
+
+impl CoordinateConvention {
+    /// Offset of a pixel's center from its integer coordinates.
+    fn center_offset(self) -> f32 {
+        match self {
+            CoordinateConvention::PixelCenter => 0.0,
+            CoordinateConvention::PixelCorner => 0.5,
+        }
+    }
+
+    /// Converts `point` from this convention to the crate's pixel-center
+    /// convention.
+    pub fn to_center(self, (x, y): (f32, f32)) -> (f32, f32) {
+        let offset = self.center_offset();
+        (x - offset, y - offset)
+    }
+
+    /// Converts `point` from the crate's pixel-center convention to this
+    /// convention.
+    pub fn from_center(self, (x, y): (f32, f32)) -> (f32, f32) {
+        let offset = self.center_offset();
+        (x + offset, y + offset)
+    }
+
+    /// Position of the center of pixel `(x, y)` in this convention, e.g. to
+    /// turn the pixels returned by the feature detectors into points.
+    pub fn pixel(self, x: u32, y: u32) -> (f32, f32) {
+        self.from_center((x as f32, y as f32))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conventions_differ_by_half_a_pixel() {
+        let corner = CoordinateConvention::PixelCorner;
+        assert_eq!(corner.pixel(3, 0), (3.5, 0.5));
+        assert_eq!(corner.to_center((3.5, 0.5)), (3.0, 0.0));
+        assert_eq!(
+            corner.from_center(corner.to_center((1.25, 7.0))),
+            (1.25, 7.0)
+        );
+
+        let center = CoordinateConvention::default();
+        assert_eq!(center.pixel(3, 0), (3.0, 0.0));
+        assert_eq!(center.to_center((1.25, 7.0)), (1.25, 7.0));
+    }
+}
@@ -0,0 +1,180 @@
+//! General 2D filtering with small arbitrary kernels.
+//!
+//! The built-in gradient and smoothing paths use fixed separable kernels.
+//! [`filter_2d`] and [`filter_2d_i16`] apply any small [`Kernel`], e.g. a
+//! custom derivative or a non-separable smoothing kernel, with the border
+//! handling of [`extend_border`](crate::extend_border). The `i16` output has
+//! the layout of the gradient planes the detectors and the tracker work on.
+
+use image::{GrayImage, ImageBuffer, Luma};
+
+use crate::border::BorderMode;
+
+/// Filter kernel of odd width and height, anchored at its center.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Kernel {
+    width: usize,
+    height: usize,
+    values: Vec<f32>,
+}
+
+impl Kernel {
+    /// A `width` x `height` kernel from its row-major `values`.
+    ///
+    /// # Panics
+    /// Panics if a side is even or `values` does not hold `width * height`
+    /// entries.
+    pub fn new(width: usize, height: usize, values: Vec<f32>) -> Self {
+        assert!(
+            width % 2 == 1 && height % 2 == 1,
+            "kernel sides must be odd"
+        );
+        assert_eq!(
+            values.len(),
+            width * height,
+            "kernel must have width * height values"
+        );
+        Kernel {
+            width,
+            height,
+            values,
+        }
+    }
+
+    /// The outer product of a horizontal `row` and a vertical `column`
+    /// kernel, e.g. `Kernel::separable(&[-1.0, 0.0, 1.0], &[1.0, 2.0, 1.0])`
+    /// for the 3x3 Sobel x derivative.
+    ///
+    /// # Panics
+    /// Panics if either length is even.
+    pub fn separable(row: &[f32], column: &[f32]) -> Self {
+        let values = column
+            .iter()
+            .flat_map(|&c| row.iter().map(move |&r| c * r))
+            .collect();
+        Kernel::new(row.len(), column.len(), values)
+    }
+
+    /// Width in pixels.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Height in pixels.
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Row-major coefficients.
+    pub fn values(&self) -> &[f32] {
+        &self.values
+    }
+}
+
+/// Filters `image` with `kernel`, synthesizing pixels beyond the edges
+/// according to `border`.
+///
+/// As with OpenCV's `filter2D`, the kernel is applied as a correlation: the
+/// output at `(x, y)` is the sum of `kernel[j][i] * image(x + i - rx, y + j -
+/// ry)`, without flipping the kernel.
+pub fn filter_2d(
+    image: &GrayImage,
+    kernel: &Kernel,
+    border: BorderMode,
+) -> ImageBuffer<Luma<f32>, Vec<f32>> {
+    let (width, height) = image.dimensions();
+    let mut out = vec![0.0f32; width as usize * height as usize];
+    correlate(image, kernel, border, |i, value| out[i] = value);
+    ImageBuffer::from_raw(width, height, out).expect("buffer matches the image size")
+}
+
+/// [`filter_2d`] rounded and saturated to `i16`, the representation of the
+/// crate's gradient planes.
+pub fn filter_2d_i16(
+    image: &GrayImage,
+    kernel: &Kernel,
+    border: BorderMode,
+) -> ImageBuffer<Luma<i16>, Vec<i16>> {
+    let (width, height) = image.dimensions();
+    let mut out = vec![0i16; width as usize * height as usize];
+    correlate(image, kernel, border, |i, value| {
+        out[i] = value.round().clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+    });
+    ImageBuffer::from_raw(width, height, out).expect("buffer matches the image size")
+}
+
+/// Computes the filter response of every pixel and hands it to `emit` with
+/// its row-major index.
+fn correlate(
+    image: &GrayImage,
+    kernel: &Kernel,
+    border: BorderMode,
+    mut emit: impl FnMut(usize, f32),
+) {
+    let (width, height) = image.dimensions();
+    let (width, height) = (width as usize, height as usize);
+    if width == 0 || height == 0 {
+        return;
+    }
+    let (rx, ry) = (kernel.width / 2, kernel.height / 2);
+    let fill = match border {
+        BorderMode::Constant(value) => value as f32,
+        _ => 0.0,
+    };
+    // Source column of every padded column, resolved once.
+    let columns: Vec<Option<usize>> = (0..(width + 2 * rx) as i64)
+        .map(|x| border.source_index(x - rx as i64, width))
+        .collect();
+    let src = image.as_raw();
+
+    for y in 0..height {
+        let rows: Vec<Option<&[u8]>> = (0..kernel.height)
+            .map(|j| {
+                border
+                    .source_index((y + j) as i64 - ry as i64, height)
+                    .map(|sy| &src[sy * width..][..width])
+            })
+            .collect();
+        for x in 0..width {
+            let mut sum = 0.0f32;
+            for (row, weights) in rows.iter().zip(kernel.values.chunks_exact(kernel.width)) {
+                for (column, &weight) in columns[x..][..kernel.width].iter().zip(weights) {
+                    let pixel = match (row, column) {
+                        (Some(row), Some(sx)) => row[*sx] as f32,
+                        _ => fill,
+                    };
+                    sum += weight * pixel;
+                }
+            }
+            emit(y * width + x, sum);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn derivative_kernel_on_a_ramp() {
+        let ramp = GrayImage::from_fn(5, 3, |x, _| Luma([10 * x as u8]));
+        let sobel_x = Kernel::separable(&[-1.0, 0.0, 1.0], &[1.0, 2.0, 1.0]);
+
+        let replicated = filter_2d(&ramp, &sobel_x, BorderMode::Replicate);
+        assert_eq!(replicated.get_pixel(2, 1)[0], 80.0);
+        assert_eq!(replicated.get_pixel(0, 0)[0], 40.0);
+
+        let constant = filter_2d_i16(&ramp, &sobel_x, BorderMode::Constant(0));
+        assert_eq!(constant.get_pixel(2, 1)[0], 80);
+        assert_eq!(constant.get_pixel(4, 1)[0], -120);
+        assert_eq!(constant.get_pixel(2, 0)[0], 60);
+    }
+
+    #[test]
+    fn i16_output_saturates() {
+        let bright = GrayImage::from_pixel(3, 3, Luma([255]));
+        let gain = Kernel::new(1, 1, vec![1000.0]);
+        let out = filter_2d_i16(&bright, &gain, BorderMode::Reflect101);
+        assert!(out.pixels().all(|p| p[0] == i16::MAX));
+    }
+}
@@ -0,0 +1,92 @@
+//! Per-iteration convergence traces for selected points (`debug-trace`
+//! feature).
+//!
+//! When a point diverges or lands somewhere unexpected, the final
+//! [`TrackResult`](crate::TrackResult) only says *that* it failed. Enabling the
+//! feature and selecting the point with
+//! [`TrackerContext::set_debug_points`](crate::TrackerContext::set_debug_points)
+//! records every Lucas-Kanade step at every pyramid level, so the failure mode
+//! (oscillation, runaway step, residual plateau) can be read off directly.
+
+/// One Lucas-Kanade iteration of a traced point.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IterationRecord {
+    /// Displacement at this level after the step, in level pixels.
+    pub dx: f32,
+    /// Displacement at this level after the step, in level pixels.
+    pub dy: f32,
+    /// Mean absolute photometric residual over the window *before* the step,
+    /// in 8-bit intensity units.
+    pub residual: f32,
+}
+
+/// All iterations a traced point performed at one pyramid level.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LevelTrace {
+    /// Pyramid level (`0` = full resolution).
+    pub level: usize,
+    /// Iterations in execution order. Empty if the point was rejected before
+    /// iterating (out of bounds or low texture at this level).
+    pub iterations: Vec<IterationRecord>,
+}
+
+/// Convergence history of one traced point, coarse level first.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PointTrace {
+    /// Index of the point in the `prev_points` slice passed to the tracker.
+    pub index: usize,
+    /// One entry per visited level, in processing order (coarse to fine).
+    pub levels: Vec<LevelTrace>,
+}
+
+/// Recorder owned by the tracker scratch. Tracing allocates freely; it is a
+/// diagnostic mode and deliberately not part of the zero-allocation contract.
+#[derive(Default)]
+pub(crate) struct DebugTrace {
+    selected: Vec<usize>,
+    pub(crate) points: Vec<PointTrace>,
+    pub(crate) recording: bool,
+}
+
+impl DebugTrace {
+    pub(crate) fn select(&mut self, indices: &[usize]) {
+        self.selected.clear();
+        self.selected.extend_from_slice(indices);
+        self.points.clear();
+    }
+
+    /// Clears previous records before a new tracking call. A no-op while
+    /// recording is paused, so a paused pass keeps the earlier records.
+    pub(crate) fn begin(&mut self) {
+        if !self.recording {
+            return;
+        }
+        self.points.clear();
+        self.points
+            .extend(self.selected.iter().map(|&index| PointTrace {
+                index,
+                levels: Vec::new(),
+            }));
+    }
+
+    /// Opens a new level for `index`, or resumes it when the point refines
+    /// the level in several rounds, and returns its record list, or `None` if
+    /// the point is not traced.
+    pub(crate) fn level(
+        &mut self,
+        index: usize,
+        level: usize,
+    ) -> Option<&mut Vec<IterationRecord>> {
+        if !self.recording {
+            return None;
+        }
+        let point = self.points.iter_mut().find(|p| p.index == index)?;
+        if point.levels.last().is_none_or(|l| l.level != level) {
+            point.levels.push(LevelTrace {
+                level,
+                iterations: Vec::new(),
+            });
+        }
+        point.levels.last_mut().map(|l| &mut l.iterations)
+    }
+}
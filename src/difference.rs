@@ -0,0 +1,124 @@
+//! Pixel-wise frame differencing and binary-mask utilities.
+//!
+//! The building blocks of simple motion detection: [`abs_difference`] of two
+//! frames, [`threshold_binary`] into a change mask, and [`erode_mask`] /
+//! [`dilate_mask`] to remove speckle and close holes. Every function takes
+//! an optional region-of-interest mask; pixels where it is 0 are left out
+//! and come out as 0.
+
+use image::GrayImage;
+
+use crate::utils::morphology::{dilate_in_place, erode_in_place};
+
+/// Absolute difference `|a - b|` of two frames, 0 outside `mask`.
+///
+/// # Panics
+/// Panics if the frames or the mask differ in size.
+pub fn abs_difference(a: &GrayImage, b: &GrayImage, mask: Option<&GrayImage>) -> GrayImage {
+    assert_eq!(
+        a.dimensions(),
+        b.dimensions(),
+        "frames must have the same size"
+    );
+    let mut out = a.clone();
+    for (value, &other) in out.iter_mut().zip(b.as_raw()) {
+        *value = value.abs_diff(other);
+    }
+    apply_mask(&mut out, mask);
+    out
+}
+
+/// Binary mask of the pixels of `image` above `threshold`: 255 where
+/// `value > threshold` inside `mask`, 0 elsewhere, like OpenCV's
+/// `THRESH_BINARY` with a maximum of 255.
+///
+/// # Panics
+/// Panics if the mask differs in size from `image`.
+pub fn threshold_binary(image: &GrayImage, threshold: u8, mask: Option<&GrayImage>) -> GrayImage {
+    let mut out = image.clone();
+    for value in out.iter_mut() {
+        *value = if *value > threshold { 255 } else { 0 };
+    }
+    apply_mask(&mut out, mask);
+    out
+}
+
+/// Shrinks the non-zero regions of a binary mask by `radius` pixels with a
+/// square structuring element (`radius` 1 is the 3x3 erosion), 0 outside
+/// `roi`. Non-zero output pixels are 255; regions touching the image border
+/// are not eroded from outside.
+///
+/// # Panics
+/// Panics if `roi` differs in size from `mask`.
+pub fn erode_mask(mask: &GrayImage, radius: u32, roi: Option<&GrayImage>) -> GrayImage {
+    let mut out = mask.clone();
+    apply_mask(&mut out, roi);
+    erode_in_place(&mut out, radius);
+    out
+}
+
+/// Grows the non-zero regions of a binary mask by `radius` pixels with a
+/// square structuring element (`radius` 1 is the 3x3 dilation), 0 outside
+/// `roi`. Non-zero output pixels are 255.
+///
+/// # Panics
+/// Panics if `roi` differs in size from `mask`.
+pub fn dilate_mask(mask: &GrayImage, radius: u32, roi: Option<&GrayImage>) -> GrayImage {
+    let mut out = mask.clone();
+    apply_mask(&mut out, roi);
+    dilate_in_place(&mut out, radius);
+    apply_mask(&mut out, roi);
+    out
+}
+
+/// Zeroes the pixels of `image` where `mask` is 0.
+fn apply_mask(image: &mut GrayImage, mask: Option<&GrayImage>) {
+    let Some(mask) = mask else {
+        return;
+    };
+    assert_eq!(
+        mask.dimensions(),
+        image.dimensions(),
+        "mask must match the image size"
+    );
+    for (value, &keep) in image.iter_mut().zip(mask.as_raw()) {
+        if keep == 0 {
+            *value = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Luma;
+
+    #[test]
+    fn masked_difference_threshold_and_morphology() {
+        let a = GrayImage::from_fn(8, 6, |x, _| Luma([100 + x as u8]));
+        let mut b = a.clone();
+        for y in 1..5 {
+            for x in 1..5 {
+                b.put_pixel(x, y, Luma([10]));
+            }
+        }
+        b.put_pixel(6, 4, Luma([0]));
+        let left = GrayImage::from_fn(8, 6, |x, _| Luma([255 * (x < 6) as u8]));
+
+        let diff = abs_difference(&a, &b, Some(&left));
+        assert_eq!(diff.get_pixel(2, 2)[0], 92);
+        assert_eq!(diff.get_pixel(6, 4)[0], 0, "outside the mask");
+
+        let changed = threshold_binary(&abs_difference(&a, &b, None), 50, None);
+        assert_eq!(changed.iter().filter(|&&v| v == 255).count(), 17);
+
+        // The 3x3 opening removes the isolated pixel and keeps the square.
+        let opened = dilate_mask(&erode_mask(&changed, 1, None), 1, None);
+        assert_eq!(opened.get_pixel(6, 4)[0], 0);
+        assert_eq!(opened.iter().filter(|&&v| v == 255).count(), 16);
+
+        let roi_dilated = dilate_mask(&changed, 1, Some(&left));
+        assert_eq!(roi_dilated.get_pixel(6, 4)[0], 0, "outside the roi");
+        assert_eq!(roi_dilated.get_pixel(5, 5)[0], 255);
+    }
+}
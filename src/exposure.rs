@@ -0,0 +1,205 @@
+//! Global brightness change between two frames.
+//!
+//! Auto-exposure and auto white balance change the brightness of the whole
+//! frame from one capture to the next, which the intensity residual of
+//! Lucas-Kanade reads as motion. [`estimate_exposure_change`] fits the change
+//! as `next = gain * prev + bias` from the mean intensities of a grid of
+//! patches. Large patches barely change their mean when the scene moves a
+//! few pixels, so the frames need no alignment, and patches on moving
+//! objects are rejected as outliers of the fit. The result either
+//! pre-compensates the next frame ([`ExposureChange::compensate`]) or feeds
+//! the tracker's photometric model (see
+//! [`TrackerContext::set_exposure_change`](crate::TrackerContext::set_exposure_change)).
+
+use image::{GrayImage, Luma};
+
+/// Settings of [`estimate_exposure_change`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ExposureParams {
+    /// Patch grid `(columns, rows)` laid over the frame; each patch
+    /// contributes its mean intensity in both frames.
+    pub cells: (u32, u32),
+    /// Patches whose mean lies within this many intensity levels of 0 or 255
+    /// in either frame are skipped, since clipping breaks the linear model.
+    pub saturation_margin: f32,
+    /// Patches whose residual under the first fit exceeds this many robust
+    /// standard deviations are dropped before the final fit.
+    pub outlier_threshold: f32,
+}
+
+impl Default for ExposureParams {
+    fn default() -> Self {
+        ExposureParams {
+            cells: (8, 6),
+            saturation_margin: 8.0,
+            outlier_threshold: 3.0,
+        }
+    }
+}
+
+/// Photometric change `next = gain * prev + bias` between two frames, in
+/// 8-bit intensity units.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ExposureChange {
+    /// Contrast factor; above 1 the next frame is brighter and more
+    /// contrasted.
+    pub gain: f32,
+    /// Offset in intensity levels.
+    pub bias: f32,
+}
+
+impl Default for ExposureChange {
+    fn default() -> Self {
+        ExposureChange::IDENTITY
+    }
+}
+
+impl ExposureChange {
+    /// No change.
+    pub const IDENTITY: ExposureChange = ExposureChange {
+        gain: 1.0,
+        bias: 0.0,
+    };
+
+    /// Predicted next-frame intensity of a previous-frame intensity.
+    pub fn apply(&self, intensity: f32) -> f32 {
+        self.gain * intensity + self.bias
+    }
+
+    /// The change from the next frame back to the previous one.
+    pub fn inverse(&self) -> ExposureChange {
+        ExposureChange {
+            gain: 1.0 / self.gain,
+            bias: -self.bias / self.gain,
+        }
+    }
+
+    /// Maps `next` back into the exposure of the previous frame, rounding
+    /// and clamping to 8 bits.
+    pub fn compensate(&self, next: &GrayImage) -> GrayImage {
+        let inverse = self.inverse();
+        let lut: [u8; 256] =
+            std::array::from_fn(|v| inverse.apply(v as f32).round().clamp(0.0, 255.0) as u8);
+        let mut out = next.clone();
+        for Luma([v]) in out.pixels_mut() {
+            *v = lut[*v as usize];
+        }
+        out
+    }
+}
+
+/// Estimates the global brightness change from `prev` to `next`.
+///
+/// Both frames are split into [`ExposureParams::cells`] patches, and
+/// `next = gain * prev + bias` is fitted by least squares to the patch
+/// means, then refitted without the outlier patches. A frame whose patches
+/// all have about the same mean cannot separate gain from bias and yields a
+/// gain of 1 with the mean offset as bias, as does a fit that inverts the
+/// contrast. Without any usable patch the result is
+/// [`ExposureChange::IDENTITY`].
+///
+/// # Panics
+/// Panics if the frames differ in size or a cell count is zero.
+pub fn estimate_exposure_change(
+    prev: &GrayImage,
+    next: &GrayImage,
+    params: &ExposureParams,
+) -> ExposureChange {
+    assert_eq!(
+        prev.dimensions(),
+        next.dimensions(),
+        "frames must have the same size"
+    );
+    let (cols, rows) = params.cells;
+    assert!(cols > 0 && rows > 0, "cell counts must be positive");
+    let (width, height) = prev.dimensions();
+    let (cols, rows) = (cols.min(width), rows.min(height));
+
+    let usable =
+        |mean: f32| mean >= params.saturation_margin && mean <= 255.0 - params.saturation_margin;
+    let mut pairs = Vec::with_capacity((cols * rows) as usize);
+    let stride = width as usize;
+    let (prev_data, next_data) = (prev.as_raw(), next.as_raw());
+    for row in 0..rows {
+        let (y0, y1) = (row * height / rows, (row + 1) * height / rows);
+        for col in 0..cols {
+            let (x0, x1) = (col * width / cols, (col + 1) * width / cols);
+            let (mut sum_prev, mut sum_next) = (0u64, 0u64);
+            for y in y0..y1 {
+                let start = y as usize * stride;
+                let span = start + x0 as usize..start + x1 as usize;
+                for (&p, &n) in prev_data[span.clone()].iter().zip(&next_data[span]) {
+                    sum_prev += p as u64;
+                    sum_next += n as u64;
+                }
+            }
+            let n = ((x1 - x0) * (y1 - y0)) as f32;
+            let pair = (sum_prev as f32 / n, sum_next as f32 / n);
+            if usable(pair.0) && usable(pair.1) {
+                pairs.push(pair);
+            }
+        }
+    }
+    if pairs.is_empty() {
+        return ExposureChange::IDENTITY;
+    }
+
+    let first = fit(&pairs);
+    let mut residuals: Vec<f32> = pairs
+        .iter()
+        .map(|&(a, b)| (b - first.apply(a)).abs())
+        .collect();
+    let middle = residuals.len() / 2;
+    let (_, median, _) = residuals.select_nth_unstable_by(middle, f32::total_cmp);
+    let limit = params.outlier_threshold * (1.4826 * *median).max(1.0);
+    pairs.retain(|&(a, b)| (b - first.apply(a)).abs() <= limit);
+    fit(&pairs)
+}
+
+/// Least-squares `b = gain * a + bias` over the `(a, b)` pairs.
+fn fit(pairs: &[(f32, f32)]) -> ExposureChange {
+    let n = pairs.len() as f32;
+    let (sa, sb) = pairs
+        .iter()
+        .fold((0.0f32, 0.0f32), |(sa, sb), &(a, b)| (sa + a, sb + b));
+    let (mean_a, mean_b) = (sa / n, sb / n);
+    let (mut var_a, mut cov) = (0.0f32, 0.0f32);
+    for &(a, b) in pairs {
+        var_a += (a - mean_a) * (a - mean_a);
+        cov += (a - mean_a) * (b - mean_b);
+    }
+    let fitted = cov / var_a;
+    let gain = if var_a > 1e-3 * n && fitted.is_finite() && fitted > 0.0 {
+        fitted
+    } else {
+        1.0
+    };
+    ExposureChange {
+        gain,
+        bias: mean_b - gain * mean_a,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inverse_undoes_the_change() {
+        let change = ExposureChange {
+            gain: 1.25,
+            bias: -12.0,
+        };
+        let back = change.inverse().apply(change.apply(100.0));
+        assert!((back - 100.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn flat_frames_fall_back_to_an_offset() {
+        let prev = GrayImage::from_pixel(64, 48, Luma([100]));
+        let next = GrayImage::from_pixel(64, 48, Luma([130]));
+        let change = estimate_exposure_change(&prev, &next, &ExposureParams::default());
+        assert_eq!(change.gain, 1.0);
+        assert!((change.bias - 30.0).abs() < 1e-3);
+    }
+}
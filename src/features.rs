@@ -1,7 +1,80 @@
-use image::{GrayImage, ImageBuffer, Luma};
+use image::{GrayImage, ImageBuffer, Luma, RgbImage};
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
 use std::cmp::Ordering;
+use std::ops::Range;
 
-use crate::utils::{box_filter_3x3::box_filter_3x3_in_place, fast_gradients::compute_gradients};
+use crate::utils::{
+    box_filter_3x3::box_filter_3x3_in_place,
+    buffer_pool::{recycle_f32, recycle_i16, recycle_u8, take_f32, take_i16, take_u8},
+    fast_gradients::{compute_gradients_into, compute_region_gradients_into},
+    integral_image::box_mean_in_place,
+    sobel::compute_sobel_gradients_into,
+};
+
+/// `gradient_size` value selecting the 3x3 Scharr operator, like OpenCV's
+/// `FILTER_SCHARR`.
+pub const FILTER_SCHARR: i32 = -1;
+
+/// Shi-Tomasi detector settings for [`good_features_to_track_with`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FeatureParams {
+    /// Minimum accepted quality as a fraction of the strongest corner's
+    /// response. 0.4 is a good value
+    pub quality_level: f32,
+    /// Minimum distance in pixels between returned corners
+    pub min_distance: u32,
+    /// Aperture of the derivative kernel: 3, 5 or 7 for a Sobel operator of
+    /// that size, as OpenCV's `gradientSize`, or [`FILTER_SCHARR`] for the
+    /// 3x3 Scharr operator. Larger apertures smooth more and respond to
+    /// coarser structure. All apertures are scaled to the same gradient
+    /// units, so responses stay comparable
+    pub gradient_size: i32,
+    /// Side of the window over which the gradient products are averaged into
+    /// the structure tensor (odd, at least 3). Larger blocks favor bigger,
+    /// more stable corners. `3` uses the separable box filter of
+    /// [`good_features_to_track`]; larger sizes are aggregated through an
+    /// integral image, so their cost does not grow with the block.
+    pub block_size: u32,
+    /// Scale of the returned responses, see [`ResponseNormalization`].
+    /// Selection is unaffected; only the reported values change.
+    pub normalization: ResponseNormalization,
+    /// Keep at most this many of the strongest corners, like OpenCV's
+    /// `maxCorners`; `None` keeps all. A limit also makes detection cheaper
+    /// on large frames, since only the candidates needed to fill it are
+    /// ever sorted.
+    pub max_corners: Option<usize>,
+}
+
+/// Scale of the responses the detectors report, for comparing eigenvalues
+/// across frames and cameras.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ResponseNormalization {
+    /// Minimum eigenvalue of the structure tensor averaged (not summed) over
+    /// the block, i.e. already divided by the window area, so responses do
+    /// not grow with `block_size`. They still scale with the square of the
+    /// image contrast.
+    #[default]
+    BlockMean,
+    /// [`BlockMean`](Self::BlockMean) divided by the variance of the image
+    /// intensities. A gain change of the camera scales both by the square
+    /// of the gain, so the ratio stays put and an absolute threshold keeps
+    /// its meaning when the exposure or the sensor changes.
+    Contrast,
+}
+
+impl Default for FeatureParams {
+    fn default() -> Self {
+        FeatureParams {
+            quality_level: 0.4,
+            min_distance: 5,
+            gradient_size: FILTER_SCHARR,
+            block_size: 3,
+            normalization: ResponseNormalization::BlockMean,
+            max_corners: None,
+        }
+    }
+}
 
 /// Finds good features points using the Shi-Tomasi algorithm
 ///
@@ -18,10 +91,281 @@ pub fn good_features_to_track(
     quality_level: f32,
     min_distance: u32,
 ) -> Vec<(u32, u32, f32)> {
-    let features = detect_candidates(image, quality_level);
+    good_features_to_track_with(
+        image,
+        &FeatureParams {
+            quality_level,
+            min_distance,
+            ..FeatureParams::default()
+        },
+    )
+}
+
+/// [`good_features_to_track`] with the full set of detector settings.
+///
+/// # Returns
+/// Vector of features with eigenvalue. Points sorted in descending order of quality
+pub fn good_features_to_track_with(
+    image: &GrayImage,
+    params: &FeatureParams,
+) -> Vec<(u32, u32, f32)> {
+    let mut features = detect_candidates(
+        image,
+        params.quality_level,
+        params.gradient_size,
+        params.block_size,
+    );
+    if params.normalization == ResponseNormalization::Contrast {
+        normalize_responses(&mut features, intensity_variance(image.as_raw().iter()));
+    }
+
+    strongest_spaced(
+        features,
+        params.min_distance,
+        image.width(),
+        image.height(),
+        params.max_corners,
+    )
+}
+
+/// [`good_features_to_track_with`] on level `level` of `pyramid`, with the
+/// results in level-0 coordinates.
+///
+/// Detecting on a coarser level is roughly `4^level` times cheaper and favors
+/// larger structures, and passing the tracking pyramid avoids building a
+/// second one. A level-`level` pixel `(x, y)` maps to `(x << level, y << level)`,
+/// the inverse of the `p / 2^level` scaling the trackers apply, so the
+/// features can be handed to them directly. `params.min_distance` is in
+/// level-0 pixels.
+///
+/// # Panics
+/// Panics if `level` is not below `pyramid.len()`.
+///
+/// # Returns
+/// Vector of features with eigenvalue. Points sorted in descending order of quality
+pub fn good_features_to_track_pyramid(
+    pyramid: &[GrayImage],
+    level: usize,
+    params: &FeatureParams,
+) -> Vec<(u32, u32, f32)> {
+    assert!(
+        level < pyramid.len(),
+        "level {level} is out of range for a pyramid of {} levels",
+        pyramid.len()
+    );
+    let mut features: Vec<_> = detect_candidates(
+        &pyramid[level],
+        params.quality_level,
+        params.gradient_size,
+        params.block_size,
+    )
+    .into_iter()
+    .map(|(x, y, quality)| (x << level, y << level, quality))
+    .collect();
+    if params.normalization == ResponseNormalization::Contrast {
+        let variance = intensity_variance(pyramid[level].as_raw().iter());
+        normalize_responses(&mut features, variance);
+    }
 
-    // Filter by distance
-    filter_by_distance(&features, min_distance, image.width(), image.height())
+    strongest_spaced(
+        features,
+        params.min_distance,
+        pyramid[0].width(),
+        pyramid[0].height(),
+        params.max_corners,
+    )
+}
+
+/// [`good_features_to_track_with`] on a color image, with the structure
+/// tensor built from the gradients of all three channels (Di Zenzo's
+/// multi-channel tensor).
+///
+/// A corner between regions of equal brightness but different color has no
+/// gradient in the grayscale image and is invisible to the grayscale
+/// detector; here every channel's edges contribute. The channel tensors are
+/// averaged, so a gray image gives the same responses as its grayscale
+/// version and `params` carries over unchanged.
+///
+/// # Returns
+/// Vector of features with eigenvalue. Points sorted in descending order of quality
+pub fn good_features_to_track_rgb(
+    image: &RgbImage,
+    params: &FeatureParams,
+) -> Vec<(u32, u32, f32)> {
+    let tensor = structure_tensor_rgb(image, params.gradient_size, params.block_size);
+    let mut features = select_candidates(tensor, params.quality_level);
+    if params.normalization == ResponseNormalization::Contrast {
+        // The mean channel variance, matching the averaged channel tensors
+        let raw = image.as_raw();
+        let variance = (0..3)
+            .map(|c| intensity_variance(raw.iter().skip(c).step_by(3)))
+            .sum::<f32>()
+            / 3.0;
+        normalize_responses(&mut features, variance);
+    }
+
+    strongest_spaced(
+        features,
+        params.min_distance,
+        image.width(),
+        image.height(),
+        params.max_corners,
+    )
+}
+
+/// Finds corners with the Harris detector.
+///
+/// The response of every pixel is `det(M) - k * trace(M)^2`, where `M` is the
+/// structure tensor averaged over a `block_size` x `block_size` window (odd,
+/// at least 3). Unlike [`good_features_to_track`], `threshold` is an absolute
+/// response value rather than a fraction of the strongest corner, and no
+/// spacing constraint is applied: every local maximum that reaches the
+/// threshold is returned.
+///
+/// # Arguments
+/// * `image` - Target image (grayscale)
+/// * `k` - Harris sensitivity. 0.04 - 0.06 are typical values
+/// * `block_size` - Side of the aggregation window
+/// * `threshold` - Minimum accepted response
+///
+/// # Returns
+/// Vector of corners with their Harris response, sorted in descending order
+/// of response
+pub fn harris_corners(
+    image: &GrayImage,
+    k: f32,
+    block_size: u32,
+    threshold: f32,
+) -> Vec<(u32, u32, f32)> {
+    let (corners, response) = harris(image, k, block_size, threshold);
+    recycle_f32(response);
+    corners
+}
+
+/// [`harris_corners`] that also returns the full response map, e.g. for
+/// visualization or custom post-processing.
+///
+/// # Returns
+/// The corners as in [`harris_corners`], and the per-pixel Harris response
+/// with the dimensions of `image`
+pub fn harris_corners_with_response(
+    image: &GrayImage,
+    k: f32,
+    block_size: u32,
+    threshold: f32,
+) -> (Vec<(u32, u32, f32)>, ResponseMap) {
+    let (width, height) = image.dimensions();
+    let (corners, response) = harris(image, k, block_size, threshold);
+    (
+        corners,
+        ImageBuffer::from_vec(width, height, response).unwrap(),
+    )
+}
+
+fn harris(
+    image: &GrayImage,
+    k: f32,
+    block_size: u32,
+    threshold: f32,
+) -> (Vec<(u32, u32, f32)>, Vec<f32>) {
+    let (width, height) = image.dimensions();
+    let (ix_sq, iy_sq, ix_iy) = structure_tensor(image, FILTER_SCHARR, block_size);
+
+    let response = compute_harris_response(&ix_sq, &iy_sq, &ix_iy, k);
+    for plane in [ix_sq, iy_sq, ix_iy] {
+        recycle_i16(plane.into_raw());
+    }
+
+    let mut corners = non_maximum_suppression(&response, width, height, threshold);
+    corners.sort_by(|a, b| b.2.partial_cmp(&a.2).unwrap_or(Ordering::Equal));
+
+    (corners, response)
+}
+
+/// Dominant gradient orientation around a keypoint, from the eigenvectors of
+/// its structure tensor.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Orientation {
+    /// Direction of the strongest gradient in radians, in `(-π/2, π/2]`,
+    /// measured from the +x axis towards +y (image rows). The direction is
+    /// unsigned: a dark-to-bright and a bright-to-dark edge give the same
+    /// angle. An edge runs perpendicular to it.
+    pub angle: f32,
+    /// `(λmax - λmin) / (λmax + λmin)` of the structure tensor: close to 1
+    /// along an edge, where the angle is well defined, and close to 0 at a
+    /// corner or in a flat region, where it is not.
+    pub coherence: f32,
+}
+
+/// Computes the dominant gradient orientation of every point, e.g. for
+/// oriented descriptors or to tell which flow component of an edge point is
+/// observable.
+///
+/// Scharr gradients are accumulated over a `block_size` x `block_size` window
+/// around the pixel nearest to each point; pixels whose derivative would read
+/// outside the image are skipped. A window without any gradient yields angle
+/// 0 and coherence 0.
+///
+/// # Arguments
+/// * `image` - Target image (grayscale)
+/// * `points` - Keypoints in pixel coordinates
+/// * `block_size` - Side of the window (odd, at least 3)
+///
+/// # Returns
+/// One orientation per point, in the same order
+pub fn keypoint_orientations(
+    image: &GrayImage,
+    points: &[(f32, f32)],
+    block_size: u32,
+) -> Vec<Orientation> {
+    assert!(
+        block_size >= 3 && block_size % 2 == 1,
+        "block_size must be odd and at least 3"
+    );
+    let (width, height) = (image.width() as i64, image.height() as i64);
+    let radius = (block_size / 2) as i64;
+    // The Scharr planes are 0 on the one-pixel border, where the derivative
+    // would read outside the image, so border pixels add nothing.
+    let (grad_x, grad_y) = compute_gradients(image, FILTER_SCHARR);
+
+    let orientations = points
+        .iter()
+        .map(|&(px, py)| {
+            let (cx, cy) = (px.round() as i64, py.round() as i64);
+            let x1 = (cx + radius + 1).clamp(0, width);
+            let x0 = (cx - radius).clamp(0, x1);
+            let (mut gxx, mut gxy, mut gyy) = (0.0f32, 0.0f32, 0.0f32);
+            for y in (cy - radius).max(0)..(cy + radius + 1).min(height) {
+                let row = (y * width) as usize;
+                let cols = row + x0 as usize..row + x1 as usize;
+                for (&gx, &gy) in grad_x[cols.clone()].iter().zip(&grad_y[cols]) {
+                    let (gx, gy) = (gx as f32, gy as f32);
+                    gxx += gx * gx;
+                    gxy += gx * gy;
+                    gyy += gy * gy;
+                }
+            }
+
+            let trace = gxx + gyy;
+            if trace <= 0.0 {
+                return Orientation {
+                    angle: 0.0,
+                    coherence: 0.0,
+                };
+            }
+            let mut angle = 0.5 * (2.0 * gxy).atan2(gxx - gyy);
+            if angle <= -std::f32::consts::FRAC_PI_2 {
+                angle += std::f32::consts::PI;
+            }
+            Orientation {
+                angle,
+                coherence: (gxx - gyy).hypot(2.0 * gxy) / trace,
+            }
+        })
+        .collect();
+    recycle_i16(grad_x);
+    recycle_i16(grad_y);
+    orientations
 }
 
 /// Finds good feature points with uniform frame coverage by detecting per grid
@@ -56,38 +400,131 @@ pub fn good_features_to_track_grid(
     min_distance: u32,
     existing_points: &[(f32, f32)],
 ) -> Vec<(u32, u32, f32)> {
-    assert!(grid_cols > 0 && grid_rows > 0, "grid must be non-empty");
+    let (width, height) = image.dimensions();
+    let mut coverage = CoverageMap::new(width, height, grid_cols, grid_rows);
+    coverage.set_points(existing_points);
+    let mut candidates = detect_candidates(image, quality_level, FILTER_SCHARR, 3);
+    candidates.sort_unstable_by(by_descending_quality);
+
+    select_in_cells(&candidates, &mut coverage, max_per_cell, min_distance)
+}
+
+/// Source pixels around a cell that [`good_features_to_track_sparse`] scans
+/// as well, keeping the zeroed gradient border of the scanned region and the
+/// reach of the 3x3 block and non-maximum suppression outside the cell.
+const SCAN_MARGIN: u32 = 4;
 
+/// Re-detection for a running tracker that only scans the grid cells of
+/// `coverage` holding fewer than `min_coverage` live tracks.
+///
+/// Well-covered regions are skipped entirely instead of being re-scanned and
+/// rejected, so topping up lost tracks costs in proportion to the area that
+/// needs new features and the spatial distribution stays even. Within the
+/// scanned cells, detection is [`good_features_to_track_grid`] with the map's
+/// grid and points: up to `max_per_cell` features per cell counting the live
+/// tracks, at least `min_distance` apart from each other and from the tracks.
+///
+/// `quality_level` is relative to the strongest corner in the scanned cells,
+/// not in the whole frame.
+///
+/// # Panics
+/// Panics if `coverage` was created for a different image size.
+///
+/// # Returns
+/// Newly detected corners as `(x, y, min_eigenvalue)`, sorted by descending
+/// quality.
+pub fn good_features_to_track_sparse(
+    image: &GrayImage,
+    coverage: &CoverageMap,
+    min_coverage: u32,
+    max_per_cell: u32,
+    quality_level: f32,
+    min_distance: u32,
+) -> Vec<(u32, u32, f32)> {
     let (width, height) = image.dimensions();
-    let candidates = detect_candidates(image, quality_level);
-
-    // Detection cell of a point, clamped to the grid.
-    let cell_of = |x: f32, y: f32| -> usize {
-        let col = ((x / width as f32) * grid_cols as f32) as u32;
-        let row = ((y / height as f32) * grid_rows as f32) as u32;
-        let col = col.min(grid_cols - 1);
-        let row = row.min(grid_rows - 1);
-        (row * grid_cols + col) as usize
-    };
+    assert_eq!(
+        (coverage.width, coverage.height),
+        (width, height),
+        "coverage map must match the image size"
+    );
 
-    // Per-cell occupancy (existing + accepted) against the feature budget.
-    let mut cell_count = vec![0u32; (grid_cols * grid_rows) as usize];
-    // Spatial hash for min_distance, independent of the detection grid.
-    let mut occupancy = OccupancyGrid::new(width, height, min_distance);
+    // Response of every sparse cell, computed on the cell plus a margin.
+    let mut scans = Vec::new();
+    let mut max_quality = 0.0f32;
+    for (col, row) in coverage.sparse_cells(min_coverage) {
+        let (x0, y0, x1, y1) = coverage.cell_bounds(col, row);
+        let (sx0, sy0) = (
+            x0.saturating_sub(SCAN_MARGIN),
+            y0.saturating_sub(SCAN_MARGIN),
+        );
+        let (sx1, sy1) = (
+            (x1 + SCAN_MARGIN).min(width),
+            (y1 + SCAN_MARGIN).min(height),
+        );
+        let (sw, sh) = (sx1 - sx0, sy1 - sy0);
+
+        let (ix_sq, iy_sq, ix_iy) = region_structure_tensor(image, (sx0, sy0), (sw, sh), 3);
+        let (response, _) = compute_min_eigenvalues(&ix_sq, &iy_sq, &ix_iy);
+        for plane in [ix_sq, iy_sq, ix_iy] {
+            recycle_i16(plane.into_raw());
+        }
+
+        // Strongest value inside the cell, away from the frame border
+        for y in y0.max(1)..y1.min(height.saturating_sub(1)) {
+            for x in x0.max(1)..x1.min(width.saturating_sub(1)) {
+                let i = ((y - sy0) * sw + (x - sx0)) as usize;
+                max_quality = max_quality.max(response[i]);
+            }
+        }
+        scans.push(((col, row), (sx0, sy0, sw, sh), response));
+    }
+
+    // Scans overlap their neighbors; each candidate is kept by its own cell
+    let threshold = quality_level * max_quality;
+    let mut candidates = Vec::new();
+    for (cell, (sx0, sy0, sw, sh), response) in scans {
+        for (x, y, q) in non_maximum_suppression(&response, sw, sh, threshold) {
+            let (x, y) = (x + sx0, y + sy0);
+            if coverage.cell_of(x as f32, y as f32) == cell {
+                candidates.push((x, y, q));
+            }
+        }
+        recycle_f32(response);
+    }
+    candidates.sort_unstable_by(by_descending_quality);
+
+    select_in_cells(
+        &candidates,
+        &mut coverage.clone(),
+        max_per_cell,
+        min_distance,
+    )
+}
 
-    for &(ex, ey) in existing_points {
-        cell_count[cell_of(ex, ey)] += 1;
+/// Greedily accepts `candidates` (sorted by descending quality) while no cell
+/// of `coverage` exceeds `max_per_cell` points and every kept point is at
+/// least `min_distance` from the others, including the map's points.
+/// Accepted points are added to `coverage`.
+fn select_in_cells(
+    candidates: &[(u32, u32, f32)],
+    coverage: &mut CoverageMap,
+    max_per_cell: u32,
+    min_distance: u32,
+) -> Vec<(u32, u32, f32)> {
+    // Spatial hash for min_distance, independent of the detection grid.
+    let mut occupancy = OccupancyGrid::new(coverage.width, coverage.height, min_distance);
+    for &(ex, ey) in &coverage.points {
         occupancy.insert(ex, ey);
     }
 
     let min_distance = min_distance as f32;
     let mut result = Vec::new();
 
-    for &(x, y, q) in &candidates {
+    for &(x, y, q) in candidates {
         let (xf, yf) = (x as f32, y as f32);
-        let cell = cell_of(xf, yf);
+        let (col, row) = coverage.cell_of(xf, yf);
 
-        if cell_count[cell] >= max_per_cell {
+        if coverage.count(col, row) >= max_per_cell {
             continue;
         }
         if occupancy.has_neighbor_within(xf, yf, min_distance) {
@@ -95,42 +532,345 @@ pub fn good_features_to_track_grid(
         }
 
         occupancy.insert(xf, yf);
-        cell_count[cell] += 1;
+        coverage.insert(xf, yf);
         result.push((x, y, q));
     }
 
     result
 }
 
-/// Runs the Shi-Tomasi pipeline and returns candidate corners sorted by
-/// descending quality, before any spacing constraint is applied.
-fn detect_candidates(image: &GrayImage, quality_level: f32) -> Vec<(u32, u32, f32)> {
-    // Compute gradients
-    let (gx, gy) = compute_gradients(image);
+/// Number of live tracks in each cell of a detection grid, used to steer
+/// re-detection to under-populated image regions (see
+/// [`good_features_to_track_sparse`]).
+///
+/// The image is split into `cols` x `rows` cells of (nearly) equal size, as in
+/// [`good_features_to_track_grid`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct CoverageMap {
+    width: u32,
+    height: u32,
+    cols: u32,
+    rows: u32,
+    counts: Vec<u32>,
+    points: Vec<(f32, f32)>,
+}
 
-    // Compute squared gradients and their product
-    let (mut ix_sq, mut iy_sq, mut ix_iy) = compute_gradient_products(&gx, &gy);
+impl CoverageMap {
+    /// Creates an empty map of a `cols` x `rows` grid over a `width` x
+    /// `height` image.
+    ///
+    /// # Panics
+    /// Panics if `cols` or `rows` is zero.
+    pub fn new(width: u32, height: u32, cols: u32, rows: u32) -> Self {
+        assert!(cols > 0 && rows > 0, "grid must be non-empty");
+        CoverageMap {
+            width,
+            height,
+            cols,
+            rows,
+            counts: vec![0; (cols * rows) as usize],
+            points: Vec::new(),
+        }
+    }
+
+    /// Replaces the live tracks (level-0 pixel coordinates) and recounts every
+    /// cell.
+    pub fn set_points(&mut self, points: &[(f32, f32)]) {
+        self.counts.fill(0);
+        self.points.clear();
+        for &(x, y) in points {
+            self.insert(x, y);
+        }
+    }
+
+    fn insert(&mut self, x: f32, y: f32) {
+        let (col, row) = self.cell_of(x, y);
+        self.counts[(row * self.cols + col) as usize] += 1;
+        self.points.push((x, y));
+    }
+
+    /// Number of grid columns.
+    pub fn cols(&self) -> u32 {
+        self.cols
+    }
 
-    // Smooth with 3x3 filters
-    box_filter_3x3_in_place(&mut ix_sq);
-    box_filter_3x3_in_place(&mut iy_sq);
-    box_filter_3x3_in_place(&mut ix_iy);
+    /// Number of grid rows.
+    pub fn rows(&self) -> u32 {
+        self.rows
+    }
+
+    /// The live tracks of the last [`set_points`](Self::set_points).
+    pub fn points(&self) -> &[(f32, f32)] {
+        &self.points
+    }
+
+    /// Number of live tracks in cell (`col`, `row`).
+    pub fn count(&self, col: u32, row: u32) -> u32 {
+        self.counts[(row * self.cols + col) as usize]
+    }
+
+    /// Cell `(col, row)` containing `(x, y)`, clamped to the grid.
+    pub fn cell_of(&self, x: f32, y: f32) -> (u32, u32) {
+        let col = ((x / self.width as f32) * self.cols as f32) as u32;
+        let row = ((y / self.height as f32) * self.rows as f32) as u32;
+        (col.min(self.cols - 1), row.min(self.rows - 1))
+    }
+
+    /// Pixel bounds `(x0, y0, x1, y1)` of cell (`col`, `row`), end exclusive.
+    pub fn cell_bounds(&self, col: u32, row: u32) -> (u32, u32, u32, u32) {
+        let x = |c: u32| (c * self.width).div_ceil(self.cols);
+        let y = |r: u32| (r * self.height).div_ceil(self.rows);
+        (x(col), y(row), x(col + 1), y(row + 1))
+    }
 
-    // Compute minimum eigenvalues
-    let mut features = compute_min_eigenvalues(&ix_sq, &iy_sq, &ix_iy);
+    /// Cells holding fewer than `min_count` live tracks, in row-major order.
+    pub fn sparse_cells(&self, min_count: u32) -> impl Iterator<Item = (u32, u32)> + '_ {
+        (0..self.rows)
+            .flat_map(move |row| (0..self.cols).map(move |col| (col, row)))
+            .filter(move |&(col, row)| self.count(col, row) < min_count)
+    }
+}
 
-    // Non-maximum suppression
-    non_maximum_suppression(&mut features, image.width(), image.height());
+/// Runs the Shi-Tomasi pipeline and returns candidate corners in raster
+/// order, before any spacing constraint is applied.
+fn detect_candidates(
+    image: &GrayImage,
+    quality_level: f32,
+    gradient_size: i32,
+    block_size: u32,
+) -> Vec<(u32, u32, f32)> {
+    select_candidates(
+        structure_tensor(image, gradient_size, block_size),
+        quality_level,
+    )
+}
 
-    // Filter by quality
-    filter_by_quality(&mut features, quality_level);
+/// Candidate corners of a structure tensor in raster order; the planes go
+/// back to the buffer pool.
+fn select_candidates(
+    (ix_sq, iy_sq, ix_iy): GradientProduct,
+    quality_level: f32,
+) -> Vec<(u32, u32, f32)> {
+    // Local maxima of the minimum-eigenvalue response and its strongest
+    // interior value, without materializing the response
+    let (mut features, max_quality) = min_eigen_maxima(&ix_sq, &iy_sq, &ix_iy);
+    for plane in [ix_sq, iy_sq, ix_iy] {
+        recycle_i16(plane.into_raw());
+    }
 
-    // Sort by descending quality
-    features.sort_by(|a, b| b.2.partial_cmp(&a.2).unwrap_or(Ordering::Equal));
+    // Only the maxima that pass the quality threshold become candidates
+    let threshold = quality_level * max_quality;
+    features.retain(|&(_, _, q)| q >= threshold);
 
     features
 }
 
+/// Descending quality, ties in raster order: the order a stable sort gives
+/// candidates that arrive in raster order.
+fn by_descending_quality(a: &(u32, u32, f32), b: &(u32, u32, f32)) -> Ordering {
+    let quality = b.2.partial_cmp(&a.2).unwrap_or(Ordering::Equal);
+    quality.then((a.1, a.0).cmp(&(b.1, b.0)))
+}
+
+/// The strongest of the raster-ordered `features` that are at least
+/// `min_distance` apart, by descending quality, at most `max_corners` of
+/// them.
+///
+/// Without a limit every candidate is sorted. With one, only a growing
+/// prefix is: a linear-time selection partitions off the next strongest
+/// candidates, which are sorted and appended, and the prefix doubles until
+/// the spacing filter keeps enough of it. Either way the result is the same
+/// as sorting everything first.
+fn strongest_spaced(
+    mut features: Vec<(u32, u32, f32)>,
+    min_distance: u32,
+    width: u32,
+    height: u32,
+    max_corners: Option<usize>,
+) -> Vec<(u32, u32, f32)> {
+    let Some(max_corners) = max_corners else {
+        features.sort_unstable_by(by_descending_quality);
+        return filter_by_distance(&features, min_distance, width, height);
+    };
+
+    // Spacing rejects some of the strongest candidates, so start with twice
+    // the limit
+    let mut sorted = 0;
+    let mut target = max_corners.saturating_mul(2);
+    loop {
+        let end = target.min(features.len());
+        if end < features.len() {
+            features[sorted..].select_nth_unstable_by(end - sorted, by_descending_quality);
+        }
+        features[sorted..end].sort_unstable_by(by_descending_quality);
+        sorted = end;
+
+        let mut kept = filter_by_distance(&features[..sorted], min_distance, width, height);
+        if kept.len() >= max_corners || sorted == features.len() {
+            kept.truncate(max_corners);
+            return kept;
+        }
+        target = target.saturating_mul(2);
+    }
+}
+
+/// Variance of the intensity samples, at least 1 so that flat images keep
+/// finite responses.
+fn intensity_variance<'a>(samples: impl Iterator<Item = &'a u8>) -> f32 {
+    let (mut n, mut sum, mut sum_sq) = (0u64, 0u64, 0u64);
+    for &v in samples {
+        n += 1;
+        sum += v as u64;
+        sum_sq += (v as u64) * (v as u64);
+    }
+    if n == 0 {
+        return 1.0;
+    }
+    let mean = sum as f64 / n as f64;
+    ((sum_sq as f64 / n as f64 - mean * mean) as f32).max(1.0)
+}
+
+/// Divides the responses of `features` by the image intensity `variance`,
+/// see [`ResponseNormalization::Contrast`].
+fn normalize_responses(features: &mut [(u32, u32, f32)], variance: f32) {
+    for (_, _, quality) in features {
+        *quality /= variance;
+    }
+}
+
+/// Computes the gradient products of `image` with the `gradient_size`
+/// derivative kernel and averages them over `block_size` x `block_size`
+/// windows, giving the `(Ixx, Iyy, Ixy)` planes of the structure tensor. The
+/// planes come from the buffer pool.
+fn structure_tensor(image: &GrayImage, gradient_size: i32, block_size: u32) -> GradientProduct {
+    check_block_size(block_size);
+    let (width, height) = image.dimensions();
+
+    // Compute gradients into pooled planes
+    let (gx, gy) = compute_gradients(image, gradient_size);
+    tensor_from_gradients(width, height, gx, gy, block_size)
+}
+
+/// [`structure_tensor`] with the Scharr kernel of the `width` x `height`
+/// region of `image` at `origin`, read in place instead of from a cropped
+/// copy.
+fn region_structure_tensor(
+    image: &GrayImage,
+    origin: (u32, u32),
+    (width, height): (u32, u32),
+    block_size: u32,
+) -> GradientProduct {
+    check_block_size(block_size);
+    let n = (width * height) as usize;
+    let (mut gx, mut gy) = (take_i16(n), take_i16(n));
+    compute_region_gradients_into(image, origin, (width, height), &mut gx, &mut gy);
+    tensor_from_gradients(width, height, gx, gy, block_size)
+}
+
+/// Structure tensor of the pooled gradient planes `gx` and `gy`, which go
+/// back to the pool.
+fn tensor_from_gradients(
+    width: u32,
+    height: u32,
+    gx: Vec<i16>,
+    gy: Vec<i16>,
+    block_size: u32,
+) -> GradientProduct {
+    // Compute squared gradients and their product
+    let mut tensor = compute_gradient_products(width, height, &gx, &gy);
+    recycle_i16(gx);
+    recycle_i16(gy);
+
+    aggregate_tensor(&mut tensor, block_size);
+    tensor
+}
+
+/// [`structure_tensor`] of a color image: the mean of the three channels'
+/// gradient products, aggregated over the block.
+fn structure_tensor_rgb(image: &RgbImage, gradient_size: i32, block_size: u32) -> GradientProduct {
+    check_block_size(block_size);
+    let (width, height) = image.dimensions();
+    let n = (width * height) as usize;
+
+    // Sum the channel products in f32, exact for these integers: three
+    // squared gradients overflow i16
+    let mut sums = [take_f32(n), take_f32(n), take_f32(n)];
+    let mut channel = GrayImage::from_raw(width, height, take_u8(n))
+        .expect("pooled buffer has exactly width * height pixels");
+    for c in 0..3 {
+        for (dst, src) in channel.iter_mut().zip(image.pixels()) {
+            *dst = src[c];
+        }
+        let (gx, gy) = compute_gradients(&channel, gradient_size);
+        let [sum_xx, sum_yy, sum_xy] = &mut sums;
+        for (((xx, yy), xy), (&gx, &gy)) in sum_xx
+            .iter_mut()
+            .zip(sum_yy.iter_mut())
+            .zip(sum_xy.iter_mut())
+            .zip(gx.iter().zip(&gy))
+        {
+            let (ix, iy) = ((gx / 32) as f32, (gy / 32) as f32);
+            *xx += ix * ix;
+            *yy += iy * iy;
+            *xy += ix * iy;
+        }
+        recycle_i16(gx);
+        recycle_i16(gy);
+    }
+    recycle_u8(channel.into_raw());
+
+    // Truncating like integer division, as the sums are exact.
+    let [ix_sq, iy_sq, ix_iy] = sums.map(|sum| {
+        let mut plane = take_i16(n);
+        for (dst, &value) in plane.iter_mut().zip(&sum) {
+            *dst = (value / 3.0) as i16;
+        }
+        recycle_f32(sum);
+        plane
+    });
+    let mut tensor = (
+        ImageBuffer::from_vec(width, height, ix_sq).unwrap(),
+        ImageBuffer::from_vec(width, height, iy_sq).unwrap(),
+        ImageBuffer::from_vec(width, height, ix_iy).unwrap(),
+    );
+
+    aggregate_tensor(&mut tensor, block_size);
+    tensor
+}
+
+fn check_block_size(block_size: u32) {
+    assert!(
+        block_size >= 3 && block_size % 2 == 1,
+        "block_size must be odd and at least 3"
+    );
+}
+
+/// Gradients of `image` with the `gradient_size` derivative kernel, as pooled
+/// planes.
+fn compute_gradients(image: &GrayImage, gradient_size: i32) -> (Vec<i16>, Vec<i16>) {
+    let n = (image.width() * image.height()) as usize;
+    let mut gx = take_i16(n);
+    let mut gy = take_i16(n);
+    match gradient_size {
+        FILTER_SCHARR => compute_gradients_into(image, &mut gx, &mut gy),
+        3 | 5 | 7 => compute_sobel_gradients_into(image, gradient_size as u32, &mut gx, &mut gy),
+        _ => panic!("gradient_size must be 3, 5, 7 or FILTER_SCHARR"),
+    }
+    (gx, gy)
+}
+
+/// Aggregates the gradient products over the block: separable 3x3 filters for
+/// the default size, an integral image for anything larger.
+fn aggregate_tensor((ix_sq, iy_sq, ix_iy): &mut GradientProduct, block_size: u32) {
+    for plane in [ix_sq, iy_sq, ix_iy] {
+        if block_size == 3 {
+            box_filter_3x3_in_place(plane);
+        } else {
+            box_mean_in_place(plane, block_size);
+        }
+    }
+}
+
 /// Uniform spatial hash used to enforce `min_distance` between kept points.
 ///
 /// Cells are `min_distance` wide, so any point closer than `min_distance` to a
@@ -195,6 +935,8 @@ impl OccupancyGrid {
     }
 }
 
+type ResponseMap = ImageBuffer<Luma<f32>, Vec<f32>>;
+
 type GradientProduct = (
     ImageBuffer<Luma<i16>, Vec<i16>>,
     ImageBuffer<Luma<i16>, Vec<i16>>,
@@ -202,19 +944,18 @@ type GradientProduct = (
 );
 
 fn compute_gradient_products(
-    gx: &ImageBuffer<Luma<i16>, Vec<i16>>,
-    gy: &ImageBuffer<Luma<i16>, Vec<i16>>,
+    width: u32,
+    height: u32,
+    gx_data: &[i16],
+    gy_data: &[i16],
 ) -> GradientProduct {
-    let (width, height) = gx.dimensions();
-    let gx_data = gx.as_raw();
-    let gy_data = gy.as_raw();
     let n = gx_data.len();
 
-    // Build the three product planes as flat buffers, avoiding per-pixel
-    // bounds-checked `put_pixel` (a noticeable win under WASM).
-    let mut ix_sq = vec![0i16; n];
-    let mut iy_sq = vec![0i16; n];
-    let mut ix_iy = vec![0i16; n];
+    // Build the three product planes as flat (pooled) buffers, avoiding
+    // per-pixel bounds-checked `put_pixel` (a noticeable win under WASM).
+    let mut ix_sq = take_i16(n);
+    let mut iy_sq = take_i16(n);
+    let mut ix_iy = take_i16(n);
 
     for i in 0..n {
         let ix = gx_data[i] / 32;
@@ -231,78 +972,235 @@ fn compute_gradient_products(
     )
 }
 
+/// Per-pixel minimum eigenvalue of the smoothed structure tensor, as a
+/// row-major `width * height` plane taken from the buffer pool, together with
+/// the largest response among interior pixels (at least 0).
+///
+/// Border pixels can never survive [`non_maximum_suppression`], and the
+/// largest interior response always does, so the returned maximum is the
+/// strongest candidate's quality: the quality threshold can be applied during
+/// suppression instead of over the full candidate list afterwards.
 fn compute_min_eigenvalues(
     a: &ImageBuffer<Luma<i16>, Vec<i16>>,
     b: &ImageBuffer<Luma<i16>, Vec<i16>>,
     c: &ImageBuffer<Luma<i16>, Vec<i16>>,
-) -> Vec<(u32, u32, f32)> {
-    let width = a.width();
+) -> (Vec<f32>, f32) {
+    let (width, height) = (a.width() as usize, a.height() as usize);
     let (a_data, b_data, c_data) = (a.as_raw(), b.as_raw(), c.as_raw());
-    let mut features = Vec::with_capacity(a_data.len());
+    let mut response = take_f32(a_data.len());
+    let mut max_quality = 0.0f32;
 
-    for i in 0..a_data.len() {
-        let a_val = a_data[i] as i32;
-        let b_val = b_data[i] as i32;
-        let c_val = c_data[i] as i32;
+    for y in 0..height {
+        let interior_row = y > 0 && y + 1 < height;
+        for x in 0..width {
+            let i = y * width + x;
+            let min_eigen = min_eigenvalue(a_data[i], b_data[i], c_data[i]);
+            response[i] = min_eigen;
 
-        let trace = a_val + b_val;
-        let discriminant = (a_val - b_val).pow(2) + 4 * c_val.pow(2);
-        let min_eigen = (((trace - discriminant) as f32).sqrt()) / 2.0;
-
-        let x = i as u32 % width;
-        let y = i as u32 / width;
-        features.push((x, y, min_eigen));
+            if interior_row && x > 0 && x + 1 < width {
+                max_quality = max_quality.max(min_eigen);
+            }
+        }
     }
 
-    features
+    (response, max_quality)
 }
 
-fn non_maximum_suppression(features: &mut Vec<(u32, u32, f32)>, width: u32, height: u32) {
-    let mut is_local_max = vec![false; features.len()];
+/// Rows of interior pixels handled by one task of [`min_eigen_maxima`].
+const MAXIMA_BAND_ROWS: usize = 32;
 
-    for y in 1..height - 1 {
+/// Minimum-eigenvalue response of one structure tensor `(a, b, c)`.
+#[inline]
+fn min_eigenvalue(a: i16, b: i16, c: i16) -> f32 {
+    let (a, b, c) = (a as i64, b as i64, c as i64);
+    let trace = a + b;
+    let discriminant = (a - b).pow(2) + 4 * c.pow(2);
+    (trace as f32 - (discriminant as f32).sqrt()) / 2.0
+}
+
+/// The pixels [`non_maximum_suppression`] would keep from the
+/// [`compute_min_eigenvalues`] response at a threshold of `-inf`, in
+/// row-major order, together with the response's largest interior value (at
+/// least 0).
+///
+/// Response, suppression and maximum are fused into one pass that never
+/// materializes the full-frame response: every band of rows computes the
+/// response rows it needs into a rolling buffer and appends its maxima to its
+/// own vector. With the `rayon` feature the bands run in parallel; they are
+/// concatenated in order, so the result does not depend on the scheduling.
+/// As the strongest interior response is always a maximum, thresholding the
+/// result afterwards matches suppressing with the threshold.
+fn min_eigen_maxima(
+    a: &ImageBuffer<Luma<i16>, Vec<i16>>,
+    b: &ImageBuffer<Luma<i16>, Vec<i16>>,
+    c: &ImageBuffer<Luma<i16>, Vec<i16>>,
+) -> (Vec<(u32, u32, f32)>, f32) {
+    let (width, height) = (a.width() as usize, a.height() as usize);
+    if width < 3 || height < 3 {
+        return (Vec::new(), 0.0);
+    }
+    let planes = (
+        a.as_raw().as_slice(),
+        b.as_raw().as_slice(),
+        c.as_raw().as_slice(),
+    );
+    let bands = (1..height - 1)
+        .step_by(MAXIMA_BAND_ROWS)
+        .map(|y| y..(y + MAXIMA_BAND_ROWS).min(height - 1));
+
+    #[cfg(feature = "rayon")]
+    let bands: Vec<_> = bands
+        .collect::<Vec<_>>()
+        .into_par_iter()
+        .map(|rows| min_eigen_maxima_band(planes, width, rows))
+        .collect();
+    #[cfg(not(feature = "rayon"))]
+    let bands: Vec<_> = bands
+        .map(|rows| min_eigen_maxima_band(planes, width, rows))
+        .collect();
+
+    let mut features = Vec::with_capacity(bands.iter().map(|(band, _)| band.len()).sum());
+    let mut max_quality = 0.0f32;
+    for (band, band_max) in bands {
+        features.extend(band);
+        max_quality = max_quality.max(band_max);
+    }
+    (features, max_quality)
+}
+
+/// [`min_eigen_maxima`] of the interior rows `rows`.
+fn min_eigen_maxima_band(
+    (a, b, c): (&[i16], &[i16], &[i16]),
+    width: usize,
+    rows: Range<usize>,
+) -> (Vec<(u32, u32, f32)>, f32) {
+    // Rolling three-row buffers of the response and of its horizontal 3-tap
+    // max; row y lives in slot y % 3 of each.
+    let mut rolling = take_f32(6 * width);
+    let (response, row_maxes) = rolling.split_at_mut(3 * width);
+    let fill = |y: usize, response: &mut [f32], row_maxes: &mut [f32]| {
+        let slot = (y % 3) * width;
+        let row = &mut response[slot..slot + width];
+        for (x, r) in row.iter_mut().enumerate() {
+            let i = y * width + x;
+            *r = min_eigenvalue(a[i], b[i], c[i]);
+        }
         for x in 1..width - 1 {
-            let idx = (y * width + x) as usize;
-            let current = features[idx].2;
+            row_maxes[slot + x] = row[x - 1].max(row[x]).max(row[x + 1]);
+        }
+    };
+    fill(rows.start - 1, response, row_maxes);
+    fill(rows.start, response, row_maxes);
 
-            let mut is_max = true;
-            for dy in -1..=1 {
-                for dx in -1..=1 {
-                    if dx == 0 && dy == 0 {
-                        continue;
-                    }
-                    let nx = x as i32 + dx;
-                    let ny = y as i32 + dy;
-                    if nx < 0 || ny < 0 || nx >= width as i32 || ny >= height as i32 {
-                        continue;
-                    }
-                    let neighbor_idx = (ny as u32 * width + nx as u32) as usize;
-                    if features[neighbor_idx].2 > current {
-                        is_max = false;
-                        break;
-                    }
-                }
-                if !is_max {
-                    break;
-                }
+    let mut features = Vec::new();
+    let mut max_quality = 0.0f32;
+    for y in rows {
+        fill(y + 1, response, row_maxes);
+        let slot = |y: usize| &row_maxes[(y % 3) * width..(y % 3 + 1) * width];
+        let (above, middle, below) = (slot(y - 1), slot(y), slot(y + 1));
+        let row = &response[(y % 3) * width..(y % 3 + 1) * width];
+
+        for x in 1..width - 1 {
+            let current = row[x];
+            max_quality = max_quality.max(current);
+            // NaN responses are never kept, see `non_maximum_suppression`.
+            if current.is_nan() {
+                continue;
+            }
+            let window_max = above[x].max(middle[x]).max(below[x]);
+            if window_max.partial_cmp(&current) != Some(Ordering::Greater) {
+                features.push((x as u32, y as u32, current));
             }
-            is_local_max[idx] = is_max;
         }
     }
 
-    features.retain(|(x, y, _)| {
-        let idx = (y * width + x) as usize;
-        is_local_max[idx]
-    });
+    recycle_f32(rolling);
+    (features, max_quality)
 }
 
-fn filter_by_quality(features: &mut Vec<(u32, u32, f32)>, quality_level: f32) {
-    let max_quality = features
-        .iter()
-        .map(|&(_, _, q)| q)
-        .fold(0.0f32, |a, b| a.max(b));
-    let threshold = quality_level * max_quality;
-    features.retain(|&(_, _, q)| q >= threshold);
+/// Per-pixel Harris response `a * b - c^2 - k * (a + b)^2` of the smoothed
+/// structure tensor, as a row-major plane taken from the buffer pool.
+fn compute_harris_response(
+    a: &ImageBuffer<Luma<i16>, Vec<i16>>,
+    b: &ImageBuffer<Luma<i16>, Vec<i16>>,
+    c: &ImageBuffer<Luma<i16>, Vec<i16>>,
+    k: f32,
+) -> Vec<f32> {
+    let (a_data, b_data, c_data) = (a.as_raw(), b.as_raw(), c.as_raw());
+    let mut response = take_f32(a_data.len());
+
+    for (i, r) in response.iter_mut().enumerate() {
+        let a_val = a_data[i] as f32;
+        let b_val = b_data[i] as f32;
+        let c_val = c_data[i] as f32;
+
+        let trace = a_val + b_val;
+        *r = a_val * b_val - c_val * c_val - k * trace * trace;
+    }
+
+    response
+}
+
+/// Returns the interior pixels of `response` that reach `threshold` and that
+/// no 8-neighbor exceeds, as `(x, y, response)` in row-major order.
+///
+/// The 3x3 maximum is separable: a horizontal 3-tap max per row, then a
+/// vertical 3-tap max over three consecutive row results, kept in a rolling
+/// three-row buffer. That is O(width * height) with two comparisons per pixel
+/// per pass, and only surviving pixels are materialized. `f32::max` ignores
+/// NaN, so a NaN neighbor never suppresses and a NaN pixel is never
+/// suppressed, exactly like a pairwise `neighbor > current` scan.
+pub(crate) fn non_maximum_suppression(
+    response: &[f32],
+    width: u32,
+    height: u32,
+    threshold: f32,
+) -> Vec<(u32, u32, f32)> {
+    let (width, height) = (width as usize, height as usize);
+    let mut features = Vec::new();
+    if width < 3 || height < 3 {
+        return features;
+    }
+
+    // Horizontal 3-tap max of one row, for interior columns.
+    let row_max = |y: usize, out: &mut [f32]| {
+        let row = &response[y * width..(y + 1) * width];
+        for x in 1..width - 1 {
+            out[x] = row[x - 1].max(row[x]).max(row[x + 1]);
+        }
+    };
+
+    let mut rolling = take_f32(3 * width);
+    {
+        let (first, rest) = rolling.split_at_mut(width);
+        row_max(0, first);
+        row_max(1, &mut rest[..width]);
+    }
+
+    for y in 1..height - 1 {
+        // Rows y-1, y and y+1 live in slots (y-1)%3, y%3 and (y+1)%3.
+        let next = (y + 1) % 3;
+        row_max(y + 1, &mut rolling[next * width..(next + 1) * width]);
+
+        let slot = |s: usize| &rolling[s * width..(s + 1) * width];
+        let (above, middle, below) = (slot((y - 1) % 3), slot(y % 3), slot(next));
+        let row = &response[y * width..(y + 1) * width];
+
+        for x in 1..width - 1 {
+            let current = row[x];
+            // `None` rejects NaN responses.
+            if matches!(current.partial_cmp(&threshold), None | Some(Ordering::Less)) {
+                continue;
+            }
+            let window_max = above[x].max(middle[x]).max(below[x]);
+            if window_max.partial_cmp(&current) != Some(Ordering::Greater) {
+                features.push((x as u32, y as u32, current));
+            }
+        }
+    }
+
+    recycle_f32(rolling);
+    features
 }
 
 fn filter_by_distance(
@@ -359,3 +1257,18 @@ fn filter_by_distance(
 
     result
 }
+
+#[cfg(test)]
+mod tests {
+    use super::min_eigenvalue;
+
+    #[test]
+    fn min_eigenvalue_of_known_tensors() {
+        // [[5, 2], [2, 2]] has the eigenvalues 6 and 1.
+        assert_eq!(min_eigenvalue(5, 2, 2), 1.0);
+        assert_eq!(min_eigenvalue(40, 40, 0), 40.0);
+        // Edges have one zero eigenvalue, along any direction.
+        assert_eq!(min_eigenvalue(100, 0, 0), 0.0);
+        assert_eq!(min_eigenvalue(i16::MAX, i16::MAX, i16::MAX), 0.0);
+    }
+}
@@ -0,0 +1,191 @@
+//! Sparse flow aggregated over a cell grid.
+//!
+//! UIs and analytics often want one motion vector per region rather than
+//! hundreds of tracks, without paying for a dense flow field.
+//! [`flow_grid`] reduces positioned flow vectors to the mean or median vector
+//! of each cell of a grid, with the number of vectors behind it;
+//! [`flow_grid_from_tracks`] feeds it the crate's sparse tracks.
+
+use crate::lk::{TrackResult, TrackStatus};
+use crate::utils::median::median;
+
+/// How the vectors of one cell are combined.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CellStatistic {
+    /// Component-wise mean.
+    #[default]
+    Mean,
+    /// Component-wise median, robust to a minority of mistracked points.
+    Median,
+}
+
+/// Settings of [`flow_grid`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FlowGridParams {
+    /// Grid of `(columns, rows)` cells the region is divided into.
+    pub cells: (u32, u32),
+    /// Combination of the vectors in a cell.
+    pub statistic: CellStatistic,
+}
+
+impl Default for FlowGridParams {
+    fn default() -> Self {
+        FlowGridParams {
+            cells: (4, 4),
+            statistic: CellStatistic::Mean,
+        }
+    }
+}
+
+/// Per-cell flow vectors, see [`flow_grid`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlowGrid {
+    /// Number of cell columns.
+    pub cols: u32,
+    /// Number of cell rows.
+    pub rows: u32,
+    /// Row-major cell vectors `(dx, dy)`; `(0, 0)` for empty cells.
+    pub vectors: Vec<(f32, f32)>,
+    /// Row-major number of vectors in each cell.
+    pub counts: Vec<u32>,
+}
+
+impl FlowGrid {
+    /// Vector of the cell in column `col` and row `row`, `None` when no
+    /// vector fell into it.
+    pub fn cell(&self, col: u32, row: u32) -> Option<(f32, f32)> {
+        let index = (row * self.cols + col) as usize;
+        (self.counts[index] > 0).then(|| self.vectors[index])
+    }
+}
+
+/// Aggregates flow vectors given as `(position, (dx, dy))` pairs, positions
+/// in region pixels, over the cell grid of a `size` region.
+///
+/// Each vector falls into the cell containing its position. Vectors outside
+/// the region and non-finite vectors are ignored.
+///
+/// # Panics
+/// Panics if the region or the cell grid is empty.
+pub fn flow_grid(
+    size: (u32, u32),
+    flow: impl IntoIterator<Item = ((f32, f32), (f32, f32))>,
+    params: &FlowGridParams,
+) -> FlowGrid {
+    let (cols, rows) = params.cells;
+    assert!(size.0 > 0 && size.1 > 0, "region must not be empty");
+    assert!(cols > 0 && rows > 0, "cell grid must not be empty");
+
+    let cell_width = size.0 as f32 / cols as f32;
+    let cell_height = size.1 as f32 / rows as f32;
+    let mut votes: Vec<(usize, f32, f32)> = flow
+        .into_iter()
+        .filter(|&((x, y), (dx, dy))| {
+            x >= 0.0
+                && y >= 0.0
+                && x < size.0 as f32
+                && y < size.1 as f32
+                && dx.is_finite()
+                && dy.is_finite()
+        })
+        .map(|((x, y), (dx, dy))| {
+            let col = ((x / cell_width) as u32).min(cols - 1);
+            let row = ((y / cell_height) as u32).min(rows - 1);
+            ((row * cols + col) as usize, dx, dy)
+        })
+        .collect();
+    votes.sort_unstable_by_key(|&(cell, _, _)| cell);
+
+    let n_cells = (cols * rows) as usize;
+    let mut vectors = vec![(0.0f32, 0.0f32); n_cells];
+    let mut counts = vec![0u32; n_cells];
+    let mut components = Vec::new();
+    for group in votes.chunk_by(|a, b| a.0 == b.0) {
+        let cell = group[0].0;
+        counts[cell] = group.len() as u32;
+        vectors[cell] = match params.statistic {
+            CellStatistic::Mean => {
+                let n = group.len() as f32;
+                let (sx, sy) = group
+                    .iter()
+                    .fold((0.0f32, 0.0f32), |(sx, sy), &(_, dx, dy)| {
+                        (sx + dx, sy + dy)
+                    });
+                (sx / n, sy / n)
+            }
+            CellStatistic::Median => {
+                components.clear();
+                components.extend(group.iter().map(|&(_, dx, _)| dx));
+                let dx = median(&mut components);
+                components.clear();
+                components.extend(group.iter().map(|&(_, _, dy)| dy));
+                (dx, median(&mut components))
+            }
+        };
+    }
+
+    FlowGrid {
+        cols,
+        rows,
+        vectors,
+        counts,
+    }
+}
+
+/// [`flow_grid`] of one sparse tracking step: every
+/// [`TrackStatus::Tracked`] point votes at its previous position with its
+/// displacement.
+///
+/// # Panics
+/// Panics if `results` does not have one entry per `prev_point`, or as
+/// [`flow_grid`].
+pub fn flow_grid_from_tracks(
+    size: (u32, u32),
+    prev_points: &[(f32, f32)],
+    results: &[TrackResult],
+    params: &FlowGridParams,
+) -> FlowGrid {
+    assert_eq!(
+        prev_points.len(),
+        results.len(),
+        "results must have one entry per prev_point"
+    );
+    let flow = prev_points
+        .iter()
+        .zip(results)
+        .filter(|(_, r)| r.status == TrackStatus::Tracked)
+        .map(|(&(x, y), r)| ((x, y), (r.pos.0 - x, r.pos.1 - y)));
+    flow_grid(size, flow, params)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cells_average_or_take_the_median_of_their_vectors() {
+        // Three vectors in the top-left cell, one of them an outlier, and
+        // one in the bottom-right cell; one vector lies outside the region.
+        let flow = [
+            ((5.0, 5.0), (1.0, 0.0)),
+            ((8.0, 2.0), (2.0, 1.0)),
+            ((1.0, 9.0), (9.0, 8.0)),
+            ((15.0, 15.0), (-1.0, 3.0)),
+            ((25.0, 5.0), (4.0, 4.0)),
+        ];
+        let mut params = FlowGridParams {
+            cells: (2, 2),
+            ..FlowGridParams::default()
+        };
+        let mean = flow_grid((20, 20), flow, &params);
+        assert_eq!(mean.counts, [3, 0, 0, 1]);
+        assert_eq!(mean.cell(0, 0), Some((4.0, 3.0)));
+        assert_eq!(mean.cell(1, 0), None);
+        assert_eq!(mean.cell(1, 1), Some((-1.0, 3.0)));
+
+        params.statistic = CellStatistic::Median;
+        let median = flow_grid((20, 20), flow, &params);
+        assert_eq!(median.cell(0, 0), Some((2.0, 1.0)));
+        assert_eq!(median.counts, mean.counts);
+    }
+}
@@ -0,0 +1,235 @@
+//! Motion-compensated frame differencing.
+//!
+//! With a moving camera a plain frame difference lights up every edge in the
+//! scene. Warping the previous frame by the estimated camera motion first
+//! leaves only what the motion does not explain: independently moving
+//! objects, appearing content and lighting changes.
+
+use image::GrayImage;
+
+use crate::block_matching::BlockMotionField;
+use crate::lk::interpolate;
+
+/// Estimated motion from the previous to the next frame, used by
+/// [`motion_compensated_difference`].
+#[derive(Debug, Clone, Copy)]
+pub enum FrameMotion<'a> {
+    /// Global 2x3 affine transform `[[a, b, tx], [c, d, ty]]` mapping
+    /// previous-frame coordinates `(x, y)` to `(a*x + b*y + tx, c*x + d*y + ty)`
+    /// in the next frame. A pure translation is `[[1, 0, dx], [0, 1, dy]]`.
+    Affine([[f32; 3]; 2]),
+    /// Per-block motion vectors, see [`block_motion`](crate::block_motion).
+    /// Each next-frame pixel takes the vector of the block it lies in.
+    Blocks(&'a BlockMotionField),
+}
+
+/// Result of [`motion_compensated_difference`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct MotionDifference {
+    /// Absolute difference between the next frame and the motion-compensated
+    /// previous frame.
+    pub residual: GrayImage,
+    /// 255 where `residual` exceeds the threshold, 0 elsewhere.
+    pub mask: GrayImage,
+}
+
+/// Warps `prev` by `motion` onto the pixel grid of `next` and returns the
+/// absolute residual together with its thresholded change mask.
+///
+/// Affine motion is sampled bilinearly, block motion moves whole pixels.
+/// Next-frame pixels whose source lies outside `prev` (content that entered
+/// the view) have no prediction; they are reported as unchanged, with a
+/// residual of 0.
+///
+/// # Arguments
+/// * `prev` / `next` - consecutive frames of the same size
+/// * `motion` - estimated motion from `prev` to `next`
+/// * `threshold` - residuals above this value are marked in the mask
+///
+/// # Panics
+/// Panics if the frames differ in size, or if an affine `motion` is not
+/// invertible.
+pub fn motion_compensated_difference(
+    prev: &GrayImage,
+    next: &GrayImage,
+    motion: FrameMotion,
+    threshold: u8,
+) -> MotionDifference {
+    assert_eq!(
+        prev.dimensions(),
+        next.dimensions(),
+        "frames must have the same size"
+    );
+    let (width, height) = next.dimensions();
+    let (max_x, max_y) = ((width - 1) as f32, (height - 1) as f32);
+
+    // Maps a next-frame pixel back to its previous-frame source.
+    let backward = match motion {
+        FrameMotion::Affine(m) => FrameMotion::Affine(invert_affine(m)),
+        blocks => blocks,
+    };
+    let source = |x: u32, y: u32| -> (f32, f32) {
+        match backward {
+            FrameMotion::Affine(m) => {
+                let (x, y) = (x as f32, y as f32);
+                (
+                    m[0][0] * x + m[0][1] * y + m[0][2],
+                    m[1][0] * x + m[1][1] * y + m[1][2],
+                )
+            }
+            FrameMotion::Blocks(field) => {
+                let col = (x / field.block_size).min(field.cols - 1);
+                let row = (y / field.block_size).min(field.rows - 1);
+                let v = field.get(col, row);
+                ((x as i32 - v.dx) as f32, (y as i32 - v.dy) as f32)
+            }
+        }
+    };
+
+    let mut residual = GrayImage::new(width, height);
+    let mut mask = GrayImage::new(width, height);
+    let stride = width as usize;
+    let rows = next
+        .as_raw()
+        .chunks_exact(stride)
+        .zip(residual.chunks_exact_mut(stride))
+        .zip(mask.chunks_exact_mut(stride));
+    for (y, ((next_row, residual_row), mask_row)) in rows.enumerate() {
+        for (x, &value) in next_row.iter().enumerate() {
+            let (sx, sy) = source(x as u32, y as u32);
+            if !(0.0..=max_x).contains(&sx) || !(0.0..=max_y).contains(&sy) {
+                continue;
+            }
+            let predicted = interpolate(prev, sx, sy);
+            let diff = (value as f32 - predicted).abs().round() as u8;
+            residual_row[x] = diff;
+            if diff > threshold {
+                mask_row[x] = 255;
+            }
+        }
+    }
+
+    MotionDifference { residual, mask }
+}
+
+/// Inverse of a 2x3 affine transform.
+fn invert_affine(m: [[f32; 3]; 2]) -> [[f32; 3]; 2] {
+    let [[a, b, tx], [c, d, ty]] = m;
+    let det = a * d - b * c;
+    assert!(
+        det.abs() > f32::EPSILON,
+        "affine transform must be invertible"
+    );
+    let (ia, ib, ic, id) = (d / det, -b / det, -c / det, a / det);
+    [
+        [ia, ib, -(ia * tx + ib * ty)],
+        [ic, id, -(ic * tx + id * ty)],
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block_matching::block_motion;
+    use image::Luma;
+
+    fn textured(w: u32, h: u32) -> GrayImage {
+        GrayImage::from_fn(w, h, |x, y| {
+            let v = 128.0 + 50.0 * (x as f32 * 0.3).sin() + 40.0 * (y as f32 * 0.23).cos();
+            Luma([v as u8])
+        })
+    }
+
+    /// `src` moved by an integer `(dx, dy)`, with a bright square pasted on top.
+    fn moved_with_object(src: &GrayImage, dx: i32, dy: i32) -> GrayImage {
+        GrayImage::from_fn(src.width(), src.height(), |x, y| {
+            if (40..50).contains(&x) && (30..40).contains(&y) {
+                return Luma([255]);
+            }
+            let sx = (x as i32 - dx).clamp(0, src.width() as i32 - 1);
+            let sy = (y as i32 - dy).clamp(0, src.height() as i32 - 1);
+            *src.get_pixel(sx as u32, sy as u32)
+        })
+    }
+
+    fn marked(mask: &GrayImage) -> Vec<(u32, u32)> {
+        mask.enumerate_pixels()
+            .filter(|(_, _, p)| p[0] == 255)
+            .map(|(x, y, _)| (x, y))
+            .collect()
+    }
+
+    #[test]
+    fn affine_compensation_leaves_only_the_object() {
+        let prev = textured(96, 72);
+        let next = moved_with_object(&prev, 3, -2);
+
+        let plain = motion_compensated_difference(
+            &prev,
+            &next,
+            FrameMotion::Affine([[1.0, 0.0, 0.0], [0.0, 1.0, 0.0]]),
+            20,
+        );
+        assert!(marked(&plain.mask).len() > 500, "uncompensated edges show");
+
+        let diff = motion_compensated_difference(
+            &prev,
+            &next,
+            FrameMotion::Affine([[1.0, 0.0, 3.0], [0.0, 1.0, -2.0]]),
+            20,
+        );
+        let changed = marked(&diff.mask);
+        assert!(!changed.is_empty());
+        assert!(
+            changed
+                .iter()
+                .all(|&(x, y)| (40..50).contains(&x) && (30..40).contains(&y)),
+            "only the object changes"
+        );
+        // Pixels that entered the view have no prediction.
+        assert_eq!(diff.residual.get_pixel(0, 10)[0], 0);
+    }
+
+    #[test]
+    fn block_compensation_matches_affine_translation() {
+        let prev = textured(96, 72);
+        let next = moved_with_object(&prev, 3, -2);
+        let field = block_motion(
+            std::slice::from_ref(&prev),
+            std::slice::from_ref(&next),
+            8,
+            4,
+        );
+
+        let diff = motion_compensated_difference(&prev, &next, FrameMotion::Blocks(&field), 20);
+        // Blocks on the frame border cannot follow the motion out of the frame,
+        // so only the inner blocks are checked.
+        let changed: Vec<_> = marked(&diff.mask)
+            .into_iter()
+            .filter(|&(x, y)| (8..88).contains(&x) && (8..64).contains(&y))
+            .collect();
+        assert!(!changed.is_empty());
+        assert!(
+            changed
+                .iter()
+                .all(|&(x, y)| (32..56).contains(&x) && (24..48).contains(&y)),
+            "changes stay around the object's blocks"
+        );
+    }
+
+    #[test]
+    fn invert_affine_round_trips() {
+        let m = [[1.1, -0.2, 5.0], [0.3, 0.9, -4.0]];
+        let inv = invert_affine(m);
+        let (x, y) = (12.0f32, -7.0f32);
+        let (u, v) = (
+            m[0][0] * x + m[0][1] * y + m[0][2],
+            m[1][0] * x + m[1][1] * y + m[1][2],
+        );
+        let back = (
+            inv[0][0] * u + inv[0][1] * v + inv[0][2],
+            inv[1][0] * u + inv[1][1] * v + inv[1][2],
+        );
+        assert!((back.0 - x).abs() < 1e-4 && (back.1 - y).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,126 @@
+//! Whole-frame translation estimate for pre-aligning tracking.
+//!
+//! Handheld footage can shake by more pixels between two frames than the
+//! pyramid of the tracker reaches. [`estimate_global_shift`] matches the
+//! frames as a whole, coarse to fine, to find the dominant translation;
+//! seeding every point with it ([`LkFlags::PREALIGN`](crate::LkFlags::PREALIGN))
+//! leaves LK only the residual motion to solve.
+
+use image::GrayImage;
+
+use crate::pyramid::validate_pyramid_pair;
+
+/// Search radius at the coarsest level, in that level's pixels.
+const SEARCH_RANGE: i32 = 32;
+
+/// Pixels compared per candidate shift; the overlap is subsampled on a
+/// regular grid to stay near this count.
+const SAMPLES: u32 = 2048;
+
+/// Estimates the translation `(dx, dy)` in level-0 pixels that best aligns
+/// `next` with `prev`, so that `prev(x, y)` matches `next(x + dx, y + dy)`.
+///
+/// The coarsest level is searched exhaustively within 32 of its pixels, and
+/// each finer level refines the doubled shift by ±1 pixel, so the reach is
+/// `32 * 2^(levels - 1)` level-0 pixels. Shifts are limited to half the
+/// frame on each axis. Candidates are scored by the mean absolute difference
+/// over the subsampled overlap; among equal scores the shorter shift wins, so
+/// flat frames report no motion. Allocation-free.
+///
+/// # Panics
+/// Panics if the pyramids fail [`validate_pyramid_pair`].
+pub fn estimate_global_shift(prev_pyramid: &[GrayImage], next_pyramid: &[GrayImage]) -> (i32, i32) {
+    if let Err(error) = validate_pyramid_pair(prev_pyramid, next_pyramid) {
+        panic!("{error}");
+    }
+    let (mut dx, mut dy) = (0i32, 0i32);
+    for level in (0..prev_pyramid.len()).rev() {
+        let range = if level + 1 == prev_pyramid.len() {
+            SEARCH_RANGE
+        } else {
+            dx *= 2;
+            dy *= 2;
+            1
+        };
+        (dx, dy) = best_shift(&prev_pyramid[level], &next_pyramid[level], (dx, dy), range);
+    }
+    (dx, dy)
+}
+
+/// Shift within `range` of `center` with the lowest mean absolute difference.
+fn best_shift(prev: &GrayImage, next: &GrayImage, center: (i32, i32), range: i32) -> (i32, i32) {
+    let (width, height) = (prev.width() as i32, prev.height() as i32);
+    let (max_x, max_y) = (width / 2, height / 2);
+    let mut best = (center, f32::INFINITY);
+    for dy in center.1 - range..=center.1 + range {
+        for dx in center.0 - range..=center.0 + range {
+            if dx.abs() > max_x || dy.abs() > max_y {
+                continue;
+            }
+            let cost = mean_abs_difference(prev, next, dx, dy);
+            let (best_shift, best_cost) = best;
+            let shorter = dx * dx + dy * dy < best_shift.0.pow(2) + best_shift.1.pow(2);
+            if cost < best_cost || (cost == best_cost && shorter) {
+                best = ((dx, dy), cost);
+            }
+        }
+    }
+    best.0
+}
+
+/// Mean absolute difference between `prev(x, y)` and `next(x + dx, y + dy)`
+/// over a grid of roughly [`SAMPLES`] points of their overlap.
+fn mean_abs_difference(prev: &GrayImage, next: &GrayImage, dx: i32, dy: i32) -> f32 {
+    let (width, height) = (prev.width() as i32, prev.height() as i32);
+    let (x0, x1) = (0.max(-dx), width.min(width - dx));
+    let (y0, y1) = (0.max(-dy), height.min(height - dy));
+    if x0 >= x1 || y0 >= y1 {
+        return f32::INFINITY;
+    }
+    let area = ((x1 - x0) * (y1 - y0)) as u32;
+    let step = ((area / SAMPLES) as f32).sqrt().max(1.0) as usize;
+
+    let (prev, next) = (prev.as_raw(), next.as_raw());
+    let (mut sum, mut count) = (0u64, 0u64);
+    for y in (y0..y1).step_by(step) {
+        let prev_row = y * width;
+        let next_row = (y + dy) * width + dx;
+        for x in (x0..x1).step_by(step) {
+            let (p, n) = (prev[(prev_row + x) as usize], next[(next_row + x) as usize]);
+            sum += p.abs_diff(n) as u64;
+            count += 1;
+        }
+    }
+    sum as f32 / count as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pyramid::build_pyramid;
+
+    #[test]
+    fn finds_shifts_beyond_the_coarse_search_range() {
+        // Random values on 4x4 blocks, which survive the pyramid's smoothing.
+        let textured = GrayImage::from_fn(200, 160, |x, y| {
+            let mut h = (x / 4).wrapping_mul(73_856_093) ^ (y / 4).wrapping_mul(19_349_663);
+            h ^= h >> 13;
+            h = h.wrapping_mul(0x5bd1_e995);
+            image::Luma([(h >> 24) as u8])
+        });
+        let shifted = |sx: i32, sy: i32| {
+            GrayImage::from_fn(200, 160, |x, y| {
+                let (u, v) = (x as i32 - sx, y as i32 - sy);
+                *textured.get_pixel(u.clamp(0, 199) as u32, v.clamp(0, 159) as u32)
+            })
+        };
+        let prev = build_pyramid(&textured, 3);
+        for shift in [(0, 0), (37, -22), (-45, 9)] {
+            let next = build_pyramid(&shifted(shift.0, shift.1), 3);
+            assert_eq!(estimate_global_shift(&prev, &next), shift);
+        }
+
+        let flat = build_pyramid(&GrayImage::from_pixel(64, 64, image::Luma([90])), 2);
+        assert_eq!(estimate_global_shift(&flat, &flat), (0, 0));
+    }
+}
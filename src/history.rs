@@ -0,0 +1,281 @@
+//! Ring buffer of recent tracking results, indexed by frame.
+//!
+//! UIs that scrub back through recent video need the tracking of frames
+//! already gone. [`ResultHistory`] keeps the results of the last few
+//! tracking steps, finds frames by index or timestamp, and composes the
+//! steps between two frames into the flow of every track that survived all
+//! of them.
+
+use std::collections::{HashMap, VecDeque};
+
+use crate::lk::{TrackResult, TrackStatus};
+
+/// Results of the tracking step into one frame, as stored by
+/// [`ResultHistory::record`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrameResults {
+    /// Caller-assigned index of the frame, increasing along the video.
+    pub frame: u64,
+    /// Capture time of the frame in milliseconds.
+    pub timestamp_ms: f64,
+    /// Track ID of every point.
+    pub ids: Vec<u64>,
+    /// Positions of the points in the previously recorded frame.
+    pub prev_points: Vec<(f32, f32)>,
+    /// Tracking results into this frame, one per point.
+    pub results: Vec<TrackResult>,
+}
+
+/// Motion of one track between two frames, see
+/// [`ResultHistory::flow_between`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ComposedFlow {
+    /// Track ID.
+    pub id: u64,
+    /// Position in the first frame.
+    pub from: (f32, f32),
+    /// Position in the second frame.
+    pub to: (f32, f32),
+}
+
+/// The last `capacity` tracking steps, oldest first.
+///
+/// Record every step with [`record`](Self::record), giving each point a
+/// track ID (see [`TrackHistory`](crate::TrackHistory) for the same
+/// convention): the IDs link the steps, since the point lists change as
+/// tracks are dropped and added. Recording beyond the capacity evicts the
+/// oldest step.
+#[derive(Debug, Clone)]
+pub struct ResultHistory {
+    capacity: usize,
+    frames: VecDeque<FrameResults>,
+}
+
+impl ResultHistory {
+    /// Creates an empty history of the last `capacity` steps.
+    ///
+    /// # Panics
+    /// Panics if `capacity` is zero.
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "capacity must be non-zero");
+        ResultHistory {
+            capacity,
+            frames: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// Most steps kept.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Number of steps kept.
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Whether no step is kept.
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Drops every step.
+    pub fn clear(&mut self) {
+        self.frames.clear();
+    }
+
+    /// Appends the step from the previously recorded frame into `frame`:
+    /// `prev_points[i]` of track `ids[i]` was tracked to `results[i]`.
+    ///
+    /// # Panics
+    /// Panics if `ids`, `prev_points` and `results` differ in length, or if
+    /// `frame` does not follow the last recorded frame.
+    pub fn record(
+        &mut self,
+        frame: u64,
+        timestamp_ms: f64,
+        ids: &[u64],
+        prev_points: &[(f32, f32)],
+        results: &[TrackResult],
+    ) {
+        assert!(
+            ids.len() == prev_points.len() && ids.len() == results.len(),
+            "ids, prev_points and results must have the same length"
+        );
+        if let Some(last) = self.frames.back() {
+            assert!(
+                frame > last.frame,
+                "frame must follow the last recorded frame"
+            );
+        }
+        if self.frames.len() == self.capacity {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(FrameResults {
+            frame,
+            timestamp_ms,
+            ids: ids.to_vec(),
+            prev_points: prev_points.to_vec(),
+            results: results.to_vec(),
+        });
+    }
+
+    /// Every kept step, oldest first.
+    pub fn frames(&self) -> impl ExactSizeIterator<Item = &FrameResults> {
+        self.frames.iter()
+    }
+
+    /// The step into `frame`, if kept.
+    pub fn get(&self, frame: u64) -> Option<&FrameResults> {
+        self.position(frame).map(|i| &self.frames[i])
+    }
+
+    /// The latest kept step whose frame was captured at or before
+    /// `timestamp_ms`.
+    pub fn at_time(&self, timestamp_ms: f64) -> Option<&FrameResults> {
+        let end = self
+            .frames
+            .partition_point(|record| record.timestamp_ms <= timestamp_ms);
+        end.checked_sub(1).map(|i| &self.frames[i])
+    }
+
+    /// Flow of every track from frame `from` to frame `to`, composed from
+    /// the steps in between.
+    ///
+    /// A track is included when it was [`TrackStatus::Tracked`] in every
+    /// step after `from` up to `to`; its displacements add up, so the steps
+    /// may start from refined positions. Composing backwards (`to` before
+    /// `from`) swaps the positions.
+    ///
+    /// # Returns
+    /// `None` when either frame is not kept; the steps into the oldest kept
+    /// frame are gone, so its own results only serve as a destination.
+    pub fn flow_between(&self, from: u64, to: u64) -> Option<Vec<ComposedFlow>> {
+        if to < from {
+            let mut flows = self.flow_between(to, from)?;
+            for flow in &mut flows {
+                std::mem::swap(&mut flow.from, &mut flow.to);
+            }
+            return Some(flows);
+        }
+        let (first, last) = (self.position(from)?, self.position(to)?);
+        let Some(first_step) = self.frames.range(first + 1..=last).next() else {
+            // The same frame: every track that reached it stays put.
+            let record = &self.frames[first];
+            return Some(
+                record
+                    .ids
+                    .iter()
+                    .zip(&record.results)
+                    .filter(|(_, r)| r.status == TrackStatus::Tracked)
+                    .map(|(&id, r)| ComposedFlow {
+                        id,
+                        from: r.pos,
+                        to: r.pos,
+                    })
+                    .collect(),
+            );
+        };
+
+        let mut flows: Vec<ComposedFlow> = first_step
+            .ids
+            .iter()
+            .zip(&first_step.prev_points)
+            .zip(&first_step.results)
+            .filter(|(_, r)| r.status == TrackStatus::Tracked)
+            .map(|((&id, &prev), r)| ComposedFlow {
+                id,
+                from: prev,
+                to: r.pos,
+            })
+            .collect();
+        for record in self.frames.range(first + 2..=last) {
+            let index: HashMap<u64, usize> = record
+                .ids
+                .iter()
+                .enumerate()
+                .map(|(i, &id)| (id, i))
+                .collect();
+            flows.retain_mut(|flow| {
+                let Some(&i) = index.get(&flow.id) else {
+                    return false;
+                };
+                let (prev, result) = (record.prev_points[i], &record.results[i]);
+                flow.to.0 += result.pos.0 - prev.0;
+                flow.to.1 += result.pos.1 - prev.1;
+                result.status == TrackStatus::Tracked
+            });
+        }
+        Some(flows)
+    }
+
+    fn position(&self, frame: u64) -> Option<usize> {
+        self.frames
+            .binary_search_by_key(&frame, |record| record.frame)
+            .ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tracked(pos: (f32, f32)) -> TrackResult {
+        TrackResult::new(pos, TrackStatus::Tracked, 0.0)
+    }
+
+    #[test]
+    fn composes_steps_and_evicts_the_oldest() {
+        let mut history = ResultHistory::new(3);
+        // Track 1 moves +1 px in x per frame, track 2 is lost in frame 12
+        // and track 3 joins in the step into frame 11.
+        history.record(
+            10,
+            0.0,
+            &[1, 2],
+            &[(0.0, 0.0); 2],
+            &[tracked((0.0, 0.0)); 2],
+        );
+        history.record(
+            11,
+            33.0,
+            &[1, 2, 3],
+            &[(0.0, 0.0), (5.0, 5.0), (9.0, 9.0)],
+            &[
+                tracked((1.0, 0.0)),
+                tracked((5.0, 6.0)),
+                tracked((9.0, 8.0)),
+            ],
+        );
+        let mut lost = tracked((5.0, 7.0));
+        lost.status = TrackStatus::Diverged;
+        history.record(
+            12,
+            66.0,
+            &[1, 2, 3],
+            // Track 1 was refined to (1.1, 0.0) before this step.
+            &[(1.1, 0.0), (5.0, 6.0), (9.0, 8.0)],
+            &[tracked((2.1, 0.0)), lost, tracked((9.0, 7.0))],
+        );
+
+        let flows = history.flow_between(10, 12).unwrap();
+        assert_eq!(flows.len(), 2);
+        assert_eq!(flows[0].id, 1);
+        assert_eq!(flows[0].from, (0.0, 0.0));
+        assert!((flows[0].to.0 - 2.0).abs() < 1e-6 && flows[0].to.1 == 0.0);
+        assert_eq!((flows[1].id, flows[1].to), (3, (9.0, 7.0)));
+        let back = history.flow_between(12, 11).unwrap();
+        assert_eq!((back[0].from, back[0].to), ((2.1, 0.0), (1.1, 0.0)));
+
+        assert_eq!(history.at_time(50.0).map(|r| r.frame), Some(11));
+        assert!(history.at_time(-1.0).is_none());
+
+        history.record(13, 99.0, &[1], &[(2.1, 0.0)], &[tracked((3.1, 0.0))]);
+        assert_eq!(history.len(), 3);
+        assert!(history.get(10).is_none());
+        assert!(history.flow_between(10, 13).is_none());
+        let flows = history.flow_between(11, 13).unwrap();
+        assert_eq!(flows.len(), 1);
+        assert!((flows[0].to.0 - 3.1).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,245 @@
+//! Histogram-of-optical-flow (HOF) descriptors over a cell grid.
+//!
+//! Classical action-recognition and anomaly-detection pipelines summarize the
+//! motion in a region as a histogram of flow directions per spatial cell.
+//! [`hof_descriptor`] builds one from any set of positioned flow vectors;
+//! [`hof_from_tracks`] and [`hof_from_block_motion`] feed it the crate's
+//! sparse tracks and block-matching field.
+
+use std::f32::consts::TAU;
+
+use crate::block_matching::BlockMotionField;
+use crate::lk::{TrackResult, TrackStatus};
+
+/// Settings of [`hof_descriptor`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HofParams {
+    /// Grid of `(columns, rows)` cells the region is divided into.
+    pub cells: (u32, u32),
+    /// Orientation bins over the full circle, not counting the no-motion bin.
+    pub bins: usize,
+    /// Flow vectors no longer than this (pixels) vote for the no-motion bin
+    /// instead of an orientation.
+    pub min_magnitude: f32,
+    /// Scale every cell's histogram to sum to 1 (cells without votes stay
+    /// zero).
+    pub normalize: bool,
+}
+
+impl Default for HofParams {
+    fn default() -> Self {
+        HofParams {
+            cells: (4, 4),
+            bins: 8,
+            min_magnitude: 0.4,
+            normalize: true,
+        }
+    }
+}
+
+/// Per-cell flow histograms, see [`hof_descriptor`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct HofDescriptor {
+    /// Number of cell columns.
+    pub cols: u32,
+    /// Number of cell rows.
+    pub rows: u32,
+    /// Entries per cell: the orientation bins followed by the no-motion bin.
+    pub bins_per_cell: usize,
+    /// Row-major cell histograms, `cols * rows * bins_per_cell` entries; the
+    /// flat vector is the descriptor fed to a classifier.
+    pub values: Vec<f32>,
+}
+
+impl HofDescriptor {
+    /// Histogram of the cell in column `col` and row `row`.
+    pub fn cell(&self, col: u32, row: u32) -> &[f32] {
+        let start = (row * self.cols + col) as usize * self.bins_per_cell;
+        &self.values[start..start + self.bins_per_cell]
+    }
+}
+
+/// Builds a HOF descriptor of a `size` region from flow vectors given as
+/// `(position, (dx, dy))` pairs, positions in region pixels.
+///
+/// Bin `k` is centered on the direction `(k + 0.5) * 360 / bins` degrees,
+/// measured from +x towards +y (image down). A vector votes its magnitude,
+/// split linearly between the two bins nearest its direction; vectors no
+/// longer than [`HofParams::min_magnitude`] vote 1 for the no-motion bin, the
+/// convention of improved dense trajectories. Vectors outside the region are
+/// ignored.
+///
+/// # Panics
+/// Panics if the region, the cell grid or `bins` is empty.
+pub fn hof_descriptor(
+    size: (u32, u32),
+    flow: impl IntoIterator<Item = ((f32, f32), (f32, f32))>,
+    params: &HofParams,
+) -> HofDescriptor {
+    let (cols, rows) = params.cells;
+    assert!(size.0 > 0 && size.1 > 0, "region must not be empty");
+    assert!(cols > 0 && rows > 0, "cell grid must not be empty");
+    assert!(params.bins > 0, "bins must be non-zero");
+
+    let bins_per_cell = params.bins + 1;
+    let mut values = vec![0.0f32; (cols * rows) as usize * bins_per_cell];
+    let cell_width = size.0 as f32 / cols as f32;
+    let cell_height = size.1 as f32 / rows as f32;
+
+    for ((x, y), (dx, dy)) in flow {
+        if !(x >= 0.0 && y >= 0.0 && x < size.0 as f32 && y < size.1 as f32) {
+            continue;
+        }
+        let col = ((x / cell_width) as u32).min(cols - 1);
+        let row = ((y / cell_height) as u32).min(rows - 1);
+        let hist = &mut values[(row * cols + col) as usize * bins_per_cell..][..bins_per_cell];
+
+        let magnitude = dx.hypot(dy);
+        if !magnitude.is_finite() {
+            continue;
+        }
+        if magnitude <= params.min_magnitude {
+            hist[params.bins] += 1.0;
+            continue;
+        }
+        let t = dy.atan2(dx).rem_euclid(TAU) / TAU * params.bins as f32 - 0.5;
+        let lower = t.floor();
+        let frac = t - lower;
+        let lower = (lower as isize).rem_euclid(params.bins as isize) as usize;
+        hist[lower] += magnitude * (1.0 - frac);
+        hist[(lower + 1) % params.bins] += magnitude * frac;
+    }
+
+    if params.normalize {
+        for hist in values.chunks_exact_mut(bins_per_cell) {
+            let sum: f32 = hist.iter().sum();
+            if sum > 0.0 {
+                hist.iter_mut().for_each(|v| *v /= sum);
+            }
+        }
+    }
+
+    HofDescriptor {
+        cols,
+        rows,
+        bins_per_cell,
+        values,
+    }
+}
+
+/// [`hof_descriptor`] of one sparse tracking step: every
+/// [`TrackStatus::Tracked`] point votes at its previous position with its
+/// displacement.
+///
+/// # Panics
+/// Panics if `results` does not have one entry per `prev_point`, or as
+/// [`hof_descriptor`].
+pub fn hof_from_tracks(
+    size: (u32, u32),
+    prev_points: &[(f32, f32)],
+    results: &[TrackResult],
+    params: &HofParams,
+) -> HofDescriptor {
+    assert_eq!(
+        prev_points.len(),
+        results.len(),
+        "results must have one entry per prev_point"
+    );
+    let flow = prev_points
+        .iter()
+        .zip(results)
+        .filter(|(_, r)| r.status == TrackStatus::Tracked)
+        .map(|(&(x, y), r)| ((x, y), (r.pos.0 - x, r.pos.1 - y)));
+    hof_descriptor(size, flow, params)
+}
+
+/// [`hof_descriptor`] of a dense [`block_motion`](crate::block_motion) field
+/// over a `size` frame: every block votes at its center with its vector.
+///
+/// # Panics
+/// As [`hof_descriptor`].
+pub fn hof_from_block_motion(
+    size: (u32, u32),
+    field: &BlockMotionField,
+    params: &HofParams,
+) -> HofDescriptor {
+    let half = field.block_size as f32 / 2.0;
+    let flow = (0..field.rows).flat_map(|row| {
+        (0..field.cols).map(move |col| {
+            let motion = field.get(col, row);
+            let center = (
+                (col * field.block_size) as f32 + half,
+                (row * field.block_size) as f32 + half,
+            );
+            (center, (motion.dx as f32, motion.dy as f32))
+        })
+    });
+    hof_descriptor(size, flow, params)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block_matching::BlockMotion;
+
+    #[test]
+    fn cells_histogram_their_flow_directions() {
+        let params = HofParams {
+            cells: (2, 1),
+            bins: 4,
+            min_magnitude: 0.5,
+            normalize: false,
+        };
+        // Bins are centered on 45, 135, 225 and 315 degrees. Left cell:
+        // straight down (90 degrees, halfway between bins 0 and 1) with
+        // magnitude 2, plus one static vector. Right cell: along +x, halfway
+        // between bins 3 and 0. The last vector lies outside the region.
+        let flow = [
+            ((10.0, 10.0), (0.0, 2.0)),
+            ((12.0, 10.0), (0.1, 0.0)),
+            ((30.0, 10.0), (3.0, 0.0)),
+            ((50.0, 10.0), (3.0, 0.0)),
+        ];
+        let hof = hof_descriptor((40, 20), flow, &params);
+        assert_eq!(hof.values.len(), 2 * 5);
+        assert_eq!(hof.cell(0, 0), [1.0, 1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(hof.cell(1, 0), [1.5, 0.0, 0.0, 1.5, 0.0]);
+
+        let normalized = hof_descriptor(
+            (40, 20),
+            flow,
+            &HofParams {
+                normalize: true,
+                ..params
+            },
+        );
+        assert_eq!(normalized.cell(1, 0), [0.5, 0.0, 0.0, 0.5, 0.0]);
+
+        // A block field moving up-left everywhere fills only the bin at 225
+        // degrees of every cell.
+        let field = BlockMotionField {
+            block_size: 8,
+            cols: 4,
+            rows: 2,
+            vectors: vec![
+                BlockMotion {
+                    dx: -2,
+                    dy: -2,
+                    sad: 0
+                };
+                8
+            ],
+        };
+        let hof = hof_from_block_motion(
+            (32, 16),
+            &field,
+            &HofParams {
+                normalize: true,
+                ..params
+            },
+        );
+        for cell in hof.values.chunks_exact(hof.bins_per_cell) {
+            assert!((cell[2] - 1.0).abs() < 1e-6, "{cell:?}");
+        }
+    }
+}
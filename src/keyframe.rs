@@ -0,0 +1,175 @@
+//! Keyframe-based tracking.
+//!
+//! Frame-to-frame tracking chains one registration per frame, so its error
+//! grows with the age of a track. [`KeyframeTracker`] instead tracks every
+//! point from a fixed keyframe straight into the current frame: the error
+//! stays that of a single registration, and every result relates the current
+//! frame to one well-defined reference, which is what a relative pose or
+//! homography between two views needs. When too few keyframe points can still
+//! be found, the current frame is promoted to the new keyframe.
+
+use image::GrayImage;
+
+use crate::lk::{DEFAULT_MIN_EIGEN_THRESHOLD, TrackResult, TrackStatus, TrackerContext};
+
+/// Settings of a [`KeyframeTracker`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeyframeParams {
+    /// Pyramid levels of the keyframe and the tracked frames.
+    pub levels: usize,
+    /// Side of the tracking window (odd).
+    pub window_size: usize,
+    /// Max iterations per pyramid level.
+    pub max_iterations: usize,
+    /// See [`DEFAULT_MIN_EIGEN_THRESHOLD`].
+    pub min_eigen_threshold: f32,
+    /// Fraction of the keyframe points that must still be
+    /// [`TrackStatus::Tracked`]; below it the current frame becomes the new
+    /// keyframe.
+    pub min_overlap: f32,
+}
+
+impl Default for KeyframeParams {
+    fn default() -> Self {
+        KeyframeParams {
+            levels: 4,
+            window_size: 21,
+            max_iterations: 30,
+            min_eigen_threshold: DEFAULT_MIN_EIGEN_THRESHOLD,
+            min_overlap: 0.6,
+        }
+    }
+}
+
+/// Tracks a point set from the last keyframe into each new frame, promoting
+/// keyframes automatically when the overlap drops.
+///
+/// Each [`track`](Self::track) call returns one result per keyframe point (see
+/// [`keyframe_points`](Self::keyframe_points)). The last tracked position of a
+/// point seeds the next search, so a point that was lost in one frame (e.g.
+/// briefly occluded) is looked for again in the next. On promotion the points
+/// tracked into the promoting frame become the new keyframe points, in order;
+/// lost points are dropped. Call [`set_keyframe`](Self::set_keyframe) to
+/// start over with a fresh point set, e.g. after re-detection.
+///
+/// Like [`TrackerContext`], tracking is allocation-free in steady state;
+/// promotion rebuilds the keyframe pyramid in place.
+#[derive(Default)]
+pub struct KeyframeTracker {
+    params: KeyframeParams,
+    context: TrackerContext,
+    keyframe_points: Vec<(f32, f32)>,
+    positions: Vec<(f32, f32)>,
+    results: Vec<TrackResult>,
+    has_keyframe: bool,
+    promoted: bool,
+    frames_since_keyframe: usize,
+}
+
+impl KeyframeTracker {
+    /// Creates a tracker without a keyframe; call
+    /// [`set_keyframe`](Self::set_keyframe) before tracking.
+    pub fn new(params: KeyframeParams) -> Self {
+        KeyframeTracker {
+            params,
+            ..Self::default()
+        }
+    }
+
+    /// Makes `image` the keyframe, with `points` (level-0 coordinates) as the
+    /// points to track from it.
+    pub fn set_keyframe(&mut self, image: &GrayImage, points: &[(f32, f32)]) {
+        self.context.prepare_prev(image, self.params.levels);
+        self.keyframe_points.clear();
+        self.keyframe_points.extend_from_slice(points);
+        self.positions.clear();
+        self.positions.extend_from_slice(points);
+        self.has_keyframe = true;
+        self.frames_since_keyframe = 0;
+    }
+
+    /// Tracks the keyframe points into `image`.
+    ///
+    /// If fewer than [`KeyframeParams::min_overlap`] of them are tracked,
+    /// `image` is promoted to the keyframe afterwards; the returned results
+    /// still refer to the previous keyframe's points.
+    ///
+    /// # Panics
+    /// Panics if no keyframe has been set.
+    pub fn track(&mut self, image: &GrayImage) -> &[TrackResult] {
+        assert!(self.has_keyframe, "set_keyframe must be called first");
+        let KeyframeParams {
+            levels,
+            window_size,
+            max_iterations,
+            min_eigen_threshold,
+            min_overlap,
+        } = self.params;
+
+        self.context.prepare_next(image, levels);
+        let results = self.context.track(
+            &self.keyframe_points,
+            Some(&self.positions),
+            window_size,
+            max_iterations,
+            min_eigen_threshold,
+        );
+        self.results.clear();
+        self.results.extend_from_slice(results);
+        self.frames_since_keyframe += 1;
+
+        for (position, result) in self.positions.iter_mut().zip(&self.results) {
+            if result.status == TrackStatus::Tracked {
+                *position = result.pos;
+            }
+        }
+
+        self.promoted = self.overlap() < min_overlap;
+        if self.promoted {
+            self.context.prepare_prev(image, levels);
+            self.keyframe_points.clear();
+            self.keyframe_points.extend(
+                self.results
+                    .iter()
+                    .filter(|r| r.status == TrackStatus::Tracked)
+                    .map(|r| r.pos),
+            );
+            self.positions.clear();
+            self.positions.extend_from_slice(&self.keyframe_points);
+            self.frames_since_keyframe = 0;
+        }
+        &self.results
+    }
+
+    /// Fraction of the keyframe points tracked by the last
+    /// [`track`](Self::track) call, 0 when there were none.
+    pub fn overlap(&self) -> f32 {
+        let tracked = self
+            .results
+            .iter()
+            .filter(|r| r.status == TrackStatus::Tracked)
+            .count();
+        tracked as f32 / self.results.len().max(1) as f32
+    }
+
+    /// Whether the last [`track`](Self::track) call promoted its frame to the
+    /// keyframe.
+    pub fn promoted(&self) -> bool {
+        self.promoted
+    }
+
+    /// The points of the current keyframe, in keyframe coordinates.
+    pub fn keyframe_points(&self) -> &[(f32, f32)] {
+        &self.keyframe_points
+    }
+
+    /// The current keyframe's pyramid.
+    pub fn keyframe_pyramid(&self) -> &[GrayImage] {
+        self.context.prev_pyramid()
+    }
+
+    /// Frames tracked since the current keyframe was set.
+    pub fn frames_since_keyframe(&self) -> usize {
+        self.frames_since_keyframe
+    }
+}
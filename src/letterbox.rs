@@ -0,0 +1,210 @@
+//! Pinning the processing resolution by letterboxing.
+//!
+//! Cameras on different devices deliver different frame sizes, and with them
+//! pyramid level sizes and memory use vary. [`Letterbox`] fits every frame
+//! into one fixed canvas, scaled uniformly and centered with padding, so all
+//! buffers downstream have the same size on every device (which keeps Wasm
+//! memory predictable), and maps coordinates between frame and canvas.
+
+use image::GrayImage;
+
+use crate::lk::{TrackResult, TrackStatus};
+
+/// Uniform fit of `width` x `height` frames into a fixed canvas, preserving
+/// the aspect ratio.
+///
+/// The scaled frame is centered on the canvas; the bars on either side are
+/// filled by repeating the frame's edge pixels, so the border adds no
+/// artificial corners for detection to pick up. Choosing canvas dimensions
+/// divisible by `2^(levels - 1)` gives pyramid levels that halve exactly.
+///
+/// Coordinates follow the crate's pixel-center convention (see
+/// [`TrackResult`]).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Letterbox {
+    original: (u32, u32),
+    canvas: (u32, u32),
+    content: (u32, u32),
+    offset: (u32, u32),
+}
+
+impl Letterbox {
+    /// Fits `width` x `height` frames into a `canvas_width` x
+    /// `canvas_height` canvas, shrinking or enlarging them as needed.
+    ///
+    /// # Panics
+    /// Panics if a dimension is zero.
+    pub fn new(width: u32, height: u32, canvas_width: u32, canvas_height: u32) -> Self {
+        assert!(width > 0 && height > 0, "frame must be non-empty");
+        assert!(
+            canvas_width > 0 && canvas_height > 0,
+            "canvas must be non-empty"
+        );
+        let scale = (canvas_width as f64 / width as f64).min(canvas_height as f64 / height as f64);
+        let content = (
+            ((width as f64 * scale).round() as u32).clamp(1, canvas_width),
+            ((height as f64 * scale).round() as u32).clamp(1, canvas_height),
+        );
+        Letterbox {
+            original: (width, height),
+            canvas: (canvas_width, canvas_height),
+            content,
+            offset: (
+                (canvas_width - content.0) / 2,
+                (canvas_height - content.1) / 2,
+            ),
+        }
+    }
+
+    /// Size of the original frames.
+    pub fn original_size(&self) -> (u32, u32) {
+        self.original
+    }
+
+    /// Size of the canvas.
+    pub fn canvas_size(&self) -> (u32, u32) {
+        self.canvas
+    }
+
+    /// Canvas area covered by the frame, as `(x, y, width, height)`; the rest
+    /// is padding.
+    pub fn content_rect(&self) -> (u32, u32, u32, u32) {
+        (self.offset.0, self.offset.1, self.content.0, self.content.1)
+    }
+
+    /// Per-axis factor from original to canvas coordinates.
+    pub fn factor(&self) -> (f32, f32) {
+        (
+            self.content.0 as f32 / self.original.0 as f32,
+            self.content.1 as f32 / self.original.1 as f32,
+        )
+    }
+
+    /// Places `image` on a new canvas, see [`apply_into`](Self::apply_into).
+    ///
+    /// # Panics
+    /// Panics if `image` is not of the original size.
+    pub fn apply(&self, image: &GrayImage) -> GrayImage {
+        let mut canvas = GrayImage::new(self.canvas.0, self.canvas.1);
+        self.apply_into(image, &mut canvas);
+        canvas
+    }
+
+    /// Places `image`, resampled bilinearly, on `canvas` with edge-repeating
+    /// padding. `canvas` is reallocated only when it does not have the canvas
+    /// size, so steady-state calls are allocation-free.
+    ///
+    /// # Panics
+    /// Panics if `image` is not of the original size.
+    pub fn apply_into(&self, image: &GrayImage, canvas: &mut GrayImage) {
+        assert_eq!(
+            image.dimensions(),
+            self.original,
+            "image must have the original size"
+        );
+        if canvas.dimensions() != self.canvas {
+            *canvas = GrayImage::new(self.canvas.0, self.canvas.1);
+        }
+        let (width, height) = self.original;
+        let (max_x, max_y) = ((width - 1) as f32, (height - 1) as f32);
+        let data = image.as_raw();
+        let stride = width as usize;
+        for (x, y, pixel) in canvas.enumerate_pixels_mut() {
+            // Clamping the source position repeats the edges into the bars.
+            let (sx, sy) = self.to_original((x as f32, y as f32));
+            let (sx, sy) = (sx.clamp(0.0, max_x), sy.clamp(0.0, max_y));
+            let (x0, y0) = (sx as usize, sy as usize);
+            let (x1, y1) = ((x0 + 1).min(stride - 1), (y0 + 1).min(height as usize - 1));
+            let (fx, fy) = (sx - x0 as f32, sy - y0 as f32);
+            let at = |x: usize, y: usize| data[y * stride + x] as f32;
+            let top = at(x0, y0) + fx * (at(x1, y0) - at(x0, y0));
+            let bottom = at(x0, y1) + fx * (at(x1, y1) - at(x0, y1));
+            pixel[0] = (top + fy * (bottom - top)).round() as u8;
+        }
+    }
+
+    /// Maps an original-frame point to canvas coordinates.
+    pub fn to_canvas(&self, (x, y): (f32, f32)) -> (f32, f32) {
+        let (fx, fy) = self.factor();
+        (
+            (x + 0.5) * fx - 0.5 + self.offset.0 as f32,
+            (y + 0.5) * fy - 0.5 + self.offset.1 as f32,
+        )
+    }
+
+    /// Maps a canvas point back to original-frame coordinates.
+    pub fn to_original(&self, (x, y): (f32, f32)) -> (f32, f32) {
+        let (fx, fy) = self.factor();
+        (
+            (x - self.offset.0 as f32 + 0.5) / fx - 0.5,
+            (y - self.offset.1 as f32 + 0.5) / fy - 0.5,
+        )
+    }
+
+    /// Maps a canvas displacement back to original pixels.
+    pub fn flow_to_original(&self, (dx, dy): (f32, f32)) -> (f32, f32) {
+        let (fx, fy) = self.factor();
+        (dx / fx, dy / fy)
+    }
+
+    /// Maps the positions of tracking results, computed on the canvas, back
+    /// to original coordinates in place. Tracked points that ended up in the
+    /// padding have left the frame and become [`TrackStatus::OutOfBounds`].
+    pub fn results_to_original(&self, results: &mut [TrackResult]) {
+        let (width, height) = (self.original.0 as f32, self.original.1 as f32);
+        for result in results {
+            let (x, y) = self.to_original(result.pos);
+            result.pos = (x, y);
+            let outside = !(x > -0.5 && y > -0.5 && x < width - 0.5 && y < height - 0.5);
+            if outside && result.status == TrackStatus::Tracked {
+                result.status = TrackStatus::OutOfBounds;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_is_centered_and_coordinates_round_trip() {
+        // 16:9 into 4:3 leaves bars above and below.
+        let letterbox = Letterbox::new(1280, 720, 640, 480);
+        assert_eq!(letterbox.content_rect(), (0, 60, 640, 360));
+        let p = (1001.25, 333.5);
+        let back = letterbox.to_original(letterbox.to_canvas(p));
+        assert!((back.0 - p.0).abs() < 1e-3 && (back.1 - p.1).abs() < 1e-3);
+        assert_eq!(letterbox.flow_to_original((1.0, -2.0)), (2.0, -4.0));
+
+        // A 2x upscale of a left/right gradient: bars repeat the first and
+        // last rows, the content keeps its columns.
+        let image = GrayImage::from_fn(4, 2, |x, y| image::Luma([(x * 60 + y * 5) as u8]));
+        let letterbox = Letterbox::new(4, 2, 8, 8);
+        assert_eq!(letterbox.content_rect(), (0, 2, 8, 4));
+        let canvas = letterbox.apply(&image);
+        assert_eq!(canvas.get_pixel(0, 0), canvas.get_pixel(0, 2));
+        assert_eq!(canvas.get_pixel(7, 7), canvas.get_pixel(7, 5));
+        assert_eq!(canvas.get_pixel(0, 2)[0], 0);
+        assert_eq!(canvas.get_pixel(7, 5)[0], 185);
+
+        let mut results = [
+            TrackResult {
+                pos: (3.0, 4.0),
+                status: TrackStatus::Tracked,
+                error: 0.0,
+                aperture: None,
+            },
+            TrackResult {
+                pos: (3.0, 0.5),
+                status: TrackStatus::Tracked,
+                error: 0.0,
+                aperture: None,
+            },
+        ];
+        letterbox.results_to_original(&mut results);
+        assert_eq!(results[0].pos, (1.25, 0.75));
+        assert_eq!(results[0].status, TrackStatus::Tracked);
+        assert_eq!(results[1].status, TrackStatus::OutOfBounds);
+    }
+}
@@ -1,23 +1,176 @@
 //! High-performance computer vision algorithms for real-time applications
 //!
 //! Provides implementations of:
-//! - Lucas-Kanade optical flow
-//! - Shi-Tomasi feature detection
+//! - Lucas-Kanade optical flow, optionally reporting lens-undistorted points
+//!   and seeded by a whole-frame shift estimate or a motion prior (e.g. from
+//!   a gyroscope), with per-point windows sized to the local texture,
+//!   masks excluding static image regions, per-pixel weight maps and
+//!   gradient-magnitude weighting; one-shot calls can pass two frames and
+//!   leave the pyramids to an automatic level count
+//! - Conversion between pixel-center and pixel-corner coordinates
+//! - Similarity (translation, rotation and scale) and affine point tracking
+//! - Global similarity transform estimation for stabilization and registration,
+//!   optionally weighted by track confidence, with alignment quality reports
+//! - Two-view classification of the camera motion (static, panning, zooming,
+//!   rolling or unmodelable)
+//! - Region-of-interest propagation along the tracked points, and a region
+//!   tracker managing its own feature population
+//! - Hierarchical block-matching motion estimation
+//! - Motion-compensated frame differencing; masked absolute differences,
+//!   thresholding and binary morphology
+//! - Global exposure (gain and bias) change estimation and compensation
+//! - Coarse moving-object masks from sparse track residuals
+//! - Per-frame motion activity with a hysteresis trigger
+//! - Track speed and heading in real-world units
+//! - Running-average background subtraction
+//! - Region and point-set moments (area, centroid, orientation)
+//! - Histogram-of-flow (HOF) descriptors and mean / median flow over a cell
+//!   grid
+//! - Shi-Tomasi and Harris feature detection
+//! - Segment-test (AGAST-style) corner detection
+//! - Frame preprocessing (resize, gamma, histogram equalization, blur) and
+//!   border extension
+//! - General 2D filtering with arbitrary small kernels
+//! - Per-stage processing resolutions with coordinate reconciliation
+//! - Letterboxing onto a fixed processing resolution
 //! - Optimized image processing pipelines
+//! - Bilinear and bicubic sub-pixel sampling of images and pyramid levels
+//! - Temporal smoothing of per-track flow vectors for display
+//! - Birth-frame appearance snapshots of tracks
+//! - A ring buffer of recent tracking results with flow composition across
+//!   frames
+//! - Struct-of-arrays tracking results for FFI and JavaScript consumers
+//! - Antialiased track overlays, fading trajectories and motion heatmaps,
+//!   with GIF / APNG output of annotated sequences (`animation` feature)
 //!
 //! Designed to be compatible with WebAssembly (Wasm).
 
+mod activity;
+mod adaptive_window;
+mod affine;
+mod agast;
+mod anchor;
+#[cfg(feature = "animation")]
+mod animation;
+mod arrays;
+mod background;
+mod batch;
+mod block_matching;
+mod border;
+mod budget;
+mod camera;
+mod convention;
+mod convolution;
+#[cfg(feature = "debug-trace")]
+mod debug_trace;
+mod difference;
+mod exposure;
 mod features;
+mod flow_grid;
+mod frame_difference;
+mod global_shift;
+mod history;
+mod hof;
+mod keyframe;
+mod letterbox;
 mod lk;
+mod moments;
+mod motion_mask;
+mod point;
+mod preprocess;
+mod prior;
 mod pyramid;
+mod qos;
+mod registration;
+mod roi;
+mod roi_tracker;
+mod sampling;
+mod scene_motion;
+mod similarity;
+mod smoothing;
+mod snapshot;
+mod speed;
+mod staged;
+mod timing;
 mod utils;
+mod viz;
 
 // Re-export main functionality
-pub use features::{good_features_to_track, good_features_to_track_grid};
+pub use activity::{ActivityEvent, ActivityMonitor, ActivityParams, motion_activity};
+pub use adaptive_window::{AdaptiveWindowParams, adaptive_windows};
+pub use affine::{AffineResult, calc_optical_flow_affine};
+pub use agast::agast_corners;
+pub use anchor::{AnchorParams, TrackAnchors};
+#[cfg(feature = "animation")]
+pub use animation::{write_apng, write_gif};
+pub use arrays::FlowArrays;
+pub use background::{BackgroundModel, BackgroundParams, FOREGROUND, SHADOW};
+pub use batch::{BatchTracker, FramePair};
+pub use block_matching::{BlockMotion, BlockMotionField, block_motion};
+pub use border::{BorderMode, extend_border};
+pub use budget::{BudgetScale, calc_optical_flow_budget};
+pub use camera::CameraIntrinsics;
+pub use convention::CoordinateConvention;
+pub use convolution::{Kernel, filter_2d, filter_2d_i16};
+#[cfg(feature = "debug-trace")]
+pub use debug_trace::{IterationRecord, LevelTrace, PointTrace};
+pub use difference::{abs_difference, dilate_mask, erode_mask, threshold_binary};
+pub use exposure::{ExposureChange, ExposureParams, estimate_exposure_change};
+pub use features::{
+    CoverageMap, FILTER_SCHARR, FeatureParams, Orientation, ResponseNormalization,
+    good_features_to_track, good_features_to_track_grid, good_features_to_track_pyramid,
+    good_features_to_track_rgb, good_features_to_track_sparse, good_features_to_track_with,
+    harris_corners, harris_corners_with_response, keypoint_orientations,
+};
+pub use flow_grid::{CellStatistic, FlowGrid, FlowGridParams, flow_grid, flow_grid_from_tracks};
+pub use frame_difference::{FrameMotion, MotionDifference, motion_compensated_difference};
+pub use global_shift::estimate_global_shift;
+pub use history::{ComposedFlow, FrameResults, ResultHistory};
+pub use hof::{HofDescriptor, HofParams, hof_descriptor, hof_from_block_motion, hof_from_tracks};
+pub use keyframe::{KeyframeParams, KeyframeTracker};
+pub use letterbox::Letterbox;
 #[allow(deprecated)]
 pub use lk::calc_optical_flow;
 pub use lk::{
-    DEFAULT_FB_THRESHOLD, DEFAULT_MIN_EIGEN_THRESHOLD, TrackResult, TrackStatus, TrackerContext,
-    calc_optical_flow_ex, calc_optical_flow_fb,
+    APERTURE_EDGE_RATIO, Aperture, BoundsPolicy, DEFAULT_EPSILON, DEFAULT_FB_THRESHOLD,
+    DEFAULT_MIN_EIGEN_THRESHOLD, FlowParams, GradientStorage, GradientWeighting, LevelIterations,
+    LkFlags, RobustLoss, TrackResult, TrackStatus, TrackWindow, TrackerContext,
+    calc_optical_flow_bidirectional, calc_optical_flow_ex, calc_optical_flow_fb,
+    calc_optical_flow_images, calc_optical_flow_pyr_lk, calc_optical_flow_rect,
+    calc_optical_flow_windows, calc_optical_flow_with,
+};
+pub use moments::{CentralMoments, Moments};
+pub use motion_mask::{MotionMask, MotionMaskParams, moving_object_mask};
+pub use point::Point2f;
+pub use preprocess::Preprocess;
+pub use prior::MotionPrior;
+pub use pyramid::{
+    PyramidError, PyramidSet, auto_pyramid_levels, build_pyramid, build_pyramid_into,
+    validate_pyramid, validate_pyramid_pair,
+};
+pub use qos::{QosController, QosParams, QualitySettings};
+pub use registration::{
+    MatchWeighting, RegistrationQuality, RigidParams, RigidTransform, estimate_rigid_transform,
+    fit_rigid_transform, registration_quality, stabilize_pair,
+};
+pub use roi::{RoiMotion, propagate_bbox, propagate_roi};
+pub use roi_tracker::{RoiTracker, RoiTrackerParams};
+pub use sampling::{sample_bicubic, sample_bilinear};
+pub use scene_motion::{
+    SceneClassification, SceneMotion, SceneMotionParams, classify_scene_motion,
+};
+pub use similarity::{SimilarityResult, calc_optical_flow_similarity};
+pub use smoothing::{FlowSmoother, SmoothingFilter};
+pub use snapshot::TrackSnapshots;
+pub use speed::{
+    GroundCalibration, SpeedReport, SpeedStats, TrackVelocity, history_speeds, measure_speeds,
+};
+pub use staged::{StageResolutions, StagedPipeline};
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub use timing::system_clock_ms;
+pub use timing::{TimingClock, TimingReport};
+pub use viz::{
+    Colormap, HeatmapParams, LOST_COLOR, MARKER_COLOR, OverlayScale, TRACKED_COLOR, TrackHistory,
+    draw_cross, draw_line, draw_tracks, draw_trajectories, flow_magnitude_heatmap, id_color,
+    track_density_heatmap,
 };
-pub use pyramid::{build_pyramid, build_pyramid_into};
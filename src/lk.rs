@@ -1,7 +1,27 @@
-use image::GrayImage;
-
-use crate::pyramid::build_pyramid_into;
-use crate::utils::fast_gradients::compute_gradients_into;
+use image::{GrayImage, ImageBuffer, Luma};
+use std::ops::{BitOr, BitOrAssign};
+
+use crate::block_matching::best_match;
+use crate::camera::CameraIntrinsics;
+use crate::convention::CoordinateConvention;
+#[cfg(feature = "debug-trace")]
+use crate::debug_trace::{DebugTrace, PointTrace};
+use crate::exposure::ExposureChange;
+use crate::global_shift::estimate_global_shift;
+use crate::point::Point2f;
+use crate::prior::MotionPrior;
+use crate::pyramid::{
+    LevelGradients, auto_pyramid_levels, build_pyramid, build_pyramid_into,
+    build_pyramid_with_gradients_into, compute_pyramid_gradients_into, join, recycle_pyramid,
+    validate_pyramid_pair,
+};
+use crate::timing::{TimingClock, TimingReport};
+use crate::utils::bilinear_window::{Mismatch, window_mismatch};
+use crate::utils::buffer_pool::{recycle_i16, take_i16};
+use crate::utils::gradient_tiles::{
+    GradientPlanes, GradientSource, QuantizedGradients, TiledGradients,
+};
+use crate::utils::integral_image::ExclusionTable;
 
 /// Default minimum-eigenvalue threshold used by [`calc_optical_flow`].
 ///
@@ -11,17 +31,26 @@ use crate::utils::fast_gradients::compute_gradients_into;
 /// [`TrackStatus::LowTexture`].
 pub const DEFAULT_MIN_EIGEN_THRESHOLD: f32 = 1e-3;
 
+/// Default convergence threshold of the Lucas-Kanade iteration: a level's
+/// refinement stops once an update moves the point by less than this many
+/// pixels along both axes.
+pub const DEFAULT_EPSILON: f32 = 1e-3;
+
 /// Why a feature point ended up where it did after tracking.
 ///
-/// See [`TrackResult`] for the coordinate convention.
+/// See [`TrackResult`] for the coordinate convention. New statuses may be
+/// added in minor releases, so matches need a wildcard arm.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum TrackStatus {
     /// The iteration converged inside the image; the position is trustworthy.
     Tracked,
     /// The search window left the image (in the previous or the next frame).
     OutOfBounds,
-    /// The iteration hit `max_iterations` without converging, or a step
-    /// exploded (non-finite or larger than the window).
+    /// The iteration hit `max_iterations` without converging, a step
+    /// exploded (non-finite or larger than the window), or the point moved
+    /// farther than the configured maximum displacement, see
+    /// [`FlowParams::max_displacement`].
     Diverged,
     /// The minimum eigenvalue of the spatial gradient matrix fell below the
     /// configured threshold, i.e. the window is too flat to track reliably.
@@ -30,20 +59,106 @@ pub enum TrackStatus {
     /// frame did not return close enough to the original position. Only
     /// produced by [`calc_optical_flow_fb`]. A strong occlusion/outlier signal.
     FbInconsistent,
+    /// The point lies on an excluded pixel of the tracking mask, see
+    /// [`FlowParams::mask`]; it was not tracked.
+    Masked,
+}
+
+impl TrackStatus {
+    /// Every status, in the order of their [`code`](Self::code)s.
+    const ALL: [TrackStatus; 6] = [
+        TrackStatus::Tracked,
+        TrackStatus::OutOfBounds,
+        TrackStatus::Diverged,
+        TrackStatus::LowTexture,
+        TrackStatus::FbInconsistent,
+        TrackStatus::Masked,
+    ];
+
+    /// Stable numeric code of the status for FFI and JavaScript consumers:
+    /// `0` for [`Tracked`](Self::Tracked), then 1 to 5 in declaration order.
+    /// New statuses get new codes; existing codes never change.
+    pub fn code(self) -> u8 {
+        match self {
+            TrackStatus::Tracked => 0,
+            TrackStatus::OutOfBounds => 1,
+            TrackStatus::Diverged => 2,
+            TrackStatus::LowTexture => 3,
+            TrackStatus::FbInconsistent => 4,
+            TrackStatus::Masked => 5,
+        }
+    }
+
+    /// The status of a [`code`](Self::code), `None` for unknown codes.
+    pub fn from_code(code: u8) -> Option<Self> {
+        Self::ALL.get(code as usize).copied()
+    }
 }
 
 /// Default forward-backward round-trip threshold (pixels) for
 /// [`calc_optical_flow_fb`].
 pub const DEFAULT_FB_THRESHOLD: f32 = 0.7;
 
+/// Eigenvalue ratio `λmin / λmax` of the spatial gradient matrix below which a
+/// window is classified as [`Aperture::Edge`].
+pub const APERTURE_EDGE_RATIO: f32 = 0.1;
+
+/// Shape of the image structure in a tracking window, from the eigenvalues of
+/// its spatial gradient matrix.
+///
+/// Only the displacement across an edge is observable (the aperture problem):
+/// for an [`Edge`](Aperture::Edge) window the component along the edge comes
+/// from the smoothness of the solve rather than from the image, and should not
+/// be trusted as much as the one across it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aperture {
+    /// Strong gradients in two directions; both displacement components are
+    /// reliable.
+    Corner,
+    /// Strong gradients in one direction only (`λmin / λmax` below
+    /// [`APERTURE_EDGE_RATIO`]).
+    Edge,
+    /// No strong gradient at all: the normalized largest eigenvalue is below
+    /// the minimum-eigenvalue threshold.
+    Flat,
+}
+
+impl Aperture {
+    /// Classifies the spatial gradient matrix `[[gxx, gxy], [gxy, gyy]]` of a
+    /// window of `area` (pixel count or weight sum).
+    pub(crate) fn classify(
+        gxx: f32,
+        gxy: f32,
+        gyy: f32,
+        area: f32,
+        min_eigen_threshold: f32,
+    ) -> Self {
+        let min_eig = min_eigenvalue(gxx, gxy, gyy);
+        let max_eig = gxx + gyy - min_eig;
+        if max_eig / area < min_eigen_threshold {
+            Aperture::Flat
+        } else if min_eig < APERTURE_EDGE_RATIO * max_eig {
+            Aperture::Edge
+        } else {
+            Aperture::Corner
+        }
+    }
+}
+
 /// Per-point result of [`calc_optical_flow_ex`].
 ///
 /// # Coordinate convention
 /// Positions are in level-0 (full resolution) pixel coordinates. The origin is
 /// the center of the top-left pixel, x grows to the right and y downwards; this
 /// matches [`crate::good_features_to_track`] and bilinear sampling throughout
-/// the crate.
+/// the crate. [`CoordinateConvention`] converts from and to pixel-corner
+/// coordinates, and [`TrackerContext::set_coordinate_convention`] applies it
+/// to a context's inputs and outputs.
+///
+/// New fields may be added in minor releases; build results outside the
+/// crate with [`TrackResult::new`].
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[non_exhaustive]
 pub struct TrackResult {
     /// Tracked position in the next frame.
     pub pos: (f32, f32),
@@ -55,7 +170,528 @@ pub struct TrackResult {
     /// Comparable across points regardless of `window_size`; intended for
     /// downstream outlier gating. It is [`f32::INFINITY`] when no residual
     /// could be measured (the window was out of bounds).
+    ///
+    /// With [`LkFlags::GET_MIN_EIGENVALS`] it holds the normalized minimum
+    /// eigenvalue instead, [`f32::INFINITY`] when the previous-frame window
+    /// was out of bounds.
     pub error: f32,
+    /// Structure of the previous-frame window at full resolution, `None` when
+    /// it was out of bounds. Classified with the call's minimum-eigenvalue
+    /// threshold.
+    pub aperture: Option<Aperture>,
+}
+
+impl TrackResult {
+    /// A result at `pos` with the given `status` and `error`, and no
+    /// [`aperture`](Self::aperture).
+    pub fn new(pos: (f32, f32), status: TrackStatus, error: f32) -> Self {
+        TrackResult {
+            pos,
+            status,
+            error,
+            aperture: None,
+        }
+    }
+
+    /// [`pos`](Self::pos) as a [`Point2f`].
+    pub fn point(&self) -> Point2f {
+        self.pos.into()
+    }
+}
+
+/// Tracking window of one point, see [`calc_optical_flow_windows`].
+///
+/// The same window is used at every pyramid level.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrackWindow<'a> {
+    /// Width of the window in pixels (odd).
+    pub width: usize,
+    /// Height of the window in pixels (odd).
+    pub height: usize,
+    /// Optional non-negative per-pixel weights, `width * height` values in
+    /// row-major order, scaling each pixel's contribution to the solve. A
+    /// weight of 0 ignores the pixel, e.g. a known occluder or the background
+    /// beside a thin structure. `None` weights all pixels equally.
+    pub weights: Option<&'a [f32]>,
+}
+
+impl TrackWindow<'_> {
+    /// An unweighted square window of side `size`.
+    pub fn new(size: usize) -> Self {
+        Self::rect(size, size)
+    }
+
+    /// An unweighted `width` x `height` window, see [`calc_optical_flow_rect`].
+    pub fn rect(width: usize, height: usize) -> Self {
+        TrackWindow {
+            width,
+            height,
+            weights: None,
+        }
+    }
+
+    /// Half-extents `(rx, ry)` of the window around its center.
+    fn radius(&self) -> (usize, usize) {
+        (self.width / 2, self.height / 2)
+    }
+}
+
+/// Behavior toggles for the Lucas-Kanade entry points, mirroring the `flags`
+/// argument of OpenCV's `calcOpticalFlowPyrLK`.
+///
+/// Flags combine with `|`. The bit values match OpenCV's, so an integer flag
+/// set from ported code converts with [`from_bits_truncate`](Self::from_bits_truncate).
+/// The default, [`empty`](Self::empty), is the classic behavior.
+///
+/// Recognized by [`calc_optical_flow_pyr_lk`] and by
+/// [`TrackerContext::set_flags`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct LkFlags(u32);
+
+impl LkFlags {
+    /// Seed the search with caller-supplied next-frame positions instead of
+    /// the previous positions (OpenCV's `OPTFLOW_USE_INITIAL_FLOW`).
+    pub const USE_INITIAL_FLOW: LkFlags = LkFlags(4);
+
+    /// Report the minimum eigenvalue of each point's spatial gradient matrix,
+    /// divided by the window area, as [`TrackResult::error`] instead of the
+    /// photometric residual (OpenCV's `OPTFLOW_LK_GET_MIN_EIGENVALS`). It is
+    /// the value compared against `min_eigen_threshold`, measured at level 0.
+    pub const GET_MIN_EIGENVALS: LkFlags = LkFlags(8);
+
+    /// After a point converges, search ±1 px around the solution in
+    /// quarter-pixel steps for the best zero-mean normalized cross-correlation
+    /// with the reference window, and move there if it correlates better (with
+    /// a parabolic fit for the final sub-step). ZNCC ignores gain and offset,
+    /// so this escapes the shallow local minima an illumination change leaves
+    /// in the intensity residual. Costs 81 window correlations per tracked
+    /// point. Not an OpenCV flag.
+    pub const ZNCC_REFINE: LkFlags = LkFlags(1 << 16);
+
+    /// Before tracking, estimate the global translation between the frames
+    /// with [`estimate_global_shift`] and seed every point with it, so large
+    /// camera shake needs no extra pyramid levels. Has no effect when
+    /// predicted positions or a [`MotionPrior`] are given, which take
+    /// precedence. Not an OpenCV flag.
+    pub const PREALIGN: LkFlags = LkFlags(1 << 17);
+
+    /// Track under the photometric model `next = gain * prev + bias`: every
+    /// iteration fits gain and bias to the current windows by least squares
+    /// and measures the residual after compensating them, so exposure and
+    /// white-balance changes between frames do not pull the solution. The
+    /// reported error is the compensated residual. Costs a second pass over
+    /// the window per iteration. Not an OpenCV flag.
+    pub const GAIN_BIAS: LkFlags = LkFlags(1 << 18);
+
+    /// Subtract the mean of each window from both the previous and the next
+    /// patch before forming the residual, i.e. fit only the bias of
+    /// [`GAIN_BIAS`](Self::GAIN_BIAS). Cheaper than the full fit and enough
+    /// for a slow brightness drift; a contrast change still pulls the
+    /// solution. [`GAIN_BIAS`](Self::GAIN_BIAS) takes precedence. Not an
+    /// OpenCV flag.
+    pub const ZERO_MEAN: LkFlags = LkFlags(1 << 19);
+
+    const ALL: u32 = Self::USE_INITIAL_FLOW.0
+        | Self::GET_MIN_EIGENVALS.0
+        | Self::ZNCC_REFINE.0
+        | Self::PREALIGN.0
+        | Self::GAIN_BIAS.0
+        | Self::ZERO_MEAN.0;
+
+    /// No flags set.
+    pub const fn empty() -> Self {
+        LkFlags(0)
+    }
+
+    /// The raw bit pattern, with OpenCV's values.
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    /// Flags from a raw bit pattern; bits without a meaning here are dropped.
+    pub const fn from_bits_truncate(bits: u32) -> Self {
+        LkFlags(bits & Self::ALL)
+    }
+
+    /// Whether every flag of `other` is set in `self`.
+    pub const fn contains(self, other: LkFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// The flags of `self` choosing the photometric model, which a backward
+    /// pass shares with its forward pass.
+    const fn photometric(self) -> LkFlags {
+        LkFlags(self.0 & (Self::GAIN_BIAS.0 | Self::ZERO_MEAN.0))
+    }
+
+    /// `self` with every flag of `other` cleared.
+    const fn without(self, other: LkFlags) -> LkFlags {
+        LkFlags(self.0 & !other.0)
+    }
+}
+
+impl BitOr for LkFlags {
+    type Output = LkFlags;
+
+    fn bitor(self, rhs: LkFlags) -> LkFlags {
+        LkFlags(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for LkFlags {
+    fn bitor_assign(&mut self, rhs: LkFlags) {
+        self.0 |= rhs.0;
+    }
+}
+
+/// Iteration limit of every pyramid level, see
+/// [`TrackerContext::set_level_iterations`].
+///
+/// Coarse levels typically converge within a few iterations, while the finest
+/// level benefits from many more; a budget per level spends the iterations
+/// where they matter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LevelIterations {
+    limits: [usize; LevelIterations::MAX_LEVELS],
+    len: usize,
+}
+
+impl LevelIterations {
+    /// Most levels [`per_level`](Self::per_level) accepts explicit limits for.
+    pub const MAX_LEVELS: usize = 8;
+
+    /// `max_iterations` on every level.
+    pub fn uniform(max_iterations: usize) -> Self {
+        Self::per_level(&[max_iterations])
+    }
+
+    /// `limits[level]` iterations on each level, finest (level 0) first;
+    /// levels past the end of `limits` use its last entry.
+    ///
+    /// # Panics
+    /// Panics if `limits` is empty or longer than
+    /// [`MAX_LEVELS`](Self::MAX_LEVELS).
+    pub fn per_level(limits: &[usize]) -> Self {
+        assert!(
+            !limits.is_empty() && limits.len() <= Self::MAX_LEVELS,
+            "between 1 and {} level limits are required",
+            Self::MAX_LEVELS
+        );
+        let mut stored = [0; Self::MAX_LEVELS];
+        stored[..limits.len()].copy_from_slice(limits);
+        LevelIterations {
+            limits: stored,
+            len: limits.len(),
+        }
+    }
+
+    /// Iteration limit of `level`.
+    pub fn get(&self, level: usize) -> usize {
+        self.limits[level.min(self.len - 1)]
+    }
+}
+
+/// Robust loss for iteratively reweighted tracking, see
+/// [`TrackerContext::set_robust_loss`].
+///
+/// Residuals are measured in units of a robust scale estimate of the window,
+/// `1.4826 * median(|r|)` (at least one gray level), so the tuning constants
+/// do not depend on image contrast. [`HUBER`](Self::HUBER) and
+/// [`TUKEY`](Self::TUKEY) hold the customary 95%-efficiency constants.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RobustLoss {
+    /// Weights pixels with residuals beyond `k` scales down by `k / |u|`, so
+    /// outliers still pull, but only linearly.
+    Huber { k: f32 },
+    /// Tukey's biweight: weights fall smoothly to 0 at `c` scales, so gross
+    /// outliers are ignored entirely.
+    Tukey { c: f32 },
+}
+
+impl RobustLoss {
+    /// Huber loss with `k = 1.345`.
+    pub const HUBER: RobustLoss = RobustLoss::Huber { k: 1.345 };
+
+    /// Tukey's biweight with `c = 4.685`.
+    pub const TUKEY: RobustLoss = RobustLoss::Tukey { c: 4.685 };
+
+    /// Weight of a residual of `u` scales.
+    fn weight(self, u: f32) -> f32 {
+        let u = u.abs();
+        match self {
+            RobustLoss::Huber { k } => {
+                if u <= k {
+                    1.0
+                } else {
+                    k / u
+                }
+            }
+            RobustLoss::Tukey { c } => {
+                if u < c {
+                    let t = 1.0 - (u / c) * (u / c);
+                    t * t
+                } else {
+                    0.0
+                }
+            }
+        }
+    }
+
+    fn validate(self) {
+        let constant = match self {
+            RobustLoss::Huber { k } => k,
+            RobustLoss::Tukey { c } => c,
+        };
+        assert!(
+            constant > 0.0 && constant.is_finite(),
+            "robust loss constant must be positive and finite"
+        );
+    }
+}
+
+/// Per-pixel weighting of the Lucas-Kanade equations by the gradient
+/// magnitude of the previous frame, see
+/// [`TrackerContext::set_gradient_weighting`].
+///
+/// Magnitudes are in gray levels per pixel of the pyramid level.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GradientWeighting {
+    /// Weights each pixel by its gradient magnitude `m`.
+    Magnitude,
+    /// Weights each pixel by `m / (m + scale)`: like the magnitude on weak
+    /// gradients, saturating at 1 on gradients well above `scale`, so a
+    /// single strong edge does not outweigh the rest of the window.
+    Saturating { scale: f32 },
+}
+
+impl GradientWeighting {
+    /// Weight of a pixel with gradient magnitude `magnitude`.
+    fn weight(self, magnitude: f32) -> f32 {
+        match self {
+            GradientWeighting::Magnitude => magnitude,
+            GradientWeighting::Saturating { scale } => magnitude / (magnitude + scale),
+        }
+    }
+
+    fn validate(self) {
+        if let GradientWeighting::Saturating { scale } = self {
+            assert!(
+                scale > 0.0 && scale.is_finite(),
+                "gradient weighting scale must be positive and finite"
+            );
+        }
+    }
+}
+
+/// What happens to returned positions that lie outside the next frame.
+///
+/// The frame spans `[0, width - 1] x [0, height - 1]` in the crate's
+/// pixel-center coordinates (see [`TrackResult`]). Non-finite positions,
+/// left by a diverged iteration, count as outside.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BoundsPolicy {
+    /// Positions are returned as the iteration left them, whatever their
+    /// status: a point skipped before iterating keeps its starting position,
+    /// a diverged one the last step's.
+    #[default]
+    AsIs,
+    /// Positions are clamped into the frame; non-finite ones fall back to
+    /// the point's previous position, clamped as well. Statuses are kept.
+    Clamp,
+    /// Points outside the frame are reported as [`TrackStatus::OutOfBounds`],
+    /// keeping their position.
+    Invalidate,
+}
+
+impl BoundsPolicy {
+    /// Applies the policy to `results` in a `width` x `height` frame.
+    fn apply(
+        self,
+        (width, height): (u32, u32),
+        prev_points: &[(f32, f32)],
+        results: &mut [TrackResult],
+    ) {
+        let (max_x, max_y) = (width as f32 - 1.0, height as f32 - 1.0);
+        let inside = |(x, y): (f32, f32)| x >= 0.0 && y >= 0.0 && x <= max_x && y <= max_y;
+        match self {
+            BoundsPolicy::AsIs => {}
+            BoundsPolicy::Clamp => {
+                for (result, &prev) in results.iter_mut().zip(prev_points) {
+                    let (x, y) = if result.pos.0.is_finite() && result.pos.1.is_finite() {
+                        result.pos
+                    } else {
+                        prev
+                    };
+                    result.pos = (x.clamp(0.0, max_x), y.clamp(0.0, max_y));
+                }
+            }
+            BoundsPolicy::Invalidate => {
+                for result in results.iter_mut().filter(|r| !inside(r.pos)) {
+                    result.status = TrackStatus::OutOfBounds;
+                }
+            }
+        }
+    }
+}
+
+/// Lucas-Kanade settings for [`calc_optical_flow_with`] and
+/// [`TrackerContext::set_params`].
+///
+/// New options are added as fields with a default, so callers that fill in
+/// what they need and take the rest from [`FlowParams::default`] keep
+/// compiling.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FlowParams<'a> {
+    /// Side of the square tracking window (odd).
+    pub window_size: usize,
+    /// Max iterations per pyramid level.
+    pub max_iterations: usize,
+    /// Convergence threshold in pixels, see [`DEFAULT_EPSILON`].
+    pub epsilon: f32,
+    /// Finest-to-coarsest levels to use, like OpenCV's `maxLevel`: `Some(n)`
+    /// tracks on levels `0..=n` only, `None` on the whole pyramid.
+    pub max_level: Option<usize>,
+    /// See [`DEFAULT_MIN_EIGEN_THRESHOLD`].
+    pub min_eigen_threshold: f32,
+    /// Behavior toggles; with [`LkFlags::USE_INITIAL_FLOW`] the `predicted`
+    /// argument is required.
+    pub flags: LkFlags,
+    /// Robust reweighting of the window pixels, see
+    /// [`TrackerContext::set_robust_loss`].
+    pub robust_loss: Option<RobustLoss>,
+    /// Treatment of positions outside the next frame.
+    pub bounds: BoundsPolicy,
+    /// Whole-frame transform seeding every point when `predicted` is
+    /// `None`, see [`TrackerContext::set_motion_prior`].
+    pub prior: Option<MotionPrior>,
+    /// Global brightness change between the frames, see
+    /// [`TrackerContext::set_exposure_change`].
+    pub exposure: Option<ExposureChange>,
+    /// Per-level iteration limits replacing `max_iterations`, see
+    /// [`TrackerContext::set_level_iterations`].
+    pub level_iterations: Option<LevelIterations>,
+    /// Largest plausible displacement in pixels, see
+    /// [`TrackerContext::set_max_displacement`].
+    pub max_displacement: Option<f32>,
+    /// Weighting of the window pixels by their gradient magnitude, see
+    /// [`TrackerContext::set_gradient_weighting`].
+    pub gradient_weighting: Option<GradientWeighting>,
+    /// Radius of the coarse block-matching search seeding every point, see
+    /// [`TrackerContext::set_block_search`].
+    pub block_search: Option<u32>,
+    /// Pixels that must never drive tracking, where this frame-sized mask is
+    /// 0, e.g. burnt-in overlays, timestamps or a vehicle hood.
+    ///
+    /// The mask covers the same pixels in both frames. A window pixel is left
+    /// out of the least-squares solve, as if weighted 0 (see
+    /// [`TrackWindow::weights`]), when a 0 pixel of the mask lies within two
+    /// pixels of the level around it, in the previous frame or in the next
+    /// frame at the displacement carried over from the coarser levels, so
+    /// neither its interpolation nor its gradient reads a masked pixel.
+    /// Points on a 0 pixel are not tracked and are reported as
+    /// [`TrackStatus::Masked`] at their input position.
+    pub mask: Option<&'a GrayImage>,
+    /// Non-negative weights of the window pixels over the previous frame,
+    /// e.g. a segmentation network's foreground probability, which lets the
+    /// pixels of the tracked object dominate the solve over the background.
+    ///
+    /// The map is sampled at each window pixel with the same bilinear
+    /// interpolation as the intensities; on coarse pyramid levels at the
+    /// pixel's full-resolution position. Negative weights count as 0, and a
+    /// window without positive weight is reported as
+    /// [`TrackStatus::LowTexture`]. The minimum eigenvalue and the error are
+    /// normalized by the weight sum, as with [`TrackWindow::weights`].
+    pub weight_map: Option<&'a ImageBuffer<Luma<f32>, Vec<f32>>>,
+    /// Fraction of the points whose convergence ends each coarse pyramid
+    /// level, see [`TrackerContext::set_level_early_exit`].
+    pub level_early_exit: Option<f32>,
+}
+
+impl Default for FlowParams<'_> {
+    fn default() -> Self {
+        FlowParams {
+            window_size: 21,
+            max_iterations: 30,
+            epsilon: DEFAULT_EPSILON,
+            max_level: None,
+            min_eigen_threshold: DEFAULT_MIN_EIGEN_THRESHOLD,
+            flags: LkFlags::empty(),
+            robust_loss: None,
+            bounds: BoundsPolicy::AsIs,
+            prior: None,
+            exposure: None,
+            level_iterations: None,
+            max_displacement: None,
+            gradient_weighting: None,
+            block_search: None,
+            mask: None,
+            weight_map: None,
+            level_early_exit: None,
+        }
+    }
+}
+
+impl FlowParams<'_> {
+    /// Iteration limit of every pyramid level.
+    fn iterations(&self) -> LevelIterations {
+        self.level_iterations
+            .unwrap_or(LevelIterations::uniform(self.max_iterations))
+    }
+
+    /// Panics on the invalid settings listed by [`calc_optical_flow_with`].
+    fn validate(&self) {
+        if let Some(loss) = self.robust_loss {
+            loss.validate();
+        }
+        if let Some(limit) = self.max_displacement {
+            validate_max_displacement(limit);
+        }
+        if let Some(weighting) = self.gradient_weighting {
+            weighting.validate();
+        }
+        if let Some(fraction) = self.level_early_exit {
+            validate_level_early_exit(fraction);
+        }
+    }
+}
+
+/// Photometric model of the Lucas-Kanade residual, from
+/// [`LkFlags::GAIN_BIAS`], [`LkFlags::ZERO_MEAN`] and the caller's global
+/// [`ExposureChange`].
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum Photometric {
+    /// Plain intensity differences.
+    Identity,
+    /// One gain and bias for every window.
+    Fixed(ExposureChange),
+    /// Gain and bias fitted per window, with `fallback_gain` where the window
+    /// cannot determine the gain.
+    Fitted { fallback_gain: f32 },
+    /// Bias fitted per window under a fixed `gain`, which matches the window
+    /// means.
+    ZeroMean { gain: f32 },
+}
+
+impl Photometric {
+    fn new(flags: LkFlags, exposure: Option<ExposureChange>) -> Self {
+        match exposure {
+            _ if flags.contains(LkFlags::GAIN_BIAS) => Photometric::Fitted {
+                fallback_gain: exposure.map_or(1.0, |change| change.gain),
+            },
+            _ if flags.contains(LkFlags::ZERO_MEAN) => Photometric::ZeroMean {
+                gain: exposure.map_or(1.0, |change| change.gain),
+            },
+            Some(change) => Photometric::Fixed(change),
+            None => Photometric::Identity,
+        }
+    }
+}
+
+/// Per-iteration buffers of the robust solve.
+#[derive(Default)]
+struct RobustBuffers {
+    residuals: Vec<f32>,
+    magnitudes: Vec<f32>,
 }
 
 /// Compute optical flow using the pyramidal Lucas-Kanade method.
@@ -117,7 +753,8 @@ pub fn calc_optical_flow(
 ///   See [`DEFAULT_MIN_EIGEN_THRESHOLD`].
 ///
 /// # Panics
-/// Panics if `predicted` is `Some` but its length differs from `prev_points`.
+/// Panics if the pyramids fail [`validate_pyramid_pair`], or if `predicted`
+/// is `Some` but its length differs from `prev_points`.
 ///
 /// # Returns
 /// One [`TrackResult`] per input point, in the same order.
@@ -130,57 +767,980 @@ pub fn calc_optical_flow_ex(
     max_iterations: usize,
     min_eigen_threshold: f32,
 ) -> Vec<TrackResult> {
-    let mut scratch = Scratch::default();
-    let mut out = Vec::new();
-    track_into(
+    let params = FlowParams {
+        window_size,
+        max_iterations,
+        min_eigen_threshold,
+        ..FlowParams::default()
+    };
+    calc_optical_flow_with(prev_pyramid, curr_pyramid, prev_points, predicted, &params)
+}
+
+/// [`calc_optical_flow_ex`] with a `window_width` x `window_height` window
+/// instead of a square one.
+///
+/// Under dominant motion along one axis (vehicles, conveyor belts) a wide,
+/// short window collects texture along the motion without reaching into
+/// unrelated rows. The window is the same at every pyramid level, and the
+/// divergence guard bounds each step by the window extent along its axis.
+///
+/// # Panics
+/// Panics as [`calc_optical_flow_ex`] does, or if either window dimension is
+/// even.
+#[allow(clippy::too_many_arguments)]
+pub fn calc_optical_flow_rect(
+    prev_pyramid: &[GrayImage],
+    curr_pyramid: &[GrayImage],
+    prev_points: &[(f32, f32)],
+    predicted: Option<&[(f32, f32)]>,
+    window_width: usize,
+    window_height: usize,
+    max_iterations: usize,
+    min_eigen_threshold: f32,
+) -> Vec<TrackResult> {
+    let params = FlowParams {
+        max_iterations,
+        min_eigen_threshold,
+        ..FlowParams::default()
+    };
+    flow_with(
         prev_pyramid,
+        None,
         curr_pyramid,
         prev_points,
         predicted,
+        Windows::Rect(window_width, window_height),
+        &params,
+    )
+}
+
+/// [`calc_optical_flow_ex`] with a window of its own for every point.
+///
+/// Each point is tracked with its [`TrackWindow`]: a small window keeps a
+/// point on a thin structure from being dominated by the background, and the
+/// weights can mask out pixels that should not drive the solve. With weights,
+/// the normalized minimum eigenvalue and the reported error are taken over the
+/// weighted window (normalized by the weight sum instead of the pixel count).
+///
+/// # Panics
+/// Panics if `windows` does not have one entry per `prev_point`, if a window
+/// size is even, or if a weight mask has the wrong length or no positive
+/// weight.
+pub fn calc_optical_flow_windows(
+    prev_pyramid: &[GrayImage],
+    curr_pyramid: &[GrayImage],
+    prev_points: &[(f32, f32)],
+    predicted: Option<&[(f32, f32)]>,
+    windows: &[TrackWindow],
+    max_iterations: usize,
+    min_eigen_threshold: f32,
+) -> Vec<TrackResult> {
+    let params = FlowParams {
+        max_iterations,
+        min_eigen_threshold,
+        ..FlowParams::default()
+    };
+    flow_with(
+        prev_pyramid,
+        None,
+        curr_pyramid,
+        prev_points,
+        predicted,
+        Windows::PerPoint(windows),
+        &params,
+    )
+}
+
+/// Per-pixel weight map over a frame, see [`FlowParams::weight_map`].
+type WeightMap = ImageBuffer<Luma<f32>, Vec<f32>>;
+
+/// OpenCV-style form of [`calc_optical_flow_ex`], with the in/out
+/// `next_points` buffer and the [`LkFlags`] of `calcOpticalFlowPyrLK`.
+///
+/// With [`LkFlags::USE_INITIAL_FLOW`], `next_points` must hold one initial
+/// position per `prev_point`, used as the prediction; otherwise its contents
+/// are ignored. On return it holds the tracked positions, and the full
+/// per-point results are returned as well.
+///
+/// # Panics
+/// Panics if [`LkFlags::USE_INITIAL_FLOW`] is set and `next_points` does not
+/// have one entry per `prev_point`.
+#[allow(clippy::too_many_arguments)]
+pub fn calc_optical_flow_pyr_lk(
+    prev_pyramid: &[GrayImage],
+    next_pyramid: &[GrayImage],
+    prev_points: &[(f32, f32)],
+    next_points: &mut Vec<(f32, f32)>,
+    window_size: usize,
+    max_iterations: usize,
+    flags: LkFlags,
+    min_eigen_threshold: f32,
+) -> Vec<TrackResult> {
+    let predicted = flags
+        .contains(LkFlags::USE_INITIAL_FLOW)
+        .then_some(next_points.as_slice());
+    let params = FlowParams {
         window_size,
         max_iterations,
         min_eigen_threshold,
+        flags,
+        ..FlowParams::default()
+    };
+    let results =
+        calc_optical_flow_with(prev_pyramid, next_pyramid, prev_points, predicted, &params);
+    next_points.clear();
+    next_points.extend(results.iter().map(|r| r.pos));
+    results
+}
+
+/// [`calc_optical_flow_ex`] with the full set of tracker settings.
+///
+/// # Panics
+/// Panics as [`calc_optical_flow_ex`] does, if [`LkFlags::USE_INITIAL_FLOW`]
+/// is set without `predicted`, if `params.robust_loss` or
+/// `params.gradient_weighting` is invalid, if `params.max_displacement`
+/// is not positive, if `params.level_early_exit` is not in `(0, 1]`, or if
+/// `params.mask` or `params.weight_map` differs in size from the frames.
+///
+/// # Returns
+/// One [`TrackResult`] per input point, in the same order.
+pub fn calc_optical_flow_with(
+    prev_pyramid: &[GrayImage],
+    curr_pyramid: &[GrayImage],
+    prev_points: &[(f32, f32)],
+    predicted: Option<&[(f32, f32)]>,
+    params: &FlowParams,
+) -> Vec<TrackResult> {
+    flow_with(
+        prev_pyramid,
+        None,
+        curr_pyramid,
+        prev_points,
+        predicted,
+        Windows::Uniform(params.window_size),
+        params,
+    )
+}
+
+/// The tracking of the free functions: [`track_into`] on pooled scratch,
+/// between the seeding by `params.prior` and `params.block_search` and the
+/// checks of `params.max_displacement` and `params.bounds`.
+fn flow_with(
+    prev_pyramid: &[GrayImage],
+    prev_gradients: Option<PrecomputedGradients>,
+    curr_pyramid: &[GrayImage],
+    prev_points: &[(f32, f32)],
+    predicted: Option<&[(f32, f32)]>,
+    windows: Windows,
+    params: &FlowParams,
+) -> Vec<TrackResult> {
+    assert!(
+        predicted.is_some() || !params.flags.contains(LkFlags::USE_INITIAL_FLOW),
+        "USE_INITIAL_FLOW requires predicted positions"
+    );
+    params.validate();
+    let prev_pyramid = used_levels(prev_pyramid, params.max_level);
+    let curr_pyramid = used_levels(curr_pyramid, params.max_level);
+    let seeded: Vec<(f32, f32)>;
+    let predicted = match (predicted, params.prior) {
+        (None, Some(prior)) => {
+            seeded = prev_points.iter().map(|&p| prior.apply(p)).collect();
+            Some(&seeded[..])
+        }
+        (predicted, _) => predicted,
+    };
+    let mut searched = Vec::new();
+    let predicted = match params.block_search {
+        Some(radius) => {
+            block_search_seeds(
+                prev_pyramid,
+                curr_pyramid,
+                prev_points,
+                predicted,
+                windows,
+                radius,
+                &mut searched,
+            );
+            Some(&searched[..])
+        }
+        None => predicted,
+    };
+
+    let mask = params.mask.map(ExclusionTable::new);
+    // Precomputed gradients leave the scratch's gradient planes unused.
+    let pooled = prev_gradients.is_none();
+    let mut scratch = if pooled {
+        Scratch::pooled(prev_pyramid)
+    } else {
+        Scratch::default()
+    };
+    let mut out = Vec::new();
+    track_into(
+        prev_pyramid,
+        prev_gradients,
+        curr_pyramid,
+        prev_points,
+        predicted,
+        windows,
+        params,
+        mask.as_ref(),
+        None,
         &mut scratch,
         &mut out,
     );
+    if pooled {
+        scratch.recycle();
+    }
+    reject_far_displacements(params.max_displacement, prev_points, &mut out);
+    params
+        .bounds
+        .apply(curr_pyramid[0].dimensions(), prev_points, &mut out);
     out
 }
 
+/// [`calc_optical_flow_with`] on two frames instead of their pyramids, for
+/// one-shot callers.
+///
+/// Both pyramids get [`auto_pyramid_levels`] levels for the frame size and
+/// `params.window_size`, or fewer under `params.max_level`. Their storage
+/// comes from and returns to the thread's buffer pool, so repeated calls on
+/// same-sized frames stop allocating image storage. Video, where each frame
+/// is the next one of one call and the previous one of the following call,
+/// should keep a [`TrackerContext`] instead of building every pyramid twice.
+///
+/// # Panics
+/// Panics as [`calc_optical_flow_with`] does without `predicted`, and if the
+/// frames differ in size.
+///
+/// # Returns
+/// One [`TrackResult`] per input point, in the same order.
+pub fn calc_optical_flow_images(
+    prev: &GrayImage,
+    curr: &GrayImage,
+    prev_points: &[(f32, f32)],
+    params: &FlowParams,
+) -> Vec<TrackResult> {
+    let (width, height) = prev.dimensions();
+    let auto = auto_pyramid_levels(width, height, params.window_size);
+    let levels = params
+        .max_level
+        .map_or(auto, |max_level| auto.min(max_level + 1));
+    let prev_pyramid = build_pyramid(prev, levels);
+    let curr_pyramid = build_pyramid(curr, levels);
+    let results = calc_optical_flow_with(&prev_pyramid, &curr_pyramid, prev_points, None, params);
+    recycle_pyramid(prev_pyramid);
+    recycle_pyramid(curr_pyramid);
+    results
+}
+
+/// Tracking windows of one call: one square size for all points, one
+/// rectangle for all points, or one [`TrackWindow`] each.
+#[derive(Clone, Copy)]
+enum Windows<'a> {
+    Uniform(usize),
+    Rect(usize, usize),
+    PerPoint(&'a [TrackWindow<'a>]),
+}
+
+impl<'a> Windows<'a> {
+    fn get(self, idx: usize) -> TrackWindow<'a> {
+        match self {
+            Windows::Uniform(size) => TrackWindow::new(size),
+            Windows::Rect(width, height) => TrackWindow::rect(width, height),
+            Windows::PerPoint(windows) => windows[idx],
+        }
+    }
+
+    /// Largest window side, or 0 without points.
+    fn max_size(self) -> usize {
+        match self {
+            Windows::Uniform(size) => size,
+            Windows::Rect(width, height) => width.max(height),
+            Windows::PerPoint(windows) => windows
+                .iter()
+                .map(|w| w.width.max(w.height))
+                .max()
+                .unwrap_or(0),
+        }
+    }
+
+    fn validate(self, n_points: usize) {
+        match self {
+            Windows::Uniform(size) => assert!(size % 2 == 1, "Window size must be odd"),
+            Windows::Rect(width, height) => assert!(
+                width % 2 == 1 && height % 2 == 1,
+                "Window width and height must be odd"
+            ),
+            Windows::PerPoint(windows) => {
+                assert_eq!(
+                    windows.len(),
+                    n_points,
+                    "windows must have one entry per prev_point"
+                );
+                for window in windows {
+                    assert!(
+                        window.width % 2 == 1 && window.height % 2 == 1,
+                        "Window width and height must be odd"
+                    );
+                    if let Some(weights) = window.weights {
+                        assert_eq!(
+                            weights.len(),
+                            window.width * window.height,
+                            "weights must have width * height entries"
+                        );
+                        assert!(
+                            weights.iter().any(|&w| w > 0.0),
+                            "weights must not all be zero"
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
 /// Reusable per-call scratch buffers for the Lucas-Kanade loop. Owned by
 /// [`TrackerContext`] (or created transiently by the free functions) so the
 /// steady-state hot path performs no heap allocation.
 #[derive(Default)]
 struct Scratch {
     offsets: Vec<(f32, f32)>,
-    prev_patch: Vec<f32>,
-    ix_patch: Vec<f32>,
-    iy_patch: Vec<f32>,
+    reference: ReferenceWindow,
     displacements: Vec<(f32, f32)>,
-    grad_x: Vec<i16>,
-    grad_y: Vec<i16>,
+    active: Vec<ActivePoint>,
+    /// Windows of the points refining in rounds, by slot in `active`.
+    references: Vec<ReferenceWindow>,
+    gradients: TiledGradients,
+    robust: RobustBuffers,
+    #[cfg(feature = "debug-trace")]
+    trace: DebugTrace,
+}
+
+/// A point still refining on the current pyramid level.
+#[derive(Clone, Copy)]
+struct ActivePoint {
+    idx: usize,
+    /// Iterations used on the level so far.
+    used: usize,
+    /// Inverse spatial gradient matrix, `None` until the window is sampled.
+    inverse: Option<(f32, f32, f32)>,
+}
+
+impl Scratch {
+    /// Scratch for a one-shot call whose level-0 gradient planes come from the
+    /// thread's buffer pool, so repeated free-function calls on same-sized
+    /// frames do not allocate image storage.
+    fn pooled(pyramid: &[GrayImage]) -> Self {
+        let n = pyramid
+            .first()
+            .map_or(0, |level| (level.width() * level.height()) as usize);
+        Scratch {
+            gradients: TiledGradients::with_planes(take_i16(n), take_i16(n)),
+            ..Scratch::default()
+        }
+    }
+
+    /// Returns the gradient planes to the thread's buffer pool.
+    fn recycle(self) {
+        let (grad_x, grad_y) = self.gradients.into_planes();
+        recycle_i16(grad_x);
+        recycle_i16(grad_y);
+    }
+}
+
+/// Previous-frame window of one point at one pyramid level: intensities and
+/// Scharr gradients (scaled to intensity units), sampled once and reused by
+/// every iteration at that level.
+///
+/// With per-pixel weights, the stored gradients are pre-multiplied by the
+/// weight, so the unweighted mismatch kernels yield the weighted
+/// right-hand side.
+#[derive(Default)]
+pub(crate) struct ReferenceWindow {
+    intensity: Vec<f32>,
+    ix: Vec<f32>,
+    iy: Vec<f32>,
+    /// Per-pixel weights, empty when unweighted.
+    weights: Vec<f32>,
+    /// Weighting by gradient magnitude applied by [`fill`](Self::fill).
+    gradient_weighting: Option<GradientWeighting>,
+}
+
+impl ReferenceWindow {
+    pub(crate) fn resize(&mut self, n_pixels: usize) {
+        self.intensity.resize(n_pixels, 0.0);
+        self.ix.resize(n_pixels, 0.0);
+        self.iy.resize(n_pixels, 0.0);
+    }
+
+    /// Installs the per-pixel weights used by subsequent [`fill`](Self::fill)s;
+    /// `None` weights every pixel by 1. Keeps the buffer's capacity.
+    fn set_weights(&mut self, weights: Option<&[f32]>) {
+        self.weights.clear();
+        if let Some(weights) = weights {
+            self.weights.extend_from_slice(weights);
+        }
+    }
+
+    /// Sets the weighting by gradient magnitude that subsequent
+    /// [`fill`](Self::fill)s multiply into the installed weights.
+    fn set_gradient_weighting(&mut self, weighting: Option<GradientWeighting>) {
+        self.gradient_weighting = weighting;
+    }
+
+    /// Samples the window centred on `(x, y)` and returns the spatial gradient
+    /// matrix `(gxx, gxy, gyy)`.
+    ///
+    /// Window offsets are whole pixels, so every sample shares the fractional
+    /// position of the center: when the full footprint is inside the image,
+    /// the bilinear weights are computed once and all three planes are read
+    /// through the same index. Near the border it falls back to per-sample
+    /// zero-padded interpolation.
+    pub(crate) fn fill(
+        &mut self,
+        img: &GrayImage,
+        gradients: &impl GradientSource,
+        x: f32,
+        y: f32,
+        (rx, ry): (usize, usize),
+        offsets: &[(f32, f32)],
+    ) -> (f32, f32, f32) {
+        let (w, h) = img.dimensions();
+        let x0 = x.floor() as i64;
+        let y0 = y.floor() as i64;
+        let (rx_i, ry_i) = (rx as i64, ry as i64);
+
+        let mut gxx = 0.0f32;
+        let mut gxy = 0.0f32;
+        let mut gyy = 0.0f32;
+
+        if x0 - rx_i >= 0 && y0 - ry_i >= 0 && x0 + rx_i + 1 < w as i64 && y0 + ry_i + 1 < h as i64
+        {
+            let stride = w as usize;
+            let data = img.as_raw();
+            let fx = x - x0 as f32;
+            let fy = y - y0 as f32;
+            let (gx, gy) = (1.0 - fx, 1.0 - fy);
+            let width = 2 * rx + 1;
+            let mut i = 0;
+
+            let col_start = (x0 - rx_i) as usize;
+            for row in (y0 - ry_i) as usize..=(y0 + ry_i) as usize {
+                for col in col_start..col_start + width {
+                    let base = row * stride + col;
+                    // SAFETY: the footprint check above keeps base, base + 1,
+                    // base + stride and base + stride + 1 inside the w*h planes,
+                    // and (col + 1, row + 1) inside the image.
+                    let (p, ix, iy) = unsafe {
+                        let bilinear_u8 = |b: usize| {
+                            *data.get_unchecked(b) as f32 * gx * gy
+                                + *data.get_unchecked(b + stride) as f32 * gx * fy
+                                + *data.get_unchecked(b + 1) as f32 * fx * gy
+                                + *data.get_unchecked(b + stride + 1) as f32 * fx * fy
+                        };
+                        let (g00, g01, g10, g11) = (
+                            gradients.get_unchecked(col, row),
+                            gradients.get_unchecked(col, row + 1),
+                            gradients.get_unchecked(col + 1, row),
+                            gradients.get_unchecked(col + 1, row + 1),
+                        );
+                        let bilinear_grad = |pick: fn((f32, f32)) -> f32| {
+                            pick(g00) * gx * gy
+                                + pick(g01) * gx * fy
+                                + pick(g10) * fx * gy
+                                + pick(g11) * fx * fy
+                        };
+                        (
+                            bilinear_u8(base),
+                            bilinear_grad(|g| g.0) / 32.0,
+                            bilinear_grad(|g| g.1) / 32.0,
+                        )
+                    };
+
+                    self.intensity[i] = p;
+                    self.ix[i] = ix;
+                    self.iy[i] = iy;
+                    gxx += ix * ix;
+                    gxy += ix * iy;
+                    gyy += iy * iy;
+                    i += 1;
+                }
+            }
+        } else {
+            for (i, (ox, oy)) in offsets.iter().enumerate() {
+                let sample_x = x + ox;
+                let sample_y = y + oy;
+                let (ix, iy) = interpolate_gradient(gradients, w, h, sample_x, sample_y);
+                let (ix, iy) = (ix / 32.0, iy / 32.0);
+
+                self.intensity[i] = interpolate(img, sample_x, sample_y);
+                self.ix[i] = ix;
+                self.iy[i] = iy;
+                gxx += ix * ix;
+                gxy += ix * iy;
+                gyy += iy * iy;
+            }
+        }
+
+        if let Some(weighting) = self.gradient_weighting {
+            if self.weights.is_empty() {
+                self.weights.resize(self.intensity.len(), 1.0);
+            }
+            for ((weight, ix), iy) in self.weights.iter_mut().zip(&self.ix).zip(&self.iy) {
+                *weight *= weighting.weight(ix.hypot(*iy));
+            }
+        }
+
+        if self.weights.is_empty() {
+            (gxx, gxy, gyy)
+        } else {
+            self.apply_weights()
+        }
+    }
+
+    /// Weights the sampled gradients in place and returns the weighted
+    /// spatial gradient matrix.
+    fn apply_weights(&mut self) -> (f32, f32, f32) {
+        let (mut gxx, mut gxy, mut gyy) = (0.0f32, 0.0f32, 0.0f32);
+        for ((ix, iy), &weight) in self.ix.iter_mut().zip(&mut self.iy).zip(&self.weights) {
+            gxx += weight * *ix * *ix;
+            gxy += weight * *ix * *iy;
+            gyy += weight * *iy * *iy;
+            *ix *= weight;
+            *iy *= weight;
+        }
+        (gxx, gxy, gyy)
+    }
+
+    /// Area of the window: its pixel count, or the sum of its weights.
+    fn area(&self) -> f32 {
+        if self.weights.is_empty() {
+            self.intensity.len() as f32
+        } else {
+            self.weights.iter().sum()
+        }
+    }
+
+    /// Zeroes the weight of every window pixel whose footprint touches a 0
+    /// pixel of the full-resolution `mask`, either around `(x, y)` in the
+    /// previous frame or displaced by `(dx, dy)` in the next. The footprint
+    /// spans two pixels of the level (`scale` full-resolution pixels each)
+    /// on every side, covering the bilinear samples and the gradient
+    /// support.
+    fn apply_mask(
+        &mut self,
+        mask: &ExclusionTable,
+        (x, y): (f32, f32),
+        (dx, dy): (f32, f32),
+        scale: f32,
+        offsets: &[(f32, f32)],
+    ) {
+        if self.weights.is_empty() {
+            self.weights.resize(offsets.len(), 1.0);
+        }
+        let margin = 2 * scale as i64;
+        let touches = |x: f32, y: f32| {
+            let (x, y) = rounded((x * scale, y * scale));
+            mask.any_excluded((x - margin, y - margin), (x + margin, y + margin))
+        };
+        for (weight, &(ox, oy)) in self.weights.iter_mut().zip(offsets) {
+            if touches(x + ox, y + oy) || touches(x + dx + ox, y + dy + oy) {
+                *weight = 0.0;
+            }
+        }
+    }
+
+    /// Multiplies the weight of every window pixel around `(x, y)` by the
+    /// full-resolution `map`, bilinearly sampled at the pixel's position
+    /// scaled up by the level's `scale`; negative samples count as 0.
+    fn apply_weight_map(
+        &mut self,
+        map: &WeightMap,
+        (x, y): (f32, f32),
+        scale: f32,
+        offsets: &[(f32, f32)],
+    ) {
+        if self.weights.is_empty() {
+            self.weights.resize(offsets.len(), 1.0);
+        }
+        for (weight, &(ox, oy)) in self.weights.iter_mut().zip(offsets) {
+            *weight *= interpolate_weight(map, (x + ox) * scale, (y + oy) * scale).max(0.0);
+        }
+    }
+
+    /// Samples the next image around `(x, y)` and accumulates the mismatch
+    /// against this window.
+    ///
+    /// With the footprint inside the image the window is processed row by row
+    /// with shared bilinear weights (SIMD where available, see
+    /// [`window_mismatch`]); near the border each sample is interpolated with
+    /// zero padding.
+    pub(crate) fn mismatch(
+        &self,
+        img: &GrayImage,
+        x: f32,
+        y: f32,
+        (rx, ry): (usize, usize),
+        offsets: &[(f32, f32)],
+    ) -> Mismatch {
+        let (w, h) = img.dimensions();
+        let x0 = x.floor() as i64;
+        let y0 = y.floor() as i64;
+        let (rx_i, ry_i) = (rx as i64, ry as i64);
+
+        if x0 - rx_i >= 0 && y0 - ry_i >= 0 && x0 + rx_i + 1 < w as i64 && y0 + ry_i + 1 < h as i64
+        {
+            let stride = w as usize;
+            let base = (y0 - ry_i) as usize * stride + (x0 - rx_i) as usize;
+            return window_mismatch(
+                img.as_raw(),
+                stride,
+                base,
+                (2 * rx + 1, 2 * ry + 1),
+                (x - x0 as f32, y - y0 as f32),
+                &self.intensity,
+                &self.ix,
+                &self.iy,
+            );
+        }
+
+        let mut acc = Mismatch::default();
+        for (i, (ox, oy)) in offsets.iter().enumerate() {
+            let error = self.intensity[i] - interpolate(img, x + ox, y + oy);
+            acc.bx += self.ix[i] * error;
+            acc.by += self.iy[i] * error;
+            acc.abs_sum += error.abs();
+        }
+        acc
+    }
+
+    /// [`mismatch`](Self::mismatch) under the photometric model
+    /// `next = gain * prev + bias` of `photometric`.
+    ///
+    /// The next-image samples are mapped back through gain and bias, so the
+    /// residual stays in previous-frame units and the spatial gradient matrix
+    /// of [`fill`](Self::fill) applies unchanged.
+    pub(crate) fn mismatch_photometric(
+        &self,
+        img: &GrayImage,
+        x: f32,
+        y: f32,
+        offsets: &[(f32, f32)],
+        photometric: Photometric,
+    ) -> Mismatch {
+        let (gain, bias) = self.gain_bias(img, x, y, offsets, photometric);
+        let mut acc = Mismatch::default();
+        for (i, (ox, oy)) in offsets.iter().enumerate() {
+            let compensated = (interpolate(img, x + ox, y + oy) - bias) / gain;
+            let error = self.intensity[i] - compensated;
+            acc.bx += self.ix[i] * error;
+            acc.by += self.iy[i] * error;
+            acc.abs_sum += error.abs();
+        }
+        acc
+    }
+
+    /// Gain and bias of `photometric` for the window sampled at `(x, y)`.
+    ///
+    /// A fitted model solves weighted least squares over the window; a fit
+    /// that is degenerate or inverts the contrast falls back to the model's
+    /// fallback gain, with the bias fitted under it. A zero-mean model only
+    /// fits the bias.
+    fn gain_bias(
+        &self,
+        img: &GrayImage,
+        x: f32,
+        y: f32,
+        offsets: &[(f32, f32)],
+        photometric: Photometric,
+    ) -> (f32, f32) {
+        let (fit_gain, fallback_gain) = match photometric {
+            Photometric::Identity => return (1.0, 0.0),
+            Photometric::Fixed(change) => return (change.gain, change.bias),
+            Photometric::Fitted { fallback_gain } => (true, fallback_gain),
+            Photometric::ZeroMean { gain } => (false, gain),
+        };
+        let weight = |i: usize| self.weights.get(i).copied().unwrap_or(1.0);
+        let (mut sw, mut sa, mut sb, mut saa, mut sab) = (0.0f32, 0.0, 0.0, 0.0, 0.0);
+        for (i, (ox, oy)) in offsets.iter().enumerate() {
+            let (w, a, b) = (
+                weight(i),
+                self.intensity[i],
+                interpolate(img, x + ox, y + oy),
+            );
+            sw += w;
+            sa += w * a;
+            sb += w * b;
+            saa += w * a * a;
+            sab += w * a * b;
+        }
+        let var_a = saa - sa * sa / sw;
+        let fitted = (sab - sa * sb / sw) / var_a;
+        let gain = if fit_gain && var_a > 1e-6 && fitted.is_finite() && fitted > 0.0 {
+            fitted
+        } else {
+            fallback_gain
+        };
+        let bias = (sb - gain * sa) / sw;
+        (gain, bias)
+    }
+
+    /// Robustly reweighted mismatch against the next image sampled at
+    /// `(x, y)`, together with the reweighted spatial gradient matrix
+    /// `(gxx, gxy, gyy)` (see [`TrackerContext::set_robust_loss`]).
+    ///
+    /// Every pixel's weight is `loss` of its residual over the robust scale
+    /// of the window, times its window weight if any. The residuals are those
+    /// of [`mismatch_photometric`](Self::mismatch_photometric).
+    fn mismatch_robust(
+        &self,
+        img: &GrayImage,
+        (x, y): (f32, f32),
+        offsets: &[(f32, f32)],
+        loss: RobustLoss,
+        photometric: Photometric,
+        buffers: &mut RobustBuffers,
+    ) -> (Mismatch, (f32, f32, f32)) {
+        let (gain, bias) = self.gain_bias(img, x, y, offsets, photometric);
+        let weight = |i: usize| self.weights.get(i).copied().unwrap_or(1.0);
+        let RobustBuffers {
+            residuals,
+            magnitudes,
+        } = buffers;
+        residuals.clear();
+        residuals.extend(offsets.iter().enumerate().map(|(i, (ox, oy))| {
+            let compensated = (interpolate(img, x + ox, y + oy) - bias) / gain;
+            self.intensity[i] - compensated
+        }));
+
+        // Scale from the median absolute residual of the pixels that count.
+        magnitudes.clear();
+        magnitudes.extend(
+            residuals
+                .iter()
+                .enumerate()
+                .filter(|&(i, _)| weight(i) > 0.0)
+                .map(|(_, r)| r.abs()),
+        );
+        let scale = if magnitudes.is_empty() {
+            1.0
+        } else {
+            let middle = magnitudes.len() / 2;
+            let (_, median, _) = magnitudes.select_nth_unstable_by(middle, f32::total_cmp);
+            (1.4826 * *median).max(1.0)
+        };
+
+        // The stored gradients carry the window weight, so the matrix divides
+        // it out once.
+        let mut acc = Mismatch::default();
+        let (mut gxx, mut gxy, mut gyy) = (0.0f32, 0.0f32, 0.0f32);
+        for (i, &error) in residuals.iter().enumerate() {
+            let window_weight = weight(i);
+            if window_weight <= 0.0 {
+                continue;
+            }
+            let robust_weight = loss.weight(error / scale);
+            let (ix, iy) = (self.ix[i], self.iy[i]);
+            acc.bx += robust_weight * ix * error;
+            acc.by += robust_weight * iy * error;
+            acc.abs_sum += error.abs();
+            let factor = robust_weight / window_weight;
+            gxx += factor * ix * ix;
+            gxy += factor * ix * iy;
+            gyy += factor * iy * iy;
+        }
+        (acc, (gxx, gxy, gyy))
+    }
+
+    /// Mean absolute photometric residual between this window and the next
+    /// image sampled at `(x, y)`, weighted by the per-pixel weights if any.
+    /// Returns [`f32::INFINITY`] if the window is out of bounds.
+    pub(crate) fn mean_error(
+        &self,
+        img: &GrayImage,
+        x: f32,
+        y: f32,
+        radius: (usize, usize),
+        offsets: &[(f32, f32)],
+    ) -> f32 {
+        if !in_bounds_rect(img, x, y, radius) {
+            return f32::INFINITY;
+        }
+        if self.weights.is_empty() {
+            return self.mismatch(img, x, y, radius, offsets).abs_sum / offsets.len() as f32;
+        }
+
+        let mut sum = 0.0;
+        for (i, (ox, oy)) in offsets.iter().enumerate() {
+            let error = self.intensity[i] - interpolate(img, x + ox, y + oy);
+            sum += self.weights[i] * error.abs();
+        }
+        sum / self.area()
+    }
+
+    /// Zero-mean normalized cross-correlation in `[-1, 1]` between this window
+    /// and the next image sampled at `(x, y)`, weighted by the per-pixel
+    /// weights if any. `None` if the window is out of bounds or either patch is
+    /// flat.
+    pub(crate) fn zncc(
+        &self,
+        img: &GrayImage,
+        x: f32,
+        y: f32,
+        radius: (usize, usize),
+        offsets: &[(f32, f32)],
+    ) -> Option<f32> {
+        if !in_bounds_rect(img, x, y, radius) {
+            return None;
+        }
+        let weight = |i: usize| self.weights.get(i).copied().unwrap_or(1.0);
+        let (mut sw, mut sa, mut sb, mut saa, mut sbb, mut sab) = (0.0f32, 0.0, 0.0, 0.0, 0.0, 0.0);
+        for (i, (ox, oy)) in offsets.iter().enumerate() {
+            let (w, a, b) = (
+                weight(i),
+                self.intensity[i],
+                interpolate(img, x + ox, y + oy),
+            );
+            sw += w;
+            sa += w * a;
+            sb += w * b;
+            saa += w * a * a;
+            sbb += w * b * b;
+            sab += w * a * b;
+        }
+        let var_a = saa - sa * sa / sw;
+        let var_b = sbb - sb * sb / sw;
+        if var_a <= 1e-6 || var_b <= 1e-6 {
+            return None;
+        }
+        Some((sab - sa * sb / sw) / (var_a * var_b).sqrt())
+    }
+}
+
+/// Quarter-pixel ZNCC search within ±1 px of `(x, y)`, see
+/// [`LkFlags::ZNCC_REFINE`]. Returns `(x, y)` unchanged unless another grid
+/// position correlates strictly better.
+fn refine_zncc(
+    reference: &ReferenceWindow,
+    img: &GrayImage,
+    (x, y): (f32, f32),
+    radius: (usize, usize),
+    offsets: &[(f32, f32)],
+) -> (f32, f32) {
+    const STEPS: i32 = 4;
+    const STEP: f32 = 0.25;
+    const SIDE: usize = 2 * STEPS as usize + 1;
+
+    let mut scores = [[f32::NEG_INFINITY; SIDE]; SIDE];
+    for (j, row) in scores.iter_mut().enumerate() {
+        for (i, score) in row.iter_mut().enumerate() {
+            let (ox, oy) = (
+                (i as i32 - STEPS) as f32 * STEP,
+                (j as i32 - STEPS) as f32 * STEP,
+            );
+            if let Some(value) = reference.zncc(img, x + ox, y + oy, radius, offsets) {
+                *score = value;
+            }
+        }
+    }
+
+    let center = STEPS as usize;
+    let (mut bi, mut bj) = (center, center);
+    for (j, row) in scores.iter().enumerate() {
+        for (i, &score) in row.iter().enumerate() {
+            if score > scores[bj][bi] {
+                (bi, bj) = (i, j);
+            }
+        }
+    }
+    if (bi, bj) == (center, center) {
+        return (x, y);
+    }
+
+    // Vertex of the parabola through the best score and its two neighbors.
+    let vertex = |left: f32, mid: f32, right: f32| {
+        let curvature = left - 2.0 * mid + right;
+        if left.is_finite() && right.is_finite() && curvature < 0.0 {
+            (0.5 * (left - right) / curvature).clamp(-0.5, 0.5)
+        } else {
+            0.0
+        }
+    };
+    let best = scores[bj][bi];
+    let sub_x = if bi > 0 && bi + 1 < SIDE {
+        vertex(scores[bj][bi - 1], best, scores[bj][bi + 1])
+    } else {
+        0.0
+    };
+    let sub_y = if bj > 0 && bj + 1 < SIDE {
+        vertex(scores[bj - 1][bi], best, scores[bj + 1][bi])
+    } else {
+        0.0
+    };
+    (
+        x + ((bi as i32 - STEPS) as f32 + sub_x) * STEP,
+        y + ((bj as i32 - STEPS) as f32 + sub_y) * STEP,
+    )
 }
 
 /// Core pyramidal Lucas-Kanade loop, writing one [`TrackResult`] per point into
 /// `out`. All temporaries live in `scratch`; given sufficient capacity this is
 /// allocation-free.
+///
+/// When `timing` is `Some`, stage durations are *added* to the report (so the
+/// two passes of a forward-backward check accumulate into one report); the
+/// caller resets it.
+///
+/// `prev_gradients`, when given, holds the gradients of every `prev_pyramid`
+/// level (see [`TrackerContext::prepare`]); otherwise they are computed per
+/// level into `scratch`.
+///
+/// `params` supplies the solver settings and the per-pixel weights; its
+/// seeding and post-checks are the caller's, and its mask comes as the
+/// `mask` table, whose excluded pixels are left out of every window.
 #[allow(clippy::too_many_arguments)]
 fn track_into(
     prev_pyramid: &[GrayImage],
+    prev_gradients: Option<PrecomputedGradients>,
     curr_pyramid: &[GrayImage],
     prev_points: &[(f32, f32)],
     predicted: Option<&[(f32, f32)]>,
-    window_size: usize,
-    max_iterations: usize,
-    min_eigen_threshold: f32,
+    windows: Windows,
+    params: &FlowParams,
+    mask: Option<&ExclusionTable>,
+    mut timing: Option<(TimingClock, &mut TimingReport)>,
     scratch: &mut Scratch,
     out: &mut Vec<TrackResult>,
 ) {
-    assert_eq!(prev_pyramid.len(), curr_pyramid.len());
-    assert!(
-        !prev_pyramid.is_empty(),
-        "pyramid must have at least 1 level"
-    );
-    assert!(window_size % 2 == 1, "Window size must be odd");
+    let prev_pyramid = used_levels(prev_pyramid, params.max_level);
+    let curr_pyramid = used_levels(curr_pyramid, params.max_level);
+    if let Err(error) = validate_pyramid_pair(prev_pyramid, curr_pyramid) {
+        panic!("{error}");
+    }
+    windows.validate(prev_points.len());
+    let frame = prev_pyramid[0].dimensions();
+    if let Some(mask) = mask {
+        assert_eq!(mask.dimensions(), frame, "mask must match the frame size");
+    }
+    if let Some(map) = params.weight_map {
+        assert_eq!(
+            map.dimensions(),
+            frame,
+            "weight map must match the frame size"
+        );
+    }
+    let FlowParams {
+        epsilon,
+        min_eigen_threshold,
+        flags,
+        robust_loss,
+        exposure,
+        gradient_weighting: gradient,
+        weight_map: map,
+        level_early_exit,
+        ..
+    } = *params;
+    let iterations = params.iterations();
     if let Some(predicted) = predicted {
         assert_eq!(
             predicted.len(),
@@ -190,27 +1750,25 @@ fn track_into(
     }
 
     let n_levels = prev_pyramid.len();
-    let radius = window_size / 2;
-    let n_pixels = window_size * window_size;
-    let epsilon = 1e-3;
     let det_epsilon = 1e-6;
+    let min_eigen_error = flags.contains(LkFlags::GET_MIN_EIGENVALS);
+    let zncc_refine = flags.contains(LkFlags::ZNCC_REFINE);
+    let photometric = Photometric::new(flags, exposure);
 
     let Scratch {
         offsets,
-        prev_patch,
-        ix_patch,
-        iy_patch,
+        reference,
         displacements,
-        grad_x: grad_x_buf,
-        grad_y: grad_y_buf,
+        active,
+        references,
+        gradients: tiles,
+        robust,
+        #[cfg(feature = "debug-trace")]
+        trace,
     } = scratch;
 
-    // Prepare reusable buffers. resize/clear+extend keep capacity, so none of
-    // this allocates once the buffers are warm.
-    build_window_offsets_into(radius, offsets);
-    prev_patch.resize(n_pixels, 0.0);
-    ix_patch.resize(n_pixels, 0.0);
-    iy_patch.resize(n_pixels, 0.0);
+    #[cfg(feature = "debug-trace")]
+    trace.begin();
 
     // Total displacement per point, accumulated coarse-to-fine in level-0 units.
     // Seeding it from a prediction makes the coarsest level start at the
@@ -223,21 +1781,25 @@ fn track_into(
                 .zip(predicted.iter())
                 .map(|((px, py), (gx, gy))| (gx - px, gy - py)),
         ),
+        None if flags.contains(LkFlags::PREALIGN) => {
+            let (dx, dy) = estimate_global_shift(prev_pyramid, curr_pyramid);
+            displacements.resize(prev_points.len(), (dx as f32, dy as f32));
+        }
         None => displacements.resize(prev_points.len(), (0.0, 0.0)),
     }
 
-    // One shared gradient buffer sized to the largest (level-0) image; smaller
-    // levels use a prefix slice.
-    let (w0, h0) = prev_pyramid[0].dimensions();
-    grad_x_buf.resize((w0 * h0) as usize, 0);
-    grad_y_buf.resize((w0 * h0) as usize, 0);
-
     // Initialize results at the input positions; the loop refines them in place.
     out.clear();
     out.extend(prev_points.iter().map(|&(x, y)| TrackResult {
         pos: (x, y),
-        status: TrackStatus::Tracked,
+        status: match mask {
+            Some(mask) if mask.any_excluded(rounded((x, y)), rounded((x, y))) => {
+                TrackStatus::Masked
+            }
+            _ => TrackStatus::Tracked,
+        },
         error: f32::INFINITY,
+        aperture: None,
     }));
 
     // Process levels from top (coarse) to bottom (fine).
@@ -247,129 +1809,309 @@ fn track_into(
 
         let prev_img = &prev_pyramid[level];
         let curr_img = &curr_pyramid[level];
-        let (lw, lh) = prev_img.dimensions();
-        let level_pixels = (lw * lh) as usize;
 
-        compute_gradients_into(
-            prev_img,
-            &mut grad_x_buf[..level_pixels],
-            &mut grad_y_buf[..level_pixels],
+        // Without precomputed gradients, only the tiles under the windows are
+        // computed (see `TiledGradients`); dense point sets fall back to one
+        // full-frame pass here. Lazily filled tiles count towards `solve_ms`.
+        let level_start = timing.as_ref().map(|(clock, _)| clock());
+        if prev_gradients.is_none() {
+            tiles.reset(prev_img, prev_points.len(), windows.max_size());
+        }
+        let gradients_end = timing.as_ref().map(|(clock, _)| clock());
+
+        // With a quota, the points refine in rounds of a growing iteration
+        // budget, so the level ends once the quota of points has converged
+        // whatever their input order.
+        let quota = level_early_exit
+            .filter(|_| !is_finest)
+            .map(|fraction| (fraction * prev_points.len() as f32).ceil() as usize);
+        let level_budget = iterations.get(level);
+        let mut budget = if quota.is_some() { 1 } else { level_budget };
+        let mut level_converged = 0;
+        active.clear();
+        active.extend(
+            (0..prev_points.len())
+                .filter(|&idx| out[idx].status != TrackStatus::Masked)
+                .map(|idx| ActivePoint {
+                    idx,
+                    used: 0,
+                    inverse: None,
+                }),
         );
-        let grad_x = &grad_x_buf[..level_pixels];
-        let grad_y = &grad_y_buf[..level_pixels];
-
-        for (idx, (prev_x, prev_y)) in prev_points.iter().enumerate() {
-            // Scale the original point for the current level.
-            let x = *prev_x / scale;
-            let y = *prev_y / scale;
-
-            // Add the current displacement, scaled for this level.
-            let mut dx = displacements[idx].0 / scale;
-            let mut dy = displacements[idx].1 / scale;
-
-            // The window must stay inside the previous image to build the patch.
-            if !in_bounds(prev_img, x, y, radius) {
-                out[idx].status = TrackStatus::OutOfBounds;
-                continue;
-            }
-
-            // Spatial gradient matrix and cached previous/gradient patches.
-            let mut gxx = 0.0f32;
-            let mut gxy = 0.0f32;
-            let mut gyy = 0.0f32;
-
-            for (i, (ox, oy)) in offsets.iter().enumerate() {
-                let sample_x = x + ox;
-                let sample_y = y + oy;
-                let ix = interpolate_i16(grad_x, lw, lh, sample_x, sample_y) / 32.0;
-                let iy = interpolate_i16(grad_y, lw, lh, sample_x, sample_y) / 32.0;
-
-                prev_patch[i] = interpolate(prev_img, sample_x, sample_y);
-                ix_patch[i] = ix;
-                iy_patch[i] = iy;
-                gxx += ix * ix;
-                gxy += ix * iy;
-                gyy += iy * iy;
-            }
+        // In rounds, every point keeps its sampled window until it stops
+        // refining, so later rounds only resume iterating.
+        if quota.is_some() && references.len() < active.len() {
+            references.resize_with(active.len(), ReferenceWindow::default);
+        }
 
-            // Reject low-texture windows up front (normalized by window area so
-            // the threshold does not depend on `window_size`).
-            let min_eig = min_eigenvalue(gxx, gxy, gyy) / n_pixels as f32;
-            if min_eig < min_eigen_threshold {
-                out[idx].status = TrackStatus::LowTexture;
-                if is_finest {
-                    out[idx].error =
-                        window_error(curr_img, prev_patch, offsets, x + dx, y + dy, radius);
+        loop {
+            let mut kept = 0;
+            for slot in 0..active.len() {
+                let ActivePoint { idx, used, inverse } = active[slot];
+                let (prev_x, prev_y) = prev_points[idx];
+
+                // Scale the original point for the current level.
+                let x = prev_x / scale;
+                let y = prev_y / scale;
+
+                // Add the current displacement, scaled for this level.
+                let mut dx = displacements[idx].0 / scale;
+                let mut dy = displacements[idx].1 / scale;
+
+                #[cfg(feature = "debug-trace")]
+                let mut records = trace.level(idx, level);
+
+                let window = windows.get(idx);
+                let radius = window.radius();
+                let n_pixels = window.width * window.height;
+                // The last offset is the bottom-right corner, which fixes the shape.
+                if offsets.last() != Some(&(radius.0 as f32, radius.1 as f32)) {
+                    build_rect_offsets_into(radius, offsets);
                 }
-                continue;
-            }
+                let reference = match quota {
+                    Some(_) => &mut references[slot],
+                    None => &mut *reference,
+                };
 
-            let Some((inv_h00, inv_h01, inv_h11)) = invert_2x2(gxx, gxy, gyy, det_epsilon) else {
-                out[idx].status = TrackStatus::LowTexture;
-                continue;
-            };
-
-            // Refine the displacement at the current level.
-            let mut converged = false;
-            let mut out_of_bounds = false;
-            let mut diverged = false;
-            for _ in 0..max_iterations {
-                let curr_x = x + dx;
-                let curr_y = y + dy;
-
-                if !in_bounds(curr_img, curr_x, curr_y, radius) {
-                    out_of_bounds = true;
-                    break;
-                }
+                let inverse = match inverse {
+                    Some(inverse) => inverse,
+                    None => {
+                        // Prepare the window's reusable buffers. resize/clear+extend keep
+                        // capacity, so none of this allocates once the buffers are warm.
+                        reference.resize(n_pixels);
+                        reference.set_weights(window.weights);
+                        reference.set_gradient_weighting(gradient);
+
+                        // The window must stay inside the previous image to build the patch.
+                        if !in_bounds_rect(prev_img, x, y, radius) {
+                            out[idx].status = TrackStatus::OutOfBounds;
+                            continue;
+                        }
+
+                        if let Some(mask) = mask {
+                            reference.apply_mask(mask, (x, y), (dx, dy), scale, offsets);
+                        }
+                        if let Some(map) = map {
+                            reference.apply_weight_map(map, (x, y), scale, offsets);
+                        }
+                        if (mask.is_some() || map.is_some()) && reference.area() <= 0.0 {
+                            out[idx].status = TrackStatus::LowTexture;
+                            continue;
+                        }
+
+                        // Spatial gradient matrix and cached previous/gradient patches;
+                        // only the next-frame window is re-sampled per iteration.
+                        let (gxx, gxy, gyy) = match prev_gradients {
+                            Some(PrecomputedGradients::Full(gradients)) => {
+                                let (grad_x, grad_y) = &gradients[level];
+                                let planes =
+                                    GradientPlanes::new(prev_img.width() as usize, grad_x, grad_y);
+                                reference.fill(prev_img, &planes, x, y, radius, offsets)
+                            }
+                            Some(PrecomputedGradients::Quantized(gradients)) => {
+                                reference.fill(prev_img, &gradients[level], x, y, radius, offsets)
+                            }
+                            None => {
+                                // Footprint of the bilinear window, as read by `fill`.
+                                let (x0, y0) = (x.floor() as i64, y.floor() as i64);
+                                let (rx, ry) = (radius.0 as i64, radius.1 as i64);
+                                tiles.ensure(prev_img, x0 - rx..x0 + rx + 2, y0 - ry..y0 + ry + 2);
+                                reference.fill(prev_img, &tiles.planes(), x, y, radius, offsets)
+                            }
+                        };
+                        // A window without gradient has no weight left to normalize by.
+                        if gradient.is_some() && reference.area() <= 0.0 {
+                            out[idx].status = TrackStatus::LowTexture;
+                            continue;
+                        }
+
+                        // Reject low-texture windows up front (normalized by window area so
+                        // the threshold does not depend on `window_size`).
+                        let min_eig = min_eigenvalue(gxx, gxy, gyy) / reference.area();
+                        if is_finest {
+                            out[idx].aperture = Some(Aperture::classify(
+                                gxx,
+                                gxy,
+                                gyy,
+                                reference.area(),
+                                min_eigen_threshold,
+                            ));
+                            if min_eigen_error {
+                                out[idx].error = min_eig;
+                            }
+                        }
+                        if min_eig < min_eigen_threshold {
+                            out[idx].status = TrackStatus::LowTexture;
+                            if is_finest && !min_eigen_error {
+                                out[idx].error =
+                                    reference.mean_error(curr_img, x + dx, y + dy, radius, offsets);
+                            }
+                            continue;
+                        }
+
+                        let Some(inverse) = invert_2x2(gxx, gxy, gyy, det_epsilon) else {
+                            out[idx].status = TrackStatus::LowTexture;
+                            continue;
+                        };
+                        inverse
+                    }
+                };
 
-                let mut bx = 0.0f32;
-                let mut by = 0.0f32;
+                // Refine the displacement at the current level.
+                let mut converged = false;
+                let mut out_of_bounds = false;
+                let mut diverged = false;
+                let mut used = used;
+                while used < budget {
+                    used += 1;
+                    let curr_x = x + dx;
+                    let curr_y = y + dy;
+
+                    if !in_bounds_rect(curr_img, curr_x, curr_y, radius) {
+                        out_of_bounds = true;
+                        break;
+                    }
+
+                    let (mismatch, (inv_h00, inv_h01, inv_h11)) = match robust_loss {
+                        Some(loss) => {
+                            let (mismatch, (hxx, hxy, hyy)) = reference.mismatch_robust(
+                                curr_img,
+                                (curr_x, curr_y),
+                                offsets,
+                                loss,
+                                photometric,
+                                robust,
+                            );
+                            // A window the loss rejects almost entirely keeps the
+                            // unweighted matrix.
+                            let inverse = invert_2x2(hxx, hxy, hyy, det_epsilon).unwrap_or(inverse);
+                            (mismatch, inverse)
+                        }
+                        None if photometric != Photometric::Identity => (
+                            reference.mismatch_photometric(
+                                curr_img,
+                                curr_x,
+                                curr_y,
+                                offsets,
+                                photometric,
+                            ),
+                            inverse,
+                        ),
+                        None => (
+                            reference.mismatch(curr_img, curr_x, curr_y, radius, offsets),
+                            inverse,
+                        ),
+                    };
+                    let Mismatch {
+                        bx,
+                        by,
+                        #[cfg(feature = "debug-trace")]
+                            abs_sum: abs_residual,
+                        ..
+                    } = mismatch;
+
+                    let ddx = inv_h00 * bx + inv_h01 * by;
+                    let ddy = inv_h01 * bx + inv_h11 * by;
+                    dx += ddx;
+                    dy += ddy;
+
+                    #[cfg(feature = "debug-trace")]
+                    if let Some(records) = records.as_mut() {
+                        records.push(crate::debug_trace::IterationRecord {
+                            dx,
+                            dy,
+                            residual: abs_residual / n_pixels as f32,
+                        });
+                    }
+
+                    // Guard against runaway steps.
+                    if !dx.is_finite()
+                        || !dy.is_finite()
+                        || ddx.abs() > window.width as f32
+                        || ddy.abs() > window.height as f32
+                    {
+                        diverged = true;
+                        break;
+                    }
+
+                    if ddx.abs() < epsilon && ddy.abs() < epsilon {
+                        converged = true;
+                        break;
+                    }
+                }
 
-                for (i, (ox, oy)) in offsets.iter().enumerate() {
-                    let curr = interpolate(curr_img, curr_x + ox, curr_y + oy);
-                    let error = prev_patch[i] - curr;
-                    bx += ix_patch[i] * error;
-                    by += iy_patch[i] * error;
+                out[idx].status = if out_of_bounds {
+                    TrackStatus::OutOfBounds
+                } else if diverged || !converged {
+                    TrackStatus::Diverged
+                } else {
+                    TrackStatus::Tracked
+                };
+                level_converged += converged as usize;
+                // Out of this round's budget, but not of the level's.
+                let refining = !(converged || out_of_bounds || diverged) && used < level_budget;
+
+                if is_finest && zncc_refine && out[idx].status == TrackStatus::Tracked {
+                    let (rx, ry) =
+                        refine_zncc(reference, curr_img, (x + dx, y + dy), radius, offsets);
+                    (dx, dy) = (rx - x, ry - y);
                 }
 
-                let ddx = inv_h00 * bx + inv_h01 * by;
-                let ddy = inv_h01 * bx + inv_h11 * by;
-                dx += ddx;
-                dy += ddy;
-
-                // Guard against runaway steps.
-                if !dx.is_finite()
-                    || !dy.is_finite()
-                    || ddx.abs() > window_size as f32
-                    || ddy.abs() > window_size as f32
-                {
-                    diverged = true;
-                    break;
+                // Update the total displacement with the current level scale.
+                displacements[idx] = (dx * scale, dy * scale);
+
+                if is_finest && !min_eigen_error {
+                    out[idx].error = if out_of_bounds {
+                        f32::INFINITY
+                    } else if photometric != Photometric::Identity
+                        && in_bounds_rect(curr_img, x + dx, y + dy, radius)
+                    {
+                        let mismatch = reference.mismatch_photometric(
+                            curr_img,
+                            x + dx,
+                            y + dy,
+                            offsets,
+                            photometric,
+                        );
+                        mismatch.abs_sum / offsets.len() as f32
+                    } else {
+                        reference.mean_error(curr_img, x + dx, y + dy, radius, offsets)
+                    };
                 }
 
-                if ddx.abs() < epsilon && ddy.abs() < epsilon {
-                    converged = true;
-                    break;
+                if refining {
+                    active[kept] = ActivePoint {
+                        idx,
+                        used,
+                        inverse: Some(inverse),
+                    };
+                    if quota.is_some() {
+                        references.swap(slot, kept);
+                    }
+                    kept += 1;
                 }
             }
+            active.truncate(kept);
+
+            // The points still refining carry their displacement to the next
+            // level once enough points have converged.
+            if active.is_empty()
+                || budget == level_budget
+                || quota.is_some_and(|quota| level_converged >= quota)
+            {
+                break;
+            }
+            budget = (2 * budget).min(level_budget);
+        }
 
-            out[idx].status = if out_of_bounds {
-                TrackStatus::OutOfBounds
-            } else if diverged || !converged {
-                TrackStatus::Diverged
-            } else {
-                TrackStatus::Tracked
-            };
-
-            // Update the total displacement with the current level scale.
-            displacements[idx] = (dx * scale, dy * scale);
-
-            if is_finest {
-                out[idx].error = if out_of_bounds {
-                    f32::INFINITY
-                } else {
-                    window_error(curr_img, prev_patch, offsets, x + dx, y + dy, radius)
-                };
+        if let (Some((clock, report)), Some(start), Some(grad_end)) =
+            (timing.as_mut(), level_start, gradients_end)
+        {
+            let end = clock();
+            report.gradients_ms += grad_end - start;
+            report.solve_ms += end - grad_end;
+            if let Some(slot) = report.per_level_ms.get_mut(level) {
+                *slot += end - start;
             }
         }
     }
@@ -414,111 +2156,276 @@ pub fn calc_optical_flow_fb(
     min_eigen_threshold: f32,
     fb_threshold: f32,
 ) -> Vec<TrackResult> {
-    let mut scratch = Scratch::default();
-    let mut forward = Vec::new();
-    track_into(
-        prev_pyramid,
-        next_pyramid,
-        prev_points,
-        predicted,
+    let params = FlowParams {
         window_size,
         max_iterations,
         min_eigen_threshold,
-        &mut scratch,
-        &mut forward,
-    );
+        ..FlowParams::default()
+    };
+    let mut forward =
+        calc_optical_flow_with(prev_pyramid, next_pyramid, prev_points, predicted, &params);
 
     let forward_pos: Vec<(f32, f32)> = forward.iter().map(|r| r.pos).collect();
-    let mut backward = Vec::new();
     // Seed the backward pass at the original points (the round-trip is expected
     // to return there). This keeps the check robust under large motion, as in
     // OpenCV's OPTFLOW_USE_INITIAL_FLOW reverse check, without weakening it: a
     // genuinely wrong forward match still fails to land back within threshold.
-    track_into(
+    let backward = calc_optical_flow_with(
         next_pyramid,
         prev_pyramid,
         &forward_pos,
         Some(prev_points),
-        window_size,
-        max_iterations,
-        min_eigen_threshold,
-        &mut scratch,
-        &mut backward,
+        &params,
     );
 
     mark_fb_inconsistent(&mut forward, &backward, prev_points, fb_threshold);
     forward
 }
 
-/// Flag forward-tracked points whose backward round-trip exceeds the threshold.
-/// Factored out so the [`TrackerContext`] path can reuse it without allocating.
-fn mark_fb_inconsistent(
-    forward: &mut [TrackResult],
-    backward: &[TrackResult],
+/// Tracks `prev_points` from the previous frame into the next one and
+/// `next_points` from the next frame back into the previous one, in one call.
+///
+/// Occlusion reasoning and consistency features need flow in both directions.
+/// Calling [`calc_optical_flow_with`] twice schedules each direction's
+/// gradient pass on its own; here the gradients of both pyramids are computed
+/// once up front and every level is shared by all points of its direction.
+/// With the `rayon` feature the two gradient passes, and then the two tracking
+/// passes, run in parallel.
+///
+/// Unlike [`calc_optical_flow_fb`], the two point sets are independent: the
+/// backward pass does not start from the forward results. Both directions
+/// track with `params`, except that the backward one undoes
+/// `params.exposure` and goes without `params.prior`, `params.weight_map`
+/// and [`LkFlags::PREALIGN`], which describe or estimate the forward motion
+/// and the previous frame.
+///
+/// # Arguments
+/// * `prev_pyramid` / `next_pyramid` - frame pyramids (shared by both passes)
+/// * `prev_points` - points to track forward (previous-frame, level-0
+///   coordinates)
+/// * `next_points` - points to track backward (next-frame, level-0
+///   coordinates)
+/// * `params` - tracker settings, as in [`calc_optical_flow_with`]
+///
+/// # Panics
+/// Panics as [`calc_optical_flow_with`] does without `predicted`, before
+/// computing any gradients.
+///
+/// # Returns
+/// The forward results, one per `prev_point`, and the backward results, one
+/// per `next_point`.
+pub fn calc_optical_flow_bidirectional(
+    prev_pyramid: &[GrayImage],
+    next_pyramid: &[GrayImage],
+    prev_points: &[(f32, f32)],
+    next_points: &[(f32, f32)],
+    params: &FlowParams,
+) -> (Vec<TrackResult>, Vec<TrackResult>) {
+    assert!(
+        !params.flags.contains(LkFlags::USE_INITIAL_FLOW),
+        "USE_INITIAL_FLOW requires predicted positions"
+    );
+    params.validate();
+    let prev_levels = used_levels(prev_pyramid, params.max_level);
+    let next_levels = used_levels(next_pyramid, params.max_level);
+    if let Err(error) = validate_pyramid_pair(prev_levels, next_levels) {
+        panic!("{error}");
+    }
+
+    let mut prev_gradients = Vec::new();
+    let mut next_gradients = Vec::new();
+    join(
+        || compute_pyramid_gradients_into(prev_levels, &mut prev_gradients),
+        || compute_pyramid_gradients_into(next_levels, &mut next_gradients),
+    );
+
+    let backward_params = FlowParams {
+        flags: params.flags.without(LkFlags::PREALIGN),
+        exposure: params.exposure.map(|change| change.inverse()),
+        prior: None,
+        weight_map: None,
+        ..*params
+    };
+    let track = |source: &[GrayImage],
+                 gradients: &[LevelGradients],
+                 target: &[GrayImage],
+                 points: &[(f32, f32)],
+                 params: &FlowParams| {
+        flow_with(
+            source,
+            Some(PrecomputedGradients::Full(gradients)),
+            target,
+            points,
+            None,
+            Windows::Uniform(params.window_size),
+            params,
+        )
+    };
+    let mut forward = Vec::new();
+    let mut backward = Vec::new();
+    join(
+        || {
+            forward = track(
+                prev_pyramid,
+                &prev_gradients,
+                next_pyramid,
+                prev_points,
+                params,
+            )
+        },
+        || {
+            backward = track(
+                next_pyramid,
+                &next_gradients,
+                prev_pyramid,
+                next_points,
+                &backward_params,
+            )
+        },
+    );
+
+    (forward, backward)
+}
+
+/// Flag forward-tracked points whose backward round-trip exceeds the threshold.
+/// Factored out so the [`TrackerContext`] path can reuse it without allocating.
+fn mark_fb_inconsistent(
+    forward: &mut [TrackResult],
+    backward: &[TrackResult],
+    prev_points: &[(f32, f32)],
+    fb_threshold: f32,
+) {
+    let threshold_sq = fb_threshold * fb_threshold;
+    for (idx, result) in forward.iter_mut().enumerate() {
+        // Only forward-tracked points are eligible; others keep their status.
+        if result.status != TrackStatus::Tracked {
+            continue;
+        }
+
+        let back = &backward[idx];
+        let dx = back.pos.0 - prev_points[idx].0;
+        let dy = back.pos.1 - prev_points[idx].1;
+        if back.status != TrackStatus::Tracked || dx * dx + dy * dy > threshold_sq {
+            result.status = TrackStatus::FbInconsistent;
+        }
+    }
+}
+
+/// Seeds every point for the refinement by exhaustive integer SAD search on
+/// the coarsest level of the pyramids: the point's window is matched within
+/// `radius` level pixels of its predicted displacement (none without
+/// `predicted`). Points whose window leaves the frames keep the prediction.
+fn block_search_seeds(
+    prev_pyramid: &[GrayImage],
+    curr_pyramid: &[GrayImage],
+    prev_points: &[(f32, f32)],
+    predicted: Option<&[(f32, f32)]>,
+    windows: Windows,
+    radius: u32,
+    seeds: &mut Vec<(f32, f32)>,
+) {
+    seeds.clear();
+    seeds.extend_from_slice(predicted.unwrap_or(prev_points));
+    // Mismatched pyramids are reported by the tracking itself.
+    let level = prev_pyramid.len().saturating_sub(1);
+    let (Some(prev), Some(next)) = (prev_pyramid.get(level), curr_pyramid.get(level)) else {
+        return;
+    };
+    let scale = 2f32.powi(level as i32);
+    let (width, height) = (prev.width() as i64, prev.height() as i64);
+
+    for (idx, (seed, &(px, py))) in seeds.iter_mut().zip(prev_points).enumerate() {
+        let (x, y) = ((px / scale).round() as i64, (py / scale).round() as i64);
+        let (rx, ry) = windows.get(idx).radius();
+        let (x0, y0) = ((x - rx as i64).max(0), (y - ry as i64).max(0));
+        let (x1, y1) = (
+            (x + rx as i64 + 1).min(width),
+            (y + ry as i64 + 1).min(height),
+        );
+        if x0 >= x1 || y0 >= y1 {
+            continue;
+        }
+        let block = (x0 as u32, y0 as u32, (x1 - x0) as u32, (y1 - y0) as u32);
+        let initial = (
+            ((seed.0 - px) / scale).round() as i32,
+            ((seed.1 - py) / scale).round() as i32,
+        );
+        if let Some((_, dx, dy)) = best_match(prev, next, block, initial, radius as i32) {
+            *seed = (px + dx as f32 * scale, py + dy as f32 * scale);
+        }
+    }
+}
+
+/// Flag tracked points that moved farther than `max_displacement` from their
+/// previous position as [`TrackStatus::Diverged`] and move them back there.
+fn reject_far_displacements(
+    max_displacement: Option<f32>,
     prev_points: &[(f32, f32)],
-    fb_threshold: f32,
+    results: &mut [TrackResult],
 ) {
-    let threshold_sq = fb_threshold * fb_threshold;
-    for (idx, result) in forward.iter_mut().enumerate() {
-        // Only forward-tracked points are eligible; others keep their status.
+    let Some(limit) = max_displacement else {
+        return;
+    };
+    let limit_sq = limit * limit;
+    for (result, &prev) in results.iter_mut().zip(prev_points) {
         if result.status != TrackStatus::Tracked {
             continue;
         }
-
-        let back = &backward[idx];
-        let dx = back.pos.0 - prev_points[idx].0;
-        let dy = back.pos.1 - prev_points[idx].1;
-        if back.status != TrackStatus::Tracked || dx * dx + dy * dy > threshold_sq {
-            result.status = TrackStatus::FbInconsistent;
+        let (dx, dy) = (result.pos.0 - prev.0, result.pos.1 - prev.1);
+        let dist_sq = dx * dx + dy * dy;
+        if dist_sq.is_nan() || dist_sq > limit_sq {
+            result.status = TrackStatus::Diverged;
+            result.pos = prev;
         }
     }
 }
 
+fn validate_max_displacement(limit: f32) {
+    assert!(limit > 0.0, "max displacement must be positive");
+}
+
+fn validate_level_early_exit(fraction: f32) {
+    assert!(
+        fraction > 0.0 && fraction <= 1.0,
+        "fraction must be in (0, 1]"
+    );
+}
+
+/// The levels `0..=max_level` of `pyramid`, or all of them without a
+/// `max_level`.
+fn used_levels(pyramid: &[GrayImage], max_level: Option<usize>) -> &[GrayImage] {
+    let levels = max_level.map_or(pyramid.len(), |max_level| max_level + 1);
+    &pyramid[..levels.min(pyramid.len())]
+}
+
 /// Minimum eigenvalue of the symmetric 2x2 matrix `[[a, b], [b, c]]`.
-fn min_eigenvalue(a: f32, b: f32, c: f32) -> f32 {
+pub(crate) fn min_eigenvalue(a: f32, b: f32, c: f32) -> f32 {
     let trace = a + c;
     let det = a * c - b * b;
     let disc = (trace * trace - 4.0 * det).max(0.0).sqrt();
     (trace - disc) / 2.0
 }
 
-/// Mean absolute photometric residual between the cached previous patch and the
-/// next image sampled at `(cx, cy)`. Returns [`f32::INFINITY`] if the window is
-/// out of bounds.
-fn window_error(
-    img: &GrayImage,
-    prev_patch: &[f32],
-    offsets: &[(f32, f32)],
-    cx: f32,
-    cy: f32,
-    radius: usize,
-) -> f32 {
-    if !in_bounds(img, cx, cy, radius) {
-        return f32::INFINITY;
-    }
-
-    let mut sum = 0.0f32;
-    for (i, (ox, oy)) in offsets.iter().enumerate() {
-        let curr = interpolate(img, cx + ox, cy + oy);
-        sum += (prev_patch[i] - curr).abs();
-    }
-    sum / offsets.len() as f32
-}
-
 /// Fills `offsets` with the `(dx, dy)` window sample positions for the given
 /// radius, reusing the existing capacity.
-fn build_window_offsets_into(radius: usize, offsets: &mut Vec<(f32, f32)>) {
+pub(crate) fn build_window_offsets_into(radius: usize, offsets: &mut Vec<(f32, f32)>) {
+    build_rect_offsets_into((radius, radius), offsets);
+}
+
+/// [`build_window_offsets_into`] for a window of half-extents `(rx, ry)`, in
+/// row-major order.
+pub(crate) fn build_rect_offsets_into((rx, ry): (usize, usize), offsets: &mut Vec<(f32, f32)>) {
     offsets.clear();
-    offsets.reserve((2 * radius + 1) * (2 * radius + 1));
+    offsets.reserve((2 * rx + 1) * (2 * ry + 1));
 
-    for j in -(radius as i32)..=radius as i32 {
-        for i in -(radius as i32)..=radius as i32 {
+    for j in -(ry as i32)..=ry as i32 {
+        for i in -(rx as i32)..=rx as i32 {
             offsets.push((i as f32, j as f32));
         }
     }
 }
 
-fn invert_2x2(a00: f32, a01: f32, a11: f32, det_epsilon: f32) -> Option<(f32, f32, f32)> {
+pub(crate) fn invert_2x2(a00: f32, a01: f32, a11: f32, det_epsilon: f32) -> Option<(f32, f32, f32)> {
     let det = a00 * a11 - a01 * a01;
     if det.abs() <= det_epsilon {
         return None;
@@ -528,14 +2435,24 @@ fn invert_2x2(a00: f32, a01: f32, a11: f32, det_epsilon: f32) -> Option<(f32, f3
     Some((a11 * inv_det, -a01 * inv_det, a00 * inv_det))
 }
 
+/// `(x, y)` rounded to the nearest pixel.
+fn rounded((x, y): (f32, f32)) -> (i64, i64) {
+    (x.round() as i64, y.round() as i64)
+}
+
 /// Checks that the window stays within image bounds
-fn in_bounds(img: &GrayImage, x: f32, y: f32, radius: usize) -> bool {
+pub(crate) fn in_bounds(img: &GrayImage, x: f32, y: f32, radius: usize) -> bool {
+    in_bounds_rect(img, x, y, (radius, radius))
+}
+
+/// [`in_bounds`] for a window of half-extents `(rx, ry)`.
+pub(crate) fn in_bounds_rect(img: &GrayImage, x: f32, y: f32, (rx, ry): (usize, usize)) -> bool {
     let (w, h) = (img.width() as f32, img.height() as f32);
-    x >= radius as f32 && x < w - radius as f32 && y >= radius as f32 && y < h - radius as f32
+    x >= rx as f32 && x < w - rx as f32 && y >= ry as f32 && y < h - ry as f32
 }
 
 /// Bilinear interpolation of the pixel value
-fn interpolate(img: &GrayImage, x: f32, y: f32) -> f32 {
+pub(crate) fn interpolate(img: &GrayImage, x: f32, y: f32) -> f32 {
     let w = img.width() as i32;
     let h = img.height() as i32;
     let x0 = x.floor() as i32;
@@ -593,9 +2510,34 @@ fn interpolate(img: &GrayImage, x: f32, y: f32) -> f32 {
     sum
 }
 
-/// Bilinear interpolation over a raw `i16` gradient buffer (`width * height`,
+/// Bilinear interpolation of a weight map, with the weights and the
+/// zero-padded border of [`interpolate`].
+fn interpolate_weight(map: &WeightMap, x: f32, y: f32) -> f32 {
+    let (w, h) = (map.width() as i32, map.height() as i32);
+    let (x0, y0) = (x.floor() as i32, y.floor() as i32);
+    let (dx, dy) = (x - x0 as f32, y - y0 as f32);
+    let read = |sx: i32, sy: i32| {
+        if sx >= 0 && sy >= 0 && sx < w && sy < h {
+            map.as_raw()[sy as usize * w as usize + sx as usize]
+        } else {
+            0.0
+        }
+    };
+    read(x0, y0) * (1.0 - dx) * (1.0 - dy)
+        + read(x0, y0 + 1) * (1.0 - dx) * dy
+        + read(x0 + 1, y0) * dx * (1.0 - dy)
+        + read(x0 + 1, y0 + 1) * dx * dy
+}
+
+/// Bilinear interpolation of both gradient components (`width * height`,
 /// row-major). Out-of-bounds samples read as 0, matching [`interpolate`].
-fn interpolate_i16(data: &[i16], width: u32, height: u32, x: f32, y: f32) -> f32 {
+pub(crate) fn interpolate_gradient(
+    gradients: &impl GradientSource,
+    width: u32,
+    height: u32,
+    x: f32,
+    y: f32,
+) -> (f32, f32) {
     let w = width as i32;
     let h = height as i32;
     let x0 = x.floor() as i32;
@@ -606,44 +2548,83 @@ fn interpolate_i16(data: &[i16], width: u32, height: u32, x: f32, y: f32) -> f32
 
     // Fast path: full 2x2 footprint in bounds (see `interpolate`).
     if x0 >= 0 && y0 >= 0 && x0 + 1 < w && y0 + 1 < h {
-        let stride = width as usize;
-        let base = y0 as usize * stride + x0 as usize;
-        // SAFETY: footprint proven in bounds and `data` is width*height long.
+        let (x0, y0) = (x0 as usize, y0 as usize);
+        // SAFETY: footprint proven inside the image.
         let (p00, p10, p01, p11) = unsafe {
             (
-                *data.get_unchecked(base) as f32,
-                *data.get_unchecked(base + 1) as f32,
-                *data.get_unchecked(base + stride) as f32,
-                *data.get_unchecked(base + stride + 1) as f32,
+                gradients.get_unchecked(x0, y0),
+                gradients.get_unchecked(x0 + 1, y0),
+                gradients.get_unchecked(x0, y0 + 1),
+                gradients.get_unchecked(x0 + 1, y0 + 1),
             )
         };
-        return p00 * (1.0 - dx) * (1.0 - dy)
-            + p01 * (1.0 - dx) * dy
-            + p10 * dx * (1.0 - dy)
-            + p11 * dx * dy;
+        let bilinear = |pick: fn((f32, f32)) -> f32| {
+            pick(p00) * (1.0 - dx) * (1.0 - dy)
+                + pick(p01) * (1.0 - dx) * dy
+                + pick(p10) * dx * (1.0 - dy)
+                + pick(p11) * dx * dy
+        };
+        return (bilinear(|g| g.0), bilinear(|g| g.1));
     }
 
     let x1 = x0 + 1;
     let y1 = y0 + 1;
-    let mut sum = 0.0;
+    let mut sum = (0.0, 0.0);
     for (sx, sy) in &[(x0, y0), (x0, y1), (x1, y0), (x1, y1)] {
-        let px = if *sx >= 0 && *sy >= 0 && *sx < w && *sy < h {
-            data[*sy as usize * width as usize + *sx as usize] as f32
+        let (px, py) = if *sx >= 0 && *sy >= 0 && *sx < w && *sy < h {
+            gradients.get(*sx as usize, *sy as usize)
         } else {
-            0.0
+            (0.0, 0.0)
         };
 
         let wx = if sx == &x0 { 1.0 - dx } else { dx };
         let wy = if sy == &y0 { 1.0 - dy } else { dy };
 
-        sum += px * wx * wy;
+        sum.0 += px * wx * wy;
+        sum.1 += py * wx * wy;
     }
 
     sum
 }
 
+/// How [`TrackerContext`] stores the previous frame's per-level gradients.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GradientStorage {
+    /// Two `i16` planes per level (exact Scharr values).
+    #[default]
+    Full,
+    /// Two `i8` planes per level with one scale per 32x32 tile: half the
+    /// memory of [`Full`](Self::Full), for devices where the gradient pyramid
+    /// is the dominant allocation. Each gradient is off by at most half a
+    /// quantization step of its tile, which shifts tracked positions by small
+    /// fractions of a pixel. Quantized levels are built after the pyramid,
+    /// without the `rayon` overlap of the full mode.
+    Quantized,
+}
+
+/// Gradients of every previous-frame level, precomputed by
+/// [`TrackerContext::prepare`] in the context's [`GradientStorage`].
+#[derive(Clone, Copy)]
+enum PrecomputedGradients<'a> {
+    Full(&'a [LevelGradients]),
+    Quantized(&'a [QuantizedGradients]),
+}
+
+impl<'a> PrecomputedGradients<'a> {
+    fn select(
+        storage: GradientStorage,
+        full: &'a [LevelGradients],
+        quantized: &'a [QuantizedGradients],
+    ) -> Self {
+        match storage {
+            GradientStorage::Full => PrecomputedGradients::Full(full),
+            GradientStorage::Quantized => PrecomputedGradients::Quantized(quantized),
+        }
+    }
+}
+
 /// Reusable owner of every buffer the tracking hot path touches: both frame
-/// pyramids, the Lucas-Kanade scratch, the result vector and the
+/// pyramids, the previous frame's gradients, the Lucas-Kanade scratch, the result vector and the
 /// forward-backward intermediates.
 ///
 /// Create one per tracking thread and call [`prepare`](Self::prepare) then
@@ -654,14 +2635,33 @@ fn interpolate_i16(data: &[i16], width: u32, height: u32, x: f32, y: f32) -> f32
 /// front-end runs every frame; the free functions
 /// ([`calc_optical_flow_ex`], [`calc_optical_flow_fb`]) are thin convenience
 /// wrappers that allocate their own scratch.
+///
+/// With a clock installed via [`set_timing_clock`](Self::set_timing_clock), the
+/// context also measures its own stages; see [`timing`](Self::timing).
 #[derive(Default)]
 pub struct TrackerContext {
     prev_pyramid: Vec<GrayImage>,
+    gradient_storage: GradientStorage,
+    params: FlowParams<'static>,
+    prev_gradients: Vec<LevelGradients>,
+    prev_quantized: Vec<QuantizedGradients>,
     next_pyramid: Vec<GrayImage>,
     scratch: Scratch,
     results: Vec<TrackResult>,
     forward_pos: Vec<(f32, f32)>,
     backward: Vec<TrackResult>,
+    clock: Option<TimingClock>,
+    timing: TimingReport,
+    /// Set once a tracking call has reported `timing.pyramid_ms`, so the next
+    /// prepare step starts the next frame's sum.
+    pyramid_reported: bool,
+    intrinsics: Option<CameraIntrinsics>,
+    convention: CoordinateConvention,
+    image_points: Vec<(f32, f32)>,
+    image_predicted: Vec<(f32, f32)>,
+    mask: Option<ExclusionTable>,
+    weight_map: Option<WeightMap>,
+    block_seeds: Vec<(f32, f32)>,
 }
 
 impl TrackerContext {
@@ -673,10 +2673,403 @@ impl TrackerContext {
     }
 
     /// Builds the previous- and next-frame pyramids into the context's reusable
-    /// buffers. Zero-alloc in steady state (same image size and `levels`).
+    /// buffers, together with the previous frame's per-level gradients used by
+    /// [`track`](Self::track). Zero-alloc in steady state (same image size and
+    /// `levels`).
+    ///
+    /// With the `rayon` feature, downsampling each previous-frame level runs in
+    /// parallel with the gradient pass over the level above it.
     pub fn prepare(&mut self, prev: &GrayImage, next: &GrayImage, levels: usize) {
-        build_pyramid_into(prev, levels, &mut self.prev_pyramid);
+        let start = self.clock.map(|clock| clock());
+        self.build_prev(prev, levels);
+        build_pyramid_into(next, levels, &mut self.next_pyramid);
+        self.add_pyramid_time(start);
+    }
+
+    /// The previous-frame half of [`prepare`](Self::prepare): rebuilds only the
+    /// previous pyramid and its gradients, keeping the next pyramid.
+    ///
+    /// Together with [`prepare_next`](Self::prepare_next) this lets a fixed
+    /// reference frame (e.g. a keyframe) be prepared once and tracked into many
+    /// frames.
+    pub fn prepare_prev(&mut self, prev: &GrayImage, levels: usize) {
+        let start = self.clock.map(|clock| clock());
+        self.build_prev(prev, levels);
+        self.add_pyramid_time(start);
+    }
+
+    /// The next-frame half of [`prepare`](Self::prepare): rebuilds only the
+    /// next pyramid, keeping the previous frame.
+    pub fn prepare_next(&mut self, next: &GrayImage, levels: usize) {
+        let start = self.clock.map(|clock| clock());
         build_pyramid_into(next, levels, &mut self.next_pyramid);
+        self.add_pyramid_time(start);
+    }
+
+    /// Adds the time since `start` to the pyramid time of the current frame,
+    /// which sums every prepare step before its tracking call.
+    fn add_pyramid_time(&mut self, start: Option<f64>) {
+        if let (Some(clock), Some(start)) = (self.clock, start) {
+            if std::mem::take(&mut self.pyramid_reported) {
+                self.timing.pyramid_ms = 0.0;
+            }
+            self.timing.pyramid_ms += clock() - start;
+        }
+    }
+
+    fn build_prev(&mut self, prev: &GrayImage, levels: usize) {
+        match self.gradient_storage {
+            GradientStorage::Full => build_pyramid_with_gradients_into(
+                prev,
+                levels,
+                &mut self.prev_pyramid,
+                &mut self.prev_gradients,
+            ),
+            GradientStorage::Quantized => {
+                build_pyramid_into(prev, levels, &mut self.prev_pyramid);
+                self.prev_quantized
+                    .resize_with(self.prev_pyramid.len(), Default::default);
+                for (level, gradients) in self.prev_pyramid.iter().zip(&mut self.prev_quantized) {
+                    gradients.quantize(level);
+                }
+            }
+        }
+    }
+
+    /// Selects how the previous frame's gradient pyramid is kept between
+    /// [`prepare`](Self::prepare) and tracking. Takes effect at the next
+    /// `prepare`; the storage of the other mode is released.
+    pub fn set_gradient_storage(&mut self, storage: GradientStorage) {
+        self.gradient_storage = storage;
+        match storage {
+            GradientStorage::Full => self.prev_quantized = Vec::new(),
+            GradientStorage::Quantized => self.prev_gradients = Vec::new(),
+        }
+    }
+
+    /// Replaces every tracking setting with those of `params`, as if each
+    /// had been set with its setter, e.g. [`set_flags`](Self::set_flags) or
+    /// [`set_mask`](Self::set_mask).
+    ///
+    /// The window size, iteration limit and eigenvalue threshold serve
+    /// [`track_with`](Self::track_with); the other tracking calls take them
+    /// as arguments.
+    ///
+    /// # Panics
+    /// Panics as the setters of the individual settings do.
+    pub fn set_params(&mut self, params: &FlowParams) {
+        params.validate();
+        let FlowParams {
+            window_size,
+            max_iterations,
+            epsilon,
+            max_level,
+            min_eigen_threshold,
+            flags,
+            robust_loss,
+            bounds,
+            prior,
+            exposure,
+            level_iterations,
+            max_displacement,
+            gradient_weighting,
+            block_search,
+            mask,
+            weight_map,
+            level_early_exit,
+        } = *params;
+        self.params = FlowParams {
+            window_size,
+            max_iterations,
+            epsilon,
+            max_level,
+            min_eigen_threshold,
+            flags,
+            robust_loss,
+            bounds,
+            prior,
+            exposure,
+            level_iterations,
+            max_displacement,
+            gradient_weighting,
+            block_search,
+            // The context keeps its own copies of the images.
+            mask: None,
+            weight_map: None,
+            level_early_exit,
+        };
+        self.set_mask(mask);
+        self.set_weight_map(weight_map.cloned());
+    }
+
+    /// Sets the convergence threshold in pixels of subsequent tracking calls;
+    /// the default is [`DEFAULT_EPSILON`].
+    pub fn set_epsilon(&mut self, epsilon: f32) {
+        self.params.epsilon = epsilon;
+    }
+
+    /// Tracks on the pyramid levels `0..=max_level` only (`Some`), like
+    /// OpenCV's `maxLevel`, or on every prepared level (`None`, the default).
+    /// Applies to subsequent tracking calls.
+    pub fn set_max_level(&mut self, max_level: Option<usize>) {
+        self.params.max_level = max_level;
+    }
+
+    /// Sets the [`LkFlags`] applied by subsequent [`track`](Self::track) /
+    /// [`track_fb`](Self::track_fb) calls.
+    ///
+    /// Here the initial flow is the `predicted` argument, which is honored
+    /// whether or not [`LkFlags::USE_INITIAL_FLOW`] is set; with the flag, a
+    /// call without `predicted` panics instead of silently starting from the
+    /// previous positions.
+    pub fn set_flags(&mut self, flags: LkFlags) {
+        self.params.flags = flags;
+    }
+
+    /// Sets the lens model of the camera (`Some`), or removes it (`None`).
+    ///
+    /// With intrinsics, subsequent tracking calls take and return
+    /// undistorted coordinates: the previous and predicted points are
+    /// distorted into the image, tracked there, and the results undistorted
+    /// again, so the frames themselves never need an undistortion warp.
+    /// Window sizes and the forward-backward threshold stay in image pixels.
+    pub fn set_intrinsics(&mut self, intrinsics: Option<CameraIntrinsics>) {
+        self.intrinsics = intrinsics;
+    }
+
+    /// Sets the coordinate convention of the points passed to and returned
+    /// by subsequent tracking calls; the default is the crate's
+    /// [`CoordinateConvention::PixelCenter`].
+    ///
+    /// Points are converted to pixel centers before tracking and back
+    /// afterwards, so the pyramid scaling and interpolation see the same
+    /// positions either way. Intrinsics set with
+    /// [`set_intrinsics`](Self::set_intrinsics) are taken to be calibrated
+    /// in this convention.
+    pub fn set_coordinate_convention(&mut self, convention: CoordinateConvention) {
+        self.convention = convention;
+    }
+
+    /// Lets every coarse pyramid level stop early once `fraction` of the
+    /// points has converged on it (`Some`), or refines every point on every
+    /// level (`None`, the default).
+    ///
+    /// The points refine each coarse level in rounds of a doubling iteration
+    /// budget; once enough have converged, the rest carry their displacement
+    /// so far to the next finer level, whatever their input order. The
+    /// finest level always refines every point fully, so in a mostly static
+    /// scene this trades a little accuracy on the slow points for less
+    /// coarse-level work. Each refining point keeps its sampled window
+    /// between rounds, so the context holds one window per point. Applies to
+    /// subsequent tracking calls.
+    ///
+    /// # Panics
+    /// Panics if `fraction` is not in `(0, 1]`.
+    pub fn set_level_early_exit(&mut self, fraction: Option<f32>) {
+        if let Some(fraction) = fraction {
+            validate_level_early_exit(fraction);
+        }
+        self.params.level_early_exit = fraction;
+    }
+
+    /// Caps the iterations of each pyramid level separately (`Some`),
+    /// overriding the `max_iterations` argument of the tracking calls, or
+    /// applies `max_iterations` to every level (`None`, the default).
+    ///
+    /// E.g. `LevelIterations::per_level(&[30, 10, 3])` keeps the full budget
+    /// on the finest level and cuts the coarse levels, where a few
+    /// iterations usually converge, to save latency. Applies to subsequent
+    /// tracking calls.
+    pub fn set_level_iterations(&mut self, iterations: Option<LevelIterations>) {
+        self.params.level_iterations = iterations;
+    }
+
+    /// Excludes the pixels where `mask` is 0 from tracking (`Some`), or
+    /// tracks on the whole frame (`None`, the default); see
+    /// [`FlowParams::mask`].
+    ///
+    /// The mask is in image coordinates, so it suits static regions such as
+    /// overlays or a vehicle hood, and it applies to both frames: the
+    /// backward pass of [`track_fb`](Self::track_fb) excludes the same
+    /// pixels, so a point tracked onto them is reported as
+    /// [`TrackStatus::FbInconsistent`]. Applies to subsequent tracking calls,
+    /// which panic if the mask does not match the frame size.
+    pub fn set_mask(&mut self, mask: Option<&GrayImage>) {
+        self.mask = mask.map(ExclusionTable::new);
+    }
+
+    /// Weights every window pixel by `map` at its position in the previous
+    /// frame (`Some`), or weights them equally (`None`, the default); see
+    /// [`FlowParams::weight_map`].
+    ///
+    /// The map describes the previous frame, e.g. an object's segmentation,
+    /// so set it for every frame pair; the backward pass of
+    /// [`track_fb`](Self::track_fb) does not use it. Combines with
+    /// [`set_mask`](Self::set_mask) and with per-point window weights.
+    /// Applies to subsequent tracking calls, which panic if the map does not
+    /// match the frame size.
+    pub fn set_weight_map(&mut self, map: Option<ImageBuffer<Luma<f32>, Vec<f32>>>) {
+        self.weight_map = map;
+    }
+
+    /// Reweights the window pixels by `loss` of their residual on every
+    /// iteration (`Some`), or solves plain least squares (`None`, the
+    /// default).
+    ///
+    /// Occlusion boundaries and specular highlights inside a window leave
+    /// large residuals on a minority of its pixels, which pull the
+    /// least-squares solution off the feature; iteratively reweighted least
+    /// squares bounds (Huber) or removes (Tukey) their influence. Each
+    /// iteration rebuilds and inverts the spatial gradient matrix under the
+    /// new weights, and the reported error stays the unweighted residual.
+    /// Applies to subsequent tracking calls.
+    ///
+    /// # Panics
+    /// Panics if the loss constant is not positive and finite.
+    pub fn set_robust_loss(&mut self, loss: Option<RobustLoss>) {
+        if let Some(loss) = loss {
+            loss.validate();
+        }
+        self.params.robust_loss = loss;
+    }
+
+    /// Seeds every point by a coarse block-matching search of `radius`
+    /// pixels on the coarsest pyramid level (`Some`), or starts the
+    /// refinement from the predictions alone (`None`, the default).
+    ///
+    /// Motion beyond what the pyramid can capture makes Lucas-Kanade
+    /// diverge. The search matches each point's window, by the sum of
+    /// absolute differences over every integer offset within `radius` of the
+    /// predicted displacement (or of none), and the refinement starts from
+    /// the best match; on a pyramid of `levels` levels it reaches
+    /// `radius * 2^(levels - 1)` full-resolution pixels. The cost grows with
+    /// the square of `radius` per point. The search replaces the shift of
+    /// [`LkFlags::PREALIGN`] and does not honor a tracking mask. Applies to
+    /// subsequent tracking calls; the backward pass of
+    /// [`track_fb`](Self::track_fb) starts from the original points without
+    /// a search.
+    pub fn set_block_search(&mut self, radius: Option<u32>) {
+        self.params.block_search = radius;
+    }
+
+    /// Weights every window pixel by `weighting` of its gradient magnitude in
+    /// the previous frame (`Some`), or weights them equally (`None`, the
+    /// default).
+    ///
+    /// In a window that mixes texture with flat area, the flat pixels add
+    /// only noise to the least-squares solve; weighting by the gradient
+    /// lets the textured part dominate and improves the conditioning of the
+    /// spatial gradient matrix. The minimum eigenvalue and the error are
+    /// normalized by the weight sum, so
+    /// [`GradientWeighting::Saturating`] keeps the eigenvalue threshold
+    /// comparable, while [`GradientWeighting::Magnitude`] weights in gray
+    /// levels per pixel. Combines with the other per-pixel weights and
+    /// applies to both passes of [`track_fb`](Self::track_fb) and to
+    /// subsequent tracking calls.
+    ///
+    /// # Panics
+    /// Panics if the saturation scale is not positive and finite.
+    pub fn set_gradient_weighting(&mut self, weighting: Option<GradientWeighting>) {
+        if let Some(weighting) = weighting {
+            weighting.validate();
+        }
+        self.params.gradient_weighting = weighting;
+    }
+
+    /// Sets the treatment of returned positions outside the next frame; the
+    /// default is [`BoundsPolicy::AsIs`]. The bounds are those of the image,
+    /// before any intrinsics or coordinate convention map the positions back
+    /// to the caller's coordinates. Applies to subsequent tracking calls.
+    pub fn set_bounds_policy(&mut self, bounds: BoundsPolicy) {
+        self.params.bounds = bounds;
+    }
+
+    /// Rejects tracks that moved farther than `limit` pixels from their
+    /// previous position (`Some`), or accepts any displacement (`None`, the
+    /// default).
+    ///
+    /// A point that converges hundreds of pixels away has locked onto a
+    /// repeated texture or another object rather than followed its feature.
+    /// Such [`TrackStatus::Tracked`] points are reported as
+    /// [`TrackStatus::Diverged`] at their previous position, so callers that
+    /// ignore the status do not jump with them. The displacement is measured
+    /// in the image, before any intrinsics or coordinate convention map the
+    /// positions back, and ahead of the [bounds policy](Self::set_bounds_policy).
+    /// Applies to subsequent tracking calls.
+    ///
+    /// # Panics
+    /// Panics if `limit` is not positive.
+    pub fn set_max_displacement(&mut self, limit: Option<f32>) {
+        if let Some(limit) = limit {
+            validate_max_displacement(limit);
+        }
+        self.params.max_displacement = limit;
+    }
+
+    /// Sets the whole-frame transform from the previous to the next frame,
+    /// e.g. integrated from a gyroscope (`Some`), or removes it (`None`, the
+    /// default).
+    ///
+    /// Tracking calls without `predicted` positions seed every point with
+    /// its image under the prior, as if `predicted` had been passed; explicit
+    /// predictions take precedence. The prior acts on the caller's
+    /// coordinates, so with [`set_intrinsics`](Self::set_intrinsics) it maps
+    /// undistorted points. It stays in effect until replaced, so set it once
+    /// per frame pair.
+    pub fn set_motion_prior(&mut self, prior: Option<MotionPrior>) {
+        self.params.prior = prior;
+    }
+
+    /// Sets the global brightness change from the previous to the next
+    /// frame, e.g. from [`estimate_exposure_change`](crate::estimate_exposure_change)
+    /// (`Some`), or removes it (`None`, the default).
+    ///
+    /// Without [`LkFlags::GAIN_BIAS`] every window's residual is measured
+    /// after undoing the change. With the flag each window still fits its own
+    /// gain and bias, and the global gain replaces the neutral fallback of
+    /// windows too flat to determine theirs; with [`LkFlags::ZERO_MEAN`] the
+    /// global gain is kept and each window fits its own bias. The backward
+    /// pass of [`track_fb`](Self::track_fb) uses the inverse change. Applies
+    /// to subsequent tracking calls.
+    pub fn set_exposure_change(&mut self, exposure: Option<ExposureChange>) {
+        self.params.exposure = exposure;
+    }
+
+    /// Enables (`Some`) or disables (`None`) per-stage timing.
+    ///
+    /// While enabled, every [`prepare`](Self::prepare) and
+    /// [`track`](Self::track) / [`track_fb`](Self::track_fb) call records its
+    /// stage durations, readable through [`timing`](Self::timing). Recording
+    /// reuses the report's storage, so the steady-state path stays
+    /// allocation-free.
+    pub fn set_timing_clock(&mut self, clock: Option<TimingClock>) {
+        self.clock = clock;
+        self.timing = TimingReport::default();
+        self.pyramid_reported = false;
+    }
+
+    /// Selects the points (indices into `prev_points`) whose per-iteration
+    /// convergence is recorded by subsequent [`track`](Self::track) /
+    /// [`track_fb`](Self::track_fb) calls. An empty slice disables recording.
+    ///
+    /// Only the forward pass of [`track_fb`](Self::track_fb) is recorded.
+    #[cfg(feature = "debug-trace")]
+    pub fn set_debug_points(&mut self, indices: &[usize]) {
+        self.scratch.trace.select(indices);
+        self.scratch.trace.recording = !indices.is_empty();
+    }
+
+    /// Convergence traces of the selected points from the last tracking call,
+    /// in selection order. See [`set_debug_points`](Self::set_debug_points).
+    #[cfg(feature = "debug-trace")]
+    pub fn debug_trace(&self) -> &[PointTrace] {
+        &self.scratch.trace.points
+    }
+
+    /// Stage timings of the last prepare + track step, or `None` when timing is
+    /// disabled. For [`track_fb`](Self::track_fb) both passes are included.
+    pub fn timing(&self) -> Option<&TimingReport> {
+        self.clock.map(|_| &self.timing)
     }
 
     /// The previous-frame pyramid built by the last [`prepare`](Self::prepare).
@@ -689,6 +3082,11 @@ impl TrackerContext {
         &self.next_pyramid
     }
 
+    /// Results of the last tracking call.
+    pub(crate) fn results(&self) -> &[TrackResult] {
+        &self.results
+    }
+
     /// Tracks `prev_points` using the prepared pyramids, returning the results
     /// held inside the context. See [`calc_optical_flow_ex`] for the argument
     /// semantics. Allocation-free in steady state.
@@ -700,18 +3098,78 @@ impl TrackerContext {
         max_iterations: usize,
         min_eigen_threshold: f32,
     ) -> &[TrackResult] {
-        track_into(
-            &self.prev_pyramid,
-            &self.next_pyramid,
+        let params = FlowParams {
+            window_size,
+            max_iterations,
+            min_eigen_threshold,
+            ..self.params
+        };
+        self.run(prev_points, predicted, &params, None)
+    }
+
+    /// Tracks `prev_points` using the prepared pyramids and every setting of
+    /// [`set_params`](Self::set_params), including its window size,
+    /// iteration limit and eigenvalue threshold. See
+    /// [`calc_optical_flow_with`] for the argument semantics.
+    /// Allocation-free in steady state.
+    pub fn track_with(
+        &mut self,
+        prev_points: &[(f32, f32)],
+        predicted: Option<&[(f32, f32)]>,
+    ) -> &[TrackResult] {
+        let params = self.params;
+        self.run(prev_points, predicted, &params, None)
+    }
+
+    /// Tracks `prev_points` with a `window_width` x `window_height` window
+    /// using the prepared pyramids. See [`calc_optical_flow_rect`] for the
+    /// argument semantics. Allocation-free in steady state.
+    pub fn track_rect(
+        &mut self,
+        prev_points: &[(f32, f32)],
+        predicted: Option<&[(f32, f32)]>,
+        window_width: usize,
+        window_height: usize,
+        max_iterations: usize,
+        min_eigen_threshold: f32,
+    ) -> &[TrackResult] {
+        let params = FlowParams {
+            max_iterations,
+            min_eigen_threshold,
+            ..self.params
+        };
+        self.run_windows(
             prev_points,
             predicted,
-            window_size,
+            Windows::Rect(window_width, window_height),
+            &params,
+            None,
+        )
+    }
+
+    /// Tracks `prev_points` with one [`TrackWindow`] per point using the
+    /// prepared pyramids. See [`calc_optical_flow_windows`] for the argument
+    /// semantics. Allocation-free in steady state.
+    pub fn track_windows(
+        &mut self,
+        prev_points: &[(f32, f32)],
+        predicted: Option<&[(f32, f32)]>,
+        windows: &[TrackWindow],
+        max_iterations: usize,
+        min_eigen_threshold: f32,
+    ) -> &[TrackResult] {
+        let params = FlowParams {
             max_iterations,
             min_eigen_threshold,
-            &mut self.scratch,
-            &mut self.results,
-        );
-        &self.results
+            ..self.params
+        };
+        self.run_windows(
+            prev_points,
+            predicted,
+            Windows::PerPoint(windows),
+            &params,
+            None,
+        )
     }
 
     /// Forward-backward consistent tracking using the prepared pyramids. See
@@ -726,37 +3184,180 @@ impl TrackerContext {
         min_eigen_threshold: f32,
         fb_threshold: f32,
     ) -> &[TrackResult] {
+        let params = FlowParams {
+            window_size,
+            max_iterations,
+            min_eigen_threshold,
+            ..self.params
+        };
+        self.run(prev_points, predicted, &params, Some(fb_threshold))
+    }
+
+    /// [`run_windows`](Self::run_windows) with square windows of
+    /// `params.window_size`.
+    fn run(
+        &mut self,
+        prev_points: &[(f32, f32)],
+        predicted: Option<&[(f32, f32)]>,
+        params: &FlowParams<'static>,
+        fb_threshold: Option<f32>,
+    ) -> &[TrackResult] {
+        let windows = Windows::Uniform(params.window_size);
+        self.run_windows(prev_points, predicted, windows, params, fb_threshold)
+    }
+
+    /// Shared body of the tracking calls: the forward pass, the backward
+    /// check when `fb_threshold` is given, and the mapping between the
+    /// caller's coordinates and pixel-center image coordinates when
+    /// intrinsics or another convention are set.
+    fn run_windows(
+        &mut self,
+        prev_points: &[(f32, f32)],
+        predicted: Option<&[(f32, f32)]>,
+        windows: Windows,
+        params: &FlowParams<'static>,
+        fb_threshold: Option<f32>,
+    ) -> &[TrackResult] {
+        self.check_initial_flow(predicted);
+        // The context owns the weight map the settings refer to.
+        let params = FlowParams {
+            weight_map: self.weight_map.as_ref(),
+            ..*params
+        };
+        let clock = self.clock;
+        if clock.is_some() {
+            self.timing.reset_tracking(self.prev_pyramid.len());
+            self.pyramid_reported = true;
+        }
+
+        // The caller's points may be undistorted or in another convention;
+        // track their pixel-center images instead.
+        let (intrinsics, convention) = (self.intrinsics, self.convention);
+        let mapped = intrinsics.is_some() || convention != CoordinateConvention::PixelCenter;
+        let to_image = |&p: &(f32, f32)| {
+            convention.to_center(intrinsics.map_or(p, |camera| camera.distort(p)))
+        };
+        // A motion prior stands in for missing predictions.
+        let prior = params.prior.filter(|_| predicted.is_none());
+        let mut image_points = std::mem::take(&mut self.image_points);
+        let mut image_predicted = std::mem::take(&mut self.image_predicted);
+        image_predicted.clear();
+        match prior {
+            Some(prior) => {
+                image_predicted.extend(prev_points.iter().map(|&p| to_image(&prior.apply(p))))
+            }
+            None if mapped => image_predicted.extend(predicted.into_iter().flatten().map(to_image)),
+            None => {}
+        }
+        let predicted = if prior.is_some() || mapped {
+            (prior.is_some() || predicted.is_some()).then_some(&image_predicted[..])
+        } else {
+            predicted
+        };
+        let prev_points = if mapped {
+            image_points.clear();
+            image_points.extend(prev_points.iter().map(to_image));
+            &image_points[..]
+        } else {
+            prev_points
+        };
+        let mut block_seeds = std::mem::take(&mut self.block_seeds);
+        let predicted = match params.block_search {
+            Some(radius) => {
+                block_search_seeds(
+                    used_levels(&self.prev_pyramid, params.max_level),
+                    used_levels(&self.next_pyramid, params.max_level),
+                    prev_points,
+                    predicted,
+                    windows,
+                    radius,
+                    &mut block_seeds,
+                );
+                Some(&block_seeds[..])
+            }
+            None => predicted,
+        };
+
         track_into(
             &self.prev_pyramid,
+            Some(PrecomputedGradients::select(
+                self.gradient_storage,
+                &self.prev_gradients,
+                &self.prev_quantized,
+            )),
             &self.next_pyramid,
             prev_points,
             predicted,
-            window_size,
-            max_iterations,
-            min_eigen_threshold,
+            windows,
+            &params,
+            self.mask.as_ref(),
+            clock.map(|clock| (clock, &mut self.timing)),
             &mut self.scratch,
             &mut self.results,
         );
 
-        self.forward_pos.clear();
-        self.forward_pos.extend(self.results.iter().map(|r| r.pos));
+        if let Some(fb_threshold) = fb_threshold {
+            self.forward_pos.clear();
+            self.forward_pos.extend(self.results.iter().map(|r| r.pos));
+
+            // Keep the forward trace: the backward pass re-uses the same scratch.
+            #[cfg(feature = "debug-trace")]
+            let recording = std::mem::replace(&mut self.scratch.trace.recording, false);
+
+            // Seed the backward pass at the original points (see calc_optical_flow_fb).
+            track_into(
+                &self.next_pyramid,
+                None,
+                &self.prev_pyramid,
+                &self.forward_pos,
+                Some(prev_points),
+                windows,
+                &FlowParams {
+                    // The backward pass has its own seed.
+                    flags: params.flags.photometric(),
+                    exposure: params.exposure.map(|change| change.inverse()),
+                    // The weight map describes the previous frame only.
+                    weight_map: None,
+                    ..params
+                },
+                self.mask.as_ref(),
+                clock.map(|clock| (clock, &mut self.timing)),
+                &mut self.scratch,
+                &mut self.backward,
+            );
+
+            #[cfg(feature = "debug-trace")]
+            {
+                self.scratch.trace.recording = recording;
+            }
 
-        // Seed the backward pass at the original points (see calc_optical_flow_fb).
-        track_into(
-            &self.next_pyramid,
-            &self.prev_pyramid,
-            &self.forward_pos,
-            Some(prev_points),
-            window_size,
-            max_iterations,
-            min_eigen_threshold,
-            &mut self.scratch,
-            &mut self.backward,
-        );
+            mark_fb_inconsistent(&mut self.results, &self.backward, prev_points, fb_threshold);
+        }
 
-        mark_fb_inconsistent(&mut self.results, &self.backward, prev_points, fb_threshold);
+        reject_far_displacements(params.max_displacement, prev_points, &mut self.results);
+        if let Some(next) = self.next_pyramid.first() {
+            params
+                .bounds
+                .apply(next.dimensions(), prev_points, &mut self.results);
+        }
+        if mapped {
+            for result in &mut self.results {
+                let pos = convention.from_center(result.pos);
+                result.pos = intrinsics.map_or(pos, |camera| camera.undistort(pos));
+            }
+        }
+        self.image_points = image_points;
+        self.image_predicted = image_predicted;
+        self.block_seeds = block_seeds;
         &self.results
     }
+
+    fn check_initial_flow(&self, predicted: Option<&[(f32, f32)]>) {
+        assert!(
+            predicted.is_some() || !self.params.flags.contains(LkFlags::USE_INITIAL_FLOW),
+            "USE_INITIAL_FLOW requires predicted positions"
+        );
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,191 @@
+//! Coarse moving-object masks from sparse track residuals.
+//!
+//! Once the camera's own motion is removed, the tracks that still move belong
+//! to independently moving objects. [`moving_object_mask`] rasterizes those
+//! residuals into a grid of cells, giving a cheap per-cell object mask from
+//! the tracks a pipeline already has, without any dense motion estimation.
+
+use image::{GrayImage, Luma};
+
+use crate::background::FOREGROUND;
+use crate::lk::{TrackResult, TrackStatus};
+use crate::registration::{Match, RigidParams, RigidTransform, fit_similarity_ransac};
+
+/// Settings of [`moving_object_mask`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MotionMaskParams {
+    /// Side of the square mask cells in pixels.
+    pub cell_size: u32,
+    /// Residual displacement (pixels) above which a track counts as moving.
+    pub residual_threshold: f32,
+    /// Tracks a cell needs before it can be marked as moving, so a single
+    /// mistracked point cannot raise a cell on its own.
+    pub min_tracks: usize,
+    /// Fraction of a cell's tracks that must be moving to mark it.
+    pub moving_fraction: f32,
+}
+
+impl Default for MotionMaskParams {
+    fn default() -> Self {
+        MotionMaskParams {
+            cell_size: 16,
+            residual_threshold: 1.0,
+            min_tracks: 1,
+            moving_fraction: 0.5,
+        }
+    }
+}
+
+/// Result of [`moving_object_mask`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct MotionMask {
+    /// Side of the cells in pixels.
+    pub cell_size: u32,
+    /// One pixel per cell: [`FOREGROUND`] for moving cells, 0 for static
+    /// cells and cells without tracks.
+    pub cells: GrayImage,
+    /// Number of tracks that voted in each cell, row-major.
+    pub track_counts: Vec<u32>,
+    /// Dominant motion that was removed, `None` when no similarity was
+    /// supported by at least half of the tracks and raw displacements were
+    /// used.
+    pub camera: Option<RigidTransform>,
+}
+
+impl MotionMask {
+    /// Whether the cell in column `col` and row `row` is moving.
+    pub fn is_moving(&self, col: u32, row: u32) -> bool {
+        self.cells.get_pixel(col, row).0[0] == FOREGROUND
+    }
+
+    /// The mask at pixel resolution, `width` x `height` (the frame size the
+    /// mask was built for), each pixel taking the value of its cell.
+    pub fn to_pixels(&self, width: u32, height: u32) -> GrayImage {
+        GrayImage::from_fn(width, height, |x, y| {
+            let col = (x / self.cell_size).min(self.cells.width() - 1);
+            let row = (y / self.cell_size).min(self.cells.height() - 1);
+            *self.cells.get_pixel(col, row)
+        })
+    }
+}
+
+/// Rasterizes the residual motion of one tracking step over a `size` frame
+/// into a per-cell moving/static mask.
+///
+/// The dominant motion is a RANSAC similarity fit over all
+/// [`TrackStatus::Tracked`] points (thresholds of [`RigidParams::default`]),
+/// used when at least half of them agree on it. Every tracked point votes in
+/// the cell of its tracked position, as moving when it lies more than
+/// [`residual_threshold`](MotionMaskParams::residual_threshold) from where
+/// the dominant motion puts it. Points outside the frame are ignored.
+///
+/// # Panics
+/// Panics if `results` does not have one entry per `prev_point`, or if the
+/// frame is empty or `cell_size` is zero.
+pub fn moving_object_mask(
+    size: (u32, u32),
+    prev_points: &[(f32, f32)],
+    results: &[TrackResult],
+    params: &MotionMaskParams,
+) -> MotionMask {
+    assert_eq!(
+        prev_points.len(),
+        results.len(),
+        "results must have one entry per prev_point"
+    );
+    assert!(size.0 > 0 && size.1 > 0, "frame must not be empty");
+    assert!(params.cell_size > 0, "cell_size must be non-zero");
+
+    let matches: Vec<Match> = prev_points
+        .iter()
+        .zip(results)
+        .filter(|(_, r)| r.status == TrackStatus::Tracked)
+        .map(|(&p, r)| (p, r.pos))
+        .collect();
+    let rigid = RigidParams::default();
+    let camera = fit_similarity_ransac(
+        &matches,
+        None,
+        rigid.inlier_threshold,
+        rigid.ransac_iterations,
+    )
+    .filter(|transform| transform.inliers >= 3 && 2 * transform.inliers >= matches.len());
+
+    let cols = size.0.div_ceil(params.cell_size);
+    let rows = size.1.div_ceil(params.cell_size);
+    let mut track_counts = vec![0u32; (cols * rows) as usize];
+    let mut moving_counts = vec![0u32; (cols * rows) as usize];
+    for &(from, to) in &matches {
+        if !(to.0 >= 0.0 && to.1 >= 0.0 && to.0 < size.0 as f32 && to.1 < size.1 as f32) {
+            continue;
+        }
+        let cell =
+            (to.1 as u32 / params.cell_size * cols + to.0 as u32 / params.cell_size) as usize;
+        let expected = camera.map_or(from, |transform| transform.apply(from));
+        track_counts[cell] += 1;
+        if (to.0 - expected.0).hypot(to.1 - expected.1) > params.residual_threshold {
+            moving_counts[cell] += 1;
+        }
+    }
+
+    let cells = GrayImage::from_fn(cols, rows, |col, row| {
+        let cell = (row * cols + col) as usize;
+        let (tracks, moving) = (track_counts[cell], moving_counts[cell]);
+        let marked = tracks > 0
+            && tracks as usize >= params.min_tracks
+            && moving as f32 >= params.moving_fraction * tracks as f32;
+        Luma([if marked { FOREGROUND } else { 0 }])
+    });
+
+    MotionMask {
+        cell_size: params.cell_size,
+        cells,
+        track_counts,
+        camera,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn object_moving_against_a_panning_camera_is_masked() {
+        let prev: Vec<(f32, f32)> = (0..8)
+            .flat_map(|j| (0..8).map(move |i| (5.0 + 10.0 * i as f32, 5.0 + 10.0 * j as f32)))
+            .collect();
+        // The camera pans by (8, 1), moving the last column out of the
+        // frame; the points in the top-left 20x20 move by 4 px more.
+        let results: Vec<TrackResult> = prev
+            .iter()
+            .map(|&(x, y)| {
+                let object = if x < 20.0 && y < 20.0 { 4.0 } else { 0.0 };
+                TrackResult {
+                    pos: (x + 8.0 + object, y + 1.0),
+                    status: TrackStatus::Tracked,
+                    error: 0.0,
+                    aperture: None,
+                }
+            })
+            .collect();
+        let params = MotionMaskParams {
+            cell_size: 20,
+            ..MotionMaskParams::default()
+        };
+        let mask = moving_object_mask((80, 80), &prev, &results, &params);
+
+        let camera = mask.camera.unwrap();
+        assert!((camera.translation().0 - 8.0).abs() < 1e-3);
+        assert_eq!(mask.cells.dimensions(), (4, 4));
+        // The object's four points land two each in cells (0, 0) and
+        // (1, 0); the latter also holds two static points, exactly the
+        // moving fraction.
+        let moving: Vec<(u32, u32)> = (0..4)
+            .flat_map(|row| (0..4).map(move |col| (col, row)))
+            .filter(|&(col, row)| mask.is_moving(col, row))
+            .collect();
+        assert_eq!(moving, [(0, 0), (1, 0)]);
+        assert_eq!(mask.to_pixels(80, 80).get_pixel(25, 5).0[0], FOREGROUND);
+        assert_eq!(mask.track_counts.iter().sum::<u32>(), 64 - 8);
+    }
+}
@@ -0,0 +1,151 @@
+//! A named 2D point for the public API.
+//!
+//! The tracking functions take and return `(f32, f32)` tuples, which are easy
+//! to mix up with the other per-point values (status, error, ids) once several
+//! of them flow through a call site. [`Point2f`] names the coordinates and
+//! converts to and from tuples, arrays and, behind the `mint` and `nalgebra`
+//! features, the point and vector types of those crates.
+
+use std::ops::{Add, Sub};
+
+/// A point (or displacement) in pixel coordinates, see
+/// [`TrackResult`](crate::TrackResult) for the coordinate convention.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Point2f {
+    pub x: f32,
+    pub y: f32,
+}
+
+impl Point2f {
+    /// Creates a point from its coordinates.
+    pub const fn new(x: f32, y: f32) -> Self {
+        Point2f { x, y }
+    }
+
+    /// Euclidean distance to `other`.
+    pub fn distance(self, other: Point2f) -> f32 {
+        (self.x - other.x).hypot(self.y - other.y)
+    }
+}
+
+impl Add for Point2f {
+    type Output = Point2f;
+
+    fn add(self, rhs: Point2f) -> Point2f {
+        Point2f::new(self.x + rhs.x, self.y + rhs.y)
+    }
+}
+
+impl Sub for Point2f {
+    type Output = Point2f;
+
+    fn sub(self, rhs: Point2f) -> Point2f {
+        Point2f::new(self.x - rhs.x, self.y - rhs.y)
+    }
+}
+
+impl From<(f32, f32)> for Point2f {
+    fn from((x, y): (f32, f32)) -> Self {
+        Point2f { x, y }
+    }
+}
+
+impl From<Point2f> for (f32, f32) {
+    fn from(p: Point2f) -> Self {
+        (p.x, p.y)
+    }
+}
+
+impl From<[f32; 2]> for Point2f {
+    fn from([x, y]: [f32; 2]) -> Self {
+        Point2f { x, y }
+    }
+}
+
+impl From<Point2f> for [f32; 2] {
+    fn from(p: Point2f) -> Self {
+        [p.x, p.y]
+    }
+}
+
+#[cfg(feature = "mint")]
+impl From<mint::Point2<f32>> for Point2f {
+    fn from(p: mint::Point2<f32>) -> Self {
+        Point2f { x: p.x, y: p.y }
+    }
+}
+
+#[cfg(feature = "mint")]
+impl From<Point2f> for mint::Point2<f32> {
+    fn from(p: Point2f) -> Self {
+        mint::Point2 { x: p.x, y: p.y }
+    }
+}
+
+#[cfg(feature = "mint")]
+impl From<mint::Vector2<f32>> for Point2f {
+    fn from(v: mint::Vector2<f32>) -> Self {
+        Point2f { x: v.x, y: v.y }
+    }
+}
+
+#[cfg(feature = "mint")]
+impl From<Point2f> for mint::Vector2<f32> {
+    fn from(p: Point2f) -> Self {
+        mint::Vector2 { x: p.x, y: p.y }
+    }
+}
+
+#[cfg(feature = "nalgebra")]
+impl From<nalgebra::Point2<f32>> for Point2f {
+    fn from(p: nalgebra::Point2<f32>) -> Self {
+        Point2f { x: p.x, y: p.y }
+    }
+}
+
+#[cfg(feature = "nalgebra")]
+impl From<Point2f> for nalgebra::Point2<f32> {
+    fn from(p: Point2f) -> Self {
+        nalgebra::Point2::new(p.x, p.y)
+    }
+}
+
+#[cfg(feature = "nalgebra")]
+impl From<nalgebra::Vector2<f32>> for Point2f {
+    fn from(v: nalgebra::Vector2<f32>) -> Self {
+        Point2f { x: v.x, y: v.y }
+    }
+}
+
+#[cfg(feature = "nalgebra")]
+impl From<Point2f> for nalgebra::Vector2<f32> {
+    fn from(p: Point2f) -> Self {
+        nalgebra::Vector2::new(p.x, p.y)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_to_and_from_tuples_and_arrays() {
+        let p = Point2f::new(1.5, -2.0);
+        assert_eq!(Point2f::from((1.5, -2.0)), p);
+        assert_eq!(Point2f::from([1.5, -2.0]), p);
+        assert_eq!(<(f32, f32)>::from(p), (1.5, -2.0));
+        assert_eq!(<[f32; 2]>::from(p), [1.5, -2.0]);
+        assert_eq!(p - Point2f::new(0.5, 1.0), Point2f::new(1.0, -3.0));
+        assert_eq!(Point2f::default().distance(Point2f::new(3.0, 4.0)), 5.0);
+    }
+
+    #[cfg(all(feature = "mint", feature = "nalgebra"))]
+    #[test]
+    fn converts_to_and_from_mint_and_nalgebra() {
+        let p = Point2f::new(3.0, 4.0);
+        assert_eq!(Point2f::from(mint::Point2::from(p)), p);
+        assert_eq!(Point2f::from(mint::Vector2::from(p)), p);
+        assert_eq!(Point2f::from(nalgebra::Point2::from(p)), p);
+        assert_eq!(nalgebra::Vector2::from(p).norm(), 5.0);
+    }
+}
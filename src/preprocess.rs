@@ -0,0 +1,395 @@
+//! Frame conditioning before detection and tracking.
+//!
+//! Detection and tracking both work best on frames prepared the same way:
+//! downscaled to the working resolution, with contrast normalized across
+//! lighting changes and sensor noise smoothed. [`Preprocess`] bundles those
+//! steps into one configured pipeline that runs them in a fixed order, so
+//! every frame is conditioned identically, and keeps its intermediate buffers
+//! between frames.
+
+use image::{GrayImage, RgbImage};
+
+/// Contrast normalization step of a [`Preprocess`] pipeline.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Equalize {
+    /// Global histogram equalization.
+    Global,
+    /// Contrast-limited adaptive histogram equalization over a grid of tiles.
+    Clahe { clip_limit: f32, tiles: (u32, u32) },
+}
+
+/// A frame conditioning pipeline, configured with builder methods and run
+/// with [`apply`](Self::apply) / [`apply_rgb`](Self::apply_rgb).
+///
+/// The configured steps always run in the same order, whatever the order of
+/// the builder calls:
+///
+/// 1. gray conversion (for color input, since every later step works on gray
+///    levels),
+/// 2. [`resize`](Self::resize),
+/// 3. [`gamma`](Self::gamma),
+/// 4. [`equalize`](Self::equalize) or [`clahe`](Self::clahe),
+/// 5. [`blur`](Self::blur).
+///
+/// The output and intermediate images are owned by the pipeline and reused,
+/// so once the input size is fixed, conditioning a frame performs no heap
+/// allocation.
+#[derive(Debug, Clone, Default)]
+pub struct Preprocess {
+    resize: Option<(u32, u32)>,
+    gamma: Option<f32>,
+    gamma_lut: Vec<u8>,
+    equalize: Option<Equalize>,
+    blur_sigma: Option<f32>,
+    kernel: Vec<f32>,
+    /// Gray conversion of color input, at the input size.
+    gray: GrayImage,
+    /// Current and scratch image, at the output size.
+    front: GrayImage,
+    back: GrayImage,
+    tile_luts: Vec<u8>,
+    blur_rows: Vec<f32>,
+}
+
+impl Preprocess {
+    /// An empty pipeline: [`apply`](Self::apply) returns a copy of its input.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Resizes frames to `width` x `height` with bilinear sampling.
+    ///
+    /// # Panics
+    /// Panics if a dimension is zero.
+    pub fn resize(mut self, width: u32, height: u32) -> Self {
+        assert!(
+            width > 0 && height > 0,
+            "resize dimensions must be non-zero"
+        );
+        self.resize = Some((width, height));
+        self
+    }
+
+    /// Applies the power law `out = 255 * (in / 255)^gamma`; values below 1
+    /// brighten dark regions.
+    ///
+    /// # Panics
+    /// Panics if `gamma` is not positive.
+    pub fn gamma(mut self, gamma: f32) -> Self {
+        assert!(gamma > 0.0, "gamma must be positive");
+        self.gamma = Some(gamma);
+        self.gamma_lut = (0..256)
+            .map(|v| (255.0 * (v as f32 / 255.0).powf(gamma)).round() as u8)
+            .collect();
+        self
+    }
+
+    /// Spreads the gray levels with global histogram equalization. Replaces a
+    /// previous [`clahe`](Self::clahe).
+    pub fn equalize(mut self) -> Self {
+        self.equalize = Some(Equalize::Global);
+        self
+    }
+
+    /// Contrast-limited adaptive histogram equalization: every tile of a
+    /// `tiles_x` x `tiles_y` grid is equalized on its own histogram, clipped at
+    /// `clip_limit` times the average bin count, and neighboring tiles are
+    /// blended bilinearly. Like OpenCV's `createCLAHE`, with 2.0 and 8x8 as
+    /// typical values. Replaces a previous [`equalize`](Self::equalize).
+    ///
+    /// # Panics
+    /// Panics if `clip_limit` is below 1 or the grid is empty.
+    pub fn clahe(mut self, clip_limit: f32, tiles_x: u32, tiles_y: u32) -> Self {
+        assert!(clip_limit >= 1.0, "clip_limit must be at least 1");
+        assert!(tiles_x > 0 && tiles_y > 0, "tile grid must be non-empty");
+        self.equalize = Some(Equalize::Clahe {
+            clip_limit,
+            tiles: (tiles_x, tiles_y),
+        });
+        self
+    }
+
+    /// Smooths with a Gaussian of standard deviation `sigma` pixels, with
+    /// edge pixels repeated beyond the border.
+    ///
+    /// # Panics
+    /// Panics if `sigma` is not positive.
+    pub fn blur(mut self, sigma: f32) -> Self {
+        assert!(sigma > 0.0, "sigma must be positive");
+        self.blur_sigma = Some(sigma);
+        let radius = (3.0 * sigma).ceil() as i32;
+        self.kernel = (-radius..=radius)
+            .map(|i| (-((i * i) as f32) / (2.0 * sigma * sigma)).exp())
+            .collect();
+        let sum: f32 = self.kernel.iter().sum();
+        self.kernel.iter_mut().for_each(|w| *w /= sum);
+        self
+    }
+
+    /// Conditions a grayscale frame and returns the result, which stays valid
+    /// until the next call.
+    pub fn apply(&mut self, image: &GrayImage) -> &GrayImage {
+        load(image, self.resize, &mut self.front);
+        self.run()
+    }
+
+    /// Converts a color frame to gray (ITU-R BT.601 weights, as OpenCV's
+    /// `COLOR_RGB2GRAY`) and conditions it like [`apply`](Self::apply).
+    pub fn apply_rgb(&mut self, image: &RgbImage) -> &GrayImage {
+        ensure_size(&mut self.gray, image.width(), image.height());
+        for (gray, rgb) in self.gray.iter_mut().zip(image.as_raw().chunks_exact(3)) {
+            let luma = 299 * rgb[0] as u32 + 587 * rgb[1] as u32 + 114 * rgb[2] as u32;
+            *gray = ((luma + 500) / 1000) as u8;
+        }
+        load(&self.gray, self.resize, &mut self.front);
+        self.run()
+    }
+
+    /// Runs the steps after the resize on `self.front`.
+    fn run(&mut self) -> &GrayImage {
+        if self.gamma.is_some() {
+            for v in self.front.iter_mut() {
+                *v = self.gamma_lut[*v as usize];
+            }
+        }
+        match self.equalize {
+            Some(Equalize::Global) => equalize_in_place(&mut self.front),
+            Some(Equalize::Clahe { clip_limit, tiles }) => {
+                ensure_size(&mut self.back, self.front.width(), self.front.height());
+                clahe(
+                    &self.front,
+                    &mut self.back,
+                    clip_limit,
+                    tiles,
+                    &mut self.tile_luts,
+                );
+                std::mem::swap(&mut self.front, &mut self.back);
+            }
+            None => {}
+        }
+        if self.blur_sigma.is_some() {
+            ensure_size(&mut self.back, self.front.width(), self.front.height());
+            gaussian_blur(
+                &self.front,
+                &mut self.back,
+                &self.kernel,
+                &mut self.blur_rows,
+            );
+            std::mem::swap(&mut self.front, &mut self.back);
+        }
+        &self.front
+    }
+}
+
+/// Copies `image` into `front`, resized to `resize` if given.
+fn load(image: &GrayImage, resize: Option<(u32, u32)>, front: &mut GrayImage) {
+    let (width, height) = resize.unwrap_or(image.dimensions());
+    ensure_size(front, width, height);
+    if image.dimensions() == (width, height) {
+        front.copy_from_slice(image.as_raw());
+    } else {
+        resize_bilinear(image, front);
+    }
+}
+
+/// Reallocates `image` only when its size differs.
+fn ensure_size(image: &mut GrayImage, width: u32, height: u32) {
+    if image.dimensions() != (width, height) {
+        *image = GrayImage::new(width, height);
+    }
+}
+
+/// Bilinear resize of `src` into `dst`, sampling at pixel centers.
+fn resize_bilinear(src: &GrayImage, dst: &mut GrayImage) {
+    let (sw, sh) = src.dimensions();
+    let (dw, dh) = dst.dimensions();
+    let (scale_x, scale_y) = (sw as f32 / dw as f32, sh as f32 / dh as f32);
+    let (max_x, max_y) = ((sw - 1) as f32, (sh - 1) as f32);
+    let data = src.as_raw();
+    let stride = sw as usize;
+
+    for (x, y, pixel) in dst.enumerate_pixels_mut() {
+        let sx = ((x as f32 + 0.5) * scale_x - 0.5).clamp(0.0, max_x);
+        let sy = ((y as f32 + 0.5) * scale_y - 0.5).clamp(0.0, max_y);
+        let (x0, y0) = (sx as usize, sy as usize);
+        let (x1, y1) = ((x0 + 1).min(sw as usize - 1), (y0 + 1).min(sh as usize - 1));
+        let (fx, fy) = (sx - x0 as f32, sy - y0 as f32);
+        let at = |x: usize, y: usize| data[y * stride + x] as f32;
+        let top = at(x0, y0) + fx * (at(x1, y0) - at(x0, y0));
+        let bottom = at(x0, y1) + fx * (at(x1, y1) - at(x0, y1));
+        pixel[0] = (top + fy * (bottom - top)).round() as u8;
+    }
+}
+
+/// Lookup table mapping gray levels through the normalized cumulative
+/// histogram `hist` of `count` pixels.
+fn equalization_lut(hist: &[u32; 256], count: u32, lut: &mut [u8]) {
+    let mut cumulative = 0;
+    for (bin, out) in hist.iter().zip(lut.iter_mut()) {
+        cumulative += bin;
+        *out = ((cumulative as u64 * 255 + count as u64 / 2) / count.max(1) as u64) as u8;
+    }
+}
+
+fn equalize_in_place(image: &mut GrayImage) {
+    let mut hist = [0u32; 256];
+    for &v in image.iter() {
+        hist[v as usize] += 1;
+    }
+    let mut lut = [0u8; 256];
+    equalization_lut(&hist, image.width() * image.height(), &mut lut);
+    for v in image.iter_mut() {
+        *v = lut[*v as usize];
+    }
+}
+
+fn clahe(
+    src: &GrayImage,
+    dst: &mut GrayImage,
+    clip_limit: f32,
+    (tiles_x, tiles_y): (u32, u32),
+    luts: &mut Vec<u8>,
+) {
+    let (width, height) = src.dimensions();
+    let (tiles_x, tiles_y) = (tiles_x.min(width), tiles_y.min(height));
+    let (tile_w, tile_h) = (width.div_ceil(tiles_x), height.div_ceil(tiles_y));
+    luts.resize((tiles_x * tiles_y * 256) as usize, 0);
+    let stride = width as usize;
+    let data = src.as_raw();
+
+    // One clipped equalization table per tile.
+    for ty in 0..tiles_y {
+        for tx in 0..tiles_x {
+            let (x0, y0) = (tx * tile_w, ty * tile_h);
+            let (x1, y1) = ((x0 + tile_w).min(width), (y0 + tile_h).min(height));
+            let mut hist = [0u32; 256];
+            for y in y0..y1 {
+                let row = &data[y as usize * stride..][x0 as usize..x1 as usize];
+                for &v in row {
+                    hist[v as usize] += 1;
+                }
+            }
+            let count = (x1 - x0) * (y1 - y0);
+
+            // Clip every bin and hand the excess back out evenly.
+            let limit = ((clip_limit * count as f32 / 256.0) as u32).max(1);
+            let mut excess = 0;
+            for bin in hist.iter_mut() {
+                excess += bin.saturating_sub(limit);
+                *bin = (*bin).min(limit);
+            }
+            let (share, rest) = (excess / 256, (excess % 256) as usize);
+            for (i, bin) in hist.iter_mut().enumerate() {
+                *bin += share + u32::from(i < rest);
+            }
+
+            let start = ((ty * tiles_x + tx) * 256) as usize;
+            equalization_lut(&hist, count, &mut luts[start..start + 256]);
+        }
+    }
+
+    // Blend the tables of the four tiles around each pixel.
+    let tile_pos = |v: u32, size: u32, tiles: u32| {
+        let t = ((v as f32 + 0.5) / size as f32 - 0.5).max(0.0);
+        let t0 = (t as u32).min(tiles - 1);
+        (t0, (t0 + 1).min(tiles - 1), (t - t0 as f32).min(1.0))
+    };
+    let rows = data.chunks_exact(stride).zip(dst.chunks_exact_mut(stride));
+    for (y, (src_row, dst_row)) in rows.enumerate() {
+        let (ty0, ty1, fy) = tile_pos(y as u32, tile_h, tiles_y);
+        for (x, (&v, out)) in src_row.iter().zip(dst_row.iter_mut()).enumerate() {
+            let v = v as usize;
+            let (tx0, tx1, fx) = tile_pos(x as u32, tile_w, tiles_x);
+            let lut = |tx: u32, ty: u32| luts[((ty * tiles_x + tx) * 256) as usize + v] as f32;
+            let top = lut(tx0, ty0) + fx * (lut(tx1, ty0) - lut(tx0, ty0));
+            let bottom = lut(tx0, ty1) + fx * (lut(tx1, ty1) - lut(tx0, ty1));
+            *out = (top + fy * (bottom - top)).round() as u8;
+        }
+    }
+}
+
+/// Separable Gaussian blur of `src` into `dst` with the normalized `kernel`,
+/// clamping reads to the image. `rows` holds the horizontal pass.
+fn gaussian_blur(src: &GrayImage, dst: &mut GrayImage, kernel: &[f32], rows: &mut Vec<f32>) {
+    let (width, height) = (src.width() as usize, src.height() as usize);
+    let radius = (kernel.len() / 2) as isize;
+    let clamp = |i: isize, len: usize| i.clamp(0, len as isize - 1) as usize;
+    rows.resize(width * height, 0.0);
+
+    let data = src.as_raw();
+    for y in 0..height {
+        let row = &data[y * width..(y + 1) * width];
+        for x in 0..width {
+            rows[y * width + x] = kernel
+                .iter()
+                .enumerate()
+                .map(|(k, w)| w * row[clamp(x as isize + k as isize - radius, width)] as f32)
+                .sum();
+        }
+    }
+    for (i, out) in dst.iter_mut().enumerate() {
+        let (x, y) = (i % width, i / width);
+        let sum: f32 = kernel
+            .iter()
+            .enumerate()
+            .map(|(k, w)| w * rows[clamp(y as isize + k as isize - radius, height) * width + x])
+            .sum();
+        *out = sum.round() as u8;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Luma, Rgb};
+
+    fn low_contrast(width: u32, height: u32) -> GrayImage {
+        GrayImage::from_fn(width, height, |x, y| Luma([100 + ((x + 2 * y) % 20) as u8]))
+    }
+
+    fn range(image: &GrayImage) -> (u8, u8) {
+        let min = image.iter().copied().min().unwrap();
+        let max = image.iter().copied().max().unwrap();
+        (min, max)
+    }
+
+    #[test]
+    fn equalization_stretches_contrast() {
+        let image = low_contrast(64, 48);
+        assert_eq!(range(&image), (100, 119));
+
+        let global = Preprocess::new().equalize().apply(&image).clone();
+        let (min, max) = range(&global);
+        assert!(min < 20 && max == 255, "{min}..{max}");
+
+        // The clip limit bounds the gain: more contrast, but far from global.
+        let local = Preprocess::new().clahe(2.0, 4, 4).apply(&image).clone();
+        let (min, max) = range(&local);
+        assert!(max - min > 2 * 19 && max - min < 150, "{min}..{max}");
+    }
+
+    #[test]
+    fn steps_run_in_fixed_order() {
+        let image = low_contrast(64, 48);
+        let mut a = Preprocess::new().blur(1.0).gamma(0.5).resize(32, 24);
+        let mut b = Preprocess::new().resize(32, 24).gamma(0.5).blur(1.0);
+        assert_eq!(a.apply(&image), b.apply(&image));
+        assert_eq!(a.apply(&image).dimensions(), (32, 24));
+
+        // A constant frame stays constant through resize and blur.
+        let flat = GrayImage::from_pixel(40, 30, Luma([128]));
+        let out = Preprocess::new()
+            .resize(20, 15)
+            .blur(2.0)
+            .apply(&flat)
+            .clone();
+        assert!(out.iter().all(|&v| v == 128));
+    }
+
+    #[test]
+    fn rgb_input_is_converted_to_gray() {
+        let rgb = RgbImage::from_pixel(8, 8, Rgb([255, 0, 0]));
+        let gray = Preprocess::new().gamma(1.0).apply_rgb(&rgb).clone();
+        assert!(gray.iter().all(|&v| v == 76));
+    }
+}
@@ -0,0 +1,153 @@
+//! Per-frame motion priors for seeding the tracker.
+//!
+//! Under fast camera rotation every feature moves by tens of pixels, more
+//! than the pyramid can recover from a zero initial displacement. A device
+//! gyroscope (or the previous frame's [`RigidTransform`](crate::RigidTransform))
+//! predicts most of that motion as one transform of the whole frame.
+//! [`MotionPrior`] holds it, and tracking with a prior seeds every point with
+//! its transformed position, leaving Lucas-Kanade only the residual motion to
+//! refine. A pure camera rotation moves the image by a homography, so an
+//! integrated gyroscope rotation converts exactly with
+//! [`MotionPrior::rotation`].
+
+use crate::camera::CameraIntrinsics;
+
+/// Whole-frame transform predicting where previous-frame points land in the
+/// next frame.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MotionPrior {
+    matrix: [[f32; 3]; 3],
+}
+
+impl MotionPrior {
+    /// Rotation by `rotation` radians and uniform scaling by `scale` about
+    /// `center`, followed by a shift of `translation` pixels.
+    ///
+    /// A gyroscope's roll about the optical axis rotates the image about the
+    /// principal point; `scale` covers zoom or motion along the axis.
+    pub fn similarity(
+        rotation: f32,
+        scale: f32,
+        center: (f32, f32),
+        translation: (f32, f32),
+    ) -> Self {
+        let (sin, cos) = rotation.sin_cos();
+        let (a, b) = (scale * cos, scale * sin);
+        let (cx, cy) = center;
+        Self::affine([
+            [a, -b, cx - a * cx + b * cy + translation.0],
+            [b, a, cy - b * cx - a * cy + translation.1],
+        ])
+    }
+
+    /// General 2x3 affine transform `[[a, b, tx], [c, d, ty]]`, in the layout
+    /// of [`RigidTransform::matrix`](crate::RigidTransform::matrix).
+    pub fn affine([row0, row1]: [[f32; 3]; 2]) -> Self {
+        MotionPrior {
+            matrix: [row0, row1, [0.0, 0.0, 1.0]],
+        }
+    }
+
+    /// Projective transform mapping `(x, y)` to the dehomogenized
+    /// `matrix * (x, y, 1)`.
+    ///
+    /// The homography should keep the tracked points in front of the camera
+    /// (a positive third coordinate); a point it sends to infinity is
+    /// predicted at a non-finite position, which the tracker reports as out
+    /// of bounds.
+    pub fn homography(matrix: [[f32; 3]; 3]) -> Self {
+        MotionPrior { matrix }
+    }
+
+    /// Image motion of a pure camera rotation, the homography
+    /// `K * rotation * K⁻¹` of the pinhole `camera`.
+    ///
+    /// `rotation` maps the viewing ray of a scene point in the previous
+    /// camera frame to its ray in the next one, with OpenCV's axes (x right,
+    /// y down, z forward). A gyroscope integrated over the frame interval
+    /// gives the camera's own rotation `R`, its next axes expressed in the
+    /// previous ones; the rays then turn by the transpose `Rᵀ`.
+    ///
+    /// Lens distortion is not part of the homography: the prediction is in
+    /// undistorted coordinates, which is what tracking with
+    /// [`TrackerContext::set_intrinsics`](crate::TrackerContext::set_intrinsics)
+    /// expects. Translation of the camera is ignored, so the prior is exact
+    /// for distant scenes only.
+    pub fn rotation(rotation: [[f32; 3]; 3], camera: &CameraIntrinsics) -> Self {
+        let k = [
+            [camera.fx, 0.0, camera.cx],
+            [0.0, camera.fy, camera.cy],
+            [0.0, 0.0, 1.0],
+        ];
+        let k_inv = [
+            [1.0 / camera.fx, 0.0, -camera.cx / camera.fx],
+            [0.0, 1.0 / camera.fy, -camera.cy / camera.fy],
+            [0.0, 0.0, 1.0],
+        ];
+        MotionPrior {
+            matrix: mul(&mul(&k, &rotation), &k_inv),
+        }
+    }
+
+    /// Maps a previous-frame point to its predicted next-frame position.
+    pub fn apply(&self, (x, y): (f32, f32)) -> (f32, f32) {
+        let m = &self.matrix;
+        let w = m[2][0] * x + m[2][1] * y + m[2][2];
+        (
+            (m[0][0] * x + m[0][1] * y + m[0][2]) / w,
+            (m[1][0] * x + m[1][1] * y + m[1][2]) / w,
+        )
+    }
+}
+
+/// Product `a * b` of two 3x3 matrices.
+fn mul(a: &[[f32; 3]; 3], b: &[[f32; 3]; 3]) -> [[f32; 3]; 3] {
+    std::array::from_fn(|i| std::array::from_fn(|j| (0..3).map(|k| a[i][k] * b[k][j]).sum()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn similarity_fixes_its_center() {
+        let prior = MotionPrior::similarity(0.3, 1.2, (100.0, 50.0), (0.0, 0.0));
+        let (x, y) = prior.apply((100.0, 50.0));
+        assert!((x - 100.0).abs() < 1e-4 && (y - 50.0).abs() < 1e-4);
+
+        // A quarter turn maps +x from the center onto +y.
+        let quarter =
+            MotionPrior::similarity(std::f32::consts::FRAC_PI_2, 2.0, (10.0, 10.0), (1.0, -1.0));
+        let (x, y) = quarter.apply((11.0, 10.0));
+        assert!((x - 11.0).abs() < 1e-4 && (y - 11.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn rotation_projects_through_the_camera() {
+        let camera = CameraIntrinsics::new(500.0, 500.0, 320.0, 240.0);
+        let (sin, cos) = 0.05f32.sin_cos();
+
+        // Rolling about the optical axis is a rotation about the principal
+        // point.
+        let roll = MotionPrior::rotation(
+            [[cos, -sin, 0.0], [sin, cos, 0.0], [0.0, 0.0, 1.0]],
+            &camera,
+        );
+        let similarity = MotionPrior::similarity(0.05, 1.0, (320.0, 240.0), (0.0, 0.0));
+        for p in [(320.0, 240.0), (100.0, 50.0), (600.0, 400.0)] {
+            let (a, b) = (roll.apply(p), similarity.apply(p));
+            assert!((a.0 - b.0).abs() < 1e-3 && (a.1 - b.1).abs() < 1e-3);
+        }
+
+        // Turning the rays towards +x moves the principal point by
+        // `f * tan(angle)`, and off-center points by more.
+        let pan = MotionPrior::rotation(
+            [[cos, 0.0, sin], [0.0, 1.0, 0.0], [-sin, 0.0, cos]],
+            &camera,
+        );
+        let (x, y) = pan.apply((320.0, 240.0));
+        assert!((x - 320.0 - 500.0 * 0.05f32.tan()).abs() < 1e-3 && (y - 240.0).abs() < 1e-3);
+        let (x, _) = pan.apply((600.0, 240.0));
+        assert!(x - 600.0 > 500.0 * 0.05f32.tan());
+    }
+}
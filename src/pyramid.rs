@@ -1,4 +1,11 @@
 use image::{GrayImage, ImageBuffer};
+use std::fmt;
+use std::ops::Deref;
+
+use crate::border::BorderMode;
+use crate::sampling::{sample_bicubic, sample_bilinear};
+use crate::utils::buffer_pool::{recycle_u8, take_u8};
+use crate::utils::fast_gradients::compute_gradients_into;
 #[cfg(all(target_arch = "wasm32", target_feature = "simd128"))]
 use std::arch::wasm32::*;
 
@@ -18,6 +25,30 @@ pub fn build_pyramid(image: &GrayImage, levels: usize) -> Vec<GrayImage> {
     pyramid
 }
 
+/// Picks a pyramid level count for `width` x `height` frames tracked with
+/// `window_size` windows: levels are added while the coarsest one still
+/// spans a full window in both dimensions.
+///
+/// # Returns
+/// At least 1, so frames smaller than a window get level 0 alone.
+pub fn auto_pyramid_levels(width: u32, height: u32, window_size: usize) -> usize {
+    let mut side = width.min(height) as usize;
+    let mut levels = 1;
+    while side / 2 >= window_size.max(1) {
+        side /= 2;
+        levels += 1;
+    }
+    levels
+}
+
+/// Hands the level storage of a pyramid built by [`build_pyramid`] back to
+/// the thread's buffer pool.
+pub(crate) fn recycle_pyramid(pyramid: Vec<GrayImage>) {
+    for level in pyramid {
+        recycle_u8(level.into_raw());
+    }
+}
+
 /// Builds the pyramid into an existing buffer, reusing each level's storage when
 /// its dimensions are unchanged.
 ///
@@ -51,7 +82,7 @@ pub fn build_pyramid_into(image: &GrayImage, levels: usize, pyramid: &mut Vec<Gr
             prev_w as usize,
             new_w as usize,
             new_h as usize,
-            &mut **new_image,
+            new_image,
         );
 
         produced += 1;
@@ -60,6 +91,265 @@ pub fn build_pyramid_into(image: &GrayImage, levels: usize, pyramid: &mut Vec<Gr
     pyramid.truncate(produced);
 }
 
+/// Why a pyramid, or a pair of pyramids, cannot be used for tracking.
+///
+/// Returned by [`validate_pyramid`], [`validate_pyramid_pair`] and
+/// [`PyramidSet`]; the tracking functions panic with the same message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PyramidError {
+    /// The pyramid has no level.
+    Empty,
+    /// Level `level` is not half the size of the level above it, as
+    /// [`build_pyramid`] makes it, so its coordinates do not scale by 2.
+    InconsistentScale {
+        level: usize,
+        expected: (u32, u32),
+        actual: (u32, u32),
+    },
+    /// The two pyramids have a different number of levels.
+    LevelCountMismatch { prev: usize, next: usize },
+    /// Level `level` has different dimensions in the two pyramids.
+    DimensionMismatch {
+        level: usize,
+        prev: (u32, u32),
+        next: (u32, u32),
+    },
+}
+
+impl fmt::Display for PyramidError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            PyramidError::Empty => write!(f, "pyramid must have at least 1 level"),
+            PyramidError::InconsistentScale {
+                level,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "pyramid level {level} is {}x{}, expected {}x{} (half of level {})",
+                actual.0,
+                actual.1,
+                expected.0,
+                expected.1,
+                level - 1
+            ),
+            PyramidError::LevelCountMismatch { prev, next } => write!(
+                f,
+                "pyramids differ in level count: {prev} (prev) vs {next} (next)"
+            ),
+            PyramidError::DimensionMismatch { level, prev, next } => write!(
+                f,
+                "pyramid level {level} differs in size: {}x{} (prev) vs {}x{} (next)",
+                prev.0, prev.1, next.0, next.1
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PyramidError {}
+
+/// Checks that `pyramid` has at least one level and that every level is half
+/// the size (rounded down) of the one above it.
+pub fn validate_pyramid(pyramid: &[GrayImage]) -> Result<(), PyramidError> {
+    if pyramid.is_empty() {
+        return Err(PyramidError::Empty);
+    }
+    for (level, pair) in pyramid.windows(2).enumerate() {
+        let (w, h) = pair[0].dimensions();
+        let expected = (w / 2, h / 2);
+        let actual = pair[1].dimensions();
+        if actual != expected {
+            return Err(PyramidError::InconsistentScale {
+                level: level + 1,
+                expected,
+                actual,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Checks that two pyramids can be tracked between: each is valid (see
+/// [`validate_pyramid`]), and they have the same level count and the same
+/// dimensions at every level.
+pub fn validate_pyramid_pair(prev: &[GrayImage], next: &[GrayImage]) -> Result<(), PyramidError> {
+    if prev.len() != next.len() {
+        return Err(PyramidError::LevelCountMismatch {
+            prev: prev.len(),
+            next: next.len(),
+        });
+    }
+    validate_pyramid(prev)?;
+    // Level 0 matching implies the rest matches once `next` halves properly.
+    if prev[0].dimensions() != next[0].dimensions() {
+        return Err(PyramidError::DimensionMismatch {
+            level: 0,
+            prev: prev[0].dimensions(),
+            next: next[0].dimensions(),
+        });
+    }
+    validate_pyramid(next)
+}
+
+/// A pyramid known to be valid (see [`validate_pyramid`]).
+///
+/// Dereferences to `[GrayImage]`, so it can be passed wherever a pyramid
+/// slice is expected; use [`check_pair`](Self::check_pair) to confirm two
+/// sets can be tracked between before doing so.
+#[derive(Debug, Clone)]
+pub struct PyramidSet {
+    levels: Vec<GrayImage>,
+}
+
+impl PyramidSet {
+    /// Builds the pyramid of `image`, see [`build_pyramid`].
+    ///
+    /// # Panics
+    /// Panics if `levels` is zero.
+    pub fn build(image: &GrayImage, levels: usize) -> Self {
+        assert!(levels > 0, "pyramid must have at least 1 level");
+        PyramidSet {
+            levels: build_pyramid(image, levels),
+        }
+    }
+
+    /// Wraps levels built elsewhere, after validating them.
+    pub fn from_levels(levels: Vec<GrayImage>) -> Result<Self, PyramidError> {
+        validate_pyramid(&levels)?;
+        Ok(PyramidSet { levels })
+    }
+
+    /// Checks that this set and `next` can be tracked between (see
+    /// [`validate_pyramid_pair`]).
+    pub fn check_pair(&self, next: &PyramidSet) -> Result<(), PyramidError> {
+        validate_pyramid_pair(&self.levels, &next.levels)
+    }
+
+    /// Returns the levels, largest first.
+    pub fn into_levels(self) -> Vec<GrayImage> {
+        self.levels
+    }
+
+    /// Bilinear sample of level `level` at `(x, y)` in that level's pixels
+    /// (a level-0 point `p` is at `p / 2^level`), see [`sample_bilinear`].
+    ///
+    /// # Panics
+    /// Panics if `level` is not below the number of levels.
+    pub fn sample(&self, level: usize, x: f32, y: f32, border: BorderMode) -> f32 {
+        sample_bilinear(&self.levels[level], x, y, border)
+    }
+
+    /// Bicubic sample of level `level` at `(x, y)` in that level's pixels,
+    /// see [`sample_bicubic`].
+    ///
+    /// # Panics
+    /// Panics if `level` is not below the number of levels.
+    pub fn sample_bicubic(&self, level: usize, x: f32, y: f32, border: BorderMode) -> f32 {
+        sample_bicubic(&self.levels[level], x, y, border)
+    }
+}
+
+impl Deref for PyramidSet {
+    type Target = [GrayImage];
+
+    fn deref(&self) -> &[GrayImage] {
+        &self.levels
+    }
+}
+
+/// Scharr gradients `(grad_x, grad_y)` of one pyramid level, as produced by
+/// [`compute_gradients_into`].
+pub(crate) type LevelGradients = (Vec<i16>, Vec<i16>);
+
+/// Builds the pyramid like [`build_pyramid_into`] and also computes every
+/// level's gradients into `gradients` (one entry per produced level).
+///
+/// Downsampling level `N` into `N + 1` and the gradient pass over level `N`
+/// only read level `N`, so the two run side by side. With the `rayon` feature
+/// they are forked onto the thread pool, hiding the pyramid cost behind the
+/// (more expensive) gradient pass; without it they run one after the other.
+/// `rayon::join` keeps both halves on the stack, so the steady state stays
+/// allocation-free either way.
+pub(crate) fn build_pyramid_with_gradients_into(
+    image: &GrayImage,
+    levels: usize,
+    pyramid: &mut Vec<GrayImage>,
+    gradients: &mut Vec<LevelGradients>,
+) {
+    ensure_level(pyramid, 0, image.width(), image.height());
+    pyramid[0].copy_from_slice(image.as_raw());
+
+    let mut level = 0;
+    loop {
+        let (w, h) = pyramid[level].dimensions();
+        if gradients.len() <= level {
+            gradients.push(Default::default());
+        }
+        let (grad_x, grad_y) = &mut gradients[level];
+        grad_x.resize((w * h) as usize, 0);
+        grad_y.resize((w * h) as usize, 0);
+
+        // Same stopping rule as `build_pyramid_into`.
+        let has_next = level + 1 < levels && w >= 2 && h >= 2;
+        if has_next {
+            ensure_level(pyramid, level + 1, w / 2, h / 2);
+        }
+
+        let (head, tail) = pyramid.split_at_mut(level + 1);
+        let current = &head[level];
+        let mut gradient_pass = move || compute_gradients_into(current, grad_x, grad_y);
+
+        match tail.first_mut() {
+            Some(next_image) if has_next => join(gradient_pass, || {
+                downsample_2x2_into(
+                    current.as_raw(),
+                    w as usize,
+                    (w / 2) as usize,
+                    (h / 2) as usize,
+                    next_image,
+                )
+            }),
+            _ => {
+                gradient_pass();
+                break;
+            }
+        }
+
+        level += 1;
+    }
+
+    pyramid.truncate(level + 1);
+    gradients.truncate(level + 1);
+}
+
+/// Computes the gradients of every level of `pyramid` into `gradients` (one
+/// entry per level), reusing the entries' storage.
+pub(crate) fn compute_pyramid_gradients_into(
+    pyramid: &[GrayImage],
+    gradients: &mut Vec<LevelGradients>,
+) {
+    gradients.resize_with(pyramid.len(), Default::default);
+    for (level, (grad_x, grad_y)) in pyramid.iter().zip(gradients.iter_mut()) {
+        let n = (level.width() * level.height()) as usize;
+        grad_x.resize(n, 0);
+        grad_y.resize(n, 0);
+        compute_gradients_into(level, grad_x, grad_y);
+    }
+}
+
+/// Runs `a` and `b`, forked onto the thread pool with the `rayon` feature and
+/// one after the other without it.
+#[cfg(feature = "rayon")]
+pub(crate) fn join(a: impl FnOnce() + Send, b: impl FnOnce() + Send) {
+    rayon::join(a, b);
+}
+
+#[cfg(not(feature = "rayon"))]
+pub(crate) fn join(a: impl FnOnce(), b: impl FnOnce()) {
+    a();
+    b();
+}
+
 /// Downsamples `src` (`prev_w` wide) by averaging each 2x2 block into one output
 /// pixel, writing `new_w * new_h` bytes into `dst`. `prev_w >= 2 * new_w` and the
 /// source must have at least `2 * new_h` rows, which the caller guarantees.
@@ -164,14 +454,21 @@ unsafe fn downsample_2x2_simd128(
 }
 
 /// Ensures `pyramid[index]` exists with the given dimensions, allocating only
-/// when the slot is missing or its size changed.
+/// when the slot is missing or its size changed. Replaced and new levels go
+/// through the thread's buffer pool, so alternating between a few frame sizes
+/// settles into reuse as well.
 fn ensure_level(pyramid: &mut Vec<GrayImage>, index: usize, width: u32, height: u32) {
+    if index < pyramid.len() && pyramid[index].dimensions() == (width, height) {
+        return;
+    }
+
+    let level = ImageBuffer::from_raw(width, height, take_u8((width * height) as usize))
+        .expect("pooled buffer has exactly width * height pixels");
     if index < pyramid.len() {
-        if pyramid[index].dimensions() != (width, height) {
-            pyramid[index] = ImageBuffer::new(width, height);
-        }
+        let old = std::mem::replace(&mut pyramid[index], level);
+        recycle_u8(old.into_raw());
     } else {
-        pyramid.push(ImageBuffer::new(width, height));
+        pyramid.push(level);
     }
 }
 
@@ -224,4 +521,74 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn pipelined_build_matches_separate_passes() {
+        for (w, h) in [(64u32, 48u32), (65, 49), (3, 3), (1, 5)] {
+            let img = make_image(w, h);
+            let (mut pyr, mut grads) = (Vec::new(), Vec::new());
+            // Build twice so the second call runs over reused buffers.
+            for levels in [2, 4] {
+                build_pyramid_with_gradients_into(&img, levels, &mut pyr, &mut grads);
+            }
+
+            assert_eq!(pyr, build_pyramid(&img, 4), "pyramid mismatch at {w}x{h}");
+            assert_eq!(grads.len(), pyr.len());
+            for (level, (grad_x, grad_y)) in pyr.iter().zip(&grads) {
+                let n = (level.width() * level.height()) as usize;
+                let (mut ex, mut ey) = (vec![0; n], vec![0; n]);
+                compute_gradients_into(level, &mut ex, &mut ey);
+                assert_eq!((grad_x, grad_y), (&ex, &ey), "gradients at {w}x{h}");
+            }
+        }
+    }
+
+    #[test]
+    fn validation_reports_incompatible_pyramids() {
+        let prev = PyramidSet::build(&make_image(65, 49), 3);
+        assert_eq!(prev.len(), 3);
+        assert_eq!(prev.check_pair(&prev.clone()), Ok(()));
+
+        let shallow = PyramidSet::build(&make_image(65, 49), 2);
+        assert_eq!(
+            prev.check_pair(&shallow),
+            Err(PyramidError::LevelCountMismatch { prev: 3, next: 2 })
+        );
+
+        let resized = PyramidSet::build(&make_image(64, 48), 3);
+        assert_eq!(
+            prev.check_pair(&resized),
+            Err(PyramidError::DimensionMismatch {
+                level: 0,
+                prev: (65, 49),
+                next: (64, 48),
+            })
+        );
+
+        // A level that was not produced by halving the one above.
+        let mut levels = prev.clone().into_levels();
+        levels[2] = make_image(20, 12);
+        assert_eq!(
+            PyramidSet::from_levels(levels).unwrap_err(),
+            PyramidError::InconsistentScale {
+                level: 2,
+                expected: (16, 12),
+                actual: (20, 12),
+            }
+        );
+        assert_eq!(
+            PyramidSet::from_levels(Vec::new()).unwrap_err(),
+            PyramidError::Empty
+        );
+    }
+
+    #[test]
+    fn auto_levels_keep_a_window_on_the_coarsest_level() {
+        // 480 -> 240 -> 120 -> 60 -> 30; 15 would be narrower than 21.
+        assert_eq!(auto_pyramid_levels(640, 480, 21), 5);
+        assert_eq!(auto_pyramid_levels(480, 640, 21), 5);
+        assert_eq!(auto_pyramid_levels(42, 42, 21), 2);
+        assert_eq!(auto_pyramid_levels(41, 300, 21), 1);
+        assert_eq!(auto_pyramid_levels(8, 8, 21), 1);
+    }
 }
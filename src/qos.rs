@@ -0,0 +1,283 @@
+//! Runtime adjustment of tracking settings to a per-frame time budget.
+//!
+//! The right point count, window size, iteration limit and pyramid depth
+//! depend on the device: settings tuned on a desktop overrun the frame budget
+//! on a low-end phone, and settings tuned for the phone waste a desktop.
+//! [`QosController`] measures instead of guessing: fed the time of every
+//! frame, it lowers the settings while the frame budget is exceeded and raises
+//! them again while there is headroom, always within user-set bounds.
+
+use crate::timing::TimingReport;
+
+/// Tracking settings adjusted by a [`QosController`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QualitySettings {
+    /// Number of points to detect and track.
+    pub max_points: usize,
+    /// Side of the tracking window (odd).
+    pub window_size: usize,
+    /// Max Lucas-Kanade iterations per pyramid level.
+    pub max_iterations: usize,
+    /// Pyramid levels.
+    pub levels: usize,
+}
+
+/// Settings of a [`QosController`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QosParams {
+    /// Per-frame time budget in milliseconds.
+    pub target_ms: f64,
+    /// Lowest settings the controller may choose.
+    pub min: QualitySettings,
+    /// Highest settings the controller may choose; also the starting point.
+    pub max: QualitySettings,
+    /// Weight of the newest frame in the running average of frame times.
+    pub smoothing: f64,
+    /// Relative dead band around `target_ms`: nothing changes while the
+    /// average stays within `target_ms * (1 ± tolerance)`.
+    pub tolerance: f64,
+}
+
+impl QosParams {
+    /// Bounds from `min` to `max` around a budget of `target_ms`, with the
+    /// default smoothing (0.3) and tolerance (0.15).
+    pub fn new(target_ms: f64, min: QualitySettings, max: QualitySettings) -> Self {
+        QosParams {
+            target_ms,
+            min,
+            max,
+            smoothing: 0.3,
+            tolerance: 0.15,
+        }
+    }
+}
+
+/// Order in which settings are lowered; they are raised in reverse, so the
+/// last setting given up is the first one restored. Iterations go first since
+/// most points converge well below the limit.
+const DEGRADE_ORDER: [Setting; 4] = [
+    Setting::Iterations,
+    Setting::Points,
+    Setting::Window,
+    Setting::Levels,
+];
+
+#[derive(Debug, Clone, Copy)]
+enum Setting {
+    Iterations,
+    Points,
+    Window,
+    Levels,
+}
+
+/// Adjusts [`QualitySettings`] to keep the measured frame time near a target.
+///
+/// Each [`update`](QosController::update) folds one frame time into a running
+/// average. Above the dead band, one setting is lowered by one step; below it,
+/// one setting is raised by one step. After a change the average restarts, so
+/// the next decision is based on frames measured with the new settings.
+///
+/// Steps are a quarter of the iterations, a fifth of the points, 2 pixels of
+/// window size and one pyramid level.
+#[derive(Debug, Clone)]
+pub struct QosController {
+    params: QosParams,
+    settings: QualitySettings,
+    average_ms: Option<f64>,
+}
+
+impl QosController {
+    /// Creates a controller starting at `params.max`.
+    ///
+    /// # Panics
+    /// Panics if `params.target_ms` is not positive, if a `min` setting
+    /// exceeds its `max`, or if a window size bound is even.
+    pub fn new(params: QosParams) -> Self {
+        let QosParams { min, max, .. } = params;
+        assert!(params.target_ms > 0.0, "target_ms must be positive");
+        assert!(
+            min.max_points <= max.max_points
+                && min.window_size <= max.window_size
+                && min.max_iterations <= max.max_iterations
+                && min.levels <= max.levels,
+            "min settings must not exceed max settings"
+        );
+        assert!(
+            min.window_size % 2 == 1 && max.window_size % 2 == 1,
+            "window size bounds must be odd"
+        );
+        QosController {
+            params,
+            settings: max,
+            average_ms: None,
+        }
+    }
+
+    /// The settings to use for the next frame.
+    pub fn settings(&self) -> QualitySettings {
+        self.settings
+    }
+
+    /// Running average of the frame times measured with the current
+    /// settings, `None` right after a change.
+    pub fn average_ms(&self) -> Option<f64> {
+        self.average_ms
+    }
+
+    /// Records the time of one frame in milliseconds and returns the settings
+    /// for the next one.
+    pub fn update(&mut self, frame_ms: f64) -> QualitySettings {
+        let QosParams {
+            target_ms,
+            smoothing,
+            tolerance,
+            ..
+        } = self.params;
+        let average = match self.average_ms {
+            Some(average) => average + smoothing * (frame_ms - average),
+            None => frame_ms,
+        };
+        self.average_ms = Some(average);
+
+        let changed = if average > target_ms * (1.0 + tolerance) {
+            DEGRADE_ORDER.iter().any(|&setting| self.lower(setting))
+        } else if average < target_ms * (1.0 - tolerance) {
+            DEGRADE_ORDER
+                .iter()
+                .rev()
+                .any(|&setting| self.raise(setting))
+        } else {
+            false
+        };
+        if changed {
+            self.average_ms = None;
+        }
+        self.settings
+    }
+
+    /// [`update`](Self::update) with the total of a [`TimingReport`]:
+    /// pyramids, gradients and solve.
+    pub fn update_from_report(&mut self, report: &TimingReport) -> QualitySettings {
+        self.update(report.pyramid_ms + report.gradients_ms + report.solve_ms)
+    }
+
+    /// Returns to `params.max` and forgets the measurements.
+    pub fn reset(&mut self) {
+        self.settings = self.params.max;
+        self.average_ms = None;
+    }
+
+    /// Lowers `setting` by one step; `false` if it is already at its bound.
+    fn lower(&mut self, setting: Setting) -> bool {
+        let bound = *setting.of(&mut self.params.min);
+        let value = setting.of(&mut self.settings);
+        let step = match setting {
+            Setting::Iterations => value.div_ceil(4),
+            Setting::Points => value.div_ceil(5),
+            Setting::Window => 2,
+            Setting::Levels => 1,
+        };
+        let next = value.saturating_sub(step).max(bound);
+        let changed = next < *value;
+        *value = next;
+        changed
+    }
+
+    /// Raises `setting` by one step; `false` if it is already at its bound.
+    fn raise(&mut self, setting: Setting) -> bool {
+        let bound = *setting.of(&mut self.params.max);
+        let value = setting.of(&mut self.settings);
+        let step = match setting {
+            Setting::Iterations => value.div_ceil(3).max(1),
+            Setting::Points => value.div_ceil(4).max(1),
+            Setting::Window => 2,
+            Setting::Levels => 1,
+        };
+        let next = (*value + step).min(bound);
+        let changed = next > *value;
+        *value = next;
+        changed
+    }
+}
+
+impl Setting {
+    fn of(self, settings: &mut QualitySettings) -> &mut usize {
+        match self {
+            Setting::Iterations => &mut settings.max_iterations,
+            Setting::Points => &mut settings.max_points,
+            Setting::Window => &mut settings.window_size,
+            Setting::Levels => &mut settings.levels,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bounds() -> (QualitySettings, QualitySettings) {
+        (
+            QualitySettings {
+                max_points: 20,
+                window_size: 9,
+                max_iterations: 5,
+                levels: 2,
+            },
+            QualitySettings {
+                max_points: 400,
+                window_size: 21,
+                max_iterations: 30,
+                levels: 4,
+            },
+        )
+    }
+
+    /// Frame time of a device that tracks `rate` point-iterations per ms over
+    /// a 21x21 window.
+    fn frame_ms(s: QualitySettings, rate: f64) -> f64 {
+        let window = (s.window_size * s.window_size) as f64 / (21.0 * 21.0);
+        let levels = s.levels as f64 / 4.0;
+        2.0 + (s.max_points * s.max_iterations) as f64 * window * levels / rate
+    }
+
+    #[test]
+    fn settles_within_budget_and_recovers_headroom() {
+        let (min, max) = bounds();
+        let mut qos = QosController::new(QosParams::new(16.0, min, max));
+        assert_eq!(qos.settings(), max);
+
+        // A slow device: degrade until the budget holds, never below `min`.
+        let mut settings = qos.settings();
+        for _ in 0..200 {
+            settings = qos.update(frame_ms(settings, 200.0));
+            assert!(settings.max_points >= min.max_points && settings.levels >= min.levels);
+            assert!(settings.window_size % 2 == 1);
+        }
+        let slow = frame_ms(settings, 200.0);
+        assert!(slow < 16.0 * 1.15, "{settings:?} takes {slow} ms");
+        assert_eq!(
+            settings.levels, max.levels,
+            "only the cheap settings gave way"
+        );
+
+        // The load drops: settings climb back to the maximum.
+        for _ in 0..200 {
+            settings = qos.update(frame_ms(settings, 1e6));
+        }
+        assert_eq!(settings, max);
+    }
+
+    #[test]
+    fn stays_at_min_when_budget_is_unreachable() {
+        let (min, max) = bounds();
+        let mut qos = QosController::new(QosParams::new(1.0, min, max));
+        for _ in 0..100 {
+            qos.update(50.0);
+        }
+        assert_eq!(qos.settings(), min);
+
+        qos.reset();
+        assert_eq!(qos.settings(), max);
+        assert_eq!(qos.average_ms(), None);
+    }
+}
@@ -0,0 +1,538 @@
+//! One-call global motion estimation between two frames.
+//!
+//! Stabilization and image registration need one transform per frame pair,
+//! not per-point tracks. [`estimate_rigid_transform`] runs the whole pipeline
+//! — Shi-Tomasi detection, forward-backward checked tracking and a robust
+//! similarity fit — with settings that work for typical video;
+//! [`stabilize_pair`] also warps the second frame onto the first.
+//! [`fit_rigid_transform`] runs only the fit, on tracks the caller already
+//! has, and [`MatchWeighting`] lets confident tracks dominate it.
+
+use image::GrayImage;
+
+use crate::features::good_features_to_track;
+use crate::lk::{
+    Aperture, DEFAULT_FB_THRESHOLD, DEFAULT_MIN_EIGEN_THRESHOLD, TrackResult, TrackStatus,
+    calc_optical_flow_fb, interpolate,
+};
+use crate::pyramid::build_pyramid;
+
+/// Settings of [`estimate_rigid_transform`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RigidParams {
+    /// Most features detected in the first frame, strongest first.
+    pub max_points: usize,
+    /// Shi-Tomasi quality level, see [`good_features_to_track`].
+    pub quality_level: f32,
+    /// Minimum distance in pixels between detected features.
+    pub min_distance: u32,
+    /// Pyramid levels used for tracking.
+    pub levels: usize,
+    /// Side of the tracking window (odd).
+    pub window_size: usize,
+    /// Max Lucas-Kanade iterations per pyramid level.
+    pub max_iterations: usize,
+    /// Round-trip threshold of the forward-backward check, see
+    /// [`DEFAULT_FB_THRESHOLD`].
+    pub fb_threshold: f32,
+    /// Largest distance in pixels between a tracked point and its position
+    /// predicted by the transform for the point to count as an inlier.
+    pub inlier_threshold: f32,
+    /// Random samples drawn by the robust fit.
+    pub ransac_iterations: usize,
+    /// How much each tracked feature counts in the fit.
+    pub weighting: MatchWeighting,
+}
+
+/// Confidence of each track in the global motion fit, see
+/// [`RigidParams::weighting`].
+///
+/// Weighted fits score RANSAC hypotheses by the summed weight of their
+/// inliers instead of the inlier count, and the least-squares refit weights
+/// each inlier's squared distance. Scenes with many weak tracks (low texture,
+/// blur, noise) then follow the few reliable ones.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum MatchWeighting {
+    /// Every tracked feature counts the same.
+    #[default]
+    Uniform,
+    /// Weight `1 / (1 + (error / scale)²)` from the photometric residual
+    /// [`TrackResult::error`]: a track with residual `scale` counts half as
+    /// much as a perfect one. Tracks without a measured residual count 0.
+    Residual {
+        /// Residual in 8-bit intensity units at which the weight halves.
+        scale: f32,
+    },
+    /// Weight from [`TrackResult::aperture`]: 1 for a corner, `edge` for an
+    /// edge, whose displacement along the edge is unreliable, and 0 for flat
+    /// or out-of-bounds windows.
+    Aperture {
+        /// Weight of an edge track, between 0 and 1.
+        edge: f32,
+    },
+}
+
+impl MatchWeighting {
+    /// Weight of one tracked feature.
+    pub fn weight(&self, result: &TrackResult) -> f32 {
+        match *self {
+            MatchWeighting::Uniform => 1.0,
+            MatchWeighting::Residual { scale } => {
+                if result.error.is_finite() {
+                    let ratio = result.error / scale;
+                    1.0 / (1.0 + ratio * ratio)
+                } else {
+                    0.0
+                }
+            }
+            MatchWeighting::Aperture { edge } => match result.aperture {
+                Some(Aperture::Corner) => 1.0,
+                Some(Aperture::Edge) => edge,
+                Some(Aperture::Flat) | None => 0.0,
+            },
+        }
+    }
+}
+
+impl Default for RigidParams {
+    fn default() -> Self {
+        RigidParams {
+            max_points: 300,
+            quality_level: 0.05,
+            min_distance: 10,
+            levels: 4,
+            window_size: 21,
+            max_iterations: 30,
+            fb_threshold: DEFAULT_FB_THRESHOLD,
+            inlier_threshold: 1.5,
+            ransac_iterations: 200,
+            weighting: MatchWeighting::Uniform,
+        }
+    }
+}
+
+/// Result of [`estimate_rigid_transform`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RigidTransform {
+    /// 2x3 similarity transform `[[a, -b, tx], [b, a, ty]]` mapping
+    /// first-frame coordinates to the second frame, in the layout of
+    /// [`FrameMotion::Affine`](crate::FrameMotion::Affine).
+    pub matrix: [[f32; 3]; 2],
+    /// Features tracked into the second frame (the candidates of the fit).
+    pub tracked: usize,
+    /// Tracked features consistent with `matrix`.
+    pub inliers: usize,
+    /// Root mean square distance between the inliers and their positions
+    /// predicted by `matrix`, in pixels.
+    pub rms_error: f32,
+}
+
+impl RigidTransform {
+    /// Rotation angle in radians.
+    pub fn rotation(&self) -> f32 {
+        self.matrix[1][0].atan2(self.matrix[0][0])
+    }
+
+    /// Uniform scale factor.
+    pub fn scale(&self) -> f32 {
+        self.matrix[0][0].hypot(self.matrix[1][0])
+    }
+
+    /// Translation `(tx, ty)` in pixels.
+    pub fn translation(&self) -> (f32, f32) {
+        (self.matrix[0][2], self.matrix[1][2])
+    }
+
+    /// Maps a first-frame point into the second frame.
+    pub fn apply(&self, (x, y): (f32, f32)) -> (f32, f32) {
+        let m = &self.matrix;
+        (
+            m[0][0] * x + m[0][1] * y + m[0][2],
+            m[1][0] * x + m[1][1] * y + m[1][2],
+        )
+    }
+}
+
+/// Estimates the similarity transform (translation, rotation and uniform
+/// scale) from `prev` to `curr`.
+///
+/// Detects up to [`RigidParams::max_points`] features in `prev`, tracks them
+/// into `curr` with [`calc_optical_flow_fb`], and fits the transform with
+/// RANSAC over pairs of tracked points followed by a least-squares refit on
+/// the inliers. Independently moving objects and bad tracks end up as
+/// outliers. The random sampling is seeded, so the result is deterministic.
+///
+/// # Returns
+/// `None` when fewer than two features could be tracked, or when no
+/// transform is supported by at least two of them.
+///
+/// # Panics
+/// Panics if the frames differ in size.
+pub fn estimate_rigid_transform(
+    prev: &GrayImage,
+    curr: &GrayImage,
+    params: &RigidParams,
+) -> Option<RigidTransform> {
+    assert_eq!(
+        prev.dimensions(),
+        curr.dimensions(),
+        "frames must have the same size"
+    );
+    let mut features = good_features_to_track(prev, params.quality_level, params.min_distance);
+    features.truncate(params.max_points);
+    let points: Vec<(f32, f32)> = features
+        .iter()
+        .map(|&(x, y, _)| (x as f32, y as f32))
+        .collect();
+
+    let results = calc_optical_flow_fb(
+        &build_pyramid(prev, params.levels),
+        &build_pyramid(curr, params.levels),
+        &points,
+        None,
+        params.window_size,
+        params.max_iterations,
+        DEFAULT_MIN_EIGEN_THRESHOLD,
+        params.fb_threshold,
+    );
+    fit_rigid_transform(&points, &results, params)
+}
+
+/// Fits the similarity transform mapping `prev_points` to their tracked
+/// positions, the fitting stage of [`estimate_rigid_transform`] for tracks
+/// from any of the crate's trackers.
+///
+/// Only [`TrackStatus::Tracked`] results take part, weighted by
+/// [`RigidParams::weighting`]; the fit uses
+/// [`RigidParams::inlier_threshold`] and [`RigidParams::ransac_iterations`].
+///
+/// # Returns
+/// `None` when fewer than two points were tracked, or when no transform is
+/// supported by at least two of them.
+///
+/// # Panics
+/// Panics if `results` does not have one entry per `prev_point`.
+pub fn fit_rigid_transform(
+    prev_points: &[(f32, f32)],
+    results: &[TrackResult],
+    params: &RigidParams,
+) -> Option<RigidTransform> {
+    assert_eq!(
+        prev_points.len(),
+        results.len(),
+        "results must have one entry per prev_point"
+    );
+    let (matches, weights): (Vec<Match>, Vec<f32>) = prev_points
+        .iter()
+        .zip(results)
+        .filter(|(_, r)| r.status == TrackStatus::Tracked)
+        .map(|(&p, r)| ((p, r.pos), params.weighting.weight(r)))
+        .unzip();
+    let weights = (params.weighting != MatchWeighting::Uniform).then_some(&weights[..]);
+
+    fit_similarity_ransac(
+        &matches,
+        weights,
+        params.inlier_threshold,
+        params.ransac_iterations,
+    )
+}
+
+/// Aligns `shaky` to `reference`: estimates the similarity transform between
+/// them with [`estimate_rigid_transform`] (default [`RigidParams`]) and warps
+/// `shaky` onto the pixel grid of `reference`.
+///
+/// For aligning photos of the same scene, e.g. before exposure stacking or
+/// averaging. The warp samples bilinearly; pixels of the result whose source
+/// lies outside `shaky` are 0.
+///
+/// # Returns
+/// The aligned image and the transform from `reference` to `shaky`
+/// coordinates, or `None` when no transform could be estimated.
+///
+/// # Panics
+/// Panics if the images differ in size.
+pub fn stabilize_pair(
+    reference: &GrayImage,
+    shaky: &GrayImage,
+) -> Option<(GrayImage, RigidTransform)> {
+    let transform = estimate_rigid_transform(reference, shaky, &RigidParams::default())?;
+    let (width, height) = reference.dimensions();
+    let (max_x, max_y) = ((width - 1) as f32, (height - 1) as f32);
+
+    let mut aligned = GrayImage::new(width, height);
+    for (x, y, pixel) in aligned.enumerate_pixels_mut() {
+        let (sx, sy) = transform.apply((x as f32, y as f32));
+        if (0.0..=max_x).contains(&sx) && (0.0..=max_y).contains(&sy) {
+            pixel[0] = interpolate(shaky, sx, sy).round() as u8;
+        }
+    }
+    Some((aligned, transform))
+}
+
+/// Result of [`registration_quality`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct RegistrationQuality {
+    /// Root mean square geometric error of the inliers in pixels, see
+    /// [`RigidTransform::rms_error`].
+    pub inlier_rmse: f32,
+    /// Fraction of the tracked features consistent with the transform.
+    pub inlier_ratio: f32,
+    /// Fraction of the reference pixels whose mapped position lies inside the
+    /// target image.
+    pub overlap: f32,
+    /// Root mean square intensity difference between the reference and the
+    /// aligned target over the overlap, in 8-bit units.
+    pub photometric_rmse: f32,
+    /// Absolute difference between the reference and the aligned target per
+    /// reference pixel; 0 outside the overlap.
+    pub residual: GrayImage,
+}
+
+/// Measures how well `transform` (from `reference` to `target` coordinates,
+/// as returned by [`estimate_rigid_transform`] and [`stabilize_pair`]) aligns
+/// the two images.
+///
+/// Pipelines reject a bad alignment on any of the metrics: a high
+/// `inlier_rmse` or low `inlier_ratio` means the features disagree on the
+/// motion, a low `overlap` leaves little shared content, and a high
+/// `photometric_rmse` means the images still differ after alignment (wrong
+/// transform, parallax or a changed scene); the `residual` map shows where.
+pub fn registration_quality(
+    reference: &GrayImage,
+    target: &GrayImage,
+    transform: &RigidTransform,
+) -> RegistrationQuality {
+    let (width, height) = reference.dimensions();
+    let (max_x, max_y) = (target.width() as f32 - 1.0, target.height() as f32 - 1.0);
+
+    let mut residual = GrayImage::new(width, height);
+    let (mut overlap, mut squared) = (0usize, 0.0f64);
+    // `max(1)` keeps the chunking valid for an empty reference.
+    let stride = (width as usize).max(1);
+    let rows = reference
+        .as_raw()
+        .chunks_exact(stride)
+        .zip(residual.chunks_exact_mut(stride));
+    for (y, (reference_row, residual_row)) in rows.enumerate() {
+        for (x, (&value, out)) in reference_row.iter().zip(residual_row).enumerate() {
+            let (sx, sy) = transform.apply((x as f32, y as f32));
+            if (0.0..=max_x).contains(&sx) && (0.0..=max_y).contains(&sy) {
+                let diff = interpolate(target, sx, sy) - value as f32;
+                *out = diff.abs().round().min(255.0) as u8;
+                squared += (diff * diff) as f64;
+                overlap += 1;
+            }
+        }
+    }
+
+    let pixels = (width as usize * height as usize).max(1);
+    RegistrationQuality {
+        inlier_rmse: transform.rms_error,
+        inlier_ratio: transform.inliers as f32 / transform.tracked.max(1) as f32,
+        overlap: overlap as f32 / pixels as f32,
+        photometric_rmse: if overlap > 0 {
+            (squared / overlap as f64).sqrt() as f32
+        } else {
+            0.0
+        },
+        residual,
+    }
+}
+
+/// A point in the first frame and its tracked position in the second.
+pub(crate) type Match = ((f32, f32), (f32, f32));
+
+/// Robust similarity fit over `(from, to)` point matches, optionally with a
+/// non-negative weight per match (see [`MatchWeighting`]).
+pub(crate) fn fit_similarity_ransac(
+    matches: &[Match],
+    weights: Option<&[f32]>,
+    inlier_threshold: f32,
+    iterations: usize,
+) -> Option<RigidTransform> {
+    if matches.len() < 2 {
+        return None;
+    }
+    let weight = |i: usize| weights.map_or(1.0, |w| w[i]);
+    let threshold_sq = inlier_threshold * inlier_threshold;
+    let is_inlier = |m: &[[f32; 3]; 2], &(from, to): &Match| {
+        let (x, y) = from;
+        let dx = m[0][0] * x + m[0][1] * y + m[0][2] - to.0;
+        let dy = m[1][0] * x + m[1][1] * y + m[1][2] - to.1;
+        dx * dx + dy * dy <= threshold_sq
+    };
+
+    // xorshift32 with a fixed seed keeps the fit reproducible.
+    let mut state = 0x9e37_79b9u32;
+    let mut next_index = || {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        state as usize % matches.len()
+    };
+
+    let mut best: Option<([[f32; 3]; 2], usize, f32)> = None;
+    for _ in 0..iterations {
+        let (i, j) = (next_index(), next_index());
+        if i == j {
+            continue;
+        }
+        let Some(model) = fit_similarity(&[matches[i], matches[j]], None) else {
+            continue;
+        };
+        let (mut count, mut score) = (0, 0.0);
+        for (k, m) in matches.iter().enumerate() {
+            if is_inlier(&model, m) {
+                count += 1;
+                score += weight(k);
+            }
+        }
+        let better = match best {
+            None => true,
+            Some((_, best_count, best_score)) => {
+                score > best_score || (score == best_score && count > best_count)
+            }
+        };
+        if better {
+            best = Some((model, count, score));
+        }
+    }
+    let (model, count, _) = best?;
+    if count < 2 {
+        return None;
+    }
+
+    // Refit on the consensus set, then measure the refined model.
+    let (consensus, consensus_weights): (Vec<_>, Vec<_>) = matches
+        .iter()
+        .enumerate()
+        .filter(|(_, m)| is_inlier(&model, m))
+        .map(|(k, &m)| (m, weight(k)))
+        .unzip();
+    let matrix =
+        fit_similarity(&consensus, weights.map(|_| &consensus_weights[..])).unwrap_or(model);
+    let inliers: Vec<_> = matches.iter().filter(|m| is_inlier(&matrix, m)).collect();
+    let transform = RigidTransform {
+        matrix,
+        tracked: matches.len(),
+        inliers: inliers.len(),
+        rms_error: 0.0,
+    };
+    let sum_sq: f32 = inliers
+        .iter()
+        .map(|&&(from, to)| {
+            let (x, y) = transform.apply(from);
+            (x - to.0).powi(2) + (y - to.1).powi(2)
+        })
+        .sum();
+    Some(RigidTransform {
+        rms_error: (sum_sq / inliers.len().max(1) as f32).sqrt(),
+        ..transform
+    })
+}
+
+/// Least-squares similarity transform over `(from, to)` matches, each
+/// squared distance scaled by its weight when `weights` is given. `None`
+/// when the (weighted) `from` points coincide.
+fn fit_similarity(matches: &[Match], weights: Option<&[f32]>) -> Option<[[f32; 3]; 2]> {
+    let weight = |i: usize| weights.map_or(1.0, |w| w[i]);
+    let (mut n, mut fx, mut fy, mut tx, mut ty) = (0.0f32, 0.0f32, 0.0f32, 0.0f32, 0.0f32);
+    for (i, &((x, y), (u, v))) in matches.iter().enumerate() {
+        let w = weight(i);
+        n += w;
+        fx += w * x;
+        fy += w * y;
+        tx += w * u;
+        ty += w * v;
+    }
+    if n <= 0.0 {
+        return None;
+    }
+    let (fx, fy, tx, ty) = (fx / n, fy / n, tx / n, ty / n);
+
+    // With centered coordinates, [a -b; b a] minimizes the squared residual
+    // in closed form.
+    let (mut dot, mut cross, mut norm) = (0.0f32, 0.0f32, 0.0f32);
+    for (i, &((x, y), (u, v))) in matches.iter().enumerate() {
+        let w = weight(i);
+        let (x, y, u, v) = (x - fx, y - fy, u - tx, v - ty);
+        dot += w * (x * u + y * v);
+        cross += w * (x * v - y * u);
+        norm += w * (x * x + y * y);
+    }
+    if norm <= 1e-6 {
+        return None;
+    }
+    let (a, b) = (dot / norm, cross / norm);
+    Some([
+        [a, -b, tx - (a * fx - b * fy)],
+        [b, a, ty - (b * fx + a * fy)],
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ransac_ignores_outliers() {
+        let (angle, scale, shift) = (0.1f32, 1.05f32, (4.0f32, -3.0f32));
+        let (s, c) = angle.sin_cos();
+        let mut matches: Vec<_> = (0..30)
+            .map(|i| {
+                let (x, y) = ((i % 6) as f32 * 40.0, (i / 6) as f32 * 35.0);
+                let to = (
+                    scale * (c * x - s * y) + shift.0,
+                    scale * (s * x + c * y) + shift.1,
+                );
+                ((x, y), to)
+            })
+            .collect();
+        // A third of the matches follow an unrelated object.
+        for m in matches.iter_mut().step_by(3) {
+            m.1 = (m.0.0 + 25.0, m.0.1 + 10.0);
+        }
+
+        let fit = fit_similarity_ransac(&matches, None, 1.0, 200).unwrap();
+        assert_eq!((fit.tracked, fit.inliers), (30, 20));
+        assert!(fit.rms_error < 1e-3, "{fit:?}");
+        assert!((fit.rotation() - angle).abs() < 1e-4);
+        assert!((fit.scale() - scale).abs() < 1e-4);
+        let (tx, ty) = fit.translation();
+        assert!((tx - shift.0).abs() < 1e-2 && (ty - shift.1).abs() < 1e-2);
+    }
+
+    #[test]
+    fn weights_favor_confident_tracks() {
+        let shift = (3.0f32, -2.0f32);
+        let (mut matches, mut weights) = (Vec::new(), Vec::new());
+        for i in 0..40 {
+            let (x, y) = ((i % 8) as f32 * 30.0, (i / 8) as f32 * 30.0);
+            // Every other track is weak: biased by up to a pixel, within
+            // the inlier threshold, and low weight.
+            let (noise, weight) = if i % 2 == 0 {
+                (0.0, 1.0)
+            } else {
+                ((i % 5) as f32 * 0.25, 0.01)
+            };
+            matches.push(((x, y), (x + shift.0 + noise, y + shift.1 - noise)));
+            weights.push(weight);
+        }
+
+        let error = |fit: &RigidTransform| {
+            let (tx, ty) = fit.apply((105.0, 60.0));
+            (tx - 105.0 - shift.0).hypot(ty - 60.0 - shift.1)
+        };
+        let uniform = fit_similarity_ransac(&matches, None, 1.5, 200).unwrap();
+        let weighted = fit_similarity_ransac(&matches, Some(&weights), 1.5, 200).unwrap();
+        assert!(error(&weighted) < 0.05, "{weighted:?}");
+        assert!(error(&weighted) < error(&uniform), "{uniform:?}");
+    }
+
+    #[test]
+    fn fit_needs_two_distinct_points() {
+        assert!(fit_similarity_ransac(&[((1.0, 1.0), (2.0, 2.0))], None, 1.0, 10).is_none());
+        assert!(fit_similarity(&[((1.0, 1.0), (2.0, 2.0)); 3], None).is_none());
+    }
+}
@@ -0,0 +1,191 @@
+//! Moving a region of interest along with the tracked points inside it.
+//!
+//! Labeling tools annotate an object once and let the video carry the
+//! annotation: [`propagate_roi`] moves a polygon from one frame to the next
+//! with the motion of the tracks inside it, and [`propagate_bbox`] does the
+//! same for an axis-aligned box.
+
+use crate::lk::{TrackResult, TrackStatus};
+use crate::registration::{Match, RigidParams, RigidTransform, fit_similarity_ransac};
+use crate::utils::median::median;
+
+/// Motion applied to a region by [`propagate_roi`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RoiMotion {
+    /// Similarity transform fitted robustly to the tracks inside the region.
+    Similarity(RigidTransform),
+    /// Median displacement `(dx, dy)` of the tracks inside the region, used
+    /// when they do not support a similarity fit (too few, or no consensus).
+    Translation((f32, f32)),
+}
+
+impl RoiMotion {
+    /// Maps a point of the previous frame into the next one.
+    pub fn apply(&self, (x, y): (f32, f32)) -> (f32, f32) {
+        match self {
+            RoiMotion::Similarity(transform) => transform.apply((x, y)),
+            RoiMotion::Translation((dx, dy)) => (x + dx, y + dy),
+        }
+    }
+}
+
+/// Moves `polygon` from the previous frame to the next one with the tracks
+/// that start inside it.
+///
+/// `prev_points` and `results` are one tracking step (e.g. of
+/// [`calc_optical_flow_ex`](crate::calc_optical_flow_ex)); only tracks with
+/// [`TrackStatus::Tracked`] that start inside the polygon count. The motion
+/// is a RANSAC similarity fit over them (with the thresholds of
+/// [`RigidParams::default`]) when at least three tracks and half of them
+/// agree on it, and their median displacement otherwise.
+///
+/// # Returns
+/// The moved vertices and the applied motion, or `None` when no track
+/// starts inside the polygon.
+///
+/// # Panics
+/// Panics if `results` does not have one entry per `prev_point`.
+pub fn propagate_roi(
+    polygon: &[(f32, f32)],
+    prev_points: &[(f32, f32)],
+    results: &[TrackResult],
+) -> Option<(Vec<(f32, f32)>, RoiMotion)> {
+    assert_eq!(
+        prev_points.len(),
+        results.len(),
+        "results must have one entry per prev_point"
+    );
+    let matches: Vec<Match> = prev_points
+        .iter()
+        .zip(results)
+        .filter(|(p, r)| r.status == TrackStatus::Tracked && contains(polygon, **p))
+        .map(|(&p, r)| (p, r.pos))
+        .collect();
+    if matches.is_empty() {
+        return None;
+    }
+
+    let motion = fit_roi_motion(&matches);
+    let moved = polygon.iter().map(|&p| motion.apply(p)).collect();
+    Some((moved, motion))
+}
+
+/// Motion of a region from the `matches` of its tracks (not empty): the
+/// similarity fit when enough of them agree, their median displacement
+/// otherwise, as described in [`propagate_roi`].
+pub(crate) fn fit_roi_motion(matches: &[Match]) -> RoiMotion {
+    let params = RigidParams::default();
+    match fit_similarity_ransac(
+        matches,
+        None,
+        params.inlier_threshold,
+        params.ransac_iterations,
+    ) {
+        Some(transform) if transform.inliers >= 3 && 2 * transform.inliers >= matches.len() => {
+            RoiMotion::Similarity(transform)
+        }
+        _ => RoiMotion::Translation(median_displacement(matches)),
+    }
+}
+
+/// [`propagate_roi`] for an axis-aligned box `(x, y, width, height)`.
+///
+/// The box's corners are moved and the returned box is their bounding box, so
+/// a rotating object keeps being enclosed.
+pub fn propagate_bbox(
+    (x, y, width, height): (f32, f32, f32, f32),
+    prev_points: &[(f32, f32)],
+    results: &[TrackResult],
+) -> Option<((f32, f32, f32, f32), RoiMotion)> {
+    let corners = [
+        (x, y),
+        (x + width, y),
+        (x + width, y + height),
+        (x, y + height),
+    ];
+    let (moved, motion) = propagate_roi(&corners, prev_points, results)?;
+    let (mut min, mut max) = (
+        (f32::INFINITY, f32::INFINITY),
+        (f32::NEG_INFINITY, f32::NEG_INFINITY),
+    );
+    for (px, py) in moved {
+        min = (min.0.min(px), min.1.min(py));
+        max = (max.0.max(px), max.1.max(py));
+    }
+    Some(((min.0, min.1, max.0 - min.0, max.1 - min.1), motion))
+}
+
+/// Even-odd rule point-in-polygon test.
+fn contains(polygon: &[(f32, f32)], (x, y): (f32, f32)) -> bool {
+    let mut inside = false;
+    let mut prev = match polygon.last() {
+        Some(&last) => last,
+        None => return false,
+    };
+    for &(px, py) in polygon {
+        let (qx, qy) = prev;
+        if (py > y) != (qy > y) && x < px + (y - py) * (qx - px) / (qy - py) {
+            inside = !inside;
+        }
+        prev = (px, py);
+    }
+    inside
+}
+
+/// Per-axis median of the displacements of `matches` (not empty).
+fn median_displacement(matches: &[Match]) -> (f32, f32) {
+    let mut dx: Vec<f32> = matches.iter().map(|(from, to)| to.0 - from.0).collect();
+    let mut dy: Vec<f32> = matches.iter().map(|(from, to)| to.1 - from.1).collect();
+    (median(&mut dx), median(&mut dy))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tracked(pos: (f32, f32)) -> TrackResult {
+        TrackResult {
+            pos,
+            status: TrackStatus::Tracked,
+            error: 0.0,
+            aperture: None,
+        }
+    }
+
+    #[test]
+    fn polygon_follows_the_tracks_inside_it() {
+        let triangle = [(10.0, 10.0), (60.0, 10.0), (10.0, 60.0)];
+        let prev: Vec<(f32, f32)> = (0..6)
+            .flat_map(|j| (0..6).map(move |i| (12.0 + 8.0 * i as f32, 12.0 + 8.0 * j as f32)))
+            .collect();
+        // Inside the triangle everything shifts by (3, -2); the points beyond
+        // its hypotenuse move elsewhere and must be ignored.
+        let results: Vec<TrackResult> = prev
+            .iter()
+            .map(|&(x, y)| {
+                if contains(&triangle, (x, y)) {
+                    tracked((x + 3.0, y - 2.0))
+                } else {
+                    tracked((x - 20.0, y + 20.0))
+                }
+            })
+            .collect();
+
+        let (moved, motion) = propagate_roi(&triangle, &prev, &results).unwrap();
+        assert!(matches!(motion, RoiMotion::Similarity(t) if t.inliers >= 3));
+        for (m, p) in moved.iter().zip(&triangle) {
+            assert!((m.0 - p.0 - 3.0).abs() < 1e-3 && (m.1 - p.1 + 2.0).abs() < 1e-3);
+        }
+
+        // A single track falls back to its displacement.
+        let (bbox, motion) = propagate_bbox(
+            (0.0, 0.0, 20.0, 20.0),
+            &[(5.0, 5.0)],
+            &[tracked((6.0, 7.0))],
+        )
+        .unwrap();
+        assert_eq!(motion, RoiMotion::Translation((1.0, 2.0)));
+        assert_eq!(bbox, (1.0, 2.0, 20.0, 20.0));
+        assert!(propagate_roi(&triangle, &[(100.0, 100.0)], &[tracked((0.0, 0.0))]).is_none());
+    }
+}
@@ -0,0 +1,229 @@
+//! Tracking a region of interest with its own feature population.
+//!
+//! Between single points and full-frame tracking sits the common case of
+//! following one object: [`RoiTracker`] detects features inside a box, tracks
+//! them, moves the box with their robust motion and tops the population up
+//! by re-detecting inside the box whenever too many tracks were lost.
+
+use image::GrayImage;
+use image::imageops::crop_imm;
+
+use crate::features::{FeatureParams, good_features_to_track_with};
+use crate::lk::{DEFAULT_MIN_EIGEN_THRESHOLD, TrackStatus, TrackerContext};
+use crate::registration::{Match, RigidParams};
+use crate::roi::{RoiMotion, fit_roi_motion};
+
+/// Settings of a [`RoiTracker`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RoiTrackerParams {
+    /// Pyramid levels of the tracked frames.
+    pub levels: usize,
+    /// Side of the tracking window (odd).
+    pub window_size: usize,
+    /// Max iterations per pyramid level.
+    pub max_iterations: usize,
+    /// See [`DEFAULT_MIN_EIGEN_THRESHOLD`].
+    pub min_eigen_threshold: f32,
+    /// Detector settings for the features inside the region;
+    /// `min_distance` also keeps new features clear of the live tracks.
+    pub features: FeatureParams,
+    /// Most points kept inside the region.
+    pub max_points: usize,
+    /// Fraction of `max_points` below which the region is re-detected.
+    pub min_coverage: f32,
+}
+
+impl Default for RoiTrackerParams {
+    fn default() -> Self {
+        RoiTrackerParams {
+            levels: 3,
+            window_size: 21,
+            max_iterations: 30,
+            min_eigen_threshold: DEFAULT_MIN_EIGEN_THRESHOLD,
+            features: FeatureParams::default(),
+            max_points: 40,
+            min_coverage: 0.5,
+        }
+    }
+}
+
+/// Follows an axis-aligned box `(x, y, width, height)` through a video with
+/// points it manages itself.
+///
+/// [`start`](Self::start) detects up to
+/// [`max_points`](RoiTrackerParams::max_points) features inside the box.
+/// Every [`track`](Self::track) call then tracks them into the next frame,
+/// fits the motion of the box to the surviving tracks as
+/// [`propagate_roi`](crate::propagate_roi) does, and moves the box: its
+/// center follows the motion and its size the scale change. Only points
+/// that were [`TrackStatus::Tracked`], agree with a similarity fit and end
+/// up inside the moved box are kept; when fewer than
+/// [`min_coverage`](RoiTrackerParams::min_coverage) of `max_points` remain,
+/// features are detected inside the box again.
+#[derive(Default)]
+pub struct RoiTracker {
+    params: RoiTrackerParams,
+    context: TrackerContext,
+    roi: (f32, f32, f32, f32),
+    points: Vec<(f32, f32)>,
+    matches: Vec<Match>,
+    motion: Option<RoiMotion>,
+    redetected: bool,
+    started: bool,
+}
+
+impl RoiTracker {
+    /// Creates a tracker without a region; call [`start`](Self::start)
+    /// before tracking.
+    pub fn new(params: RoiTrackerParams) -> Self {
+        RoiTracker {
+            params,
+            ..Self::default()
+        }
+    }
+
+    /// Starts following `roi` from `image`, detecting the initial points
+    /// inside it.
+    pub fn start(&mut self, image: &GrayImage, roi: (f32, f32, f32, f32)) {
+        self.context.prepare_prev(image, self.params.levels);
+        self.roi = roi;
+        self.points.clear();
+        self.motion = None;
+        self.detect(image);
+        self.redetected = true;
+        self.started = true;
+    }
+
+    /// Tracks the region into `image`, which becomes the previous frame of
+    /// the next call.
+    ///
+    /// # Returns
+    /// The motion applied to the region, or `None` when no point was
+    /// tracked; the region then stays where it was.
+    ///
+    /// # Panics
+    /// Panics if [`start`](Self::start) has not been called.
+    pub fn track(&mut self, image: &GrayImage) -> Option<RoiMotion> {
+        assert!(self.started, "start must be called first");
+        let RoiTrackerParams {
+            levels,
+            window_size,
+            max_iterations,
+            min_eigen_threshold,
+            max_points,
+            min_coverage,
+            ..
+        } = self.params;
+
+        self.context.prepare_next(image, levels);
+        let results = self.context.track(
+            &self.points,
+            None,
+            window_size,
+            max_iterations,
+            min_eigen_threshold,
+        );
+        self.matches.clear();
+        self.matches.extend(
+            self.points
+                .iter()
+                .zip(results)
+                .filter(|(_, r)| r.status == TrackStatus::Tracked)
+                .map(|(&p, r)| (p, r.pos)),
+        );
+
+        self.motion = (!self.matches.is_empty()).then(|| fit_roi_motion(&self.matches));
+        self.points.clear();
+        if let Some(motion) = self.motion {
+            let (x, y, width, height) = self.roi;
+            let (cx, cy) = motion.apply((x + 0.5 * width, y + 0.5 * height));
+            let scale = match motion {
+                RoiMotion::Similarity(transform) => transform.scale(),
+                RoiMotion::Translation(_) => 1.0,
+            };
+            let (width, height) = (width * scale, height * scale);
+            self.roi = (cx - 0.5 * width, cy - 0.5 * height, width, height);
+
+            // Tracks off the fitted motion drifted onto the background.
+            let threshold = RigidParams::default().inlier_threshold;
+            let roi = self.roi;
+            self.points.extend(
+                self.matches
+                    .iter()
+                    .filter(|&&(from, to)| match motion {
+                        RoiMotion::Similarity(transform) => {
+                            let (px, py) = transform.apply(from);
+                            (px - to.0).hypot(py - to.1) <= threshold
+                        }
+                        RoiMotion::Translation(_) => true,
+                    })
+                    .map(|&(_, to)| to)
+                    .filter(|&p| contains(roi, p)),
+            );
+        }
+
+        self.redetected = (self.points.len() as f32) < min_coverage * max_points as f32;
+        if self.redetected {
+            self.detect(image);
+        }
+        self.context.prepare_prev(image, levels);
+        self.motion
+    }
+
+    /// The region in the last frame, as `(x, y, width, height)`.
+    pub fn roi(&self) -> (f32, f32, f32, f32) {
+        self.roi
+    }
+
+    /// The points inside the region in the last frame.
+    pub fn points(&self) -> &[(f32, f32)] {
+        &self.points
+    }
+
+    /// Motion applied to the region by the last [`track`](Self::track) call.
+    pub fn motion(&self) -> Option<RoiMotion> {
+        self.motion
+    }
+
+    /// Whether the last [`start`](Self::start) or [`track`](Self::track)
+    /// call detected features.
+    pub fn redetected(&self) -> bool {
+        self.redetected
+    }
+
+    /// Adds the strongest features inside the region of `image` until
+    /// `max_points` are kept, each at least `min_distance` from the others.
+    fn detect(&mut self, image: &GrayImage) {
+        let (x, y, width, height) = self.roi;
+        let (x0, y0) = (x.max(0.0).ceil(), y.max(0.0).ceil());
+        let x1 = (x + width).min(image.width() as f32 - 1.0).floor();
+        let y1 = (y + height).min(image.height() as f32 - 1.0).floor();
+        if x1 <= x0 || y1 <= y0 {
+            return;
+        }
+        let (x0, y0) = (x0 as u32, y0 as u32);
+        let crop = crop_imm(image, x0, y0, x1 as u32 - x0 + 1, y1 as u32 - y0 + 1).to_image();
+
+        let min_distance = self.params.features.min_distance as f32;
+        // A flat region passes its zero responses as features.
+        let features = good_features_to_track_with(&crop, &self.params.features);
+        for (cx, cy, _) in features.into_iter().filter(|&(_, _, q)| q > 0.0) {
+            if self.points.len() >= self.params.max_points {
+                break;
+            }
+            let p = ((x0 + cx) as f32, (y0 + cy) as f32);
+            if self
+                .points
+                .iter()
+                .all(|q| (q.0 - p.0).hypot(q.1 - p.1) >= min_distance)
+            {
+                self.points.push(p);
+            }
+        }
+    }
+}
+
+/// Whether `(px, py)` lies inside the box `(x, y, width, height)`.
+fn contains((x, y, width, height): (f32, f32, f32, f32), (px, py): (f32, f32)) -> bool {
+    px >= x && py >= y && px <= x + width && py <= y + height
+}
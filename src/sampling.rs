@@ -0,0 +1,128 @@
+//! Sub-pixel sampling of images and pyramid levels.
+//!
+//! Custom refinement or measurement on top of the tracker should read the
+//! image the way the tracker does. [`sample_bilinear`] is the tracker's own
+//! interpolation, with the image edge handled by a [`BorderMode`] instead of
+//! the tracker's zero padding; [`sample_bicubic`] trades a 4x4 footprint for
+//! a smoother result. [`PyramidSet::sample`](crate::PyramidSet::sample) and
+//! [`PyramidSet::sample_bicubic`](crate::PyramidSet::sample_bicubic) apply
+//! them to one level of a pyramid.
+
+use image::GrayImage;
+
+use crate::border::BorderMode;
+use crate::lk::interpolate;
+
+/// Bilinear interpolation of `image` at `(x, y)`, pixel centers at integer
+/// coordinates, with pixels beyond the edges synthesized by `border`.
+///
+/// Where the 2x2 footprint lies inside the image the result is bit-identical
+/// to the tracker's sampling.
+///
+/// # Panics
+/// Panics if `image` is empty and `border` is not [`BorderMode::Constant`].
+pub fn sample_bilinear(image: &GrayImage, x: f32, y: f32, border: BorderMode) -> f32 {
+    let (x0, y0) = (x.floor(), y.floor());
+    let (width, height) = image.dimensions();
+    if x0 >= 0.0 && y0 >= 0.0 && x0 + 1.0 < width as f32 && y0 + 1.0 < height as f32 {
+        return interpolate(image, x, y);
+    }
+
+    let (dx, dy) = (x - x0, y - y0);
+    let (x0, y0) = (x0 as i64, y0 as i64);
+    let pixel = |sx, sy| read(image, sx, sy, border);
+    // Same term order as the tracker's interpolation.
+    pixel(x0, y0) * (1.0 - dx) * (1.0 - dy)
+        + pixel(x0, y0 + 1) * (1.0 - dx) * dy
+        + pixel(x0 + 1, y0) * dx * (1.0 - dy)
+        + pixel(x0 + 1, y0 + 1) * dx * dy
+}
+
+/// Bicubic interpolation of `image` at `(x, y)` over the 4x4 neighborhood,
+/// with OpenCV's `INTER_CUBIC` kernel (`a = -0.75`) and pixels beyond the
+/// edges synthesized by `border`.
+///
+/// The result is not clamped, so it may overshoot the 0..=255 range next to
+/// strong edges.
+///
+/// # Panics
+/// Panics if `image` is empty and `border` is not [`BorderMode::Constant`].
+pub fn sample_bicubic(image: &GrayImage, x: f32, y: f32, border: BorderMode) -> f32 {
+    let (x0, y0) = (x.floor(), y.floor());
+    let wx = cubic_weights(x - x0);
+    let wy = cubic_weights(y - y0);
+    let (x0, y0) = (x0 as i64, y0 as i64);
+
+    let mut sum = 0.0;
+    for (j, wy) in wy.iter().enumerate() {
+        let row: f32 = wx
+            .iter()
+            .enumerate()
+            .map(|(i, wx)| wx * read(image, x0 + i as i64 - 1, y0 + j as i64 - 1, border))
+            .sum();
+        sum += wy * row;
+    }
+    sum
+}
+
+/// Weights of the four taps at offsets -1, 0, 1, 2 for a fractional
+/// position `t` in `[0, 1)`.
+fn cubic_weights(t: f32) -> [f32; 4] {
+    const A: f32 = -0.75;
+    let near = |d: f32| ((A + 2.0) * d - (A + 3.0)) * d * d + 1.0;
+    let far = |d: f32| ((A * d - 5.0 * A) * d + 8.0 * A) * d - 4.0 * A;
+    [far(1.0 + t), near(t), near(1.0 - t), far(2.0 - t)]
+}
+
+/// Pixel `(x, y)` of `image`, or the value `border` supplies beyond the edges.
+fn read(image: &GrayImage, x: i64, y: i64, border: BorderMode) -> f32 {
+    let (width, height) = image.dimensions();
+    match (
+        border.source_index(x, width as usize),
+        border.source_index(y, height as usize),
+    ) {
+        (Some(sx), Some(sy)) => image.as_raw()[sy * width as usize + sx] as f32,
+        _ => match border {
+            BorderMode::Constant(value) => value as f32,
+            _ => unreachable!("only a constant border leaves pixels unmapped"),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Luma;
+
+    #[test]
+    fn samplers_agree_with_the_tracker_and_the_border() {
+        let ramp = GrayImage::from_fn(6, 5, |x, y| Luma([(20 * x + 3 * y) as u8]));
+        for &(x, y) in &[(2.25f32, 1.5f32), (0.0, 0.0), (3.6, 2.7)] {
+            assert_eq!(
+                sample_bilinear(&ramp, x, y, BorderMode::Replicate),
+                interpolate(&ramp, x, y)
+            );
+        }
+
+        // The cubic weights sum to 1...
+        let flat = GrayImage::from_pixel(6, 5, Luma([80]));
+        let cubic = sample_bicubic(&flat, 2.3, 1.6, BorderMode::Reflect101);
+        assert!((cubic - 80.0).abs() < 1e-4, "{cubic}");
+        // ...and interpolate the pixels exactly.
+        assert_eq!(sample_bicubic(&ramp, 3.0, 2.0, BorderMode::Wrap), 66.0);
+
+        // Half a pixel beyond the left edge.
+        assert_eq!(
+            sample_bilinear(&ramp, -0.5, 0.0, BorderMode::Replicate),
+            0.0
+        );
+        assert_eq!(
+            sample_bilinear(&ramp, -0.5, 0.0, BorderMode::Reflect101),
+            10.0
+        );
+        assert_eq!(
+            sample_bilinear(&ramp, -0.5, 0.0, BorderMode::Constant(100)),
+            50.0
+        );
+    }
+}
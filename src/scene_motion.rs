@@ -0,0 +1,215 @@
+//! Two-view classification of the dominant camera motion.
+//!
+//! Applications branch on what the camera did between two frames: skip
+//! stabilization on a cut, refocus on a zoom, keep a static shot untouched.
+//! [`classify_scene_motion`] fits the global similarity motion to a tracking
+//! step and labels the frame pair from the fit and its support.
+
+use crate::lk::{TrackResult, TrackStatus};
+use crate::registration::{RigidParams, RigidTransform, fit_rigid_transform};
+
+/// Dominant motion of a frame pair, see [`classify_scene_motion`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SceneMotion {
+    /// No component of the global motion reaches
+    /// [`SceneMotionParams::static_threshold`].
+    Static,
+    /// Panning, tilting or shake: the translation dominates.
+    Translation,
+    /// Zooming or moving along the optical axis: the scale change dominates.
+    Zoom,
+    /// Rolling about the optical axis: the rotation dominates.
+    Rotation,
+    /// No similarity motion explains the tracks: too few survived (a cut, a
+    /// blackout) or too few agree on one motion (large moving objects,
+    /// parallax, chaotic scenes).
+    Unmodelable,
+}
+
+/// Settings of [`classify_scene_motion`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SceneMotionParams {
+    /// Robust fit of the global motion; its inlier threshold, RANSAC
+    /// iterations and track weighting are used.
+    pub rigid: RigidParams,
+    /// Fewest tracked points for the fit to be trusted.
+    pub min_tracks: usize,
+    /// Smallest fraction of the tracked points that must agree with the fit.
+    pub min_inlier_ratio: f32,
+    /// Displacement in pixels, at the spread of the tracked points, that a
+    /// motion component must reach to count as motion.
+    pub static_threshold: f32,
+}
+
+impl Default for SceneMotionParams {
+    fn default() -> Self {
+        SceneMotionParams {
+            rigid: RigidParams::default(),
+            min_tracks: 8,
+            min_inlier_ratio: 0.5,
+            static_threshold: 0.5,
+        }
+    }
+}
+
+/// Result of [`classify_scene_motion`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SceneClassification {
+    /// Label of the frame pair.
+    pub motion: SceneMotion,
+    /// Fitted global motion, `None` when the fit failed.
+    pub transform: Option<RigidTransform>,
+    /// Fraction of the tracked points consistent with `transform`, 0 without
+    /// a fit.
+    pub inlier_ratio: f32,
+    /// Displacement in pixels of the tracked points' centroid.
+    pub translation: f32,
+    /// Displacement in pixels caused by the scale change at the root mean
+    /// square distance of the tracked points from their centroid.
+    pub zoom: f32,
+    /// Displacement in pixels caused by the rotation at that distance.
+    pub rotation: f32,
+}
+
+/// Labels a tracking step by its dominant global motion.
+///
+/// Fits a similarity transform to the [`TrackStatus::Tracked`] points with
+/// [`fit_rigid_transform`], then splits it into translation, scale change
+/// and rotation, each expressed as the displacement in pixels it causes
+/// over the tracked points, so the three compare directly. The pair is
+/// [`SceneMotion::Unmodelable`] when fewer than
+/// [`min_tracks`](SceneMotionParams::min_tracks) points were tracked or fewer
+/// than [`min_inlier_ratio`](SceneMotionParams::min_inlier_ratio) of them
+/// fit, [`SceneMotion::Static`] when every component stays below
+/// [`static_threshold`](SceneMotionParams::static_threshold), and otherwise
+/// labeled by its largest component.
+///
+/// # Panics
+/// Panics if `results` does not have one entry per `prev_point`.
+pub fn classify_scene_motion(
+    prev_points: &[(f32, f32)],
+    results: &[TrackResult],
+    params: &SceneMotionParams,
+) -> SceneClassification {
+    let unmodelable = SceneClassification {
+        motion: SceneMotion::Unmodelable,
+        transform: None,
+        inlier_ratio: 0.0,
+        translation: 0.0,
+        zoom: 0.0,
+        rotation: 0.0,
+    };
+    let Some(transform) = fit_rigid_transform(prev_points, results, &params.rigid) else {
+        return unmodelable;
+    };
+    let inlier_ratio = transform.inliers as f32 / transform.tracked as f32;
+    if transform.tracked < params.min_tracks || inlier_ratio < params.min_inlier_ratio {
+        return SceneClassification {
+            transform: Some(transform),
+            inlier_ratio,
+            ..unmodelable
+        };
+    }
+
+    // Centroid and spread of the points that took part in the fit.
+    let tracked: Vec<(f32, f32)> = prev_points
+        .iter()
+        .zip(results)
+        .filter(|(_, r)| r.status == TrackStatus::Tracked)
+        .map(|(&p, _)| p)
+        .collect();
+    let n = tracked.len() as f32;
+    let (cx, cy) = tracked
+        .iter()
+        .fold((0.0, 0.0), |(sx, sy), &(x, y)| (sx + x / n, sy + y / n));
+    let spread = (tracked
+        .iter()
+        .map(|&(x, y)| (x - cx).powi(2) + (y - cy).powi(2))
+        .sum::<f32>()
+        / n)
+        .sqrt();
+
+    let (mx, my) = transform.apply((cx, cy));
+    let translation = (mx - cx).hypot(my - cy);
+    let zoom = (transform.scale() - 1.0).abs() * spread;
+    let rotation = transform.rotation().abs() * transform.scale() * spread;
+
+    let motion = if translation.max(zoom).max(rotation) < params.static_threshold {
+        SceneMotion::Static
+    } else if translation >= zoom && translation >= rotation {
+        SceneMotion::Translation
+    } else if zoom >= rotation {
+        SceneMotion::Zoom
+    } else {
+        SceneMotion::Rotation
+    };
+    SceneClassification {
+        motion,
+        transform: Some(transform),
+        inlier_ratio,
+        translation,
+        zoom,
+        rotation,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step(prev: &[(f32, f32)], motion: impl Fn((f32, f32)) -> (f32, f32)) -> Vec<TrackResult> {
+        prev.iter()
+            .map(|&p| TrackResult {
+                pos: motion(p),
+                status: TrackStatus::Tracked,
+                error: 0.0,
+                aperture: None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn labels_follow_the_dominant_component() {
+        let prev: Vec<(f32, f32)> = (0..8)
+            .flat_map(|j| (0..8).map(move |i| (20.0 + 40.0 * i as f32, 20.0 + 30.0 * j as f32)))
+            .collect();
+        let params = SceneMotionParams::default();
+        let label = |motion: &dyn Fn((f32, f32)) -> (f32, f32)| {
+            classify_scene_motion(&prev, &step(&prev, motion), &params).motion
+        };
+        let (cx, cy) = (160.0, 125.0);
+
+        assert_eq!(label(&|(x, y)| (x + 0.1, y - 0.1)), SceneMotion::Static);
+        assert_eq!(
+            label(&|(x, y)| (x + 5.0, y + 2.0)),
+            SceneMotion::Translation
+        );
+        assert_eq!(
+            label(&|(x, y)| (cx + 1.05 * (x - cx), cy + 1.05 * (y - cy) + 0.5)),
+            SceneMotion::Zoom
+        );
+        let (s, c) = 0.04f32.sin_cos();
+        assert_eq!(
+            label(&|(x, y)| {
+                let (x, y) = (x - cx, y - cy);
+                (cx + c * x - s * y, cy + s * x + c * y)
+            }),
+            SceneMotion::Rotation
+        );
+
+        // Every track goes its own way.
+        let chaotic = label(&|(x, y)| {
+            let k = (x * 7.0 + y * 13.0) as u32;
+            (x + (k % 17) as f32 - 8.0, y + (k % 11) as f32 - 5.0)
+        });
+        assert_eq!(chaotic, SceneMotion::Unmodelable);
+
+        // A cut: almost nothing survives.
+        let mut cut = step(&prev, |p| p);
+        for r in &mut cut[4..] {
+            r.status = TrackStatus::LowTexture;
+        }
+        let result = classify_scene_motion(&prev, &cut, &params);
+        assert_eq!(result.motion, SceneMotion::Unmodelable);
+    }
+}
@@ -0,0 +1,376 @@
+//! Similarity (4-DOF) Lucas-Kanade tracking.
+//!
+//! The translation-only tracker assumes every window moves rigidly in the
+//! image plane. Under camera roll or zoom the window content also turns and
+//! changes size, which biases the translation estimate and eventually breaks
+//! convergence. [`calc_optical_flow_similarity`] additionally estimates a
+//! rotation and a uniform scale per window: a middle ground between pure
+//! translation and a full affine warp, with fewer parameters and a
+//! better-conditioned solve.
+
+use image::GrayImage;
+
+use crate::lk::{
+    TrackStatus, build_window_offsets_into, in_bounds, interpolate, interpolate_gradient,
+    min_eigenvalue,
+};
+use crate::pyramid::validate_pyramid_pair;
+use crate::utils::buffer_pool::{recycle_i16, take_i16};
+use crate::utils::gradient_tiles::TiledGradients;
+
+/// Per-point result of [`calc_optical_flow_similarity`].
+///
+/// Positions follow the coordinate convention of
+/// [`TrackResult`](crate::TrackResult).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SimilarityResult {
+    /// Tracked position of the window center in the next frame.
+    pub pos: (f32, f32),
+    /// Rotation of the window content in radians; positive angles turn the
+    /// +x axis towards +y.
+    pub rotation: f32,
+    /// Scale of the window content, `> 1` when it grew.
+    pub scale: f32,
+    /// Why tracking ended the way it did.
+    pub status: TrackStatus,
+    /// Mean absolute photometric residual over the warped window at level 0,
+    /// in 8-bit intensity units; [`f32::INFINITY`] when it could not be
+    /// measured.
+    pub error: f32,
+}
+
+/// Warp `z -> alpha * z + t` of one window, with complex `alpha = (re, im)`
+/// combining rotation and scale and `z` relative to the previous center.
+#[derive(Clone, Copy)]
+struct Similarity {
+    alpha: (f32, f32),
+    t: (f32, f32),
+}
+
+impl Similarity {
+    fn apply(&self, (u, v): (f32, f32)) -> (f32, f32) {
+        let (re, im) = self.alpha;
+        (re * u - im * v + self.t.0, im * u + re * v + self.t.1)
+    }
+
+    /// Half extent of the warped square window of `radius`.
+    fn extent(&self, radius: usize) -> usize {
+        (radius as f32 * (self.alpha.0.abs() + self.alpha.1.abs())).ceil() as usize
+    }
+
+    /// `self ∘ step⁻¹`, the inverse-compositional update.
+    fn compose_inverse(&self, step: &Similarity) -> Similarity {
+        let (br, bi) = step.alpha;
+        let norm = br * br + bi * bi;
+        // alpha / beta
+        let ratio = (
+            (self.alpha.0 * br + self.alpha.1 * bi) / norm,
+            (self.alpha.1 * br - self.alpha.0 * bi) / norm,
+        );
+        let shift = (
+            ratio.0 * step.t.0 - ratio.1 * step.t.1,
+            ratio.1 * step.t.0 + ratio.0 * step.t.1,
+        );
+        Similarity {
+            alpha: ratio,
+            t: (self.t.0 - shift.0, self.t.1 - shift.1),
+        }
+    }
+}
+
+/// Pyramidal Lucas-Kanade tracking of a similarity warp (translation,
+/// rotation and uniform scale) per point.
+///
+/// Each window is registered with inverse-compositional Gauss-Newton on four
+/// parameters, coarse to fine: the translation doubles from level to level,
+/// rotation and scale carry over unchanged. The iteration starts from the
+/// identity (or the predicted translation) at the coarsest level.
+///
+/// Texture is judged as in [`calc_optical_flow_ex`](crate::calc_optical_flow_ex),
+/// by the normalized minimum eigenvalue of the translational gradient matrix;
+/// a window whose rotation and scale cannot be told apart (e.g. a single
+/// straight edge) is reported as [`TrackStatus::LowTexture`] as well.
+///
+/// # Arguments
+/// * `prev_pyramid` / `curr_pyramid` - frame pyramids, see
+///   [`build_pyramid`](crate::build_pyramid)
+/// * `prev_points` - points to track (level-0 coordinates)
+/// * `predicted` - optional predicted positions, see
+///   [`calc_optical_flow_ex`](crate::calc_optical_flow_ex)
+/// * `window_size` - side of the square window in the previous frame (odd)
+/// * `max_iterations` - max iterations per pyramid level
+/// * `min_eigen_threshold` - see
+///   [`DEFAULT_MIN_EIGEN_THRESHOLD`](crate::DEFAULT_MIN_EIGEN_THRESHOLD)
+///
+/// # Panics
+/// Panics if the pyramids fail [`validate_pyramid_pair`], or if `predicted`
+/// is `Some` but its length differs from `prev_points`.
+///
+/// # Returns
+/// One [`SimilarityResult`] per input point, in the same order.
+pub fn calc_optical_flow_similarity(
+    prev_pyramid: &[GrayImage],
+    curr_pyramid: &[GrayImage],
+    prev_points: &[(f32, f32)],
+    predicted: Option<&[(f32, f32)]>,
+    window_size: usize,
+    max_iterations: usize,
+    min_eigen_threshold: f32,
+) -> Vec<SimilarityResult> {
+    if let Err(error) = validate_pyramid_pair(prev_pyramid, curr_pyramid) {
+        panic!("{error}");
+    }
+    if let Some(predicted) = predicted {
+        assert_eq!(
+            predicted.len(),
+            prev_points.len(),
+            "predicted must have one entry per prev_point"
+        );
+    }
+
+    let epsilon = 1e-3;
+    let radius = window_size / 2;
+    let mut offsets = Vec::new();
+    build_window_offsets_into(radius, &mut offsets);
+    let n_pixels = offsets.len();
+    let area = n_pixels as f32;
+
+    let n = (prev_pyramid[0].width() * prev_pyramid[0].height()) as usize;
+    let mut tiles = TiledGradients::with_planes(take_i16(n), take_i16(n));
+    let mut template = vec![0.0f32; n_pixels];
+    let mut steepest = vec![[0.0f32; 4]; n_pixels];
+
+    // Warps in level-0 units: the translation is the displacement of the
+    // window center.
+    let mut warps: Vec<Similarity> = prev_points
+        .iter()
+        .enumerate()
+        .map(|(i, &(px, py))| {
+            let t = predicted.map_or((0.0, 0.0), |p| (p[i].0 - px, p[i].1 - py));
+            Similarity {
+                alpha: (1.0, 0.0),
+                t,
+            }
+        })
+        .collect();
+    let mut out: Vec<SimilarityResult> = prev_points
+        .iter()
+        .map(|&pos| SimilarityResult {
+            pos,
+            rotation: 0.0,
+            scale: 1.0,
+            status: TrackStatus::Tracked,
+            error: f32::INFINITY,
+        })
+        .collect();
+
+    for level in (0..prev_pyramid.len()).rev() {
+        let scale = 2f32.powi(level as i32);
+        let prev_img = &prev_pyramid[level];
+        let curr_img = &curr_pyramid[level];
+        let (w, h) = prev_img.dimensions();
+        tiles.reset(prev_img, prev_points.len(), window_size);
+
+        for (idx, &(prev_x, prev_y)) in prev_points.iter().enumerate() {
+            let (x, y) = (prev_x / scale, prev_y / scale);
+            let mut warp = Similarity {
+                t: (warps[idx].t.0 / scale, warps[idx].t.1 / scale),
+                ..warps[idx]
+            };
+
+            if !in_bounds(prev_img, x, y, radius) {
+                out[idx].status = TrackStatus::OutOfBounds;
+                continue;
+            }
+
+            // Template and steepest-descent images at the identity warp.
+            let (x0, y0) = (x.floor() as i64, y.floor() as i64);
+            let r = radius as i64;
+            tiles.ensure(prev_img, x0 - r..x0 + r + 2, y0 - r..y0 + r + 2);
+            let mut hessian = [[0.0f32; 4]; 4];
+            for (i, &(u, v)) in offsets.iter().enumerate() {
+                let (ix, iy) = interpolate_gradient(&tiles.planes(), w, h, x + u, y + v);
+                let (ix, iy) = (ix / 32.0, iy / 32.0);
+                template[i] = interpolate(prev_img, x + u, y + v);
+                let sd = [ix * u + iy * v, iy * u - ix * v, ix, iy];
+                for (row, &a) in hessian.iter_mut().zip(&sd) {
+                    for (cell, &b) in row.iter_mut().zip(&sd) {
+                        *cell += a * b;
+                    }
+                }
+                steepest[i] = sd;
+            }
+
+            let min_eig = min_eigenvalue(hessian[2][2], hessian[2][3], hessian[3][3]);
+            if min_eig / area < min_eigen_threshold {
+                out[idx].status = TrackStatus::LowTexture;
+                continue;
+            }
+
+            // Rotation and scale must be observable as well.
+            if solve_normal(hessian, [0.0; 4]).is_none() {
+                out[idx].status = TrackStatus::LowTexture;
+                continue;
+            }
+
+            let mut converged = false;
+            let mut out_of_bounds = false;
+            let mut diverged = false;
+            for _ in 0..max_iterations {
+                let (cx, cy) = (x + warp.t.0, y + warp.t.1);
+                if !in_bounds(curr_img, cx, cy, warp.extent(radius)) {
+                    out_of_bounds = true;
+                    break;
+                }
+
+                let mut b = [0.0f32; 4];
+                for (i, &offset) in offsets.iter().enumerate() {
+                    let (wx, wy) = warp.apply(offset);
+                    let error = interpolate(curr_img, x + wx, y + wy) - template[i];
+                    for (acc, &s) in b.iter_mut().zip(&steepest[i]) {
+                        *acc += s * error;
+                    }
+                }
+
+                let Some(dp) = solve_normal(hessian, b) else {
+                    diverged = true;
+                    break;
+                };
+                let step = Similarity {
+                    alpha: (1.0 + dp[0], dp[1]),
+                    t: (dp[2], dp[3]),
+                };
+                warp = warp.compose_inverse(&step);
+
+                // Largest displacement of a window pixel caused by the step.
+                let motion =
+                    dp[2].abs().max(dp[3].abs()) + radius as f32 * (dp[0].abs() + dp[1].abs());
+                if !motion.is_finite()
+                    || !warp.alpha.0.is_finite()
+                    || !warp.t.0.is_finite()
+                    || !warp.t.1.is_finite()
+                    || motion > window_size as f32
+                {
+                    diverged = true;
+                    break;
+                }
+                if motion < epsilon {
+                    converged = true;
+                    break;
+                }
+            }
+
+            out[idx].status = if out_of_bounds {
+                TrackStatus::OutOfBounds
+            } else if diverged || !converged {
+                TrackStatus::Diverged
+            } else {
+                TrackStatus::Tracked
+            };
+            warps[idx] = Similarity {
+                t: (warp.t.0 * scale, warp.t.1 * scale),
+                ..warp
+            };
+
+            if level == 0 && !out_of_bounds && !diverged {
+                let (cx, cy) = (x + warp.t.0, y + warp.t.1);
+                if in_bounds(curr_img, cx, cy, warp.extent(radius)) {
+                    let sum: f32 = offsets
+                        .iter()
+                        .zip(&template)
+                        .map(|(&offset, &t)| {
+                            let (wx, wy) = warp.apply(offset);
+                            (interpolate(curr_img, x + wx, y + wy) - t).abs()
+                        })
+                        .sum();
+                    out[idx].error = sum / area;
+                }
+            }
+        }
+    }
+
+    for ((result, warp), &(x, y)) in out.iter_mut().zip(&warps).zip(prev_points) {
+        let (re, im) = warp.alpha;
+        result.pos = (x + warp.t.0, y + warp.t.1);
+        result.rotation = im.atan2(re);
+        result.scale = (re * re + im * im).sqrt();
+    }
+
+    let (grad_x, grad_y) = tiles.into_planes();
+    recycle_i16(grad_x);
+    recycle_i16(grad_y);
+    out
+}
+
+/// Solves the symmetric positive semi-definite `N` x `N` system `a * x = b`
+/// by Gaussian elimination with partial pivoting, or `None` when it is (near)
+/// singular. Shared with the affine tracker.
+pub(crate) fn solve_normal<const N: usize>(
+    mut a: [[f32; N]; N],
+    mut b: [f32; N],
+) -> Option<[f32; N]> {
+    let scale = (0..N).map(|i| a[i][i]).fold(0.0f32, f32::max);
+    if scale.is_nan() || scale <= 0.0 {
+        return None;
+    }
+
+    for col in 0..N {
+        let pivot = (col..N).max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))?;
+        if a[pivot][col].abs() <= scale * 1e-6 {
+            return None;
+        }
+        a.swap(col, pivot);
+        b.swap(col, pivot);
+        let pivot_row = a[col];
+        for row in col + 1..N {
+            let factor = a[row][col] / pivot_row[col];
+            for (cell, &p) in a[row].iter_mut().zip(&pivot_row).skip(col) {
+                *cell -= factor * p;
+            }
+            b[row] -= factor * b[col];
+        }
+    }
+
+    let mut x = [0.0f32; N];
+    for row in (0..N).rev() {
+        let tail: f32 = (row + 1..N).map(|k| a[row][k] * x[k]).sum();
+        x[row] = (b[row] - tail) / a[row][row];
+    }
+    Some(x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn solve_normal_recovers_solution() {
+        let a = [
+            [4.0, 1.0, 0.5, 0.0],
+            [1.0, 3.0, 0.0, 0.2],
+            [0.5, 0.0, 2.0, 0.1],
+            [0.0, 0.2, 0.1, 1.0],
+        ];
+        let x = [1.0, -2.0, 0.5, 3.0];
+        let b: Vec<f32> = a
+            .iter()
+            .map(|row| row.iter().zip(&x).map(|(p, q)| p * q).sum())
+            .collect();
+        let solved = solve_normal(a, [b[0], b[1], b[2], b[3]]).unwrap();
+        for (s, e) in solved.iter().zip(&x) {
+            assert!((s - e).abs() < 1e-4, "{solved:?}");
+        }
+        assert!(solve_normal([[1.0; 4]; 4], [1.0; 4]).is_none());
+    }
+
+    #[test]
+    fn compose_inverse_undoes_step() {
+        let warp = Similarity {
+            alpha: (1.1, 0.2),
+            t: (3.0, -1.0),
+        };
+        let composed = warp.compose_inverse(&warp);
+        assert!((composed.alpha.0 - 1.0).abs() < 1e-6 && composed.alpha.1.abs() < 1e-6);
+        assert!(composed.t.0.abs() < 1e-6 && composed.t.1.abs() < 1e-6);
+    }
+}
@@ -0,0 +1,239 @@
+//! Temporal smoothing of per-track displacements for display.
+//!
+//! Frame-to-frame flow vectors jitter by a fraction of a pixel even on
+//! steady motion, which makes drawn arrows flicker. [`FlowSmoother`] filters
+//! the displacement of every track over time, either exponentially or by a
+//! running median, without estimating any motion state: the smoothed vectors
+//! are for visualization and the tracked positions are left untouched.
+
+use std::collections::VecDeque;
+
+use crate::lk::{TrackResult, TrackStatus};
+use crate::utils::median::median;
+
+/// Temporal filter applied by a [`FlowSmoother`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SmoothingFilter {
+    /// Exponential moving average: each frame moves the smoothed vector by
+    /// `alpha` (in `(0, 1]`) of the way towards the new displacement. Lower
+    /// values are smoother but lag more.
+    Exponential { alpha: f32 },
+    /// Component-wise median of the last `window` displacements, which
+    /// rejects single-frame outliers without blurring sudden changes for
+    /// longer than half the window.
+    Median { window: usize },
+}
+
+impl Default for SmoothingFilter {
+    fn default() -> Self {
+        SmoothingFilter::Exponential { alpha: 0.3 }
+    }
+}
+
+/// Filter state of one track.
+#[derive(Debug, Clone, Default)]
+struct TrackFilter {
+    average: Option<(f32, f32)>,
+    recent: VecDeque<(f32, f32)>,
+}
+
+/// Temporally smoothed displacements of a set of tracks.
+///
+/// Tracks are identified by their index, like the points of
+/// [`TrackerContext::track`](crate::TrackerContext::track): keep the smoother
+/// in step with the point list by calling [`push`](Self::push) for every new
+/// track and [`retain`](Self::retain) when tracks are dropped. After each
+/// tracking step, [`smooth`](Self::smooth) returns the filtered displacement
+/// of every track.
+#[derive(Debug, Clone)]
+pub struct FlowSmoother {
+    filter: SmoothingFilter,
+    tracks: Vec<TrackFilter>,
+    smoothed: Vec<Option<(f32, f32)>>,
+    scratch: Vec<f32>,
+}
+
+impl FlowSmoother {
+    /// Creates a smoother without tracks.
+    ///
+    /// # Panics
+    /// Panics if an exponential `alpha` is not in `(0, 1]` or a median
+    /// `window` is zero.
+    pub fn new(filter: SmoothingFilter) -> Self {
+        match filter {
+            SmoothingFilter::Exponential { alpha } => {
+                assert!(alpha > 0.0 && alpha <= 1.0, "alpha must be in (0, 1]")
+            }
+            SmoothingFilter::Median { window } => assert!(window > 0, "window must be non-zero"),
+        }
+        FlowSmoother {
+            filter,
+            tracks: Vec::new(),
+            smoothed: Vec::new(),
+            scratch: Vec::new(),
+        }
+    }
+
+    /// Number of smoothed tracks.
+    pub fn len(&self) -> usize {
+        self.tracks.len()
+    }
+
+    /// Whether no track is smoothed.
+    pub fn is_empty(&self) -> bool {
+        self.tracks.is_empty()
+    }
+
+    /// Adds a track without displacement history.
+    pub fn push(&mut self) {
+        self.tracks.push(TrackFilter::default());
+    }
+
+    /// Forgets the displacement history of track `index`, e.g. after it was
+    /// re-detected elsewhere.
+    ///
+    /// # Panics
+    /// Panics if `index` is out of range.
+    pub fn reset(&mut self, index: usize) {
+        self.tracks[index] = TrackFilter::default();
+    }
+
+    /// Keeps only the tracks for which `keep(index)` returns `true`,
+    /// preserving their order.
+    pub fn retain(&mut self, mut keep: impl FnMut(usize) -> bool) {
+        let mut index = 0;
+        self.tracks.retain(|_| {
+            index += 1;
+            keep(index - 1)
+        });
+    }
+
+    /// Feeds one tracking step and returns the smoothed displacement
+    /// `(dx, dy)` of every track, to be drawn from its previous position.
+    ///
+    /// Only [`TrackStatus::Tracked`] results are filtered; any other status
+    /// resets the track's history and yields `None`, so a track that is lost
+    /// and found again does not resume from stale motion. The first
+    /// displacement after a reset is passed through unchanged.
+    ///
+    /// # Panics
+    /// Panics if `prev_points` or `results` does not have one entry per
+    /// track.
+    pub fn smooth(
+        &mut self,
+        prev_points: &[(f32, f32)],
+        results: &[TrackResult],
+    ) -> &[Option<(f32, f32)>] {
+        assert_eq!(
+            prev_points.len(),
+            results.len(),
+            "results must have one entry per prev_point"
+        );
+        assert_eq!(
+            results.len(),
+            self.tracks.len(),
+            "results must have one entry per smoothed track"
+        );
+        self.smoothed.clear();
+        for ((track, &(x, y)), result) in self.tracks.iter_mut().zip(prev_points).zip(results) {
+            if result.status != TrackStatus::Tracked {
+                *track = TrackFilter::default();
+                self.smoothed.push(None);
+                continue;
+            }
+            let displacement = (result.pos.0 - x, result.pos.1 - y);
+            let smoothed = match self.filter {
+                SmoothingFilter::Exponential { alpha } => {
+                    let average = track.average.map_or(displacement, |(ax, ay)| {
+                        (
+                            ax + alpha * (displacement.0 - ax),
+                            ay + alpha * (displacement.1 - ay),
+                        )
+                    });
+                    track.average = Some(average);
+                    average
+                }
+                SmoothingFilter::Median { window } => {
+                    if track.recent.len() == window {
+                        track.recent.pop_front();
+                    }
+                    track.recent.push_back(displacement);
+                    self.scratch.clear();
+                    self.scratch.extend(track.recent.iter().map(|d| d.0));
+                    let dx = median(&mut self.scratch);
+                    self.scratch.clear();
+                    self.scratch.extend(track.recent.iter().map(|d| d.1));
+                    (dx, median(&mut self.scratch))
+                }
+            };
+            self.smoothed.push(Some(smoothed));
+        }
+        &self.smoothed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(pos: (f32, f32), status: TrackStatus) -> TrackResult {
+        TrackResult {
+            pos,
+            status,
+            error: 0.0,
+            aperture: None,
+        }
+    }
+
+    #[test]
+    fn median_rejects_single_frame_outliers_and_loss_resets() {
+        let mut smoother = FlowSmoother::new(SmoothingFilter::Median { window: 3 });
+        smoother.push();
+        smoother.push();
+        let prev = [(10.0, 10.0), (50.0, 50.0)];
+        let dx = [1.0, 1.2, 9.0, 0.8, 1.0];
+        let mut first = Vec::new();
+        for &d in &dx {
+            let results = [
+                result((10.0 + d, 10.0), TrackStatus::Tracked),
+                result((50.0, 52.0), TrackStatus::Tracked),
+            ];
+            let smoothed = smoother.smooth(&prev, &results);
+            assert_eq!(smoothed[1], Some((0.0, 2.0)));
+            first.push(smoothed[0].unwrap().0);
+        }
+        // Window medians of 1 | 1, 1.2 | 1, 1.2, 9 | 1.2, 9, 0.8 | 9, 0.8, 1.
+        for (actual, expected) in first.iter().zip([1.0, 1.1, 1.2, 1.2, 1.0]) {
+            assert!((actual - expected).abs() < 1e-5, "{first:?}");
+        }
+
+        let lost = [
+            result((11.0, 10.0), TrackStatus::Tracked),
+            result((0.0, 0.0), TrackStatus::OutOfBounds),
+        ];
+        assert_eq!(smoother.smooth(&prev, &lost)[1], None);
+        let found = [
+            result((11.0, 10.0), TrackStatus::Tracked),
+            result((53.0, 50.0), TrackStatus::Tracked),
+        ];
+        assert_eq!(smoother.smooth(&prev, &found)[1], Some((3.0, 0.0)));
+
+        smoother.retain(|i| i == 1);
+        assert_eq!(smoother.len(), 1);
+    }
+
+    #[test]
+    fn exponential_average_converges_to_steady_motion() {
+        let mut smoother = FlowSmoother::new(SmoothingFilter::Exponential { alpha: 0.5 });
+        smoother.push();
+        let prev = [(0.0, 0.0)];
+        let mut last = (0.0, 0.0);
+        for (frame, d) in [0.0, 4.0, 4.0, 4.0].into_iter().enumerate() {
+            last = smoother.smooth(&prev, &[result((d, -d), TrackStatus::Tracked)])[0].unwrap();
+            if frame == 1 {
+                assert_eq!(last, (2.0, -2.0));
+            }
+        }
+        assert_eq!(last, (3.5, -3.5));
+    }
+}
@@ -0,0 +1,159 @@
+//! Appearance snapshots of tracks at birth.
+//!
+//! A track is just a position; what it was following is only known from the
+//! frame it was born in. [`TrackSnapshots`] keeps a small patch of that frame
+//! for every track, so applications can render thumbnails, verify tracks by
+//! eye or feed their own re-identification models.
+
+use image::{GrayImage, Luma};
+
+use crate::lk::interpolate;
+
+/// Birth patch of one track.
+#[derive(Debug, Clone)]
+struct Snapshot {
+    patch: GrayImage,
+    birth: (f32, f32),
+}
+
+/// Birth-frame patches of a set of tracks.
+///
+/// Tracks are identified by their index, like the points of
+/// [`TrackerContext::track`](crate::TrackerContext::track): keep the
+/// snapshots in step with the point list by calling [`push`](Self::push) for
+/// every new track and [`retain`](Self::retain) when tracks are dropped.
+///
+/// Patches are `size` x `size`, resampled bilinearly so that the birth
+/// position falls on the patch center even at sub-pixel positions. Near the
+/// frame border the edge pixels are repeated.
+#[derive(Debug, Clone)]
+pub struct TrackSnapshots {
+    size: u32,
+    snapshots: Vec<Snapshot>,
+}
+
+impl TrackSnapshots {
+    /// Creates an empty set of `size` x `size` snapshots.
+    ///
+    /// # Panics
+    /// Panics if `size` is zero.
+    pub fn new(size: u32) -> Self {
+        assert!(size > 0, "size must be non-zero");
+        TrackSnapshots {
+            size,
+            snapshots: Vec::new(),
+        }
+    }
+
+    /// Side of the patches in pixels.
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    /// Number of tracks with a snapshot.
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    /// Whether no track has a snapshot.
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+
+    /// Adds a track born at `pos` (level-0 coordinates) in `image`, storing
+    /// its patch.
+    ///
+    /// # Panics
+    /// Panics if `image` is empty.
+    pub fn push(&mut self, image: &GrayImage, pos: (f32, f32)) {
+        let snapshot = self.capture(image, pos);
+        self.snapshots.push(snapshot);
+    }
+
+    /// Replaces the snapshot of track `index`, e.g. after it was re-detected.
+    ///
+    /// # Panics
+    /// Panics if `index` is out of range or `image` is empty.
+    pub fn replace(&mut self, index: usize, image: &GrayImage, pos: (f32, f32)) {
+        self.snapshots[index] = self.capture(image, pos);
+    }
+
+    /// Keeps only the tracks for which `keep(index)` returns `true`,
+    /// preserving their order.
+    pub fn retain(&mut self, mut keep: impl FnMut(usize) -> bool) {
+        let mut index = 0;
+        self.snapshots.retain(|_| {
+            index += 1;
+            keep(index - 1)
+        });
+    }
+
+    /// Patch of track `index`.
+    ///
+    /// # Panics
+    /// Panics if `index` is out of range.
+    pub fn patch(&self, index: usize) -> &GrayImage {
+        &self.snapshots[index].patch
+    }
+
+    /// Position of track `index` in its birth frame.
+    ///
+    /// # Panics
+    /// Panics if `index` is out of range.
+    pub fn birth_position(&self, index: usize) -> (f32, f32) {
+        self.snapshots[index].birth
+    }
+
+    /// Patches of all tracks, in track order.
+    pub fn patches(&self) -> impl ExactSizeIterator<Item = &GrayImage> {
+        self.snapshots.iter().map(|snapshot| &snapshot.patch)
+    }
+
+    fn capture(&self, image: &GrayImage, (x, y): (f32, f32)) -> Snapshot {
+        let (width, height) = image.dimensions();
+        assert!(width > 0 && height > 0, "image must not be empty");
+        let (max_x, max_y) = ((width - 1) as f32, (height - 1) as f32);
+        let half = (self.size - 1) as f32 / 2.0;
+        let patch = GrayImage::from_fn(self.size, self.size, |u, v| {
+            // Clamping the sample position repeats the frame's edges.
+            let sx = (x + u as f32 - half).clamp(0.0, max_x);
+            let sy = (y + v as f32 - half).clamp(0.0, max_y);
+            Luma([interpolate(image, sx, sy).round() as u8])
+        });
+        Snapshot {
+            patch,
+            birth: (x, y),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patches_are_centered_on_the_birth_position() {
+        let image = GrayImage::from_fn(40, 30, |x, y| Luma([(x * 5 + y) as u8]));
+        let mut snapshots = TrackSnapshots::new(5);
+        snapshots.push(&image, (10.0, 12.0));
+        snapshots.push(&image, (0.0, 0.0));
+        snapshots.push(&image, (20.5, 7.0));
+
+        // Integer positions copy the pixels around them.
+        let patch = snapshots.patch(0);
+        assert_eq!(patch.dimensions(), (5, 5));
+        assert_eq!(patch.get_pixel(2, 2)[0], 62);
+        assert_eq!(patch.get_pixel(0, 0)[0], 8 * 5 + 10);
+        // At the corner the edge pixels are repeated.
+        assert_eq!(snapshots.patch(1).get_pixel(0, 0)[0], 0);
+        assert_eq!(snapshots.patch(1).get_pixel(1, 1)[0], 0);
+        // Half-pixel positions are interpolated.
+        assert_eq!(snapshots.patch(2).get_pixel(2, 2)[0], 110);
+
+        snapshots.retain(|i| i != 1);
+        assert_eq!(snapshots.len(), 2);
+        assert_eq!(snapshots.birth_position(1), (20.5, 7.0));
+        snapshots.replace(0, &image, (5.0, 5.0));
+        assert_eq!(snapshots.patches().next().unwrap().get_pixel(2, 2)[0], 30);
+    }
+}
@@ -0,0 +1,228 @@
+//! Speed and heading of tracks in real-world units.
+//!
+//! Traffic and sports analysis wants meters per second, not pixels per frame.
+//! A [`GroundCalibration`] maps image positions onto the ground plane, either
+//! by a uniform scale (top-down or distant cameras) or by a homography
+//! (oblique cameras, where a pixel covers more ground further away).
+//! [`measure_speeds`] converts one tracking step and
+//! [`history_speeds`] the trails of a [`TrackHistory`], both summarized by
+//! [`SpeedStats`].
+
+use crate::lk::{TrackResult, TrackStatus};
+use crate::utils::median::median;
+use crate::viz::TrackHistory;
+
+/// Mapping from image pixels to ground-plane meters.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GroundCalibration {
+    /// Uniform scale: image pixels per meter. Ground axes are the image axes
+    /// (+y pointing down).
+    PixelsPerMeter(f32),
+    /// Row-major 3x3 homography taking image `(x, y, 1)` to homogeneous
+    /// ground coordinates in meters.
+    Homography([[f32; 3]; 3]),
+}
+
+impl GroundCalibration {
+    /// Ground position in meters of image point `(x, y)`, `None` when the
+    /// homography sends it to infinity (on or beyond the horizon).
+    pub fn to_ground(&self, (x, y): (f32, f32)) -> Option<(f32, f32)> {
+        match *self {
+            GroundCalibration::PixelsPerMeter(scale) => Some((x / scale, y / scale)),
+            GroundCalibration::Homography(h) => {
+                let w = h[2][0] * x + h[2][1] * y + h[2][2];
+                let gx = (h[0][0] * x + h[0][1] * y + h[0][2]) / w;
+                let gy = (h[1][0] * x + h[1][1] * y + h[1][2]) / w;
+                (w.abs() > 1e-9 && gx.is_finite() && gy.is_finite()).then_some((gx, gy))
+            }
+        }
+    }
+}
+
+/// Ground-plane velocity of one track.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrackVelocity {
+    /// Velocity `(vx, vy)` in meters per second along the ground axes.
+    pub velocity: (f32, f32),
+    /// Speed in meters per second.
+    pub speed: f32,
+    /// Direction of motion in radians from the ground +x axis towards +y.
+    pub heading: f32,
+}
+
+impl TrackVelocity {
+    /// Velocity of moving from image point `from` to `to` in `seconds`;
+    /// `None` if either point has no ground position.
+    fn between(
+        from: (f32, f32),
+        to: (f32, f32),
+        seconds: f32,
+        calibration: &GroundCalibration,
+    ) -> Option<Self> {
+        let (from, to) = (calibration.to_ground(from)?, calibration.to_ground(to)?);
+        let velocity = ((to.0 - from.0) / seconds, (to.1 - from.1) / seconds);
+        Some(TrackVelocity {
+            velocity,
+            speed: velocity.0.hypot(velocity.1),
+            heading: velocity.1.atan2(velocity.0),
+        })
+    }
+}
+
+/// Aggregate of the speeds of several tracks, in meters per second.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpeedStats {
+    /// Number of tracks measured.
+    pub count: usize,
+    pub mean: f32,
+    pub median: f32,
+    /// Speed not exceeded by 85% of the tracks, the usual reference of
+    /// traffic speed studies.
+    pub percentile_85: f32,
+    pub max: f32,
+}
+
+impl SpeedStats {
+    /// Statistics of `velocities`, `None` when empty.
+    pub fn from_velocities<'a>(
+        velocities: impl IntoIterator<Item = &'a TrackVelocity>,
+    ) -> Option<Self> {
+        let mut speeds: Vec<f32> = velocities.into_iter().map(|v| v.speed).collect();
+        if speeds.is_empty() {
+            return None;
+        }
+        // Sorts the speeds for the percentile and the maximum too.
+        let median = median(&mut speeds);
+        let n = speeds.len();
+        // Nearest-rank percentile.
+        let rank = ((0.85 * n as f32).ceil() as usize).clamp(1, n);
+        Some(SpeedStats {
+            count: n,
+            mean: speeds.iter().sum::<f32>() / n as f32,
+            median,
+            percentile_85: speeds[rank - 1],
+            max: speeds[n - 1],
+        })
+    }
+}
+
+/// Result of [`measure_speeds`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpeedReport {
+    /// Velocity of every input point, `None` where it was not
+    /// [`TrackStatus::Tracked`] or has no ground position.
+    pub tracks: Vec<Option<TrackVelocity>>,
+    /// Statistics over the measured tracks, `None` when there are none.
+    pub stats: Option<SpeedStats>,
+}
+
+/// Converts one tracking step, `frame_interval` seconds long, to ground-plane
+/// velocities.
+///
+/// # Panics
+/// Panics if `results` does not have one entry per `prev_point`, or if
+/// `frame_interval` is not positive.
+pub fn measure_speeds(
+    prev_points: &[(f32, f32)],
+    results: &[TrackResult],
+    calibration: &GroundCalibration,
+    frame_interval: f32,
+) -> SpeedReport {
+    assert_eq!(
+        prev_points.len(),
+        results.len(),
+        "results must have one entry per prev_point"
+    );
+    assert!(frame_interval > 0.0, "frame_interval must be positive");
+    let tracks: Vec<Option<TrackVelocity>> = prev_points
+        .iter()
+        .zip(results)
+        .map(|(&from, r)| {
+            (r.status == TrackStatus::Tracked)
+                .then(|| TrackVelocity::between(from, r.pos, frame_interval, calibration))
+                .flatten()
+        })
+        .collect();
+    let stats = SpeedStats::from_velocities(tracks.iter().flatten());
+    SpeedReport { tracks, stats }
+}
+
+/// Average velocity of every track of `history` over its kept trail, from
+/// the oldest to the newest position, with frames `frame_interval` seconds
+/// apart. Averaging over the trail smooths the per-frame jitter of
+/// [`measure_speeds`].
+///
+/// # Returns
+/// `(id, velocity)` of the tracks with at least two positions on the ground,
+/// in ID order.
+///
+/// # Panics
+/// Panics if `frame_interval` is not positive.
+pub fn history_speeds(
+    history: &TrackHistory,
+    calibration: &GroundCalibration,
+    frame_interval: f32,
+) -> Vec<(u64, TrackVelocity)> {
+    assert!(frame_interval > 0.0, "frame_interval must be positive");
+    history
+        .trails()
+        .filter(|(_, trail)| trail.len() >= 2)
+        .filter_map(|(id, trail)| {
+            let seconds = (trail.len() - 1) as f32 * frame_interval;
+            let (first, last) = (trail[0], trail[trail.len() - 1]);
+            TrackVelocity::between(first, last, seconds, calibration).map(|v| (id, v))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn velocities_are_measured_on_the_ground_plane() {
+        // Ground (x / y, 100 / y): rows near the top of the image are far
+        // away, and a pixel there covers more ground.
+        let calibration =
+            GroundCalibration::Homography([[1.0, 0.0, 0.0], [0.0, 0.0, 100.0], [0.0, 1.0, 0.0]]);
+        assert_eq!(calibration.to_ground((50.0, 0.0)), None);
+
+        let prev = [(10.0, 50.0), (10.0, 10.0), (0.0, 0.0), (40.0, 20.0)];
+        let tracked = |pos| TrackResult {
+            pos,
+            status: TrackStatus::Tracked,
+            error: 0.0,
+            aperture: None,
+        };
+        let results = [
+            tracked((20.0, 50.0)),
+            // The same image motion further away covers five times the ground.
+            tracked((20.0, 10.0)),
+            tracked((1.0, 1.0)),
+            TrackResult {
+                status: TrackStatus::Diverged,
+                ..tracked((0.0, 0.0))
+            },
+        ];
+        let report = measure_speeds(&prev, &results, &calibration, 0.5);
+        let near = report.tracks[0].unwrap();
+        assert!((near.speed - 0.4).abs() < 1e-5 && near.heading.abs() < 1e-5);
+        let far = report.tracks[1].unwrap();
+        assert!((far.speed - 2.0).abs() < 1e-5, "{far:?}");
+        assert_eq!((report.tracks[2], report.tracks[3]), (None, None));
+
+        let stats = report.stats.unwrap();
+        assert_eq!(stats.count, 2);
+        assert_eq!(stats.max, far.speed);
+        assert_eq!(stats.percentile_85, far.speed);
+        assert!((stats.median - 0.5 * (near.speed + far.speed)).abs() < 1e-5);
+
+        let mut history = TrackHistory::new(5);
+        for frame in 0..5 {
+            history.record(&[3], &[(100.0 + 4.0 * frame as f32, 30.0)]);
+        }
+        let speeds = history_speeds(&history, &GroundCalibration::PixelsPerMeter(20.0), 0.1);
+        assert_eq!(speeds.len(), 1);
+        assert!((speeds[0].1.speed - 2.0).abs() < 1e-5);
+    }
+}
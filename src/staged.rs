@@ -0,0 +1,218 @@
+//! Running each pipeline stage at its own resolution.
+//!
+//! The stages of a tracking pipeline need different detail. Corner detection
+//! finds the same strong corners at half resolution, global motion is
+//! estimated well enough from a quarter, while the per-point tracks should
+//! keep full precision. [`StagedPipeline`] runs every stage at the resolution
+//! of its [`StageResolutions`] and reports all results in original-frame
+//! coordinates, so stages can be tuned independently to hit a frame budget.
+
+use image::GrayImage;
+
+use crate::budget::BudgetScale;
+use crate::features::{FeatureParams, good_features_to_track_with};
+use crate::lk::{TrackResult, calc_optical_flow_ex};
+use crate::pyramid::build_pyramid;
+use crate::registration::{RigidParams, RigidTransform, estimate_rigid_transform};
+
+/// Processing resolution of each stage of a [`StagedPipeline`], as a divisor
+/// of the original frame size: 1 is full resolution, 2 half, 4 a quarter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StageResolutions {
+    /// Divisor for feature detection.
+    pub detection: u32,
+    /// Divisor for point tracking.
+    pub tracking: u32,
+    /// Divisor for global motion estimation.
+    pub global_motion: u32,
+}
+
+impl Default for StageResolutions {
+    /// Detection at half, tracking at full and global motion at a quarter
+    /// resolution.
+    fn default() -> Self {
+        StageResolutions {
+            detection: 2,
+            tracking: 1,
+            global_motion: 4,
+        }
+    }
+}
+
+/// Feature detection, tracking and global motion estimation on frames of one
+/// size, each at its own processing resolution.
+///
+/// Inputs and outputs are always in original-frame coordinates; every stage
+/// downscales the frames it is given (see [`BudgetScale::downscale`]) and
+/// maps coordinates with the pixel-center convention of [`BudgetScale`].
+/// Pixel-valued settings (detection `min_distance`, window sizes, RANSAC
+/// thresholds) are in the pixels of the stage's resolution.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StagedPipeline {
+    detection: BudgetScale,
+    tracking: BudgetScale,
+    global_motion: BudgetScale,
+}
+
+impl StagedPipeline {
+    /// Pipeline for `width` x `height` frames.
+    ///
+    /// # Panics
+    /// Panics if a dimension or a divisor is zero.
+    pub fn new(width: u32, height: u32, resolutions: StageResolutions) -> Self {
+        StagedPipeline {
+            detection: BudgetScale::with_divisor(width, height, resolutions.detection),
+            tracking: BudgetScale::with_divisor(width, height, resolutions.tracking),
+            global_motion: BudgetScale::with_divisor(width, height, resolutions.global_motion),
+        }
+    }
+
+    /// Scale of the detection stage.
+    pub fn detection_scale(&self) -> BudgetScale {
+        self.detection
+    }
+
+    /// Scale of the tracking stage.
+    pub fn tracking_scale(&self) -> BudgetScale {
+        self.tracking
+    }
+
+    /// Scale of the global motion stage.
+    pub fn global_motion_scale(&self) -> BudgetScale {
+        self.global_motion
+    }
+
+    /// [`good_features_to_track_with`] at the detection resolution.
+    ///
+    /// # Returns
+    /// `(x, y, quality)` of every corner in original coordinates, strongest
+    /// first.
+    ///
+    /// # Panics
+    /// Panics if `frame` is not of the original size.
+    pub fn detect(&self, frame: &GrayImage, params: &FeatureParams) -> Vec<(f32, f32, f32)> {
+        good_features_to_track_with(&self.detection.downscale(frame), params)
+            .into_iter()
+            .map(|(x, y, quality)| {
+                let (x, y) = self.detection.to_original((x as f32, y as f32));
+                (x, y, quality)
+            })
+            .collect()
+    }
+
+    /// [`calc_optical_flow_ex`] at the tracking resolution with `levels`
+    /// pyramid levels; see [`calc_optical_flow_budget`](crate::calc_optical_flow_budget),
+    /// which does the same for a pixel budget.
+    ///
+    /// # Panics
+    /// Panics if the frames are not of the original size.
+    #[allow(clippy::too_many_arguments)]
+    pub fn track(
+        &self,
+        prev: &GrayImage,
+        next: &GrayImage,
+        prev_points: &[(f32, f32)],
+        levels: usize,
+        window_size: usize,
+        max_iterations: usize,
+        min_eigen_threshold: f32,
+    ) -> Vec<TrackResult> {
+        let scale = &self.tracking;
+        let points: Vec<(f32, f32)> = prev_points
+            .iter()
+            .map(|&p| scale.to_processing(p))
+            .collect();
+        let mut results = calc_optical_flow_ex(
+            &build_pyramid(&scale.downscale(prev), levels),
+            &build_pyramid(&scale.downscale(next), levels),
+            &points,
+            None,
+            window_size,
+            max_iterations,
+            min_eigen_threshold,
+        );
+        scale.results_to_original(&mut results);
+        results
+    }
+
+    /// [`estimate_rigid_transform`] at the global motion resolution.
+    ///
+    /// The transform maps original `prev` coordinates to original `next`
+    /// coordinates and its `rms_error` is in original pixels. When the
+    /// divisor does not split both axes evenly, the axes scale slightly
+    /// differently and the mapped matrix is an affine transform within
+    /// rounding of a similarity.
+    ///
+    /// # Panics
+    /// Panics if the frames are not of the original size.
+    pub fn global_motion(
+        &self,
+        prev: &GrayImage,
+        next: &GrayImage,
+        params: &RigidParams,
+    ) -> Option<RigidTransform> {
+        let scale = &self.global_motion;
+        let transform =
+            estimate_rigid_transform(&scale.downscale(prev), &scale.downscale(next), params)?;
+
+        let matrix = affine_to_original(scale, transform.matrix);
+        let (fx, fy) = scale.factor();
+        Some(RigidTransform {
+            matrix,
+            rms_error: transform.rms_error * 2.0 / (fx + fy),
+            ..transform
+        })
+    }
+}
+
+/// Expresses the 2x3 affine transform `m` between processing coordinates of
+/// `scale` as one between original coordinates.
+fn affine_to_original(scale: &BudgetScale, m: [[f32; 3]; 2]) -> [[f32; 3]; 2] {
+    // Original -> processing is p = f * o + (f - 1) / 2 per axis; conjugate
+    // `m` with it.
+    let (fx, fy) = scale.factor();
+    let (ox, oy) = (0.5 * (fx - 1.0), 0.5 * (fy - 1.0));
+    [
+        [
+            m[0][0],
+            m[0][1] * fy / fx,
+            (m[0][0] * ox + m[0][1] * oy + m[0][2] - ox) / fx,
+        ],
+        [
+            m[1][0] * fx / fy,
+            m[1][1],
+            (m[1][0] * ox + m[1][1] * oy + m[1][2] - oy) / fy,
+        ],
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stage_transforms_are_mapped_to_original_coordinates() {
+        let pipeline = StagedPipeline::new(640, 480, StageResolutions::default());
+        assert_eq!(pipeline.detection_scale().processing_size(), (320, 240));
+        assert_eq!(pipeline.tracking_scale().processing_size(), (640, 480));
+        let scale = pipeline.global_motion_scale();
+        assert_eq!(scale.processing_size(), (160, 120));
+
+        // A processing-resolution similarity and its original-resolution form
+        // must agree on every point.
+        let (s, c) = 0.1f32.sin_cos();
+        let m = [[1.1 * c, -1.1 * s, 3.0], [1.1 * s, 1.1 * c, -2.0]];
+        let mapped = affine_to_original(&scale, m);
+        let apply = |m: &[[f32; 3]; 2], (x, y): (f32, f32)| {
+            (
+                m[0][0] * x + m[0][1] * y + m[0][2],
+                m[1][0] * x + m[1][1] * y + m[1][2],
+            )
+        };
+        for p in [(0.0, 0.0), (320.0, 240.0), (600.0, 30.0)] {
+            let expected = scale.to_original(apply(&m, scale.to_processing(p)));
+            let actual = apply(&mapped, p);
+            assert!((expected.0 - actual.0).abs() < 1e-3 && (expected.1 - actual.1).abs() < 1e-3);
+        }
+    }
+}
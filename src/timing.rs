@@ -0,0 +1,63 @@
+//! Lightweight per-call timing, independent of any profiler or `tracing`
+//! subscriber.
+//!
+//! Browsers make native profilers awkward to use, so [`TrackerContext`] can
+//! optionally measure its own stages and hand back a [`TimingReport`] that an
+//! application can use to adapt quality settings at runtime.
+//!
+//! [`TrackerContext`]: crate::TrackerContext
+
+/// Monotonic millisecond clock used to time the tracking stages.
+///
+/// Only differences between two readings are used, so the origin is arbitrary.
+/// On native targets [`system_clock_ms`] is a ready-made choice; on
+/// `wasm32-unknown-unknown` (where `std::time::Instant` panics) pass a binding
+/// to `performance.now()` instead.
+pub type TimingClock = fn() -> f64;
+
+/// Wall-clock breakdown of the most recent prepare + track step.
+///
+/// All values are in milliseconds. `gradients_ms + solve_ms` equals the sum of
+/// `per_level_ms`; `pyramid_ms` is measured separately by
+/// [`TrackerContext::prepare`](crate::TrackerContext::prepare).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TimingReport {
+    /// Time spent building both frame pyramids, including the previous
+    /// frame's gradients that [`TrackerContext::prepare`] computes alongside.
+    /// Those levels then report no gradient time during tracking. Separate
+    /// `prepare_prev` and `prepare_next` steps before a tracking call add up.
+    ///
+    /// [`TrackerContext::prepare`]: crate::TrackerContext::prepare
+    pub pyramid_ms: f64,
+    /// Time spent computing spatial gradients, summed over all levels.
+    pub gradients_ms: f64,
+    /// Total time per pyramid level (gradients + solve), indexed by level
+    /// (`0` = full resolution).
+    pub per_level_ms: Vec<f64>,
+    /// Time spent in the per-point Lucas-Kanade iterations, summed over all
+    /// levels.
+    pub solve_ms: f64,
+}
+
+impl TimingReport {
+    /// Clears the tracking stages (gradients, levels, solve) for a new call,
+    /// keeping `pyramid_ms` and the level vector's capacity.
+    pub(crate) fn reset_tracking(&mut self, n_levels: usize) {
+        self.gradients_ms = 0.0;
+        self.solve_ms = 0.0;
+        self.per_level_ms.clear();
+        self.per_level_ms.resize(n_levels, 0.0);
+    }
+}
+
+/// Milliseconds elapsed since the first call, from [`std::time::Instant`].
+///
+/// Not available on `wasm32-unknown-unknown`, which has no system clock.
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub fn system_clock_ms() -> f64 {
+    use std::sync::OnceLock;
+    use std::time::Instant;
+
+    static ORIGIN: OnceLock<Instant> = OnceLock::new();
+    ORIGIN.get_or_init(Instant::now).elapsed().as_secs_f64() * 1e3
+}
@@ -0,0 +1,507 @@
+#[cfg(target_arch = "aarch64")]
+use std::arch::aarch64::*;
+#[cfg(all(target_arch = "wasm32", target_feature = "simd128"))]
+use std::arch::wasm32::*;
+#[cfg(all(target_arch = "x86_64", target_feature = "sse2"))]
+use std::arch::x86_64::*;
+
+/// Sums of one Lucas-Kanade mismatch pass over a window: the right-hand side
+/// `b = sum(grad * (reference - sample))` and the total absolute residual.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Mismatch {
+    pub bx: f32,
+    pub by: f32,
+    pub abs_sum: f32,
+}
+
+/// Bilinearly samples a `width` x `height` window of `data` whose top-left
+/// integer corner is at `base`, and accumulates the mismatch against the
+/// cached `reference` window and its gradients `ix` / `iy` (row-major,
+/// `width * height` each).
+///
+/// All window samples share the same fractional offset `(fx, fy)`, so the
+/// four bilinear weights are computed once. The caller guarantees the
+/// `(width + 1)` x `(height + 1)` footprint starting at `base` lies inside the
+/// image (`stride` pixels per row).
+///
+/// Selection is done per target, like the gradient kernels:
+/// - `x86_64`: SSE2 (always present), 4 window pixels per step
+/// - `aarch64`: NEON, 4 pixels per step
+/// - `wasm32`: simd128 when built with `+simd128`, 4 pixels per step
+/// - everything else: scalar
+///
+/// Square windows of side 7, 11, 15 and 21 run a dedicated instantiation with
+/// the size fixed at compile time; other sizes use the generic loop.
+#[allow(clippy::too_many_arguments)]
+pub fn window_mismatch(
+    data: &[u8],
+    stride: usize,
+    base: usize,
+    (width, height): (usize, usize),
+    frac: (f32, f32),
+    reference: &[f32],
+    ix: &[f32],
+    iy: &[f32],
+) -> Mismatch {
+    let n = width * height;
+    assert!(reference.len() >= n && ix.len() >= n && iy.len() >= n);
+    assert!(n == 0 || base + height * stride + width < data.len());
+
+    // Common window sizes get their own instantiation: with a constant side
+    // the row loop's chunk count and tail are known at compile time, so the
+    // compiler fully unrolls them.
+    match (width, height) {
+        (7, 7) => dispatch::<7>(data, stride, base, width, height, frac, reference, ix, iy),
+        (11, 11) => dispatch::<11>(data, stride, base, width, height, frac, reference, ix, iy),
+        (15, 15) => dispatch::<15>(data, stride, base, width, height, frac, reference, ix, iy),
+        (21, 21) => dispatch::<21>(data, stride, base, width, height, frac, reference, ix, iy),
+        _ => dispatch::<DYNAMIC>(data, stride, base, width, height, frac, reference, ix, iy),
+    }
+}
+
+/// `SIDE` value selecting the runtime `side` and `rows` arguments instead of
+/// a compile-time window size.
+const DYNAMIC: usize = 0;
+
+/// Picks the kernel for the current target. `SIDE` is either [`DYNAMIC`] or
+/// equal to both `side` (the row length) and `rows`.
+#[allow(clippy::too_many_arguments)]
+#[inline(always)]
+fn dispatch<const SIDE: usize>(
+    data: &[u8],
+    stride: usize,
+    base: usize,
+    side: usize,
+    rows: usize,
+    frac: (f32, f32),
+    reference: &[f32],
+    ix: &[f32],
+    iy: &[f32],
+) -> Mismatch {
+    debug_assert!(SIDE == DYNAMIC || (SIDE == side && SIDE == rows));
+
+    #[cfg(all(target_arch = "x86_64", target_feature = "sse2"))]
+    {
+        unsafe {
+            window_mismatch_sse2::<SIDE>(data, stride, base, side, rows, frac, reference, ix, iy)
+        }
+    }
+    #[cfg(target_arch = "aarch64")]
+    {
+        unsafe {
+            window_mismatch_neon::<SIDE>(data, stride, base, side, rows, frac, reference, ix, iy)
+        }
+    }
+    #[cfg(all(target_arch = "wasm32", target_feature = "simd128"))]
+    {
+        unsafe {
+            window_mismatch_simd128::<SIDE>(data, stride, base, side, rows, frac, reference, ix, iy)
+        }
+    }
+    #[cfg(not(any(
+        all(target_arch = "x86_64", target_feature = "sse2"),
+        target_arch = "aarch64",
+        all(target_arch = "wasm32", target_feature = "simd128")
+    )))]
+    {
+        window_mismatch_scalar::<SIDE>(data, stride, base, side, rows, frac, reference, ix, iy)
+    }
+}
+
+/// Resolves the window side of a kernel instantiation.
+#[inline(always)]
+const fn resolve_side<const SIDE: usize>(side: usize) -> usize {
+    if SIDE == DYNAMIC { side } else { SIDE }
+}
+
+/// Bilinear weights `(w00, w01, w10, w11)` for the top-left, bottom-left,
+/// top-right and bottom-right neighbors.
+fn weights((fx, fy): (f32, f32)) -> (f32, f32, f32, f32) {
+    let (gx, gy) = (1.0 - fx, 1.0 - fy);
+    (gx * gy, gx * fy, fx * gy, fx * fy)
+}
+
+/// Scalar reference; also handles the `< 4` pixel tail of each SIMD row.
+#[allow(clippy::too_many_arguments)]
+#[inline(always)]
+fn accumulate_scalar(
+    data: &[u8],
+    stride: usize,
+    row_base: usize,
+    cols: std::ops::Range<usize>,
+    row_index: usize,
+    (w00, w01, w10, w11): (f32, f32, f32, f32),
+    (reference, ix, iy): (&[f32], &[f32], &[f32]),
+    acc: &mut Mismatch,
+) {
+    for c in cols {
+        let b = row_base + c;
+        let i = row_index + c;
+        let v = data[b] as f32 * w00
+            + data[b + stride] as f32 * w01
+            + data[b + 1] as f32 * w10
+            + data[b + stride + 1] as f32 * w11;
+        let e = reference[i] - v;
+        acc.bx += ix[i] * e;
+        acc.by += iy[i] * e;
+        acc.abs_sum += e.abs();
+    }
+}
+
+// Reference implementation; the dispatched path on targets without a SIMD
+// kernel, and the ground truth for the equivalence test.
+#[allow(dead_code, clippy::too_many_arguments)]
+fn window_mismatch_scalar<const SIDE: usize>(
+    data: &[u8],
+    stride: usize,
+    base: usize,
+    side: usize,
+    rows: usize,
+    frac: (f32, f32),
+    reference: &[f32],
+    ix: &[f32],
+    iy: &[f32],
+) -> Mismatch {
+    let (side, rows) = (resolve_side::<SIDE>(side), resolve_side::<SIDE>(rows));
+    let w = weights(frac);
+    let mut acc = Mismatch::default();
+    for r in 0..rows {
+        accumulate_scalar(
+            data,
+            stride,
+            base + r * stride,
+            0..side,
+            r * side,
+            w,
+            (reference, ix, iy),
+            &mut acc,
+        );
+    }
+    acc
+}
+
+#[cfg(all(target_arch = "x86_64", target_feature = "sse2"))]
+#[target_feature(enable = "sse2")]
+unsafe fn load4_u8_as_f32_sse2(ptr: *const u8) -> __m128 {
+    unsafe {
+        let bytes = _mm_cvtsi32_si128((ptr as *const i32).read_unaligned());
+        let zero = _mm_setzero_si128();
+        _mm_cvtepi32_ps(_mm_unpacklo_epi16(_mm_unpacklo_epi8(bytes, zero), zero))
+    }
+}
+
+#[cfg(all(target_arch = "x86_64", target_feature = "sse2"))]
+#[target_feature(enable = "sse2")]
+#[allow(clippy::too_many_arguments)]
+unsafe fn window_mismatch_sse2<const SIDE: usize>(
+    data: &[u8],
+    stride: usize,
+    base: usize,
+    side: usize,
+    rows: usize,
+    frac: (f32, f32),
+    reference: &[f32],
+    ix: &[f32],
+    iy: &[f32],
+) -> Mismatch {
+    let (side, rows) = (resolve_side::<SIDE>(side), resolve_side::<SIDE>(rows));
+    let w = weights(frac);
+    let (w00, w01, w10, w11) = (
+        _mm_set1_ps(w.0),
+        _mm_set1_ps(w.1),
+        _mm_set1_ps(w.2),
+        _mm_set1_ps(w.3),
+    );
+    let sign = _mm_set1_ps(-0.0);
+    let (mut vbx, mut vby, mut vabs) = (_mm_setzero_ps(), _mm_setzero_ps(), _mm_setzero_ps());
+    let mut acc = Mismatch::default();
+    let chunks_end = side - side % 4;
+
+    for r in 0..rows {
+        let row_base = base + r * stride;
+        let row_index = r * side;
+        let (top, bottom) = unsafe {
+            (
+                data.as_ptr().add(row_base),
+                data.as_ptr().add(row_base + stride),
+            )
+        };
+
+        let mut c = 0;
+        while c < chunks_end {
+            // SAFETY: c + 4 <= side, so the widest read (bottom + c + 1, 4
+            // bytes) ends at column side, inside the caller's footprint; the
+            // f32 windows hold side * rows elements.
+            unsafe {
+                let v = _mm_add_ps(
+                    _mm_add_ps(
+                        _mm_mul_ps(load4_u8_as_f32_sse2(top.add(c)), w00),
+                        _mm_mul_ps(load4_u8_as_f32_sse2(bottom.add(c)), w01),
+                    ),
+                    _mm_add_ps(
+                        _mm_mul_ps(load4_u8_as_f32_sse2(top.add(c + 1)), w10),
+                        _mm_mul_ps(load4_u8_as_f32_sse2(bottom.add(c + 1)), w11),
+                    ),
+                );
+                let i = row_index + c;
+                let e = _mm_sub_ps(_mm_loadu_ps(reference.as_ptr().add(i)), v);
+                vbx = _mm_add_ps(vbx, _mm_mul_ps(_mm_loadu_ps(ix.as_ptr().add(i)), e));
+                vby = _mm_add_ps(vby, _mm_mul_ps(_mm_loadu_ps(iy.as_ptr().add(i)), e));
+                vabs = _mm_add_ps(vabs, _mm_andnot_ps(sign, e));
+            }
+            c += 4;
+        }
+
+        accumulate_scalar(
+            data,
+            stride,
+            row_base,
+            chunks_end..side,
+            row_index,
+            w,
+            (reference, ix, iy),
+            &mut acc,
+        );
+    }
+
+    let hsum = |v: __m128| {
+        let mut lanes = [0.0f32; 4];
+        unsafe { _mm_storeu_ps(lanes.as_mut_ptr(), v) };
+        (lanes[0] + lanes[1]) + (lanes[2] + lanes[3])
+    };
+    acc.bx += hsum(vbx);
+    acc.by += hsum(vby);
+    acc.abs_sum += hsum(vabs);
+    acc
+}
+
+#[cfg(target_arch = "aarch64")]
+#[target_feature(enable = "neon")]
+unsafe fn load4_u8_as_f32_neon(ptr: *const u8) -> float32x4_t {
+    unsafe {
+        let word = (ptr as *const u32).read_unaligned();
+        let bytes = vreinterpret_u8_u32(vdup_n_u32(word));
+        vcvtq_f32_u32(vmovl_u16(vget_low_u16(vmovl_u8(bytes))))
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+#[target_feature(enable = "neon")]
+#[allow(clippy::too_many_arguments)]
+unsafe fn window_mismatch_neon<const SIDE: usize>(
+    data: &[u8],
+    stride: usize,
+    base: usize,
+    side: usize,
+    rows: usize,
+    frac: (f32, f32),
+    reference: &[f32],
+    ix: &[f32],
+    iy: &[f32],
+) -> Mismatch {
+    let (side, rows) = (resolve_side::<SIDE>(side), resolve_side::<SIDE>(rows));
+    let w = weights(frac);
+    let (w00, w01, w10, w11) = (
+        vdupq_n_f32(w.0),
+        vdupq_n_f32(w.1),
+        vdupq_n_f32(w.2),
+        vdupq_n_f32(w.3),
+    );
+    let (mut vbx, mut vby, mut vabs) = (vdupq_n_f32(0.0), vdupq_n_f32(0.0), vdupq_n_f32(0.0));
+    let mut acc = Mismatch::default();
+    let chunks_end = side - side % 4;
+
+    for r in 0..rows {
+        let row_base = base + r * stride;
+        let row_index = r * side;
+        let (top, bottom) = unsafe {
+            (
+                data.as_ptr().add(row_base),
+                data.as_ptr().add(row_base + stride),
+            )
+        };
+
+        let mut c = 0;
+        while c < chunks_end {
+            // SAFETY: see the SSE2 kernel; the reads are the same.
+            unsafe {
+                let v = vaddq_f32(
+                    vaddq_f32(
+                        vmulq_f32(load4_u8_as_f32_neon(top.add(c)), w00),
+                        vmulq_f32(load4_u8_as_f32_neon(bottom.add(c)), w01),
+                    ),
+                    vaddq_f32(
+                        vmulq_f32(load4_u8_as_f32_neon(top.add(c + 1)), w10),
+                        vmulq_f32(load4_u8_as_f32_neon(bottom.add(c + 1)), w11),
+                    ),
+                );
+                let i = row_index + c;
+                let e = vsubq_f32(vld1q_f32(reference.as_ptr().add(i)), v);
+                vbx = vaddq_f32(vbx, vmulq_f32(vld1q_f32(ix.as_ptr().add(i)), e));
+                vby = vaddq_f32(vby, vmulq_f32(vld1q_f32(iy.as_ptr().add(i)), e));
+                vabs = vaddq_f32(vabs, vabsq_f32(e));
+            }
+            c += 4;
+        }
+
+        accumulate_scalar(
+            data,
+            stride,
+            row_base,
+            chunks_end..side,
+            row_index,
+            w,
+            (reference, ix, iy),
+            &mut acc,
+        );
+    }
+
+    acc.bx += vaddvq_f32(vbx);
+    acc.by += vaddvq_f32(vby);
+    acc.abs_sum += vaddvq_f32(vabs);
+    acc
+}
+
+#[cfg(all(target_arch = "wasm32", target_feature = "simd128"))]
+#[target_feature(enable = "simd128")]
+unsafe fn load4_u8_as_f32_simd128(ptr: *const u8) -> v128 {
+    unsafe {
+        let bytes = v128_load32_zero(ptr as *const u32);
+        f32x4_convert_u32x4(u32x4_extend_low_u16x8(u16x8_extend_low_u8x16(bytes)))
+    }
+}
+
+#[cfg(all(target_arch = "wasm32", target_feature = "simd128"))]
+#[target_feature(enable = "simd128")]
+#[allow(clippy::too_many_arguments)]
+unsafe fn window_mismatch_simd128<const SIDE: usize>(
+    data: &[u8],
+    stride: usize,
+    base: usize,
+    side: usize,
+    rows: usize,
+    frac: (f32, f32),
+    reference: &[f32],
+    ix: &[f32],
+    iy: &[f32],
+) -> Mismatch {
+    let (side, rows) = (resolve_side::<SIDE>(side), resolve_side::<SIDE>(rows));
+    let w = weights(frac);
+    let (w00, w01, w10, w11) = (
+        f32x4_splat(w.0),
+        f32x4_splat(w.1),
+        f32x4_splat(w.2),
+        f32x4_splat(w.3),
+    );
+    let (mut vbx, mut vby, mut vabs) = (f32x4_splat(0.0), f32x4_splat(0.0), f32x4_splat(0.0));
+    let mut acc = Mismatch::default();
+    let chunks_end = side - side % 4;
+
+    for r in 0..rows {
+        let row_base = base + r * stride;
+        let row_index = r * side;
+        let (top, bottom) = unsafe {
+            (
+                data.as_ptr().add(row_base),
+                data.as_ptr().add(row_base + stride),
+            )
+        };
+
+        let mut c = 0;
+        while c < chunks_end {
+            // SAFETY: see the SSE2 kernel; the reads are the same.
+            unsafe {
+                let v = f32x4_add(
+                    f32x4_add(
+                        f32x4_mul(load4_u8_as_f32_simd128(top.add(c)), w00),
+                        f32x4_mul(load4_u8_as_f32_simd128(bottom.add(c)), w01),
+                    ),
+                    f32x4_add(
+                        f32x4_mul(load4_u8_as_f32_simd128(top.add(c + 1)), w10),
+                        f32x4_mul(load4_u8_as_f32_simd128(bottom.add(c + 1)), w11),
+                    ),
+                );
+                let i = row_index + c;
+                let e = f32x4_sub(v128_load(reference.as_ptr().add(i) as *const v128), v);
+                vbx = f32x4_add(
+                    vbx,
+                    f32x4_mul(v128_load(ix.as_ptr().add(i) as *const v128), e),
+                );
+                vby = f32x4_add(
+                    vby,
+                    f32x4_mul(v128_load(iy.as_ptr().add(i) as *const v128), e),
+                );
+                vabs = f32x4_add(vabs, f32x4_abs(e));
+            }
+            c += 4;
+        }
+
+        accumulate_scalar(
+            data,
+            stride,
+            row_base,
+            chunks_end..side,
+            row_index,
+            w,
+            (reference, ix, iy),
+            &mut acc,
+        );
+    }
+
+    let hsum = |v: v128| {
+        (f32x4_extract_lane::<0>(v) + f32x4_extract_lane::<1>(v))
+            + (f32x4_extract_lane::<2>(v) + f32x4_extract_lane::<3>(v))
+    };
+    acc.bx += hsum(vbx);
+    acc.by += hsum(vby);
+    acc.abs_sum += hsum(vabs);
+    acc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() <= 1e-3 * a.abs().max(b.abs()).max(1.0)
+    }
+
+    #[test]
+    fn selected_kernel_matches_scalar_reference() {
+        let (w, h) = (64usize, 48usize);
+        let data: Vec<u8> = (0..w * h)
+            .map(|i| ((i * 37 + (i / w) * 11 + (i ^ (i >> 3)) * 5) & 0xff) as u8)
+            .collect();
+
+        // Odd sides exercise both the 4-wide chunks and the scalar tail;
+        // the rectangles take the generic loop.
+        for (side, rows) in [
+            (1usize, 1usize),
+            (3, 3),
+            (7, 7),
+            (11, 11),
+            (15, 15),
+            (21, 21),
+            (21, 7),
+            (5, 15),
+        ] {
+            let n = side * rows;
+            let reference: Vec<f32> = (0..n).map(|i| (i * 7 % 251) as f32 + 0.25).collect();
+            let ix: Vec<f32> = (0..n).map(|i| (i as f32 * 0.37).sin() * 40.0).collect();
+            let iy: Vec<f32> = (0..n).map(|i| (i as f32 * 0.21).cos() * 40.0).collect();
+
+            for frac in [(0.0, 0.0), (0.3, 0.7), (0.99, 0.01)] {
+                let base = 5 * w + 9;
+                let expected = window_mismatch_scalar::<DYNAMIC>(
+                    &data, w, base, side, rows, frac, &reference, &ix, &iy,
+                );
+                let actual =
+                    window_mismatch(&data, w, base, (side, rows), frac, &reference, &ix, &iy);
+
+                assert!(close(expected.bx, actual.bx), "bx {side}x{rows} {frac:?}");
+                assert!(close(expected.by, actual.by), "by {side}x{rows} {frac:?}");
+                assert!(close(expected.abs_sum, actual.abs_sum), "abs {side}x{rows}");
+            }
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use std::cell::RefCell;
+
+/// Upper bound on idle buffers kept per pool, so a burst of odd-sized frames
+/// cannot pin an unbounded amount of memory.
+const MAX_IDLE: usize = 16;
+
+/// Free list of temporary image planes, keyed by capacity.
+///
+/// Detection and the allocating tracking entry points take their gradient,
+/// gradient-product and pyramid planes from here and hand them back when done,
+/// so a steady stream of same-sized frames stops allocating image storage
+/// after the first frame.
+pub(crate) struct BufferPool<T> {
+    idle: Vec<Vec<T>>,
+}
+
+impl<T: Copy + Default> BufferPool<T> {
+    pub(crate) const fn new() -> Self {
+        BufferPool { idle: Vec::new() }
+    }
+
+    /// Returns a buffer of exactly `len` elements set to `T::default()`,
+    /// reusing the smallest idle buffer whose capacity fits.
+    pub(crate) fn take(&mut self, len: usize) -> Vec<T> {
+        let best = self
+            .idle
+            .iter()
+            .enumerate()
+            .filter(|(_, buf)| buf.capacity() >= len)
+            .min_by_key(|(_, buf)| buf.capacity())
+            .map(|(i, _)| i);
+
+        let mut buf = match best {
+            Some(i) => self.idle.swap_remove(i),
+            None => Vec::with_capacity(len),
+        };
+        buf.clear();
+        buf.resize(len, T::default());
+        buf
+    }
+
+    /// Returns `buf` to the pool. When the pool is full the smallest idle
+    /// buffer is dropped instead, keeping the most reusable capacity around.
+    pub(crate) fn recycle(&mut self, buf: Vec<T>) {
+        if buf.capacity() == 0 {
+            return;
+        }
+        if self.idle.len() >= MAX_IDLE {
+            let smallest = self
+                .idle
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, b)| b.capacity())
+                .map(|(i, _)| i);
+            match smallest {
+                Some(i) if self.idle[i].capacity() < buf.capacity() => {
+                    self.idle.swap_remove(i);
+                }
+                _ => return,
+            }
+        }
+        self.idle.push(buf);
+    }
+}
+
+thread_local! {
+    static U8_POOL: RefCell<BufferPool<u8>> = const { RefCell::new(BufferPool::new()) };
+    static I16_POOL: RefCell<BufferPool<i16>> = const { RefCell::new(BufferPool::new()) };
+    static F32_POOL: RefCell<BufferPool<f32>> = const { RefCell::new(BufferPool::new()) };
+}
+
+/// Takes a zeroed `u8` plane of `len` pixels from this thread's pool.
+pub(crate) fn take_u8(len: usize) -> Vec<u8> {
+    U8_POOL.with(|pool| pool.borrow_mut().take(len))
+}
+
+/// Hands a `u8` plane back to this thread's pool.
+pub(crate) fn recycle_u8(buf: Vec<u8>) {
+    U8_POOL.with(|pool| pool.borrow_mut().recycle(buf));
+}
+
+/// Takes a zeroed `i16` plane of `len` pixels from this thread's pool.
+pub(crate) fn take_i16(len: usize) -> Vec<i16> {
+    I16_POOL.with(|pool| pool.borrow_mut().take(len))
+}
+
+/// Hands an `i16` plane back to this thread's pool.
+pub(crate) fn recycle_i16(buf: Vec<i16>) {
+    I16_POOL.with(|pool| pool.borrow_mut().recycle(buf));
+}
+
+/// Takes a zeroed `f32` plane of `len` pixels from this thread's pool.
+pub(crate) fn take_f32(len: usize) -> Vec<f32> {
+    F32_POOL.with(|pool| pool.borrow_mut().take(len))
+}
+
+/// Hands an `f32` plane back to this thread's pool.
+pub(crate) fn recycle_f32(buf: Vec<f32>) {
+    F32_POOL.with(|pool| pool.borrow_mut().recycle(buf));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn take_reuses_recycled_storage() {
+        let mut pool = BufferPool::<i16>::new();
+        let mut buf = pool.take(64);
+        buf[3] = 7;
+        let ptr = buf.as_ptr();
+        pool.recycle(buf);
+
+        // A smaller request fits into the recycled buffer and is re-zeroed.
+        let again = pool.take(48);
+        assert_eq!(again.as_ptr(), ptr);
+        assert_eq!(again.len(), 48);
+        assert!(again.iter().all(|&v| v == 0));
+    }
+
+    #[test]
+    fn take_prefers_tightest_fit() {
+        let mut pool = BufferPool::<u8>::new();
+        let big = pool.take(1000);
+        let small = pool.take(100);
+        let small_ptr = small.as_ptr();
+        pool.recycle(big);
+        pool.recycle(small);
+
+        let reused = pool.take(90);
+        assert_eq!(reused.as_ptr(), small_ptr);
+    }
+
+    #[test]
+    fn recycle_is_bounded() {
+        let mut pool = BufferPool::<u8>::new();
+        for len in 1..=(MAX_IDLE + 8) {
+            pool.recycle(vec![0; len]);
+        }
+        assert_eq!(pool.idle.len(), MAX_IDLE);
+        // The largest buffers survive.
+        assert!(pool.idle.iter().all(|b| b.capacity() > 8));
+    }
+}
@@ -1,4 +1,4 @@
-use image::{GrayImage, ImageBuffer, Luma};
+use image::GrayImage;
 #[cfg(target_arch = "aarch64")]
 use std::arch::aarch64::*;
 #[cfg(target_arch = "wasm32")]
@@ -14,26 +14,6 @@ const HORIZONTAL_SCHARR_3X3_OLD: [i32; 9] = [-3, 0, 3, -10, 0, 10, -3, 0, 3];
 #[allow(dead_code)]
 const VERTICAL_SCHARR_3X3_OLD: [i32; 9] = [-3, -10, -3, 0, 0, 0, 3, 10, 3];
 
-type GradientProduct = (
-    ImageBuffer<Luma<i16>, Vec<i16>>,
-    ImageBuffer<Luma<i16>, Vec<i16>>,
-);
-
-/// Computes signed Scharr gradients, allocating fresh output buffers.
-///
-/// Prefer [`compute_gradients_into`] on a hot path so the gradient buffers can
-/// be reused across frames.
-pub fn compute_gradients(img: &GrayImage) -> GradientProduct {
-    let (width, height) = img.dimensions();
-    let mut grad_x = vec![0i16; (width * height) as usize];
-    let mut grad_y = vec![0i16; (width * height) as usize];
-    compute_gradients_into(img, &mut grad_x, &mut grad_y);
-    (
-        ImageBuffer::from_vec(width, height, grad_x).unwrap(),
-        ImageBuffer::from_vec(width, height, grad_y).unwrap(),
-    )
-}
-
 /// Computes signed Scharr gradients into caller-provided buffers (length
 /// `width * height` each), performing no heap allocation.
 ///
@@ -99,6 +79,38 @@ pub fn compute_gradients_into(img: &GrayImage, grad_x: &mut [i16], grad_y: &mut
     );
 }
 
+/// Scharr gradients of the `width` x `height` region of `img` whose top-left
+/// pixel is `(x0, y0)`, read in place from the image buffer into
+/// `width * height` planes.
+///
+/// Matches [`compute_gradients_into`] on the cropped region, including its
+/// zero border, so scanning part of a frame needs no cropped copy.
+///
+/// # Panics
+/// Panics if the region does not lie inside `img`.
+pub fn compute_region_gradients_into(
+    img: &GrayImage,
+    (x0, y0): (u32, u32),
+    (width, height): (u32, u32),
+    grad_x: &mut [i16],
+    grad_y: &mut [i16],
+) {
+    assert!(
+        x0 + width <= img.width() && y0 + height <= img.height(),
+        "region must lie inside the image"
+    );
+    let stride = img.width() as usize;
+    compute_region_manual_into(
+        img.as_raw(),
+        y0 as usize * stride + x0 as usize,
+        stride,
+        (width as usize, height as usize),
+        (&HORIZONTAL_SCHARR_3X3_OLD, &VERTICAL_SCHARR_3X3_OLD),
+        grad_x,
+        grad_y,
+    );
+}
+
 // Scalar reference / fallback. Unused on wasm32 built with +simd128.
 #[allow(dead_code)]
 fn compute_gradients_manual_into(
@@ -109,31 +121,66 @@ fn compute_gradients_manual_into(
     grad_y: &mut [i16],
 ) {
     let (width, height) = img.dimensions();
-    let width = width as usize;
+    compute_region_manual_into(
+        img.as_raw(),
+        0,
+        width as usize,
+        (width as usize, height as usize),
+        (kernel_x, kernel_y),
+        grad_x,
+        grad_y,
+    );
+}
 
+// Convolves the `width` x `height` region starting at `src[origin]`, whose
+// rows are `stride` apart, into `width * height` planes.
+//
+// Indexes the raw pixel slice through precomputed row offsets instead of
+// `get_pixel`: this is the path non-SIMD WASM builds run every frame, and the
+// per-pixel accessor's bounds checks are not elided there.
+fn compute_region_manual_into(
+    src: &[u8],
+    origin: usize,
+    stride: usize,
+    (width, height): (usize, usize),
+    (kernel_x, kernel_y): (&[i32; 9], &[i32; 9]),
+    grad_x: &mut [i16],
+    grad_y: &mut [i16],
+) {
     // Borders are never written below, so clear the whole buffer first; this
     // also wipes any data left over from a previous (reused) frame.
     grad_x.fill(0);
     grad_y.fill(0);
 
-    if width < 3 || (height as usize) < 3 {
+    if width < 3 || height < 3 {
         return;
     }
+    assert!(
+        origin + (height - 1) * stride + width <= src.len(),
+        "region must lie inside the image"
+    );
 
     for y in 1..height - 1 {
-        for x in 1..width as u32 - 1 {
+        let row = origin + y * stride;
+        let rows = [row - stride, row, row + stride];
+
+        for x in 1..width - 1 {
             let mut gx: i32 = 0;
             let mut gy: i32 = 0;
 
-            for ky in 0..3 {
+            for (ky, &row) in rows.iter().enumerate() {
+                let base = row + x - 1;
                 for kx in 0..3 {
-                    let pixel = img.get_pixel(x + kx - 1, y + ky - 1)[0] as i32;
-                    gx += pixel * kernel_x[(ky * 3 + kx) as usize];
-                    gy += pixel * kernel_y[(ky * 3 + kx) as usize];
+                    // SAFETY: 1 <= y < height-1 and 1 <= x < width-1, so
+                    // base + kx <= origin + (height-1)*stride + width-1,
+                    // which the assert above keeps inside `src`.
+                    let pixel = unsafe { *src.get_unchecked(base + kx) } as i32;
+                    gx += pixel * kernel_x[ky * 3 + kx];
+                    gy += pixel * kernel_y[ky * 3 + kx];
                 }
             }
 
-            let idx = y as usize * width + x as usize;
+            let idx = y * width + x;
             grad_x[idx] = gx as i16;
             grad_y[idx] = gy as i16;
         }
@@ -450,6 +497,25 @@ unsafe fn load_u8x16_as_i16x8x2_simd(ptr: *const u8) -> (v128, v128) {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use image::{ImageBuffer, Luma};
+
+    type GradientProduct = (
+        ImageBuffer<Luma<i16>, Vec<i16>>,
+        ImageBuffer<Luma<i16>, Vec<i16>>,
+    );
+
+    /// Allocating convenience over [`compute_gradients_into`]; the library
+    /// itself always goes through pooled or reused buffers.
+    fn compute_gradients(img: &GrayImage) -> GradientProduct {
+        let (width, height) = img.dimensions();
+        let mut grad_x = vec![0i16; (width * height) as usize];
+        let mut grad_y = vec![0i16; (width * height) as usize];
+        compute_gradients_into(img, &mut grad_x, &mut grad_y);
+        (
+            ImageBuffer::from_vec(width, height, grad_x).unwrap(),
+            ImageBuffer::from_vec(width, height, grad_y).unwrap(),
+        )
+    }
 
     /// Allocating manual reference used by the equivalence test.
     fn compute_gradients_manual(
@@ -491,6 +557,21 @@ mod tests {
         assert_eq!(expected.1, actual.1, "vertical gradients differ");
     }
 
+    #[test]
+    fn region_gradients_match_the_cropped_image() {
+        let img = make_test_image(64, 48);
+        let (origin, size) = ((7, 5), (30, 21));
+        let crop = image::imageops::crop_imm(&img, origin.0, origin.1, size.0, size.1).to_image();
+        let expected = compute_gradients(&crop);
+
+        let n = (size.0 * size.1) as usize;
+        let (mut grad_x, mut grad_y) = (vec![0i16; n], vec![0i16; n]);
+        compute_region_gradients_into(&img, origin, size, &mut grad_x, &mut grad_y);
+
+        assert_eq!(expected.0.into_raw(), grad_x, "horizontal gradients differ");
+        assert_eq!(expected.1.into_raw(), grad_y, "vertical gradients differ");
+    }
+
     #[test]
     fn tiny_images_return_zero_gradients() {
         for (width, height) in [(0, 0), (1, 1), (2, 2), (2, 5), (5, 2)] {
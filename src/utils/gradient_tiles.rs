@@ -0,0 +1,418 @@
+use image::GrayImage;
+use std::ops::Range;
+
+use crate::utils::fast_gradients::compute_gradients_into;
+
+/// Side of a square gradient tile, in pixels.
+pub(crate) const TILE: usize = 32;
+
+/// Scharr gradient planes of one image, filled tile by tile on demand.
+///
+/// Tracking a sparse point set only ever reads the gradients under its
+/// windows, so instead of a full-frame pass per level the tracker asks for
+/// the footprint of each window via [`ensure`](Self::ensure) and only the
+/// tiles it touches are computed (once). Values are bit-identical to
+/// [`compute_gradients_into`]: zero on the one-pixel border, exact integer
+/// Scharr inside.
+///
+/// When the windows would cover a large part of the image anyway,
+/// [`reset`](Self::reset) computes the whole frame with the SIMD kernel up
+/// front instead.
+#[derive(Default)]
+pub(crate) struct TiledGradients {
+    width: usize,
+    height: usize,
+    tiles_x: usize,
+    ready: Vec<bool>,
+    grad_x: Vec<i16>,
+    grad_y: Vec<i16>,
+}
+
+impl TiledGradients {
+    /// Wraps existing plane storage (e.g. from the buffer pool).
+    pub(crate) fn with_planes(grad_x: Vec<i16>, grad_y: Vec<i16>) -> Self {
+        TiledGradients {
+            grad_x,
+            grad_y,
+            ..TiledGradients::default()
+        }
+    }
+
+    /// Releases the plane storage.
+    pub(crate) fn into_planes(self) -> (Vec<i16>, Vec<i16>) {
+        (self.grad_x, self.grad_y)
+    }
+
+    /// Starts a new image. `windows` windows of `window_side` pixels are
+    /// expected to be sampled; if their tiles would cover more than about half
+    /// of the image, the full frame is computed now and every tile is ready.
+    ///
+    /// The planes keep their capacity, so a sequence of same-sized (or
+    /// shrinking, e.g. pyramid levels) images does not allocate.
+    pub(crate) fn reset(&mut self, img: &GrayImage, windows: usize, window_side: usize) {
+        let (width, height) = (img.width() as usize, img.height() as usize);
+        let n = width * height;
+        self.width = width;
+        self.height = height;
+        self.tiles_x = width.div_ceil(TILE);
+        let n_tiles = self.tiles_x * height.div_ceil(TILE);
+
+        self.grad_x.resize(n, 0);
+        self.grad_y.resize(n, 0);
+
+        // A window's footprint straddles at most this many tiles per axis.
+        let tiles_per_axis = (window_side + 1).div_ceil(TILE) + 1;
+        let lazy_tiles = windows.saturating_mul(tiles_per_axis * tiles_per_axis);
+        let eager = lazy_tiles.saturating_mul(2) >= n_tiles;
+
+        if eager {
+            compute_gradients_into(img, &mut self.grad_x[..n], &mut self.grad_y[..n]);
+        }
+        self.ready.clear();
+        self.ready.resize(n_tiles, eager);
+    }
+
+    /// Makes sure gradients are available for the pixel rectangle
+    /// `xs` x `ys` (clamped to the image).
+    pub(crate) fn ensure(&mut self, img: &GrayImage, xs: Range<i64>, ys: Range<i64>) {
+        let clamp_x = |v: i64| v.clamp(0, self.width as i64) as usize;
+        let clamp_y = |v: i64| v.clamp(0, self.height as i64) as usize;
+        let (x0, x1) = (clamp_x(xs.start), clamp_x(xs.end));
+        let (y0, y1) = (clamp_y(ys.start), clamp_y(ys.end));
+        if x0 >= x1 || y0 >= y1 {
+            return;
+        }
+
+        for ty in y0 / TILE..=(y1 - 1) / TILE {
+            for tx in x0 / TILE..=(x1 - 1) / TILE {
+                let tile = ty * self.tiles_x + tx;
+                if self.ready[tile] {
+                    continue;
+                }
+                self.ready[tile] = true;
+
+                let cols = tx * TILE..((tx + 1) * TILE).min(self.width);
+                let rows = ty * TILE..((ty + 1) * TILE).min(self.height);
+                let origin = rows.start * self.width + cols.start;
+                compute_tile(
+                    img.as_raw(),
+                    self.width,
+                    self.height,
+                    cols,
+                    rows,
+                    &mut self.grad_x[origin..],
+                    &mut self.grad_y[origin..],
+                    self.width,
+                );
+            }
+        }
+    }
+
+    /// The gradient planes of the current image (`width * height` each).
+    /// Only tiles covered by [`ensure`](Self::ensure) (or an eager
+    /// [`reset`](Self::reset)) hold valid values.
+    pub(crate) fn planes(&self) -> GradientPlanes<'_> {
+        let n = self.width * self.height;
+        GradientPlanes::new(self.width, &self.grad_x[..n], &self.grad_y[..n])
+    }
+}
+
+/// Read access to one image's Scharr gradient planes, whatever their storage.
+pub(crate) trait GradientSource {
+    /// `(grad_x, grad_y)` at pixel `(x, y)`, in Scharr units.
+    ///
+    /// # Safety
+    /// `(x, y)` must lie inside the image.
+    unsafe fn get_unchecked(&self, x: usize, y: usize) -> (f32, f32);
+
+    /// Bounds-checked [`get_unchecked`](Self::get_unchecked).
+    fn get(&self, x: usize, y: usize) -> (f32, f32);
+}
+
+/// Full-precision `i16` gradient planes of an image `width` pixels wide.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct GradientPlanes<'a> {
+    pub(crate) width: usize,
+    pub(crate) grad_x: &'a [i16],
+    pub(crate) grad_y: &'a [i16],
+}
+
+impl<'a> GradientPlanes<'a> {
+    /// Wraps row-major planes of `width`-pixel rows.
+    pub(crate) fn new(width: usize, grad_x: &'a [i16], grad_y: &'a [i16]) -> Self {
+        GradientPlanes {
+            width,
+            grad_x,
+            grad_y,
+        }
+    }
+}
+
+impl GradientSource for GradientPlanes<'_> {
+    #[inline(always)]
+    unsafe fn get_unchecked(&self, x: usize, y: usize) -> (f32, f32) {
+        let i = y * self.width + x;
+        unsafe {
+            (
+                *self.grad_x.get_unchecked(i) as f32,
+                *self.grad_y.get_unchecked(i) as f32,
+            )
+        }
+    }
+
+    #[inline(always)]
+    fn get(&self, x: usize, y: usize) -> (f32, f32) {
+        assert!(x < self.width, "x out of bounds");
+        let i = y * self.width + x;
+        (self.grad_x[i] as f32, self.grad_y[i] as f32)
+    }
+}
+
+/// Scharr gradients of one image stored as `i8` with one scale per
+/// [`TILE`] x [`TILE`] tile and plane, half the size of the `i16` planes.
+///
+/// Each tile is computed into a tile-sized `i16` buffer and quantized with
+/// `scale = max |g| / 127`, so the error per sample is at most half a step
+/// of the tile's own range; flat tiles keep their small gradients precisely.
+#[derive(Default)]
+pub(crate) struct QuantizedGradients {
+    width: usize,
+    tiles_x: usize,
+    grad_x: Vec<i8>,
+    grad_y: Vec<i8>,
+    scale_x: Vec<f32>,
+    scale_y: Vec<f32>,
+}
+
+impl QuantizedGradients {
+    /// Computes and quantizes the gradients of `img`, reusing the storage of
+    /// a previous image of the same size.
+    pub(crate) fn quantize(&mut self, img: &GrayImage) {
+        let (width, height) = (img.width() as usize, img.height() as usize);
+        self.width = width;
+        self.tiles_x = width.div_ceil(TILE);
+        let n_tiles = self.tiles_x * height.div_ceil(TILE);
+
+        self.grad_x.resize(width * height, 0);
+        self.grad_y.resize(width * height, 0);
+        self.scale_x.resize(n_tiles, 0.0);
+        self.scale_y.resize(n_tiles, 0.0);
+
+        let mut tile_x = [0i16; TILE * TILE];
+        let mut tile_y = [0i16; TILE * TILE];
+        for tile in 0..n_tiles {
+            let (tx, ty) = (tile % self.tiles_x, tile / self.tiles_x);
+            let cols = tx * TILE..((tx + 1) * TILE).min(width);
+            let rows = ty * TILE..((ty + 1) * TILE).min(height);
+            compute_tile(
+                img.as_raw(),
+                width,
+                height,
+                cols.clone(),
+                rows.clone(),
+                &mut tile_x,
+                &mut tile_y,
+                TILE,
+            );
+
+            for (tile_plane, plane, scales) in [
+                (&tile_x, &mut self.grad_x, &mut self.scale_x),
+                (&tile_y, &mut self.grad_y, &mut self.scale_y),
+            ] {
+                let max_abs = rows
+                    .clone()
+                    .flat_map(|y| {
+                        let row = (y - rows.start) * TILE;
+                        &tile_plane[row..row + cols.len()]
+                    })
+                    .map(|g| g.unsigned_abs())
+                    .max()
+                    .unwrap_or(0);
+                let scale = max_abs as f32 / 127.0;
+                scales[tile] = scale;
+                let inv = if scale > 0.0 { 1.0 / scale } else { 0.0 };
+
+                for y in rows.clone() {
+                    let src = &tile_plane[(y - rows.start) * TILE..][..cols.len()];
+                    let dst = &mut plane[y * width + cols.start..][..cols.len()];
+                    for (q, &g) in dst.iter_mut().zip(src) {
+                        *q = (g as f32 * inv).round().clamp(-127.0, 127.0) as i8;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Index of the tile holding pixel `(x, y)`; [`TILE`] is a power of
+    /// two, so this costs two shifts instead of a division by the width.
+    #[inline(always)]
+    fn tile_of(&self, x: usize, y: usize) -> usize {
+        (y / TILE) * self.tiles_x + x / TILE
+    }
+}
+
+impl GradientSource for QuantizedGradients {
+    #[inline(always)]
+    unsafe fn get_unchecked(&self, x: usize, y: usize) -> (f32, f32) {
+        let (i, tile) = (y * self.width + x, self.tile_of(x, y));
+        unsafe {
+            (
+                *self.grad_x.get_unchecked(i) as f32 * *self.scale_x.get_unchecked(tile),
+                *self.grad_y.get_unchecked(i) as f32 * *self.scale_y.get_unchecked(tile),
+            )
+        }
+    }
+
+    fn get(&self, x: usize, y: usize) -> (f32, f32) {
+        assert!(x < self.width, "x out of bounds");
+        let (i, tile) = (y * self.width + x, self.tile_of(x, y));
+        (
+            self.grad_x[i] as f32 * self.scale_x[tile],
+            self.grad_y[i] as f32 * self.scale_y[tile],
+        )
+    }
+}
+
+/// Scalar Scharr over `cols` x `rows` of a `width` x `height` image, writing
+/// the same values as [`compute_gradients_into`]. The kernel is applied in its
+/// separable difference form, which is exact in integers.
+///
+/// Output pixel `(x, y)` goes to index
+/// `(y - rows.start) * dst_stride + (x - cols.start)` of `grad_x` / `grad_y`,
+/// so the destination can be a full plane (sliced at the tile origin, stride
+/// `width`) or a tile-sized buffer.
+#[allow(clippy::too_many_arguments)]
+fn compute_tile(
+    src: &[u8],
+    width: usize,
+    height: usize,
+    cols: Range<usize>,
+    rows: Range<usize>,
+    grad_x: &mut [i16],
+    grad_y: &mut [i16],
+    dst_stride: usize,
+) {
+    // Interior columns of this tile; the first and last image columns are
+    // border pixels.
+    let inner = cols.start.max(1)..cols.end.min(width.saturating_sub(1));
+    let tile_width = cols.len();
+
+    for y in rows.clone() {
+        let out = (y - rows.start) * dst_stride;
+        let (out_x, out_y) = (
+            &mut grad_x[out..out + tile_width],
+            &mut grad_y[out..out + tile_width],
+        );
+        if y == 0 || y + 1 >= height || inner.is_empty() {
+            out_x.fill(0);
+            out_y.fill(0);
+            continue;
+        }
+
+        for x in (cols.start..inner.start).chain(inner.end..cols.end) {
+            out_x[x - cols.start] = 0;
+            out_y[x - cols.start] = 0;
+        }
+
+        let row = y * width;
+        let (above, below) = (&src[row - width..row], &src[row + width..row + 2 * width]);
+        let middle = &src[row..row + width];
+        for x in inner.clone() {
+            let px = |line: &[u8], dx: usize| line[x + dx - 1] as i32;
+            let gx = 3 * (px(above, 2) - px(above, 0))
+                + 10 * (px(middle, 2) - px(middle, 0))
+                + 3 * (px(below, 2) - px(below, 0));
+            let gy = 3 * (px(below, 0) - px(above, 0))
+                + 10 * (px(below, 1) - px(above, 1))
+                + 3 * (px(below, 2) - px(above, 2));
+            out_x[x - cols.start] = gx as i16;
+            out_y[x - cols.start] = gy as i16;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Luma;
+
+    fn noise(width: u32, height: u32) -> GrayImage {
+        GrayImage::from_fn(width, height, |x, y| {
+            let mut s = (y * width + x).wrapping_mul(2654435761) ^ 0x9e3779b9;
+            s ^= s >> 15;
+            Luma([(s.wrapping_mul(0x85ebca6b) & 0xff) as u8])
+        })
+    }
+
+    #[test]
+    fn lazy_tiles_match_full_frame() {
+        for (w, h) in [(100u32, 70u32), (33, 64), (2, 9), (40, 1)] {
+            let img = noise(w, h);
+            let n = (w * h) as usize;
+            let (mut ex, mut ey) = (vec![0; n], vec![0; n]);
+            compute_gradients_into(&img, &mut ex, &mut ey);
+
+            let mut tiles = TiledGradients::default();
+            tiles.reset(&img, 0, 1);
+            // Rectangles at the corners, straddling tile edges and past the
+            // image bounds.
+            for (xs, ys) in [(-5..8, -5..8), (28..70, 30..36), (90..140, 60..90)] {
+                tiles.ensure(&img, xs, ys);
+            }
+
+            let GradientPlanes {
+                grad_x: gx,
+                grad_y: gy,
+                ..
+            } = tiles.planes();
+            for (tile, _) in tiles.ready.iter().enumerate().filter(|(_, r)| **r) {
+                let (tx, ty) = (tile % tiles.tiles_x, tile / tiles.tiles_x);
+                for y in ty * TILE..((ty + 1) * TILE).min(h as usize) {
+                    for x in tx * TILE..((tx + 1) * TILE).min(w as usize) {
+                        let i = y * w as usize + x;
+                        assert_eq!((gx[i], gy[i]), (ex[i], ey[i]), "({x}, {y}) at {w}x{h}");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn quantized_gradients_stay_within_half_a_step() {
+        let (w, h) = (70u32, 45u32);
+        let img = noise(w, h);
+        let n = (w * h) as usize;
+        let (mut ex, mut ey) = (vec![0; n], vec![0; n]);
+        compute_gradients_into(&img, &mut ex, &mut ey);
+
+        let mut quantized = QuantizedGradients::default();
+        quantized.quantize(&img);
+
+        let full = GradientPlanes::new(w as usize, &ex, &ey);
+        for (x, y) in (0..h as usize).flat_map(|y| (0..w as usize).map(move |x| (x, y))) {
+            let tile = quantized.tile_of(x, y);
+            let (qx, qy) = quantized.get(x, y);
+            let (gx, gy) = full.get(x, y);
+            assert!(
+                (qx - gx).abs() <= quantized.scale_x[tile] * 0.5 + 1e-3,
+                "x at ({x}, {y})"
+            );
+            assert!(
+                (qy - gy).abs() <= quantized.scale_y[tile] * 0.5 + 1e-3,
+                "y at ({x}, {y})"
+            );
+        }
+    }
+
+    #[test]
+    fn dense_reset_computes_everything() {
+        let img = noise(64, 64);
+        let mut tiles = TiledGradients::default();
+        tiles.reset(&img, 100, 21);
+        assert!(tiles.ready.iter().all(|&r| r));
+
+        let (mut ex, mut ey) = (vec![0; 64 * 64], vec![0; 64 * 64]);
+        compute_gradients_into(&img, &mut ex, &mut ey);
+        assert_eq!(tiles.planes(), GradientPlanes::new(64, &ex, &ey));
+    }
+}
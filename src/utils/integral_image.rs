@@ -0,0 +1,131 @@
+use image::{GrayImage, ImageBuffer, Luma};
+
+/// Replaces every pixel by the mean of the `block_size` x `block_size` window
+/// centred on it. Near the border the window is clipped to the image and the
+/// mean is taken over the pixels inside, like the 3x3 box filter.
+///
+/// Window sums come from a summed-area table, so the cost per pixel does not
+/// depend on `block_size`. Sums are accumulated in `i64`, which cannot
+/// overflow for any `i16` plane that fits in memory; the integer mean is
+/// truncated towards zero.
+pub fn box_mean_in_place(image: &mut ImageBuffer<Luma<i16>, Vec<i16>>, block_size: u32) {
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    let radius = (block_size / 2) as usize;
+    let data: &mut [i16] = image;
+
+    // table[(y + 1) * stride + (x + 1)] = sum of data[..=y][..=x]
+    let stride = width + 1;
+    let mut table = vec![0i64; stride * (height + 1)];
+    for y in 0..height {
+        let mut row_sum = 0i64;
+        for x in 0..width {
+            row_sum += data[y * width + x] as i64;
+            table[(y + 1) * stride + x + 1] = table[y * stride + x + 1] + row_sum;
+        }
+    }
+
+    for y in 0..height {
+        let (y0, y1) = (y.saturating_sub(radius), (y + radius + 1).min(height));
+        for x in 0..width {
+            let (x0, x1) = (x.saturating_sub(radius), (x + radius + 1).min(width));
+            let sum = table[y1 * stride + x1] - table[y0 * stride + x1] - table[y1 * stride + x0]
+                + table[y0 * stride + x0];
+            let count = ((y1 - y0) * (x1 - x0)) as i64;
+            data[y * width + x] = (sum / count) as i16;
+        }
+    }
+}
+
+/// Summed-area table of the 0 pixels of a mask, telling in constant time
+/// whether a box contains any of them.
+#[derive(Debug, Clone, Default)]
+pub struct ExclusionTable {
+    width: usize,
+    height: usize,
+    // table[(y + 1) * (width + 1) + (x + 1)] = 0 pixels in mask[..=y][..=x]
+    table: Vec<u32>,
+}
+
+impl ExclusionTable {
+    pub fn new(mask: &GrayImage) -> Self {
+        let (width, height) = (mask.width() as usize, mask.height() as usize);
+        let stride = width + 1;
+        let mut table = vec![0u32; stride * (height + 1)];
+        for (y, row) in mask.as_raw().chunks_exact(width.max(1)).enumerate() {
+            let mut row_sum = 0u32;
+            for (x, &value) in row.iter().enumerate() {
+                row_sum += (value == 0) as u32;
+                table[(y + 1) * stride + x + 1] = table[y * stride + x + 1] + row_sum;
+            }
+        }
+        ExclusionTable {
+            width,
+            height,
+            table,
+        }
+    }
+
+    pub fn dimensions(&self) -> (u32, u32) {
+        (self.width as u32, self.height as u32)
+    }
+
+    /// Whether the box `x0..=x1` x `y0..=y1`, clipped to the mask, holds a 0
+    /// pixel.
+    pub fn any_excluded(&self, (x0, y0): (i64, i64), (x1, y1): (i64, i64)) -> bool {
+        let clip = |v: i64, len: usize| v.clamp(0, len as i64) as usize;
+        let (x0, x1) = (clip(x0, self.width), clip(x1 + 1, self.width));
+        let (y0, y1) = (clip(y0, self.height), clip(y1 + 1, self.height));
+        if x0 >= x1 || y0 >= y1 {
+            return false;
+        }
+        let stride = self.width + 1;
+        let t = &self.table;
+        t[y1 * stride + x1] + t[y0 * stride + x0] != t[y0 * stride + x1] + t[y1 * stride + x0]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exclusion_table_finds_zero_pixels() {
+        let mut mask = GrayImage::from_pixel(9, 7, Luma([255]));
+        mask.put_pixel(4, 3, Luma([0]));
+        let table = ExclusionTable::new(&mask);
+        assert!(table.any_excluded((4, 3), (4, 3)));
+        assert!(table.any_excluded((-5, -5), (4, 3)));
+        assert!(table.any_excluded((4, 3), (20, 20)));
+        assert!(!table.any_excluded((5, 0), (8, 6)));
+        assert!(!table.any_excluded((0, 4), (8, 6)));
+        assert!(!table.any_excluded((9, 0), (12, 6)), "beyond the mask");
+    }
+
+    #[test]
+    fn matches_naive_clipped_mean() {
+        let (w, h) = (23u32, 17u32);
+        let source = ImageBuffer::from_fn(w, h, |x, y| {
+            Luma([((x * 7919 + y * 104729) % 16129) as i16 - 8000])
+        });
+
+        for block_size in [3u32, 5, 9, 31] {
+            let r = (block_size / 2) as i64;
+            let mut filtered = source.clone();
+            box_mean_in_place(&mut filtered, block_size);
+
+            for y in 0..h as i64 {
+                for x in 0..w as i64 {
+                    let (mut sum, mut count) = (0i64, 0i64);
+                    for sy in (y - r).max(0)..=(y + r).min(h as i64 - 1) {
+                        for sx in (x - r).max(0)..=(x + r).min(w as i64 - 1) {
+                            sum += source.get_pixel(sx as u32, sy as u32)[0] as i64;
+                            count += 1;
+                        }
+                    }
+                    let got = filtered.get_pixel(x as u32, y as u32)[0];
+                    assert_eq!(got, (sum / count) as i16, "({x}, {y}) block {block_size}");
+                }
+            }
+        }
+    }
+}
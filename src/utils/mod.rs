@@ -1,2 +1,9 @@
+pub mod bilinear_window;
 pub mod box_filter_3x3;
+pub mod buffer_pool;
 pub mod fast_gradients;
+pub mod gradient_tiles;
+pub mod integral_image;
+pub mod median;
+pub mod morphology;
+pub mod sobel;
@@ -0,0 +1,103 @@
+use image::GrayImage;
+
+/// Grows every non-zero region of a binary mask by `radius` pixels, using a
+/// `(2 * radius + 1)` square structuring element clipped to the image.
+/// Non-zero output pixels are set to 255.
+pub fn dilate_in_place(mask: &mut GrayImage, radius: u32) {
+    morph_in_place(mask, radius, true);
+}
+
+/// Shrinks every non-zero region of a binary mask by `radius` pixels, using a
+/// `(2 * radius + 1)` square structuring element clipped to the image, so
+/// regions touching the border are not eroded from outside.
+/// Non-zero output pixels are set to 255.
+pub fn erode_in_place(mask: &mut GrayImage, radius: u32) {
+    morph_in_place(mask, radius, false);
+}
+
+/// Separable square min/max: a horizontal pass then a vertical pass, each
+/// counting the set pixels in a sliding window.
+fn morph_in_place(mask: &mut GrayImage, radius: u32, dilate: bool) {
+    let (width, height) = (mask.width() as usize, mask.height() as usize);
+    let radius = radius as usize;
+    if radius == 0 || width == 0 || height == 0 {
+        return;
+    }
+
+    let data: &mut [u8] = mask;
+    let mut line = Vec::with_capacity(width.max(height));
+
+    // A window with any set pixel dilates; only a fully set one survives
+    // erosion.
+    let mut pass = |len: usize, index: &dyn Fn(usize) -> usize, data: &mut [u8]| {
+        line.clear();
+        line.extend((0..len).map(|i| data[index(i)] != 0));
+        let mut count = line[..radius.min(len)].iter().filter(|&&v| v).count();
+        for i in 0..len {
+            if i + radius < len && line[i + radius] {
+                count += 1;
+            }
+            if i > radius && line[i - radius - 1] {
+                count -= 1;
+            }
+            let window = (i + radius + 1).min(len) - i.saturating_sub(radius);
+            let set = if dilate { count > 0 } else { count == window };
+            data[index(i)] = if set { 255 } else { 0 };
+        }
+    };
+
+    for y in 0..height {
+        pass(width, &|x| y * width + x, data);
+    }
+    for x in 0..width {
+        pass(height, &|y| y * width + x, data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Luma;
+
+    fn naive(mask: &GrayImage, radius: i64, dilate: bool) -> GrayImage {
+        let (w, h) = (mask.width() as i64, mask.height() as i64);
+        GrayImage::from_fn(w as u32, h as u32, |x, y| {
+            let mut any = false;
+            let mut all = true;
+            for sy in (y as i64 - radius).max(0)..=(y as i64 + radius).min(h - 1) {
+                for sx in (x as i64 - radius).max(0)..=(x as i64 + radius).min(w - 1) {
+                    let set = mask.get_pixel(sx as u32, sy as u32)[0] != 0;
+                    any |= set;
+                    all &= set;
+                }
+            }
+            let set = if dilate { any } else { all };
+            Luma([255 * set as u8])
+        })
+    }
+
+    #[test]
+    fn matches_naive_square_morphology() {
+        let mask = GrayImage::from_fn(29, 17, |x, y| {
+            let set = (x * 7 + y * 13) % 5 < 3 || (10..20).contains(&x);
+            Luma([200 * set as u8])
+        });
+        for radius in [1u32, 2, 4] {
+            let mut dilated = mask.clone();
+            dilate_in_place(&mut dilated, radius);
+            assert_eq!(
+                dilated,
+                naive(&mask, radius as i64, true),
+                "dilate r={radius}"
+            );
+
+            let mut eroded = mask.clone();
+            erode_in_place(&mut eroded, radius);
+            assert_eq!(
+                eroded,
+                naive(&mask, radius as i64, false),
+                "erode r={radius}"
+            );
+        }
+    }
+}
@@ -0,0 +1,124 @@
+use image::GrayImage;
+
+/// Computes signed Sobel gradients with a `aperture` x `aperture` kernel
+/// (3, 5 or 7) into caller-provided buffers (length `width * height` each).
+///
+/// The kernels are the binomial smoothing / derivative pairs OpenCV uses, and
+/// the output is rescaled to the gain of [`compute_gradients_into`], so a unit
+/// intensity ramp reads 32 whatever the aperture and the planes can be fed to
+/// the same downstream code. Pixels closer than `aperture / 2` to the border
+/// are set to zero.
+///
+/// [`compute_gradients_into`]: super::fast_gradients::compute_gradients_into
+pub fn compute_sobel_gradients_into(
+    img: &GrayImage,
+    aperture: u32,
+    grad_x: &mut [i16],
+    grad_y: &mut [i16],
+) {
+    let (smooth, derivative, gain): (&[i32], &[i32], i32) = match aperture {
+        3 => (&[1, 2, 1], &[-1, 0, 1], 8),
+        5 => (&[1, 4, 6, 4, 1], &[-1, -2, 0, 2, 1], 128),
+        7 => (&[1, 6, 15, 20, 15, 6, 1], &[-1, -4, -5, 0, 5, 4, 1], 2048),
+        _ => panic!("Sobel aperture must be 3, 5 or 7"),
+    };
+
+    let (width, height) = img.dimensions();
+    let (width, height) = (width as usize, height as usize);
+    let radius = aperture as usize / 2;
+
+    grad_x.fill(0);
+    grad_y.fill(0);
+    if width <= 2 * radius || height <= 2 * radius {
+        return;
+    }
+
+    // Vertical pass: smoothed columns feed the x derivative, differentiated
+    // columns the y derivative.
+    let src = img.as_raw();
+    let mut smoothed = vec![0i32; width * height];
+    let mut differentiated = vec![0i32; width * height];
+    for y in radius..height - radius {
+        for x in 0..width {
+            let (mut s, mut d) = (0, 0);
+            for k in 0..aperture as usize {
+                let pixel = src[(y + k - radius) * width + x] as i32;
+                s += pixel * smooth[k];
+                d += pixel * derivative[k];
+            }
+            smoothed[y * width + x] = s;
+            differentiated[y * width + x] = d;
+        }
+    }
+
+    // Horizontal pass, rescaled to the Scharr gain of 32.
+    for y in radius..height - radius {
+        for x in radius..width - radius {
+            let (mut gx, mut gy) = (0, 0);
+            for k in 0..aperture as usize {
+                let i = y * width + x + k - radius;
+                gx += smoothed[i] * derivative[k];
+                gy += differentiated[i] * smooth[k];
+            }
+            grad_x[y * width + x] = (gx * 32 / gain) as i16;
+            grad_y[y * width + x] = (gy * 32 / gain) as i16;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::fast_gradients::compute_gradients_into;
+    use image::Luma;
+
+    #[test]
+    fn ramp_gain_matches_scharr() {
+        let (w, h) = (24u32, 20u32);
+        let n = (w * h) as usize;
+        let ramp_x = GrayImage::from_fn(w, h, |x, y| Luma([(3 * x + y) as u8]));
+
+        let (mut sx, mut sy) = (vec![0i16; n], vec![0i16; n]);
+        compute_gradients_into(&ramp_x, &mut sx, &mut sy);
+        let centre = (h / 2 * w + w / 2) as usize;
+
+        for aperture in [3u32, 5, 7] {
+            let (mut gx, mut gy) = (vec![0i16; n], vec![0i16; n]);
+            compute_sobel_gradients_into(&ramp_x, aperture, &mut gx, &mut gy);
+            assert_eq!((gx[centre], gy[centre]), (sx[centre], sy[centre]));
+            assert_eq!((gx[centre], gy[centre]), (96, 32));
+
+            // Border band of `aperture / 2` pixels stays zero.
+            let r = aperture / 2;
+            assert_eq!(gx[((r - 1) * w + w / 2) as usize], 0);
+            assert_ne!(gx[(r * w + w / 2) as usize], 0);
+        }
+    }
+
+    #[test]
+    fn matches_direct_convolution() {
+        let (w, h) = (19u32, 15u32);
+        let n = (w * h) as usize;
+        let img = GrayImage::from_fn(w, h, |x, y| Luma([((x * 37 + y * 91) % 251) as u8]));
+
+        let (mut gx, mut gy) = (vec![0i16; n], vec![0i16; n]);
+        compute_sobel_gradients_into(&img, 5, &mut gx, &mut gy);
+
+        let smooth = [1i32, 4, 6, 4, 1];
+        let derivative = [-1i32, -2, 0, 2, 1];
+        for y in 2..h as usize - 2 {
+            for x in 2..w as usize - 2 {
+                let (mut ex, mut ey) = (0i32, 0i32);
+                for ky in 0..5 {
+                    for kx in 0..5 {
+                        let p = img.get_pixel((x + kx - 2) as u32, (y + ky - 2) as u32)[0] as i32;
+                        ex += p * smooth[ky] * derivative[kx];
+                        ey += p * derivative[ky] * smooth[kx];
+                    }
+                }
+                let i = y * w as usize + x;
+                assert_eq!((gx[i], gy[i]), ((ex / 4) as i16, (ey / 4) as i16));
+            }
+        }
+    }
+}
@@ -0,0 +1,579 @@
+//! Drawing tracking results onto frames at any output scale.
+//!
+//! Tracking often runs on a downscaled or grayscale copy of the frame (see
+//! [`BudgetScale`](crate::BudgetScale)), while the overlay belongs on the
+//! original color frame. The functions here take an [`OverlayScale`] from
+//! processing to canvas coordinates and draw antialiased, so sub-pixel
+//! positions and short flow vectors stay visible instead of snapping to the
+//! pixel grid. [`draw_trajectories`] renders the recent path of every track
+//! kept in a [`TrackHistory`].
+//!
+//! For dashboards that show where motion happens rather than individual
+//! tracks, [`flow_magnitude_heatmap`] and [`track_density_heatmap`] splat the
+//! sparse points into a dense, color-mapped image.
+
+use std::collections::{BTreeMap, VecDeque};
+
+use image::{Rgb, RgbImage};
+
+use crate::lk::{TrackResult, TrackStatus};
+use crate::utils::buffer_pool::{recycle_f32, take_f32};
+
+/// Color of tracked flow vectors.
+pub const TRACKED_COLOR: Rgb<u8> = Rgb([0, 255, 0]);
+/// Color of the markers at tracked positions.
+pub const MARKER_COLOR: Rgb<u8> = Rgb([255, 0, 0]);
+/// Color of the markers of lost points.
+pub const LOST_COLOR: Rgb<u8> = Rgb([128, 128, 128]);
+
+/// Mapping from processing coordinates to canvas coordinates, with the
+/// crate's pixel-center convention.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OverlayScale {
+    factor: (f32, f32),
+}
+
+impl OverlayScale {
+    /// Processing and canvas coordinates coincide.
+    pub const IDENTITY: OverlayScale = OverlayScale { factor: (1.0, 1.0) };
+
+    /// Canvas pixels per processing pixel along x and y, e.g. `(2.0, 2.0)`
+    /// when processing ran at half resolution.
+    pub fn new(x: f32, y: f32) -> Self {
+        OverlayScale { factor: (x, y) }
+    }
+
+    /// The scale from a `processing` size to a `canvas` size.
+    pub fn between(processing: (u32, u32), canvas: (u32, u32)) -> Self {
+        Self::new(
+            canvas.0 as f32 / processing.0 as f32,
+            canvas.1 as f32 / processing.1 as f32,
+        )
+    }
+
+    /// Maps a processing-resolution point to canvas coordinates.
+    pub fn apply(&self, (x, y): (f32, f32)) -> (f32, f32) {
+        let (fx, fy) = self.factor;
+        ((x + 0.5) * fx - 0.5, (y + 0.5) * fy - 0.5)
+    }
+}
+
+/// Draws one tracking step: a flow vector from every tracked point's previous
+/// position to its new one with a marker at the end, and a marker at the
+/// previous position of every lost point.
+///
+/// Positions are mapped with `scale`; line widths and marker sizes are in
+/// canvas pixels, so the overlay reads the same at every scale.
+///
+/// # Panics
+/// Panics if `results` does not have one entry per `prev_point`.
+pub fn draw_tracks(
+    canvas: &mut RgbImage,
+    prev_points: &[(f32, f32)],
+    results: &[TrackResult],
+    scale: OverlayScale,
+) {
+    assert_eq!(
+        prev_points.len(),
+        results.len(),
+        "results must have one entry per prev_point"
+    );
+    for (&prev, result) in prev_points.iter().zip(results) {
+        let from = scale.apply(prev);
+        if result.status == TrackStatus::Tracked {
+            let to = scale.apply(result.pos);
+            draw_line(canvas, from, to, TRACKED_COLOR);
+            draw_cross(canvas, to, 2.0, MARKER_COLOR);
+        } else {
+            draw_cross(canvas, from, 2.0, LOST_COLOR);
+        }
+    }
+}
+
+/// Draws an antialiased one-pixel line between two canvas points.
+///
+/// Every pixel is blended with `color` by its coverage, falling off linearly
+/// with its center's distance from the segment.
+pub fn draw_line(canvas: &mut RgbImage, from: (f32, f32), to: (f32, f32), color: Rgb<u8>) {
+    stroke(canvas, from, to, color, 1.0);
+}
+
+/// [`draw_line`] with every pixel's coverage scaled by `opacity`.
+fn stroke(canvas: &mut RgbImage, from: (f32, f32), to: (f32, f32), color: Rgb<u8>, opacity: f32) {
+    let (width, height) = canvas.dimensions();
+    if width == 0 || height == 0 {
+        return;
+    }
+    let x_range = from.0.min(to.0).floor() - 1.0..=from.0.max(to.0).ceil() + 1.0;
+    let y_range = from.1.min(to.1).floor() - 1.0..=from.1.max(to.1).ceil() + 1.0;
+    let clamp = |v: f32, max: u32| v.clamp(0.0, (max - 1) as f32) as u32;
+
+    let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+    let length_sq = dx * dx + dy * dy;
+    for y in clamp(*y_range.start(), height)..=clamp(*y_range.end(), height) {
+        for x in clamp(*x_range.start(), width)..=clamp(*x_range.end(), width) {
+            let (px, py) = (x as f32 - from.0, y as f32 - from.1);
+            let t = if length_sq > 0.0 {
+                ((px * dx + py * dy) / length_sq).clamp(0.0, 1.0)
+            } else {
+                0.0
+            };
+            let distance = (px - t * dx).hypot(py - t * dy);
+            blend(canvas, x, y, color, (1.0 - distance) * opacity);
+        }
+    }
+}
+
+/// Draws an antialiased `+` marker with arms of `radius` canvas pixels.
+pub fn draw_cross(canvas: &mut RgbImage, (x, y): (f32, f32), radius: f32, color: Rgb<u8>) {
+    draw_line(canvas, (x - radius, y), (x + radius, y), color);
+    draw_line(canvas, (x, y - radius), (x, y + radius), color);
+}
+
+/// Recent positions of every live track, keyed by a caller-assigned ID, for
+/// [`draw_trajectories`].
+///
+/// The crate's tracking functions work on plain point lists, so the caller
+/// owns the track identities: after every frame, [`record`](Self::record)
+/// the IDs and positions of the tracks still alive. Tracks missing from a
+/// frame are ended and forgotten.
+#[derive(Debug, Clone, Default)]
+pub struct TrackHistory {
+    length: usize,
+    trails: BTreeMap<u64, VecDeque<(f32, f32)>>,
+}
+
+impl TrackHistory {
+    /// History keeping the last `length` positions of every track.
+    pub fn new(length: usize) -> Self {
+        TrackHistory {
+            length,
+            trails: BTreeMap::new(),
+        }
+    }
+
+    /// Appends one frame: `positions[i]` is the position of track `ids[i]`.
+    /// Tracks not listed are ended.
+    ///
+    /// # Panics
+    /// Panics if `ids` and `positions` differ in length.
+    pub fn record(&mut self, ids: &[u64], positions: &[(f32, f32)]) {
+        assert_eq!(
+            ids.len(),
+            positions.len(),
+            "positions must have one entry per id"
+        );
+        let mut trails = std::mem::take(&mut self.trails);
+        for (&id, &pos) in ids.iter().zip(positions) {
+            let mut trail = trails.remove(&id).unwrap_or_default();
+            trail.push_back(pos);
+            while trail.len() > self.length {
+                trail.pop_front();
+            }
+            self.trails.insert(id, trail);
+        }
+    }
+
+    /// Positions of track `id`, oldest first.
+    pub fn trail(&self, id: u64) -> Option<&VecDeque<(f32, f32)>> {
+        self.trails.get(&id)
+    }
+
+    /// Every live track with its positions, oldest first, in ID order.
+    pub fn trails(&self) -> impl Iterator<Item = (u64, &VecDeque<(f32, f32)>)> {
+        self.trails.iter().map(|(&id, trail)| (id, trail))
+    }
+
+    /// Number of live tracks.
+    pub fn len(&self) -> usize {
+        self.trails.len()
+    }
+
+    /// Whether no track is alive.
+    pub fn is_empty(&self) -> bool {
+        self.trails.is_empty()
+    }
+}
+
+/// Draws every track of `history` as a polyline in its [`id_color`], fading
+/// from transparent at the oldest position to opaque at the newest.
+///
+/// Positions are mapped with `scale` like in [`draw_tracks`].
+pub fn draw_trajectories(canvas: &mut RgbImage, history: &TrackHistory, scale: OverlayScale) {
+    for (id, trail) in history.trails() {
+        let color = id_color(id);
+        let segments = trail.len().saturating_sub(1);
+        for (k, (&from, &to)) in trail.iter().zip(trail.iter().skip(1)).enumerate() {
+            let opacity = (k + 1) as f32 / segments as f32;
+            stroke(canvas, scale.apply(from), scale.apply(to), color, opacity);
+        }
+    }
+}
+
+/// A saturated color derived from a track ID, stable across frames and runs
+/// and spread so that consecutive IDs differ clearly.
+pub fn id_color(id: u64) -> Rgb<u8> {
+    // SplitMix64 finalizer, so nearby IDs land on unrelated hues.
+    let mut z = id.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^= z >> 31;
+    let hue = (z >> 40) as f32 / (1u64 << 24) as f32 * 6.0;
+
+    // HSV with saturation 0.85 and value 1.
+    let (low, f) = (255.0 * 0.15, hue.fract());
+    let rising = low + (255.0 - low) * f;
+    let falling = 255.0 - (255.0 - low) * f;
+    let [r, g, b] = match hue as u32 {
+        0 => [255.0, rising, low],
+        1 => [falling, 255.0, low],
+        2 => [low, 255.0, rising],
+        3 => [low, falling, 255.0],
+        4 => [rising, low, 255.0],
+        _ => [255.0, low, falling],
+    };
+    Rgb([r.round() as u8, g.round() as u8, b.round() as u8])
+}
+
+/// Blends `color` into pixel `(x, y)` with opacity `alpha`, clamped to
+/// `[0, 1]`.
+fn blend(canvas: &mut RgbImage, x: u32, y: u32, color: Rgb<u8>, alpha: f32) {
+    let alpha = alpha.clamp(0.0, 1.0);
+    if alpha == 0.0 {
+        return;
+    }
+    let pixel = canvas.get_pixel_mut(x, y);
+    for (channel, &target) in pixel.0.iter_mut().zip(&color.0) {
+        let mixed = *channel as f32 + (target as f32 - *channel as f32) * alpha;
+        *channel = mixed.round() as u8;
+    }
+}
+
+/// Mapping from a value in `[0, 1]` to a color.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Colormap {
+    /// Black to white.
+    Gray,
+    /// Black through red and yellow to white.
+    Hot,
+    /// Blue through cyan, yellow and red, like MATLAB's `jet`.
+    Jet,
+    /// Perceptually uniform dark purple through teal to yellow.
+    #[default]
+    Viridis,
+}
+
+/// Viridis sampled at 0, 1/4, 1/2, 3/4 and 1.
+const VIRIDIS: [[f32; 3]; 5] = [
+    [68.0, 1.0, 84.0],
+    [59.0, 82.0, 139.0],
+    [33.0, 145.0, 140.0],
+    [94.0, 201.0, 98.0],
+    [253.0, 231.0, 37.0],
+];
+
+impl Colormap {
+    /// Color of `value`, clamped to `[0, 1]`.
+    pub fn map(&self, value: f32) -> Rgb<u8> {
+        let v = if value.is_nan() {
+            0.0
+        } else {
+            value.clamp(0.0, 1.0)
+        };
+        let unit = |c: f32| (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+        match self {
+            Colormap::Gray => Rgb([unit(v); 3]),
+            Colormap::Hot => Rgb([unit(3.0 * v), unit(3.0 * v - 1.0), unit(3.0 * v - 2.0)]),
+            Colormap::Jet => Rgb([
+                unit(1.5 - (4.0 * v - 3.0).abs()),
+                unit(1.5 - (4.0 * v - 2.0).abs()),
+                unit(1.5 - (4.0 * v - 1.0).abs()),
+            ]),
+            Colormap::Viridis => {
+                let t = v * (VIRIDIS.len() - 1) as f32;
+                let i = (t as usize).min(VIRIDIS.len() - 2);
+                let f = t - i as f32;
+                let (a, b) = (VIRIDIS[i], VIRIDIS[i + 1]);
+                Rgb(std::array::from_fn(|c| {
+                    (a[c] + (b[c] - a[c]) * f).round() as u8
+                }))
+            }
+        }
+    }
+}
+
+/// Settings of [`flow_magnitude_heatmap`] and [`track_density_heatmap`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HeatmapParams {
+    /// Colormap of the output.
+    pub colormap: Colormap,
+    /// Standard deviation in heatmap pixels of the Gaussian each point is
+    /// splatted with.
+    pub sigma: f32,
+    /// Value mapped to the top of the colormap; `None` uses the largest value
+    /// of the heatmap, so every frame spans the full colormap.
+    pub max_value: Option<f32>,
+}
+
+impl Default for HeatmapParams {
+    fn default() -> Self {
+        HeatmapParams {
+            colormap: Colormap::default(),
+            sigma: 8.0,
+            max_value: None,
+        }
+    }
+}
+
+/// Heatmap of the flow magnitude around the tracked points of one tracking
+/// step.
+///
+/// Every [`TrackStatus::Tracked`] point is splatted at its previous position,
+/// mapped with `scale` onto a `size` heatmap, with the length of its flow
+/// vector in processing pixels. A pixel's value is the Gaussian-weighted mean
+/// magnitude of the points around it, fading to zero where the summed weight
+/// drops below that of a single point's center, so areas without tracks stay
+/// cold.
+///
+/// # Panics
+/// Panics if `results` does not have one entry per `prev_point`.
+pub fn flow_magnitude_heatmap(
+    size: (u32, u32),
+    prev_points: &[(f32, f32)],
+    results: &[TrackResult],
+    scale: OverlayScale,
+    params: &HeatmapParams,
+) -> RgbImage {
+    assert_eq!(
+        prev_points.len(),
+        results.len(),
+        "results must have one entry per prev_point"
+    );
+    let len = (size.0 * size.1) as usize;
+    let mut weights = take_f32(len);
+    let mut values = take_f32(len);
+    for (&prev, result) in prev_points.iter().zip(results) {
+        if result.status == TrackStatus::Tracked {
+            let magnitude = (result.pos.0 - prev.0).hypot(result.pos.1 - prev.1);
+            splat(
+                &mut weights,
+                size,
+                scale.apply(prev),
+                params.sigma,
+                |i, w| {
+                    values[i] += w * magnitude;
+                },
+            );
+        }
+    }
+    for (value, &weight) in values.iter_mut().zip(&weights) {
+        *value /= weight.max(1.0);
+    }
+    let heatmap = colorize(&values, size, params);
+    recycle_f32(weights);
+    recycle_f32(values);
+    heatmap
+}
+
+/// Heatmap of the density of `points`, e.g. the tracked positions of one
+/// frame or of a window of frames.
+///
+/// Every point is mapped with `scale` onto a `size` heatmap and splatted with
+/// a Gaussian of peak 1, so a pixel's value is roughly the number of points
+/// within `sigma` of it.
+pub fn track_density_heatmap(
+    size: (u32, u32),
+    points: &[(f32, f32)],
+    scale: OverlayScale,
+    params: &HeatmapParams,
+) -> RgbImage {
+    let mut density = take_f32((size.0 * size.1) as usize);
+    for &point in points {
+        splat(
+            &mut density,
+            size,
+            scale.apply(point),
+            params.sigma,
+            |_, _| {},
+        );
+    }
+    let heatmap = colorize(&density, size, params);
+    recycle_f32(density);
+    heatmap
+}
+
+/// Adds a Gaussian of peak 1 centered on `center` to `weights`, truncated at
+/// three standard deviations, and reports every touched index and weight to
+/// `visit`.
+fn splat(
+    weights: &mut [f32],
+    (width, height): (u32, u32),
+    center: (f32, f32),
+    sigma: f32,
+    mut visit: impl FnMut(usize, f32),
+) {
+    let sigma = sigma.max(f32::EPSILON);
+    let reach = 3.0 * sigma;
+    let x0 = (center.0 - reach).ceil().max(0.0);
+    let y0 = (center.1 - reach).ceil().max(0.0);
+    let x1 = (center.0 + reach).floor().min(width as f32 - 1.0);
+    let y1 = (center.1 + reach).floor().min(height as f32 - 1.0);
+    if x0 > x1 || y0 > y1 {
+        return;
+    }
+    let inv = -0.5 / (sigma * sigma);
+    for y in y0 as usize..=y1 as usize {
+        let dy = y as f32 - center.1;
+        for x in x0 as usize..=x1 as usize {
+            let dx = x as f32 - center.0;
+            let w = ((dx * dx + dy * dy) * inv).exp();
+            let i = y * width as usize + x;
+            weights[i] += w;
+            visit(i, w);
+        }
+    }
+}
+
+/// Color-maps `values`, normalized by [`HeatmapParams::max_value`].
+fn colorize(values: &[f32], (width, height): (u32, u32), params: &HeatmapParams) -> RgbImage {
+    let max = params
+        .max_value
+        .unwrap_or_else(|| values.iter().copied().fold(0.0, f32::max));
+    let scale = if max > 0.0 { 1.0 / max } else { 0.0 };
+    let mut heatmap = RgbImage::new(width, height);
+    for (pixel, &value) in heatmap.pixels_mut().zip(values) {
+        *pixel = params.colormap.map(value * scale);
+    }
+    heatmap
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tracks_are_drawn_at_canvas_scale() {
+        let mut canvas = RgbImage::new(64, 48);
+        let prev = [(5.0f32, 5.0f32), (20.0, 4.0)];
+        let results = [
+            TrackResult {
+                pos: (9.5, 5.0),
+                status: TrackStatus::Tracked,
+                error: 0.0,
+                aperture: None,
+            },
+            TrackResult {
+                pos: (0.0, 0.0),
+                status: TrackStatus::OutOfBounds,
+                error: f32::INFINITY,
+                aperture: None,
+            },
+        ];
+        // Processing ran at half resolution.
+        let scale = OverlayScale::between((32, 24), (64, 48));
+        assert_eq!(scale.apply((5.0, 5.0)), (10.5, 10.5));
+        draw_tracks(&mut canvas, &prev, &results, scale);
+
+        // The flow vector runs from (10.5, 10.5) to (19.5, 10.5), splitting
+        // its coverage between rows 10 and 11.
+        let at = |canvas: &RgbImage, x, y| canvas.get_pixel(x, y).0;
+        assert_eq!(at(&canvas, 15, 10), [0, 128, 0]);
+        assert_eq!(at(&canvas, 15, 11), [0, 128, 0]);
+        assert_eq!(at(&canvas, 15, 12), [0, 0, 0]);
+        // The marker sits on the new position, (19.5, 10.5); the lost point is
+        // marked gray where it started, (40.5, 8.5).
+        assert_eq!(at(&canvas, 21, 10), [128, 0, 0]);
+        assert_eq!(at(&canvas, 42, 9), [64, 64, 64]);
+
+        // Lines leaving the canvas are clipped.
+        draw_line(&mut canvas, (-10.0, -10.0), (100.0, 100.0), TRACKED_COLOR);
+        assert_eq!(at(&canvas, 30, 30), [0, 255, 0]);
+    }
+
+    #[test]
+    fn heatmaps_are_hot_where_points_move_and_gather() {
+        let ends = (Colormap::Jet.map(0.0), Colormap::Jet.map(1.0));
+        assert_eq!(ends, (Rgb([0, 0, 128]), Rgb([128, 0, 0])));
+        assert_eq!(Colormap::Viridis.map(1.0), Rgb([253, 231, 37]));
+
+        // A cluster moving by 4 px on the left, one moving by 1 px on the
+        // right, and a lost point in between that must not count.
+        let mut prev = Vec::new();
+        let mut results = Vec::new();
+        for i in 0..5 {
+            for (x, dx) in [(10.0, 4.0), (50.0, 1.0)] {
+                let p = (x + i as f32, 20.0);
+                prev.push(p);
+                results.push(TrackResult {
+                    pos: (p.0 + dx, p.1),
+                    status: TrackStatus::Tracked,
+                    error: 0.0,
+                    aperture: None,
+                });
+            }
+        }
+        prev.push((30.0, 20.0));
+        results.push(TrackResult {
+            pos: (90.0, 20.0),
+            status: TrackStatus::Diverged,
+            error: f32::INFINITY,
+            aperture: None,
+        });
+
+        let params = HeatmapParams {
+            colormap: Colormap::Gray,
+            sigma: 3.0,
+            max_value: Some(4.0),
+        };
+        let flow =
+            flow_magnitude_heatmap((64, 40), &prev, &results, OverlayScale::IDENTITY, &params);
+        let level = |image: &RgbImage, x, y| image.get_pixel(x, y)[0];
+        assert_eq!(level(&flow, 12, 20), 255);
+        assert_eq!(level(&flow, 52, 20), 64);
+        assert_eq!(level(&flow, 30, 20), 0);
+        assert_eq!(level(&flow, 12, 35), 0);
+
+        // Density at twice the processing resolution, auto-normalized.
+        let params = HeatmapParams {
+            max_value: None,
+            ..params
+        };
+        let density = track_density_heatmap((128, 80), &prev, OverlayScale::new(2.0, 2.0), &params);
+        assert_eq!(density.dimensions(), (128, 80));
+        assert!(level(&density, 104, 41) > 200);
+        assert!(level(&density, 61, 41) > 0);
+        assert_eq!(level(&density, 60, 10), 0);
+    }
+
+    #[test]
+    fn trajectories_fade_with_age_in_stable_colors() {
+        let mut history = TrackHistory::new(3);
+        for frame in 0..5 {
+            let x = 10.0 + 10.0 * frame as f32;
+            // Track 9 ends after frame 1.
+            let (ids, positions): (Vec<u64>, Vec<(f32, f32)>) = if frame < 2 {
+                (vec![7, 9], vec![(x, 10.0), (x, 30.0)])
+            } else {
+                (vec![7], vec![(x, 10.0)])
+            };
+            history.record(&ids, &positions);
+        }
+        assert_eq!(history.len(), 1);
+        assert!(history.trail(9).is_none());
+        let trail: Vec<_> = history.trail(7).unwrap().iter().copied().collect();
+        assert_eq!(trail, [(30.0, 10.0), (40.0, 10.0), (50.0, 10.0)]);
+
+        let mut canvas = RgbImage::new(64, 24);
+        draw_trajectories(&mut canvas, &history, OverlayScale::IDENTITY);
+        let color = id_color(7);
+        assert_eq!(color, id_color(7));
+        assert_ne!(color, id_color(8));
+        // The newer segment is opaque, the older one half transparent, and
+        // nothing is drawn before the kept history.
+        assert_eq!(*canvas.get_pixel(45, 10), color);
+        let old = canvas.get_pixel(35, 10);
+        let half = |c: u8| (c as f32 * 0.5).round() as u8;
+        assert_eq!(old.0, color.0.map(half));
+        assert_eq!(*canvas.get_pixel(25, 10), Rgb([0, 0, 0]));
+    }
+}
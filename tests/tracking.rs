@@ -1,10 +1,23 @@
 //! End-to-end synthetic tests for detection, tracking, status codes,
 //! prediction, the forward-backward check and grid detection.
 
-use image::{GrayImage, Luma};
+use image::{GrayImage, ImageBuffer, Luma, Rgb, RgbImage};
 use optical_flow_lk::{
-    DEFAULT_FB_THRESHOLD, DEFAULT_MIN_EIGEN_THRESHOLD, TrackStatus, TrackerContext, build_pyramid,
-    calc_optical_flow_ex, calc_optical_flow_fb, good_features_to_track_grid,
+    AnchorParams, Aperture, BatchTracker, BoundsPolicy, CameraIntrinsics, CoordinateConvention,
+    CoverageMap, DEFAULT_FB_THRESHOLD, DEFAULT_MIN_EIGEN_THRESHOLD, ExposureParams, FILTER_SCHARR,
+    FeatureParams, FlowParams, FramePair, GradientStorage, GradientWeighting, KeyframeParams,
+    KeyframeTracker, LevelIterations, LkFlags, MotionPrior, RegistrationQuality,
+    ResponseNormalization, RigidParams, RobustLoss, RoiTracker, RoiTrackerParams, StageResolutions,
+    StagedPipeline, TrackAnchors, TrackResult, TrackStatus, TrackWindow, TrackerContext,
+    agast_corners, auto_pyramid_levels, build_pyramid, calc_optical_flow_affine,
+    calc_optical_flow_bidirectional, calc_optical_flow_budget, calc_optical_flow_ex,
+    calc_optical_flow_fb, calc_optical_flow_images, calc_optical_flow_pyr_lk,
+    calc_optical_flow_rect, calc_optical_flow_similarity, calc_optical_flow_windows,
+    calc_optical_flow_with, estimate_exposure_change, estimate_global_shift,
+    estimate_rigid_transform, good_features_to_track_grid, good_features_to_track_pyramid,
+    good_features_to_track_rgb, good_features_to_track_sparse, good_features_to_track_with,
+    harris_corners, harris_corners_with_response, keypoint_orientations, registration_quality,
+    stabilize_pair, system_clock_ms,
 };
 
 const WIN: usize = 21;
@@ -81,15 +94,15 @@ fn shift(src: &GrayImage, sx: f32, sy: f32) -> GrayImage {
     out
 }
 
-/// Rotate the image by `angle` radians about `(cx, cy)`.
-fn rotate(src: &GrayImage, angle: f32, cx: f32, cy: f32) -> GrayImage {
+/// Rotate the image by `angle` radians and scale it by `scale` about `(cx, cy)`.
+fn rotate_scale(src: &GrayImage, angle: f32, scale: f32, cx: f32, cy: f32) -> GrayImage {
     let (w, h) = src.dimensions();
     let (s, c) = angle.sin_cos();
     let mut out = GrayImage::new(w, h);
     for y in 0..h {
         for x in 0..w {
             // Map output pixel back into the source frame.
-            let (ox, oy) = (x as f32 - cx, y as f32 - cy);
+            let (ox, oy) = ((x as f32 - cx) / scale, (y as f32 - cy) / scale);
             let srcx = c * ox + s * oy + cx;
             let srcy = -s * ox + c * oy + cy;
             out.put_pixel(x, y, Luma([sample(src, srcx, srcy) as u8]));
@@ -98,6 +111,22 @@ fn rotate(src: &GrayImage, angle: f32, cx: f32, cy: f32) -> GrayImage {
     out
 }
 
+/// Applies `p -> a * (p - c) + c` to the content of `src`.
+fn warp_affine(src: &GrayImage, a: [[f32; 2]; 2], cx: f32, cy: f32) -> GrayImage {
+    let (w, h) = src.dimensions();
+    let det = a[0][0] * a[1][1] - a[0][1] * a[1][0];
+    let mut out = GrayImage::new(w, h);
+    for y in 0..h {
+        for x in 0..w {
+            let (ox, oy) = (x as f32 - cx, y as f32 - cy);
+            let srcx = (a[1][1] * ox - a[0][1] * oy) / det + cx;
+            let srcy = (-a[1][0] * ox + a[0][0] * oy) / det + cy;
+            out.put_pixel(x, y, Luma([sample(src, srcx, srcy) as u8]));
+        }
+    }
+    out
+}
+
 fn dist(a: (f32, f32), b: (f32, f32)) -> f32 {
     ((a.0 - b.0).powi(2) + (a.1 - b.1).powi(2)).sqrt()
 }
@@ -140,7 +169,7 @@ fn small_rotation_tracks_accurately() {
     let prev = textured(320, 240);
     let (cx, cy) = (160.0f32, 120.0f32);
     let angle = 1.5f32.to_radians();
-    let next = rotate(&prev, angle, cx, cy);
+    let next = rotate_scale(&prev, angle, 1.0, cx, cy);
 
     // Points moderately close to the center so the rotation is near-translational
     // within the window.
@@ -197,6 +226,32 @@ fn point_leaving_frame_is_out_of_bounds() {
     assert!(res[0].error.is_infinite());
 }
 
+#[test]
+fn aperture_separates_corners_edges_and_flat_regions() {
+    let img = GrayImage::from_fn(120, 100, |x, y| {
+        let inside = (30..80).contains(&x) && (25..70).contains(&y);
+        Luma([if inside { 180 } else { 60 }])
+    });
+    // A corner of the square, the middle of its top edge, and its interior.
+    let pts = vec![(30.0f32, 25.0), (55.0, 25.0), (55.0, 47.0), (-50.0, 50.0)];
+    let pyr = build_pyramid(&img, 3);
+    let res = calc_optical_flow_ex(
+        &pyr,
+        &pyr,
+        &pts,
+        None,
+        WIN,
+        ITERS,
+        DEFAULT_MIN_EIGEN_THRESHOLD,
+    );
+
+    assert_eq!(res[0].aperture, Some(Aperture::Corner));
+    assert_eq!(res[1].aperture, Some(Aperture::Edge));
+    assert_eq!(res[2].aperture, Some(Aperture::Flat));
+    assert_eq!(res[2].status, TrackStatus::LowTexture);
+    assert_eq!(res[3].aperture, None);
+}
+
 #[test]
 fn flat_region_is_low_texture() {
     // Constant image -> zero gradients -> minimum eigenvalue 0 everywhere.
@@ -267,45 +322,51 @@ fn prediction_improves_large_displacement() {
     );
 }
 
-/// Stamp a textured occluder (copied from a distant region) so the forward pass
-/// can confidently latch onto a *wrong* match.
-fn occlude_textured(img: &mut GrayImage, src: &GrayImage, cx: i32, cy: i32, half: i32) {
-    let (w, h) = img.dimensions();
-    let (ox, oy) = (90, 70);
-    for y in (cy - half)..=(cy + half) {
-        for x in (cx - half)..=(cx + half) {
-            let (sx, sy) = (x + ox, y + oy);
-            if x >= 0
-                && y >= 0
-                && x < w as i32
-                && y < h as i32
-                && sx >= 0
-                && sy >= 0
-                && sx < w as i32
-                && sy < h as i32
-            {
-                let v = src.get_pixel(sx as u32, sy as u32)[0];
-                img.put_pixel(x as u32, y as u32, Luma([v]));
-            }
-        }
-    }
-}
-
 #[test]
-fn fb_check_catches_occlusion() {
+fn pyr_lk_flags_control_initial_flow() {
     let prev = textured(320, 240);
-    let (sx, sy) = (3.0f32, 2.0f32);
-    let mut next = shift(&prev, sx, sy);
-
-    let open = (80.0f32, 70.0f32);
-    let hidden = (200.0f32, 150.0f32);
-    occlude_textured(&mut next, &prev, 200 + sx as i32, 150 + sy as i32, 22);
+    let (sx, sy) = (26.0f32, 23.0f32);
+    let next = shift(&prev, sx, sy);
+    let pts = vec![(160.0f32, 120.0), (110.0, 95.0), (205.0, 150.0)];
+    let guess: Vec<(f32, f32)> = pts.iter().map(|&(x, y)| (x + sx, y + sy)).collect();
 
     let pp = build_pyramid(&prev, 4);
     let np = build_pyramid(&next, 4);
-    let pts = vec![open, hidden];
+    let pyr_lk = |next_points: &mut Vec<(f32, f32)>, flags| {
+        calc_optical_flow_pyr_lk(
+            &pp,
+            &np,
+            &pts,
+            next_points,
+            WIN,
+            ITERS,
+            flags,
+            DEFAULT_MIN_EIGEN_THRESHOLD,
+        )
+    };
 
-    let res = calc_optical_flow_fb(
+    // With the flag, `next_points` seeds the search, exactly like `predicted`.
+    let mut next_points = guess.clone();
+    let seeded = pyr_lk(&mut next_points, LkFlags::USE_INITIAL_FLOW);
+    let expected = calc_optical_flow_ex(
+        &pp,
+        &np,
+        &pts,
+        Some(&guess),
+        WIN,
+        ITERS,
+        DEFAULT_MIN_EIGEN_THRESHOLD,
+    );
+    assert_eq!(seeded, expected);
+    assert_eq!(
+        next_points,
+        seeded.iter().map(|r| r.pos).collect::<Vec<_>>()
+    );
+
+    // Without it, the buffer is output only: stale contents are ignored.
+    let mut next_points = vec![(0.0, 0.0); 7];
+    let unseeded = pyr_lk(&mut next_points, LkFlags::empty());
+    let expected = calc_optical_flow_ex(
         &pp,
         &np,
         &pts,
@@ -313,30 +374,356 @@ fn fb_check_catches_occlusion() {
         WIN,
         ITERS,
         DEFAULT_MIN_EIGEN_THRESHOLD,
-        DEFAULT_FB_THRESHOLD,
     );
+    assert_eq!(unseeded, expected);
+    assert_eq!(next_points.len(), pts.len());
 
     assert_eq!(
-        res[0].status,
-        TrackStatus::Tracked,
-        "open point should survive FB"
+        LkFlags::from_bits_truncate(4 | 1),
+        LkFlags::USE_INITIAL_FLOW
     );
-    assert_ne!(
-        res[1].status,
-        TrackStatus::Tracked,
-        "occluded point must be rejected"
+}
+
+#[test]
+fn prealign_flag_tracks_shake_beyond_the_pyramid() {
+    let prev = textured(320, 240);
+    let (sx, sy) = (38.0f32, -31.0f32);
+    let next = shift(&prev, sx, sy);
+    let pts = vec![(160.0f32, 120.0), (110.0, 140.0), (200.0, 150.0)];
+
+    // Two levels reach far less than the shake on their own.
+    let pp = build_pyramid(&prev, 2);
+    let np = build_pyramid(&next, 2);
+    assert_eq!(estimate_global_shift(&pp, &np), (38, -31));
+
+    let pyr_lk = |flags| {
+        let mut next_points = Vec::new();
+        calc_optical_flow_pyr_lk(
+            &pp,
+            &np,
+            &pts,
+            &mut next_points,
+            WIN,
+            ITERS,
+            flags,
+            DEFAULT_MIN_EIGEN_THRESHOLD,
+        )
+    };
+    let aligned = pyr_lk(LkFlags::PREALIGN);
+    let plain = pyr_lk(LkFlags::empty());
+    for (i, (a, p)) in aligned.iter().zip(&plain).enumerate() {
+        let exp = (pts[i].0 + sx, pts[i].1 + sy);
+        assert_eq!(a.status, TrackStatus::Tracked, "pt{i}");
+        assert!(dist(a.pos, exp) < 0.2, "pt{i} pre-aligned err too large");
+        assert!(
+            dist(p.pos, exp) > 1.0,
+            "pt{i} should be lost without pre-alignment"
+        );
+    }
+}
+
+#[test]
+fn motion_prior_seeds_tracking_under_fast_rotation() {
+    let prev = textured(320, 240);
+    let (cx, cy) = (160.0f32, 120.0f32);
+    // Gyro-like motion: a pan with some roll and zoom.
+    let angle = 4f32.to_radians();
+    let next = shift(&rotate_scale(&prev, angle, 1.02, cx, cy), 24.0, -18.0);
+    let pts = vec![
+        (80.0f32, 90.0),
+        (240.0, 150.0),
+        (150.0, 50.0),
+        (190.0, 190.0),
+    ];
+    let prior = MotionPrior::similarity(angle, 1.02, (cx, cy), (24.0, -18.0));
+
+    // A single level cannot follow the 20+ px these points move.
+    let pp = build_pyramid(&prev, 1);
+    let np = build_pyramid(&next, 1);
+    let with = |prior| {
+        let params = FlowParams {
+            prior,
+            ..FlowParams::default()
+        };
+        calc_optical_flow_with(&pp, &np, &pts, None, &params)
+    };
+    let seeded = with(Some(prior));
+    let plain = with(None);
+    for (i, (s, p)) in seeded.iter().zip(&plain).enumerate() {
+        let exp = prior.apply(pts[i]);
+        assert_eq!(s.status, TrackStatus::Tracked, "pt{i}");
+        assert!(dist(s.pos, exp) < 0.5, "pt{i} seeded err too large");
+        assert!(
+            dist(p.pos, exp) > 1.0,
+            "pt{i} should be lost without the prior"
+        );
+    }
+
+    // The context seeds the same way, and explicit predictions win.
+    let mut ctx = TrackerContext::new();
+    ctx.prepare(&prev, &next, 1);
+    ctx.set_motion_prior(Some(prior));
+    assert_eq!(
+        ctx.track(&pts, None, WIN, ITERS, DEFAULT_MIN_EIGEN_THRESHOLD),
+        &seeded[..]
+    );
+    assert_eq!(
+        ctx.track(&pts, Some(&pts), WIN, ITERS, DEFAULT_MIN_EIGEN_THRESHOLD),
+        &plain[..]
     );
 }
 
 #[test]
-fn context_matches_free_functions() {
+fn zncc_refinement_recovers_from_illumination_change() {
+    // The next frame is shifted and relit with a strong horizontal ramp, which
+    // biases the intensity residual but not the normalized correlation.
     let prev = textured(320, 240);
-    let next = shift(&prev, 2.0, -1.5);
-    let pts = vec![(160.0f32, 120.0), (90.0, 80.0), (210.0, 160.0)];
+    let (sx, sy) = (2.3f32, -1.6f32);
+    let moved = shift(&prev, sx, sy);
+    let next = GrayImage::from_fn(320, 240, |x, y| {
+        let gain = 0.7 + 0.6 * x as f32 / 320.0;
+        Luma([(moved.get_pixel(x, y)[0] as f32 * gain + 15.0).min(255.0) as u8])
+    });
+    let pts: Vec<(f32, f32)> = (0..5)
+        .flat_map(|j| (0..7).map(move |i| (50.0 + 36.0 * i as f32, 50.0 + 35.0 * j as f32)))
+        .collect();
+    let (pp, np) = (build_pyramid(&prev, 3), build_pyramid(&next, 3));
+    let run = |np: &[GrayImage], flags| {
+        let mut next_points = Vec::new();
+        calc_optical_flow_pyr_lk(&pp, np, &pts, &mut next_points, WIN, ITERS, flags, 0.0)
+    };
+    let mean_error = |results: &[TrackResult]| {
+        let tracked: Vec<f32> = results
+            .iter()
+            .zip(&pts)
+            .filter(|(r, _)| r.status == TrackStatus::Tracked)
+            .map(|(r, p)| dist(r.pos, (p.0 + sx, p.1 + sy)))
+            .collect();
+        tracked.iter().sum::<f32>() / tracked.len() as f32
+    };
 
-    let pp = build_pyramid(&prev, 4);
-    let np = build_pyramid(&next, 4);
-    let free = calc_optical_flow_ex(
+    let plain = run(&np, LkFlags::empty());
+    let refined = run(&np, LkFlags::ZNCC_REFINE);
+    let statuses = |results: &[TrackResult]| results.iter().map(|r| r.status).collect::<Vec<_>>();
+    assert_eq!(statuses(&plain), statuses(&refined));
+    let (before, after) = (mean_error(&plain), mean_error(&refined));
+    assert!(after < 0.4 && after * 2.0 < before, "{before} -> {after}");
+
+    // Under constant lighting the LK solution is already the best match.
+    let np = build_pyramid(&moved, 3);
+    assert!(mean_error(&run(&np, LkFlags::ZNCC_REFINE)) < 0.1);
+}
+
+#[test]
+fn gain_bias_flag_tracks_through_exposure_change() {
+    // The next frame is shifted and darkened with an offset, as after an
+    // auto-exposure step.
+    let prev = textured(320, 240);
+    let (sx, sy) = (3.4f32, -2.2f32);
+    let moved = shift(&prev, sx, sy);
+    let next = GrayImage::from_fn(320, 240, |x, y| {
+        Luma([(moved.get_pixel(x, y)[0] as f32 * 0.6 + 40.0) as u8])
+    });
+    let pts: Vec<(f32, f32)> = (0..4)
+        .flat_map(|j| (0..6).map(move |i| (50.0 + 44.0 * i as f32, 50.0 + 45.0 * j as f32)))
+        .collect();
+    let (pp, np) = (build_pyramid(&prev, 3), build_pyramid(&next, 3));
+    let run = |flags| {
+        let mut next_points = Vec::new();
+        calc_optical_flow_pyr_lk(
+            &pp,
+            &np,
+            &pts,
+            &mut next_points,
+            WIN,
+            ITERS,
+            flags,
+            DEFAULT_MIN_EIGEN_THRESHOLD,
+        )
+    };
+    let worst = |results: &[TrackResult]| {
+        results
+            .iter()
+            .zip(&pts)
+            .map(|(r, p)| dist(r.pos, (p.0 + sx, p.1 + sy)))
+            .fold(0.0f32, f32::max)
+    };
+
+    let plain = run(LkFlags::empty());
+    let compensated = run(LkFlags::GAIN_BIAS);
+    assert!(
+        compensated.iter().all(|r| r.status == TrackStatus::Tracked),
+        "{compensated:?}"
+    );
+    let (before, after) = (worst(&plain), worst(&compensated));
+    assert!(after < 0.2 && after * 2.0 < before, "{before} -> {after}");
+    // The residual left after compensation is only quantization.
+    let mean_residual = |results: &[TrackResult]| {
+        results.iter().map(|r| r.error).sum::<f32>() / results.len() as f32
+    };
+    assert!(mean_residual(&compensated) < 2.0);
+    assert!(mean_residual(&compensated) * 4.0 < mean_residual(&plain));
+}
+
+#[test]
+fn zero_mean_flag_tracks_through_brightness_drift() {
+    let prev = textured(320, 240);
+    let (sx, sy) = (3.4f32, -2.2f32);
+    let moved = shift(&prev, sx, sy);
+    let next = GrayImage::from_fn(320, 240, |x, y| {
+        Luma([moved.get_pixel(x, y)[0].saturating_add(35)])
+    });
+    let pts: Vec<(f32, f32)> = (0..4)
+        .flat_map(|j| (0..6).map(move |i| (50.0 + 44.0 * i as f32, 50.0 + 45.0 * j as f32)))
+        .collect();
+    let (pp, np) = (build_pyramid(&prev, 3), build_pyramid(&next, 3));
+    let run = |flags| {
+        let params = FlowParams {
+            flags,
+            ..FlowParams::default()
+        };
+        calc_optical_flow_with(&pp, &np, &pts, None, &params)
+    };
+    let worst = |results: &[TrackResult]| {
+        results
+            .iter()
+            .zip(&pts)
+            .map(|(r, p)| dist(r.pos, (p.0 + sx, p.1 + sy)))
+            .fold(0.0f32, f32::max)
+    };
+
+    let plain = run(LkFlags::empty());
+    let zero_mean = run(LkFlags::ZERO_MEAN);
+    assert!(
+        zero_mean.iter().all(|r| r.status == TrackStatus::Tracked),
+        "{zero_mean:?}"
+    );
+    let (before, after) = (worst(&plain), worst(&zero_mean));
+    assert!(after < 0.2 && after * 2.0 < before, "{before} -> {after}");
+}
+
+#[test]
+fn estimated_exposure_change_compensates_the_residual() {
+    // A brightness ramp gives the patches different means to fit the gain to;
+    // it runs across the motion, which would otherwise shift the means too.
+    // Cropping both frames from a larger scene avoids black borders.
+    let tex = textured(340, 260);
+    let scene = GrayImage::from_fn(340, 260, |x, y| {
+        Luma([(tex.get_pixel(x, y)[0] as f32 * 0.6 + y as f32 * 0.5) as u8])
+    });
+    let (sx, sy) = (3.4f32, 0.0f32);
+    let crop = |img: &GrayImage| image::imageops::crop_imm(img, 10, 10, 320, 240).to_image();
+    let prev = crop(&scene);
+    let moved = crop(&shift(&scene, sx, sy));
+    let next = GrayImage::from_fn(320, 240, |x, y| {
+        Luma([(moved.get_pixel(x, y)[0] as f32 * 0.6 + 40.0).round() as u8])
+    });
+
+    let change = estimate_exposure_change(&prev, &next, &ExposureParams::default());
+    assert!((change.gain - 0.6).abs() < 0.05, "{change:?}");
+    assert!((change.bias - 40.0).abs() < 6.0, "{change:?}");
+    // Pre-compensation restores the moved frame's intensities.
+    let restored = change.compensate(&next);
+    let diff = restored
+        .pixels()
+        .zip(moved.pixels())
+        .map(|(a, b)| (a[0] as f32 - b[0] as f32).abs())
+        .sum::<f32>()
+        / (320 * 240) as f32;
+    assert!(diff < 2.0, "{diff}");
+
+    // Given to the tracker, the change is undone inside the residual.
+    let pts: Vec<(f32, f32)> = (0..4)
+        .flat_map(|j| (0..6).map(move |i| (50.0 + 44.0 * i as f32, 50.0 + 45.0 * j as f32)))
+        .collect();
+    let (pp, np) = (build_pyramid(&prev, 3), build_pyramid(&next, 3));
+    let run = |exposure| {
+        let params = FlowParams {
+            exposure,
+            ..FlowParams::default()
+        };
+        calc_optical_flow_with(&pp, &np, &pts, None, &params)
+    };
+    let worst = |results: &[TrackResult]| {
+        results
+            .iter()
+            .zip(&pts)
+            .map(|(r, p)| dist(r.pos, (p.0 + sx, p.1 + sy)))
+            .fold(0.0f32, f32::max)
+    };
+    let plain = run(None);
+    let compensated = run(Some(change));
+    assert!(
+        compensated.iter().all(|r| r.status == TrackStatus::Tracked),
+        "{compensated:?}"
+    );
+    let (before, after) = (worst(&plain), worst(&compensated));
+    assert!(after < 0.2 && after * 2.0 < before, "{before} -> {after}");
+    assert!(compensated.iter().all(|r| r.error < 3.0), "{compensated:?}");
+
+    // The context applies it the same way.
+    let mut ctx = TrackerContext::new();
+    ctx.prepare(&prev, &next, 3);
+    ctx.set_exposure_change(Some(change));
+    let tracked = ctx.track(&pts, None, WIN, ITERS, DEFAULT_MIN_EIGEN_THRESHOLD);
+    assert_eq!(worst(tracked), after);
+}
+
+#[test]
+fn robust_loss_tracks_past_a_partial_occluder() {
+    // The scene moves, but a static striped occluder covers the right third
+    // of every window in the next frame.
+    let prev = textured(320, 240);
+    let (sx, sy) = (2.6f32, 1.7f32);
+    let moved = shift(&prev, sx, sy);
+    let pts: Vec<(f32, f32)> = (0..3)
+        .flat_map(|j| (0..5).map(move |i| (60.0 + 50.0 * i as f32, 60.0 + 55.0 * j as f32)))
+        .collect();
+    let next = GrayImage::from_fn(320, 240, |x, y| {
+        let occluded = pts.iter().any(|&(px, py)| {
+            let (u, v) = (x as f32 - px, y as f32 - py);
+            (4.0..=12.0).contains(&u) && v.abs() <= 12.0
+        });
+        if occluded {
+            Luma([if (x / 2 + y / 3) % 2 == 0 { 250 } else { 20 }])
+        } else {
+            *moved.get_pixel(x, y)
+        }
+    });
+
+    let mut context = TrackerContext::new();
+    context.prepare(&prev, &next, 3);
+    let mut worst = |loss| {
+        context.set_robust_loss(loss);
+        let results = context.track(&pts, None, WIN, ITERS, DEFAULT_MIN_EIGEN_THRESHOLD);
+        results
+            .iter()
+            .zip(&pts)
+            .map(|(r, p)| dist(r.pos, (p.0 + sx, p.1 + sy)))
+            .fold(0.0f32, f32::max)
+    };
+    let plain = worst(None);
+    let huber = worst(Some(RobustLoss::HUBER));
+    let tukey = worst(Some(RobustLoss::TUKEY));
+    assert!(tukey < 0.2 && tukey * 3.0 < plain, "{plain} -> {tukey}");
+    assert!(huber < 0.3, "{plain} -> {huber}");
+}
+
+#[test]
+fn flow_params_match_the_flat_api_and_limit_levels() {
+    let prev = textured(320, 240);
+    let (sx, sy) = (9.0f32, -7.0f32);
+    let next = shift(&prev, sx, sy);
+    let pts = [(100.0, 90.0), (160.0, 120.0), (220.0, 150.0)];
+    let (pp, np) = (build_pyramid(&prev, 3), build_pyramid(&next, 3));
+
+    let params = FlowParams {
+        window_size: WIN,
+        max_iterations: ITERS,
+        ..FlowParams::default()
+    };
+    let with = calc_optical_flow_with(&pp, &np, &pts, None, &params);
+    let flat = calc_optical_flow_ex(
         &pp,
         &np,
         &pts,
@@ -345,59 +732,1898 @@ fn context_matches_free_functions() {
         ITERS,
         DEFAULT_MIN_EIGEN_THRESHOLD,
     );
+    assert_eq!(with, flat);
 
-    let mut ctx = TrackerContext::new();
-    ctx.prepare(&prev, &next, 4);
-    let ctx_res = ctx.track(&pts, None, WIN, ITERS, DEFAULT_MIN_EIGEN_THRESHOLD);
+    // Level 0 alone cannot reach a 9 px shift with a 21 px window.
+    let single_level = FlowParams {
+        max_level: Some(0),
+        ..params
+    };
+    let results = calc_optical_flow_with(&pp, &np, &pts, None, &single_level);
+    assert!(
+        results
+            .iter()
+            .zip(&pts)
+            .any(|(r, p)| dist(r.pos, (p.0 + sx, p.1 + sy)) > 1.0),
+        "{results:?}"
+    );
 
-    for (a, b) in free.iter().zip(ctx_res.iter()) {
-        assert_eq!(a.pos, b.pos);
-        assert_eq!(a.status, b.status);
+    // A coarse epsilon still lands within it.
+    let coarse = FlowParams {
+        epsilon: 0.1,
+        ..params
+    };
+    for (result, p) in calc_optical_flow_with(&pp, &np, &pts, None, &coarse)
+        .iter()
+        .zip(&pts)
+    {
+        assert_eq!(result.status, TrackStatus::Tracked);
+        assert!(dist(result.pos, (p.0 + sx, p.1 + sy)) < 0.3);
     }
 }
 
 #[test]
-fn grid_detection_is_uniform_and_respects_occupancy() {
-    let img = textured(320, 240);
-    let (cols, rows, max_per_cell, min_dist) = (4u32, 3u32, 3u32, 8u32);
+fn context_params_match_calc_optical_flow_with() {
+    let prev = textured(320, 240);
+    let next = shift(&prev, 4.6, -3.1);
+    let pts = [(100.0, 90.0), (160.0, 120.0), (220.0, 150.0), (60.0, 200.0)];
+    let (pp, np) = (build_pyramid(&prev, 3), build_pyramid(&next, 3));
+    let mask = GrayImage::from_fn(320, 240, |x, _| Luma([255 * (x > 30) as u8]));
+    let weights = ImageBuffer::from_fn(320, 240, |x, _| Luma([1.0 + x as f32 / 320.0]));
 
-    let pts = good_features_to_track_grid(&img, cols, rows, max_per_cell, 0.05, min_dist, &[]);
+    // Every setting the context only had as a setter, and the ones it
+    // hardcoded, through one struct.
+    let params = FlowParams {
+        window_size: 15,
+        max_iterations: 20,
+        epsilon: 0.005,
+        max_level: Some(1),
+        robust_loss: Some(RobustLoss::HUBER),
+        gradient_weighting: Some(GradientWeighting::Saturating { scale: 30.0 }),
+        mask: Some(&mask),
+        weight_map: Some(&weights),
+        level_early_exit: Some(0.5),
+        ..FlowParams::default()
+    };
+    let expected = calc_optical_flow_with(&pp, &np, &pts, None, &params);
 
-    // Spacing respected.
-    for i in 0..pts.len() {
-        for j in (i + 1)..pts.len() {
-            let d2 = (pts[i].0 as i32 - pts[j].0 as i32).pow(2)
-                + (pts[i].1 as i32 - pts[j].1 as i32).pow(2);
-            assert!(
-                d2 >= (min_dist * min_dist) as i32,
-                "points closer than min_distance"
-            );
+    let mut ctx = TrackerContext::new();
+    ctx.prepare(&prev, &next, 3);
+    ctx.set_params(&params);
+    assert_eq!(ctx.track_with(&pts, None), &expected[..]);
+
+    // The individual setters reach the same settings.
+    let mut ctx = TrackerContext::new();
+    ctx.prepare(&prev, &next, 3);
+    ctx.set_epsilon(0.005);
+    ctx.set_max_level(Some(1));
+    ctx.set_robust_loss(params.robust_loss);
+    ctx.set_gradient_weighting(params.gradient_weighting);
+    ctx.set_mask(Some(&mask));
+    ctx.set_weight_map(Some(weights.clone()));
+    ctx.set_level_early_exit(Some(0.5));
+    assert_eq!(
+        ctx.track(&pts, None, 15, 20, DEFAULT_MIN_EIGEN_THRESHOLD),
+        &expected[..]
+    );
+}
+
+#[test]
+fn bounds_policy_clamps_or_invalidates_points_outside_the_frame() {
+    // Textured left half, flat right half; the flat point is predicted past
+    // the right edge and kept there as low-texture.
+    let tex = textured(320, 240);
+    let prev = GrayImage::from_fn(320, 240, |x, y| {
+        if x < 160 {
+            *tex.get_pixel(x, y)
+        } else {
+            Luma([128])
         }
+    });
+    let pts = [(80.0, 120.0), (300.0, 120.0)];
+    let predicted = [(80.0, 120.0), (330.0, 121.5)];
+    let (pp, np) = (build_pyramid(&prev, 3), build_pyramid(&prev, 3));
+    let run = |bounds| {
+        let params = FlowParams {
+            bounds,
+            ..FlowParams::default()
+        };
+        calc_optical_flow_with(&pp, &np, &pts, Some(&predicted), &params)
+    };
+
+    let as_is = run(BoundsPolicy::AsIs);
+    assert_eq!(as_is[1].pos, (330.0, 121.5));
+    assert_eq!(as_is[1].status, TrackStatus::LowTexture);
+
+    let clamped = run(BoundsPolicy::Clamp);
+    assert_eq!(clamped[1].pos, (319.0, 121.5));
+    assert_eq!(clamped[1].status, TrackStatus::LowTexture);
+
+    let invalidated = run(BoundsPolicy::Invalidate);
+    assert_eq!(invalidated[1].pos, (330.0, 121.5));
+    assert_eq!(invalidated[1].status, TrackStatus::OutOfBounds);
+
+    for results in [&as_is, &clamped, &invalidated] {
+        assert_eq!(results[0], as_is[0]);
+        assert_eq!(results[0].status, TrackStatus::Tracked);
     }
+}
 
-    // Per-cell budget respected and coverage is broad.
-    let mut counts = vec![0u32; (cols * rows) as usize];
-    for &(x, y, _) in &pts {
-        let c = (x * cols / 320).min(cols - 1);
-        let r = (y * rows / 240).min(rows - 1);
-        counts[(r * cols + c) as usize] += 1;
+#[test]
+fn block_search_bootstraps_large_displacements() {
+    let prev = textured(320, 240);
+    let motion = (52.0f32, -36.0f32);
+    let next = shift(&prev, motion.0, motion.1);
+    let pts = vec![
+        (100.0f32, 120.0),
+        (160.0, 100.0),
+        (200.0, 150.0),
+        (120.0, 170.0),
+    ];
+    // Three levels leave about 16 px of motion on the top level, beyond what
+    // the window recovers from a zero start.
+    let (pp, np) = (build_pyramid(&prev, 3), build_pyramid(&next, 3));
+    let run = |block_search| {
+        let params = FlowParams {
+            block_search,
+            ..FlowParams::default()
+        };
+        calc_optical_flow_with(&pp, &np, &pts, None, &params)
+    };
+
+    let plain = run(None);
+    let searched = run(Some(16));
+    for (i, (p, s)) in plain.iter().zip(&searched).enumerate() {
+        let exp = (pts[i].0 + motion.0, pts[i].1 + motion.1);
+        assert_eq!(s.status, TrackStatus::Tracked, "pt{i}");
+        assert!(dist(s.pos, exp) < 0.2, "pt{i}: {:?}", s.pos);
+        assert!(
+            p.status != TrackStatus::Tracked || dist(p.pos, exp) > 1.0,
+            "pt{i} should be lost without the search"
+        );
     }
-    assert!(counts.iter().all(|&c| c <= max_per_cell));
-    assert!(
-        counts.iter().filter(|&&c| c > 0).count() >= 10,
-        "should cover most cells"
-    );
 
-    // A cell pre-filled by existing points yields no new detections there.
-    let existing: Vec<(f32, f32)> = vec![(20.0, 20.0), (40.0, 30.0), (60.0, 10.0)];
-    let pts2 =
-        good_features_to_track_grid(&img, cols, rows, max_per_cell, 0.05, min_dist, &existing);
-    let in_cell0 = pts2
-        .iter()
-        .filter(|&&(x, y, _)| x * cols / 320 == 0 && y * rows / 240 == 0)
-        .count();
+    let mut ctx = TrackerContext::new();
+    ctx.prepare(&prev, &next, 3);
+    ctx.set_block_search(Some(16));
     assert_eq!(
-        in_cell0, 0,
-        "cell filled by existing_points must be skipped"
+        ctx.track(&pts, None, WIN, ITERS, DEFAULT_MIN_EIGEN_THRESHOLD),
+        &searched[..]
     );
 }
+
+#[test]
+fn max_displacement_rejects_implausible_jumps() {
+    let prev = textured(320, 240);
+    let next = shift(&prev, 6.0, -3.0);
+    let pts = [(100.0, 120.0), (200.0, 100.0)];
+    let (pp, np) = (build_pyramid(&prev, 3), build_pyramid(&next, 3));
+    let run = |max_displacement| {
+        let params = FlowParams {
+            max_displacement,
+            ..FlowParams::default()
+        };
+        calc_optical_flow_with(&pp, &np, &pts, None, &params)
+    };
+
+    for result in run(Some(10.0)) {
+        assert_eq!(result.status, TrackStatus::Tracked);
+    }
+    for (result, &p) in run(Some(5.0)).iter().zip(&pts) {
+        assert_eq!(result.status, TrackStatus::Diverged);
+        assert_eq!(result.pos, p);
+    }
+
+    let mut ctx = TrackerContext::new();
+    ctx.prepare(&prev, &next, 3);
+    ctx.set_max_displacement(Some(5.0));
+    let rejected = ctx.track(&pts, None, WIN, ITERS, DEFAULT_MIN_EIGEN_THRESHOLD);
+    assert!(rejected.iter().all(|r| r.status == TrackStatus::Diverged));
+    ctx.set_max_displacement(None);
+    let accepted = ctx.track(&pts, None, WIN, ITERS, DEFAULT_MIN_EIGEN_THRESHOLD);
+    assert!(accepted.iter().all(|r| r.status == TrackStatus::Tracked));
+}
+
+#[test]
+fn min_eigenvalue_flag_replaces_error() {
+    // Textured left half, flat right half.
+    let tex = textured(320, 240);
+    let prev = GrayImage::from_fn(320, 240, |x, y| {
+        if x < 160 {
+            *tex.get_pixel(x, y)
+        } else {
+            Luma([128])
+        }
+    });
+    let next = shift(&prev, 1.5, -0.5);
+    let pts = vec![(80.0f32, 120.0), (260.0, 120.0), (2.0, 120.0)];
+
+    let mut ctx = TrackerContext::new();
+    ctx.prepare(&prev, &next, 3);
+    let residual = ctx
+        .track(&pts, None, WIN, ITERS, DEFAULT_MIN_EIGEN_THRESHOLD)
+        .to_vec();
+    ctx.set_flags(LkFlags::GET_MIN_EIGENVALS);
+    let eigen = ctx
+        .track(&pts, None, WIN, ITERS, DEFAULT_MIN_EIGEN_THRESHOLD)
+        .to_vec();
+
+    let statuses: Vec<_> = eigen.iter().map(|r| r.status).collect();
+    assert_eq!(
+        statuses,
+        [
+            TrackStatus::Tracked,
+            TrackStatus::LowTexture,
+            TrackStatus::OutOfBounds
+        ]
+    );
+    for (e, r) in eigen.iter().zip(&residual) {
+        assert_eq!((e.pos, e.status), (r.pos, r.status));
+    }
+    assert!(eigen[0].error > DEFAULT_MIN_EIGEN_THRESHOLD);
+    assert_ne!(eigen[0].error, residual[0].error);
+    assert_eq!(eigen[1].error, 0.0);
+    assert_eq!(eigen[2].error, f32::INFINITY);
+}
+
+#[test]
+fn pixel_corner_convention_tracks_the_same_features() {
+    let prev = textured(320, 240);
+    let (sx, sy) = (1.6f32, -0.9f32);
+    let next = shift(&prev, sx, sy);
+    let centers = [(80.0, 70.0), (160.0, 120.0), (231.0, 170.0)];
+    let corners: Vec<(f32, f32)> = centers.iter().map(|&(x, y)| (x + 0.5, y + 0.5)).collect();
+
+    let mut context = TrackerContext::new();
+    context.prepare(&prev, &next, 3);
+    let reference: Vec<TrackResult> = context
+        .track(&centers, None, WIN, ITERS, DEFAULT_MIN_EIGEN_THRESHOLD)
+        .to_vec();
+    context.set_coordinate_convention(CoordinateConvention::PixelCorner);
+    let results = context.track(&corners, None, WIN, ITERS, DEFAULT_MIN_EIGEN_THRESHOLD);
+    for (result, expected) in results.iter().zip(&reference) {
+        assert_eq!(result.status, TrackStatus::Tracked);
+        let (x, y) = expected.pos;
+        assert!(dist(result.pos, (x + 0.5, y + 0.5)) < 1e-4, "{result:?}");
+    }
+}
+
+#[test]
+fn context_with_intrinsics_reports_undistorted_points() {
+    let prev = textured(320, 240);
+    let next = shift(&prev, 1.5, -0.5);
+    let camera = CameraIntrinsics {
+        k1: -0.25,
+        k2: 0.05,
+        ..CameraIntrinsics::new(300.0, 300.0, 160.0, 120.0)
+    };
+    // Undistorted positions; near the corners the lens moves them by pixels.
+    let pts = vec![(160.0f32, 120.0), (60.0, 50.0), (250.0, 190.0)];
+    let image_pts: Vec<_> = pts.iter().map(|&p| camera.distort(p)).collect();
+    assert!(dist(pts[1], image_pts[1]) > 2.0);
+
+    let mut ctx = TrackerContext::new();
+    ctx.prepare(&prev, &next, 3);
+    let plain = ctx
+        .track(&image_pts, None, WIN, ITERS, DEFAULT_MIN_EIGEN_THRESHOLD)
+        .to_vec();
+    ctx.set_intrinsics(Some(camera));
+    let undistorted = ctx
+        .track(&pts, None, WIN, ITERS, DEFAULT_MIN_EIGEN_THRESHOLD)
+        .to_vec();
+    let fb = ctx
+        .track_fb(
+            &pts,
+            None,
+            WIN,
+            ITERS,
+            DEFAULT_MIN_EIGEN_THRESHOLD,
+            DEFAULT_FB_THRESHOLD,
+        )
+        .to_vec();
+
+    for i in 0..pts.len() {
+        assert_eq!(undistorted[i].status, TrackStatus::Tracked);
+        assert_eq!(fb[i].status, TrackStatus::Tracked);
+        // Tracking ran on the image points; only the reported positions
+        // differ.
+        assert_eq!(undistorted[i].pos, camera.undistort(plain[i].pos));
+        let (x, y) = image_pts[i];
+        let expected = camera.undistort((x + 1.5, y - 0.5));
+        assert!(
+            dist(undistorted[i].pos, expected) < 0.1,
+            "{i}: {:?}",
+            undistorted[i]
+        );
+        assert!(dist(fb[i].pos, expected) < 0.1, "{i}: {:?}", fb[i]);
+    }
+}
+
+#[test]
+fn context_level_early_exit_keeps_small_motion_accurate() {
+    let prev = textured(320, 240);
+    let (sx, sy) = (2.3f32, -1.4f32);
+    let next = shift(&prev, sx, sy);
+    let pts: Vec<(f32, f32)> = (0..4)
+        .flat_map(|j| (0..4).map(move |i| (70.0 + 60.0 * i as f32, 50.0 + 45.0 * j as f32)))
+        .collect();
+
+    let mut ctx = TrackerContext::new();
+    ctx.prepare(&prev, &next, 3);
+    let full = ctx
+        .track(&pts, None, WIN, ITERS, DEFAULT_MIN_EIGEN_THRESHOLD)
+        .to_vec();
+
+    // Waiting for every point is the same as not exiting early.
+    ctx.set_level_early_exit(Some(1.0));
+    let all = ctx
+        .track(&pts, None, WIN, ITERS, DEFAULT_MIN_EIGEN_THRESHOLD)
+        .to_vec();
+    assert_eq!(all, full);
+
+    // Under one shared motion the points converge in about the same round,
+    // so ending the coarse levels early costs them no accuracy.
+    ctx.set_level_early_exit(Some(0.5));
+    let early = ctx.track(&pts, None, WIN, ITERS, DEFAULT_MIN_EIGEN_THRESHOLD);
+    for (i, r) in early.iter().enumerate() {
+        assert_eq!(r.status, TrackStatus::Tracked, "pt{i}");
+        let e = dist(r.pos, (pts[i].0 + sx, pts[i].1 + sy));
+        assert!(e < 0.2, "pt{i}: err {e} >= 0.2");
+    }
+}
+
+#[test]
+fn context_level_early_exit_refines_late_moving_points() {
+    // The left half stays, the right half moves further than the finest
+    // level can track alone.
+    let prev = textured(480, 240);
+    let (sx, sy) = (7.3f32, -5.2f32);
+    let moved = shift(&prev, sx, sy);
+    let next = GrayImage::from_fn(480, 240, |x, y| {
+        if x < 240 {
+            *prev.get_pixel(x, y)
+        } else {
+            *moved.get_pixel(x, y)
+        }
+    });
+    // The static points come first, so converging them meets the quota.
+    let mut pts: Vec<(f32, f32)> = (0..3)
+        .flat_map(|j| (0..4).map(move |i| (40.0 + 30.0 * i as f32, 60.0 + 60.0 * j as f32)))
+        .collect();
+    let n_static = pts.len();
+    pts.extend((0..4).map(|j| (360.0, 50.0 + 45.0 * j as f32)));
+
+    let mut ctx = TrackerContext::new();
+    ctx.prepare(&prev, &next, 3);
+    ctx.set_level_early_exit(Some(0.5));
+    let results = ctx
+        .track(&pts, None, WIN, ITERS, DEFAULT_MIN_EIGEN_THRESHOLD)
+        .to_vec();
+    for (i, r) in results.iter().enumerate() {
+        let expected = if i < n_static {
+            pts[i]
+        } else {
+            (pts[i].0 + sx, pts[i].1 + sy)
+        };
+        assert_eq!(r.status, TrackStatus::Tracked, "pt{i}");
+        let e = dist(r.pos, expected);
+        assert!(e < 0.2, "pt{i}: err {e} >= 0.2");
+    }
+
+    // The input order does not change any point's result.
+    pts.rotate_left(n_static);
+    let rotated = ctx.track(&pts, None, WIN, ITERS, DEFAULT_MIN_EIGEN_THRESHOLD);
+    assert_eq!(rotated[..pts.len() - n_static], results[n_static..]);
+    assert_eq!(rotated[pts.len() - n_static..], results[..n_static]);
+}
+
+#[test]
+fn level_iterations_budget_each_level() {
+    let prev = textured(320, 240);
+    let (sx, sy) = (6.3f32, -4.6f32);
+    let next = shift(&prev, sx, sy);
+    let pts: Vec<(f32, f32)> = (0..4)
+        .flat_map(|j| (0..4).map(move |i| (70.0 + 60.0 * i as f32, 50.0 + 45.0 * j as f32)))
+        .collect();
+
+    let mut ctx = TrackerContext::new();
+    ctx.prepare(&prev, &next, 3);
+    let full = ctx
+        .track(&pts, None, WIN, ITERS, DEFAULT_MIN_EIGEN_THRESHOLD)
+        .to_vec();
+
+    // A uniform budget is the plain iteration limit.
+    ctx.set_level_iterations(Some(LevelIterations::uniform(ITERS)));
+    let uniform = ctx
+        .track(&pts, None, WIN, 1, DEFAULT_MIN_EIGEN_THRESHOLD)
+        .to_vec();
+    assert_eq!(uniform, full);
+
+    // A few coarse iterations leave the finest level little to do.
+    let budget = LevelIterations::per_level(&[ITERS, 3]);
+    ctx.set_level_iterations(Some(budget));
+    let cheap = ctx
+        .track(&pts, None, WIN, ITERS, DEFAULT_MIN_EIGEN_THRESHOLD)
+        .to_vec();
+    for (i, r) in cheap.iter().enumerate() {
+        assert_eq!(r.status, TrackStatus::Tracked, "pt{i}");
+        let e = dist(r.pos, (pts[i].0 + sx, pts[i].1 + sy));
+        assert!(e < 0.2, "pt{i}: err {e} >= 0.2");
+    }
+
+    // No iterations on the coarse levels: the finest level alone cannot
+    // bridge the motion.
+    ctx.set_level_iterations(Some(LevelIterations::per_level(&[ITERS, 0])));
+    let skipped = ctx.track(&pts, None, WIN, ITERS, DEFAULT_MIN_EIGEN_THRESHOLD);
+    assert!(
+        skipped
+            .iter()
+            .zip(&pts)
+            .any(|(r, p)| dist(r.pos, (p.0 + sx, p.1 + sy)) > 1.0)
+    );
+
+    let params = FlowParams {
+        max_level: Some(2),
+        level_iterations: Some(budget),
+        ..FlowParams::default()
+    };
+    let pp = build_pyramid(&prev, 3);
+    let np = build_pyramid(&next, 3);
+    assert_eq!(calc_optical_flow_with(&pp, &np, &pts, None, &params), cheap);
+}
+
+#[test]
+#[should_panic(expected = "USE_INITIAL_FLOW requires predicted positions")]
+fn context_initial_flow_flag_requires_prediction() {
+    let prev = textured(96, 80);
+    let mut ctx = TrackerContext::new();
+    ctx.prepare(&prev, &prev, 2);
+    ctx.set_flags(LkFlags::USE_INITIAL_FLOW);
+    ctx.track(
+        &[(48.0, 40.0)],
+        None,
+        WIN,
+        ITERS,
+        DEFAULT_MIN_EIGEN_THRESHOLD,
+    );
+}
+
+#[test]
+fn similarity_tracking_recovers_roll_and_zoom() {
+    let prev = textured(320, 240);
+    let (cx, cy) = (160.0f32, 120.0f32);
+    let (angle, zoom) = (8f32.to_radians(), 1.08f32);
+    let next = rotate_scale(&prev, angle, zoom, cx, cy);
+
+    let pts = vec![
+        (120.0f32, 100.0),
+        (200.0, 140.0),
+        (140.0, 150.0),
+        (185.0, 90.0),
+    ];
+    let pp = build_pyramid(&prev, 3);
+    let np = build_pyramid(&next, 3);
+    let res = calc_optical_flow_similarity(
+        &pp,
+        &np,
+        &pts,
+        None,
+        WIN,
+        ITERS,
+        DEFAULT_MIN_EIGEN_THRESHOLD,
+    );
+
+    let (s, c) = angle.sin_cos();
+    for (i, r) in res.iter().enumerate() {
+        let (ox, oy) = (pts[i].0 - cx, pts[i].1 - cy);
+        let exp = (zoom * (c * ox - s * oy) + cx, zoom * (s * ox + c * oy) + cy);
+        assert_eq!(r.status, TrackStatus::Tracked, "pt{i}");
+        let e = dist(r.pos, exp);
+        assert!(e < 0.3, "pt{i}: err {e} >= 0.3");
+        assert!(
+            (r.rotation - angle).abs() < 0.02,
+            "pt{i}: rotation {}",
+            r.rotation
+        );
+        assert!((r.scale - zoom).abs() < 0.02, "pt{i}: scale {}", r.scale);
+        assert!(r.error < 5.0, "pt{i}: error {}", r.error);
+    }
+
+    // A flat window is rejected like in translation-only tracking.
+    let flat = vec![GrayImage::from_pixel(64, 64, Luma([90]))];
+    let res = calc_optical_flow_similarity(
+        &flat,
+        &flat,
+        &[(32.0, 32.0)],
+        None,
+        WIN,
+        ITERS,
+        DEFAULT_MIN_EIGEN_THRESHOLD,
+    );
+    assert_eq!(res[0].status, TrackStatus::LowTexture);
+}
+
+#[test]
+fn affine_tracking_recovers_shear_and_anisotropic_scale() {
+    let prev = textured(320, 240);
+    let (cx, cy) = (160.0f32, 120.0f32);
+    let a = [[1.06f32, 0.08], [-0.03, 0.95]];
+    let next = warp_affine(&prev, a, cx, cy);
+
+    let pts = vec![
+        (120.0f32, 100.0),
+        (200.0, 140.0),
+        (140.0, 150.0),
+        (185.0, 90.0),
+    ];
+    let pp = build_pyramid(&prev, 3);
+    let np = build_pyramid(&next, 3);
+    let res = calc_optical_flow_affine(
+        &pp,
+        &np,
+        &pts,
+        None,
+        WIN,
+        ITERS,
+        DEFAULT_MIN_EIGEN_THRESHOLD,
+    );
+
+    for (i, r) in res.iter().enumerate() {
+        let (ox, oy) = (pts[i].0 - cx, pts[i].1 - cy);
+        let exp = (
+            a[0][0] * ox + a[0][1] * oy + cx,
+            a[1][0] * ox + a[1][1] * oy + cy,
+        );
+        assert_eq!(r.status, TrackStatus::Tracked, "pt{i}");
+        let e = dist(r.pos, exp);
+        assert!(e < 0.3, "pt{i}: err {e} >= 0.3");
+        for (row, expected) in r.matrix.iter().zip(&a) {
+            for (m, e) in row.iter().zip(expected) {
+                assert!((m - e).abs() < 0.02, "pt{i}: matrix {:?}", r.matrix);
+            }
+        }
+        assert!(r.error < 5.0, "pt{i}: error {}", r.error);
+    }
+
+    let flat = vec![GrayImage::from_pixel(64, 64, Luma([90]))];
+    let res = calc_optical_flow_affine(
+        &flat,
+        &flat,
+        &[(32.0, 32.0)],
+        None,
+        WIN,
+        ITERS,
+        DEFAULT_MIN_EIGEN_THRESHOLD,
+    );
+    assert_eq!(res[0].status, TrackStatus::LowTexture);
+}
+
+#[test]
+fn anchors_pull_drifted_tracks_back_to_birth_template() {
+    let birth = textured(160, 120);
+    let frame = shift(&birth, 3.0, -2.0);
+    let mut anchors = TrackAnchors::new(AnchorParams {
+        interval: 3,
+        ..AnchorParams::default()
+    });
+    for pos in [(80.0, 60.0), (60.0, 50.0), (100.0, 70.0), (40.0, 40.0)] {
+        anchors.push(&birth, pos);
+    }
+    // The second track is dropped by the caller.
+    anchors.retain(|i| i != 1);
+    assert_eq!(anchors.len(), 3);
+
+    let tracked = |pos, status| TrackResult::new(pos, status, 0.0);
+    let drifted = [
+        tracked((83.6, 57.3), TrackStatus::Tracked),
+        tracked((106.0, 68.0), TrackStatus::Tracked),
+        tracked((43.5, 38.5), TrackStatus::Diverged),
+    ];
+
+    let mut results = drifted;
+    for _ in 0..2 {
+        assert_eq!(anchors.correct(&frame, &mut results), 0, "not due yet");
+    }
+    assert_eq!(results, drifted);
+
+    assert_eq!(anchors.correct(&frame, &mut results), 1);
+    let e = dist(results[0].pos, (83.0, 58.0));
+    assert!(e < 0.1, "corrected err {e} >= 0.1");
+    assert!(results[0].error < 2.0);
+    // Too far from the template to trust, and a failed track: left alone.
+    assert_eq!(results[1..], drifted[1..]);
+}
+
+#[test]
+fn keyframe_tracking_promotes_when_overlap_drops() {
+    let base = textured(320, 240);
+    let mut points = Vec::new();
+    for y in [70.0f32, 120.0, 170.0] {
+        for x in [60.0f32, 120.0, 180.0, 240.0] {
+            points.push((x, y));
+        }
+    }
+
+    let mut tracker = KeyframeTracker::new(KeyframeParams {
+        min_overlap: 0.8,
+        ..KeyframeParams::default()
+    });
+    tracker.set_keyframe(&base, &points);
+
+    // Every frame is tracked from the keyframe; the right column leaves the
+    // image at frame 9 and the frame becomes the new keyframe.
+    let mut keyframe_motion = (0.0f32, 0.0f32);
+    for k in 1..=12 {
+        let motion = (8.0 * k as f32, -2.4 * k as f32);
+        let frame = shift(&base, motion.0, motion.1);
+        let keyframe_points = tracker.keyframe_points().to_vec();
+        let results = tracker.track(&frame);
+        assert_eq!(results.len(), keyframe_points.len());
+
+        for (p, r) in keyframe_points.iter().zip(results) {
+            let truth = (
+                p.0 + motion.0 - keyframe_motion.0,
+                p.1 + motion.1 - keyframe_motion.1,
+            );
+            if r.status == TrackStatus::Tracked {
+                let e = dist(r.pos, truth);
+                assert!(e < 0.2, "frame {k}: err {e} >= 0.2");
+            } else {
+                assert!(truth.0 + (WIN / 2) as f32 >= 319.0, "frame {k}: lost {p:?}");
+            }
+        }
+
+        assert_eq!(tracker.promoted(), k == 9, "frame {k}");
+        if tracker.promoted() {
+            assert_eq!(tracker.overlap(), 0.75);
+            assert_eq!(tracker.keyframe_points().len(), 9);
+            assert_eq!(tracker.frames_since_keyframe(), 0);
+            keyframe_motion = motion;
+        } else {
+            assert_eq!(tracker.overlap(), 1.0, "frame {k}");
+        }
+    }
+    assert_eq!(tracker.frames_since_keyframe(), 3);
+}
+
+#[test]
+fn roi_tracker_follows_an_object_over_a_static_background() {
+    let background = textured(320, 240);
+    let object = textured(400, 300);
+    let frame = |(ox, oy): (f32, f32)| {
+        GrayImage::from_fn(320, 240, |x, y| {
+            let (u, v) = (x as f32 - ox, y as f32 - oy);
+            if (0.0..120.0).contains(&u) && (0.0..90.0).contains(&v) {
+                Luma([sample(&object, u + 150.0, v + 120.0) as u8])
+            } else {
+                *background.get_pixel(x, y)
+            }
+        })
+    };
+
+    let mut tracker = RoiTracker::new(RoiTrackerParams {
+        window_size: 15,
+        ..RoiTrackerParams::default()
+    });
+    tracker.start(&frame((100.0, 80.0)), (100.0, 80.0, 120.0, 90.0));
+    assert!(!tracker.points().is_empty());
+    for k in 1..=6 {
+        let origin = (100.0 + 3.0 * k as f32, 80.0 + 2.0 * k as f32);
+        assert!(tracker.track(&frame(origin)).is_some(), "frame {k}");
+        let (x, y, w, h) = tracker.roi();
+        assert!(dist((x, y), origin) < 1.0, "frame {k}: roi {:?}", (x, y));
+        assert!((w - 120.0).abs() < 1.0 && (h - 90.0).abs() < 1.0);
+        for &(px, py) in tracker.points() {
+            assert!(px >= x && py >= y && px <= x + w && py <= y + h);
+        }
+    }
+
+    // A blank frame loses every point and leaves the region in place; the
+    // next frame repopulates it.
+    let roi = tracker.roi();
+    assert!(
+        tracker
+            .track(&GrayImage::from_pixel(320, 240, Luma([128])))
+            .is_none()
+    );
+    assert_eq!(tracker.roi(), roi);
+    assert!(tracker.points().is_empty());
+    tracker.track(&frame((118.0, 92.0)));
+    assert!(tracker.redetected());
+    assert!(!tracker.points().is_empty());
+}
+
+/// Stamp a textured occluder (copied from a distant region) so the forward pass
+/// can confidently latch onto a *wrong* match.
+fn occlude_textured(img: &mut GrayImage, src: &GrayImage, cx: i32, cy: i32, half: i32) {
+    let (w, h) = img.dimensions();
+    let (ox, oy) = (90, 70);
+    for y in (cy - half)..=(cy + half) {
+        for x in (cx - half)..=(cx + half) {
+            let (sx, sy) = (x + ox, y + oy);
+            if x >= 0
+                && y >= 0
+                && x < w as i32
+                && y < h as i32
+                && sx >= 0
+                && sy >= 0
+                && sx < w as i32
+                && sy < h as i32
+            {
+                let v = src.get_pixel(sx as u32, sy as u32)[0];
+                img.put_pixel(x as u32, y as u32, Luma([v]));
+            }
+        }
+    }
+}
+
+#[test]
+fn fb_check_catches_occlusion() {
+    let prev = textured(320, 240);
+    let (sx, sy) = (3.0f32, 2.0f32);
+    let mut next = shift(&prev, sx, sy);
+
+    let open = (80.0f32, 70.0f32);
+    let hidden = (200.0f32, 150.0f32);
+    occlude_textured(&mut next, &prev, 200 + sx as i32, 150 + sy as i32, 22);
+
+    let pp = build_pyramid(&prev, 4);
+    let np = build_pyramid(&next, 4);
+    let pts = vec![open, hidden];
+
+    let res = calc_optical_flow_fb(
+        &pp,
+        &np,
+        &pts,
+        None,
+        WIN,
+        ITERS,
+        DEFAULT_MIN_EIGEN_THRESHOLD,
+        DEFAULT_FB_THRESHOLD,
+    );
+
+    assert_eq!(
+        res[0].status,
+        TrackStatus::Tracked,
+        "open point should survive FB"
+    );
+    assert_ne!(
+        res[1].status,
+        TrackStatus::Tracked,
+        "occluded point must be rejected"
+    );
+}
+
+#[test]
+fn per_point_windows_and_weights() {
+    let prev = textured(320, 240);
+    let (sx, sy) = (2.0f32, 1.0f32);
+    let shifted = shift(&prev, sx, sy);
+    let pts = vec![(80.0f32, 70.0), (160.0, 120.0), (240.0, 170.0)];
+
+    // A static occluder covers everything left of the middle point's window
+    // centre (minus 4 px) in the next frame.
+    let next = GrayImage::from_fn(320, 240, |x, y| {
+        if x < 156 && (100..140).contains(&y) {
+            *prev.get_pixel(x + 100, y + 60)
+        } else {
+            *shifted.get_pixel(x, y)
+        }
+    });
+    let pp = build_pyramid(&prev, 2);
+    let np = build_pyramid(&next, 2);
+
+    // Uniform per-point windows reproduce the plain call.
+    let uniform = vec![TrackWindow::new(WIN); pts.len()];
+    let plain = calc_optical_flow_ex(
+        &pp,
+        &np,
+        &pts,
+        None,
+        WIN,
+        ITERS,
+        DEFAULT_MIN_EIGEN_THRESHOLD,
+    );
+    let same = calc_optical_flow_windows(
+        &pp,
+        &np,
+        &pts,
+        None,
+        &uniform,
+        ITERS,
+        DEFAULT_MIN_EIGEN_THRESHOLD,
+    );
+    assert_eq!(plain, same);
+    let exp = (pts[1].0 + sx, pts[1].1 + sy);
+    assert!(
+        dist(plain[1].pos, exp) > 0.2,
+        "occluder should bias the plain window"
+    );
+
+    // Ignoring the occluded columns (and mixing window sizes) recovers it.
+    let weights: Vec<f32> = (0..WIN * WIN)
+        .map(|i| if i % WIN < 9 { 0.0 } else { 1.0 })
+        .collect();
+    let windows = [
+        TrackWindow::new(15),
+        TrackWindow {
+            width: WIN,
+            height: WIN,
+            weights: Some(&weights),
+        },
+        TrackWindow::new(31),
+    ];
+    let mut ctx = TrackerContext::new();
+    ctx.prepare(&prev, &next, 2);
+    let res = ctx.track_windows(&pts, None, &windows, ITERS, DEFAULT_MIN_EIGEN_THRESHOLD);
+    for (i, r) in res.iter().enumerate() {
+        let exp = (pts[i].0 + sx, pts[i].1 + sy);
+        assert_eq!(r.status, TrackStatus::Tracked, "pt{i}");
+        assert!(dist(r.pos, exp) < 0.1, "pt{i}: {:?} vs {exp:?}", r.pos);
+    }
+    assert!(res[1].error < plain[1].error);
+}
+
+#[test]
+fn mask_excludes_a_static_overlay() {
+    // A burnt-in overlay (a high-contrast checker) stays put while the scene
+    // behind it moves.
+    let scene = textured(320, 240);
+    let (sx, sy) = (2.6f32, -1.7f32);
+    let overlay = |x: u32, y: u32| (150..170).contains(&x) && (40..200).contains(&y);
+    let burn = |img: &GrayImage| {
+        GrayImage::from_fn(320, 240, |x, y| {
+            if overlay(x, y) {
+                Luma([255 * ((x / 4 + y / 4) % 2) as u8])
+            } else {
+                *img.get_pixel(x, y)
+            }
+        })
+    };
+    let prev = burn(&scene);
+    let next = burn(&shift(&scene, sx, sy));
+    let mask = GrayImage::from_fn(320, 240, |x, y| Luma([255 * !overlay(x, y) as u8]));
+    // Beside the overlay, with a third of the window on it, and inside it.
+    let pts = vec![(143.0f32, 80.0), (177.0, 120.0), (160.0, 160.0)];
+    let (pp, np) = (build_pyramid(&prev, 3), build_pyramid(&next, 3));
+
+    let plain = calc_optical_flow_ex(&pp, &np, &pts, None, WIN, ITERS, 0.0);
+    let params = FlowParams {
+        window_size: WIN,
+        max_iterations: ITERS,
+        min_eigen_threshold: 0.0,
+        mask: Some(&mask),
+        ..FlowParams::default()
+    };
+    let masked = calc_optical_flow_with(&pp, &np, &pts, None, &params);
+    for i in 0..2 {
+        let exp = (pts[i].0 + sx, pts[i].1 + sy);
+        assert_eq!(masked[i].status, TrackStatus::Tracked, "pt{i}");
+        assert!(dist(masked[i].pos, exp) < 0.2, "pt{i}: {:?}", masked[i].pos);
+        assert!(
+            dist(plain[i].pos, exp) > 0.5,
+            "pt{i}: plain {:?}",
+            plain[i].pos
+        );
+    }
+    assert_eq!(masked[2].status, TrackStatus::Masked);
+    assert_eq!(masked[2].pos, pts[2]);
+
+    // The context form tracks the same way.
+    let mut ctx = TrackerContext::new();
+    ctx.prepare(&prev, &next, 3);
+    ctx.set_mask(Some(&mask));
+    assert_eq!(ctx.track(&pts, None, WIN, ITERS, 0.0), &masked[..]);
+}
+
+#[test]
+fn weight_map_follows_the_segmented_object() {
+    // An object moves one way and the background the other; a soft
+    // segmentation of the previous frame down-weights the background.
+    let prev = textured(320, 240);
+    let (object, background) = ((2.4f32, 1.3f32), (-2.0f32, 0.5f32));
+    let inside = |x: f32, y: f32| (100.0..160.0).contains(&x) && (80.0..140.0).contains(&y);
+    let next = GrayImage::from_fn(320, 240, |x, y| {
+        let (x, y) = (x as f32, y as f32);
+        let (dx, dy) = if inside(x - object.0, y - object.1) {
+            object
+        } else {
+            background
+        };
+        Luma([sample(&prev, x - dx, y - dy).round() as u8])
+    });
+    let weights = ImageBuffer::from_fn(320, 240, |x, y| {
+        Luma([if inside(x as f32, y as f32) {
+            1.0f32
+        } else {
+            0.02
+        }])
+    });
+    // Near the object's right and bottom edges, with background in the
+    // window.
+    let pts = vec![(156.0f32, 110.0), (130.0, 135.0)];
+    let (pp, np) = (build_pyramid(&prev, 3), build_pyramid(&next, 3));
+
+    let plain = calc_optical_flow_ex(&pp, &np, &pts, None, WIN, ITERS, 0.0);
+    let params = FlowParams {
+        window_size: WIN,
+        max_iterations: ITERS,
+        min_eigen_threshold: 0.0,
+        weight_map: Some(&weights),
+        ..FlowParams::default()
+    };
+    let weighted = calc_optical_flow_with(&pp, &np, &pts, None, &params);
+    for (i, (p, r)) in plain.iter().zip(&weighted).enumerate() {
+        let exp = (pts[i].0 + object.0, pts[i].1 + object.1);
+        assert_eq!(r.status, TrackStatus::Tracked, "pt{i}");
+        assert!(dist(r.pos, exp) < 0.2, "pt{i}: {:?} vs {exp:?}", r.pos);
+        assert!(
+            dist(p.pos, exp) > 3.0 * dist(r.pos, exp),
+            "pt{i}: plain {:?}",
+            p.pos
+        );
+    }
+
+    // A uniform map is the plain call, and the context form agrees.
+    let ones = ImageBuffer::from_pixel(320, 240, Luma([1.0f32]));
+    let uniform = FlowParams {
+        weight_map: Some(&ones),
+        ..params
+    };
+    let same = calc_optical_flow_with(&pp, &np, &pts, None, &uniform);
+    for (p, r) in plain.iter().zip(&same) {
+        assert!(dist(p.pos, r.pos) < 1e-4);
+    }
+    let mut ctx = TrackerContext::new();
+    ctx.prepare(&prev, &next, 3);
+    ctx.set_weight_map(Some(weights));
+    assert_eq!(ctx.track(&pts, None, WIN, ITERS, 0.0), &weighted[..]);
+}
+
+#[test]
+fn gradient_weighting_tracks_mixed_windows() {
+    // Texture on the left blending into a faint shading ramp on the right,
+    // moving together; the windows straddle the boundary.
+    let tex = textured(320, 240);
+    let base = GrayImage::from_fn(320, 240, |x, y| {
+        let t = ((x as f32 - 155.0) / 10.0).clamp(0.0, 1.0);
+        let ramp = 60.0 + 0.25 * y as f32;
+        Luma([(tex.get_pixel(x, y)[0] as f32 * (1.0 - t) + ramp * t) as u8])
+    });
+    let motion = (1.3f32, 0.6f32);
+    let (prev, next) = (base.clone(), shift(&base, motion.0, motion.1));
+    let pts: Vec<(f32, f32)> = (0..6).map(|i| (156.0, 50.0 + 28.0 * i as f32)).collect();
+    let (pp, np) = (build_pyramid(&prev, 3), build_pyramid(&next, 3));
+    let run = |gradient_weighting| {
+        let params = FlowParams {
+            gradient_weighting,
+            ..FlowParams::default()
+        };
+        calc_optical_flow_with(&pp, &np, &pts, None, &params)
+    };
+
+    let plain = run(None);
+    for weighting in [
+        GradientWeighting::Magnitude,
+        GradientWeighting::Saturating { scale: 10.0 },
+    ] {
+        let weighted = run(Some(weighting));
+        for (i, (r, p)) in weighted.iter().zip(&plain).enumerate() {
+            let exp = (pts[i].0 + motion.0, pts[i].1 + motion.1);
+            assert_eq!(r.status, TrackStatus::Tracked, "{weighting:?} pt{i}");
+            assert!(dist(r.pos, exp) < 0.2, "{weighting:?} pt{i}: {:?}", r.pos);
+            assert_ne!(r.pos, p.pos, "{weighting:?} pt{i} unweighted");
+        }
+    }
+
+    // The context form agrees, and a flat frame leaves no weight at all.
+    let mut ctx = TrackerContext::new();
+    ctx.prepare(&prev, &next, 3);
+    ctx.set_gradient_weighting(Some(GradientWeighting::Magnitude));
+    assert_eq!(
+        ctx.track(&pts, None, WIN, ITERS, DEFAULT_MIN_EIGEN_THRESHOLD),
+        &run(Some(GradientWeighting::Magnitude))[..]
+    );
+    let flat = build_pyramid(&GrayImage::from_pixel(320, 240, Luma([90])), 3);
+    let params = FlowParams {
+        gradient_weighting: Some(GradientWeighting::Magnitude),
+        ..FlowParams::default()
+    };
+    let lost = calc_optical_flow_with(&flat, &flat, &pts, None, &params);
+    assert!(lost.iter().all(|r| r.status == TrackStatus::LowTexture));
+}
+
+#[test]
+fn wide_window_follows_a_horizontally_moving_band() {
+    // A 9-row band moves right while everything around it moves left, like
+    // a conveyor belt between static-looking surroundings.
+    let prev = textured(320, 240);
+    let (band, sx) = (100..109, 2.0);
+    let next = GrayImage::from_fn(320, 240, |x, y| {
+        let dx = if band.contains(&y) { sx } else { -sx };
+        Luma([sample(&prev, x as f32 - dx, y as f32).round() as u8])
+    });
+    let pts = vec![(100.0f32, 104.0), (160.0, 104.0), (220.0, 104.0)];
+    let (pp, np) = (build_pyramid(&prev, 1), build_pyramid(&next, 1));
+
+    let square = calc_optical_flow_ex(&pp, &np, &pts, None, WIN, ITERS, 0.0);
+    let wide = calc_optical_flow_rect(&pp, &np, &pts, None, 41, 5, ITERS, 0.0);
+    for (i, (sq, r)) in square.iter().zip(&wide).enumerate() {
+        let exp = (pts[i].0 + sx, pts[i].1);
+        assert_eq!(r.status, TrackStatus::Tracked, "pt{i}");
+        assert!(dist(r.pos, exp) < 0.1, "pt{i}: {:?} vs {exp:?}", r.pos);
+        assert!(dist(sq.pos, exp) > 1.0, "pt{i}: square window {:?}", sq.pos);
+    }
+
+    // A square rectangle is the plain square window, and the context form
+    // matches the free function.
+    assert_eq!(
+        calc_optical_flow_rect(&pp, &np, &pts, None, WIN, WIN, ITERS, 0.0),
+        square
+    );
+    let mut ctx = TrackerContext::new();
+    ctx.prepare(&prev, &next, 1);
+    assert_eq!(ctx.track_rect(&pts, None, 41, 5, ITERS, 0.0), &wide[..]);
+}
+
+#[test]
+fn context_matches_free_functions() {
+    let prev = textured(320, 240);
+    let next = shift(&prev, 2.0, -1.5);
+    let pts = vec![(160.0f32, 120.0), (90.0, 80.0), (210.0, 160.0)];
+
+    let pp = build_pyramid(&prev, 4);
+    let np = build_pyramid(&next, 4);
+    let free = calc_optical_flow_ex(
+        &pp,
+        &np,
+        &pts,
+        None,
+        WIN,
+        ITERS,
+        DEFAULT_MIN_EIGEN_THRESHOLD,
+    );
+
+    let mut ctx = TrackerContext::new();
+    ctx.prepare(&prev, &next, 4);
+    let ctx_res = ctx.track(&pts, None, WIN, ITERS, DEFAULT_MIN_EIGEN_THRESHOLD);
+
+    for (a, b) in free.iter().zip(ctx_res.iter()) {
+        assert_eq!(a.pos, b.pos);
+        assert_eq!(a.status, b.status);
+    }
+}
+
+#[test]
+fn every_gradient_size_finds_square_corners() {
+    let img = GrayImage::from_fn(120, 100, |x, y| {
+        let inside = (30..80).contains(&x) && (25..70).contains(&y);
+        Luma([if inside { 120 } else { 100 }])
+    });
+    let corners = [(30.0f32, 25.0f32), (79.0, 25.0), (30.0, 69.0), (79.0, 69.0)];
+
+    for gradient_size in [FILTER_SCHARR, 3, 5, 7] {
+        let params = FeatureParams {
+            quality_level: 0.3,
+            min_distance: 10,
+            gradient_size,
+            block_size: 5,
+            ..FeatureParams::default()
+        };
+        let pts = good_features_to_track_with(&img, &params);
+
+        assert!(
+            !pts.is_empty(),
+            "gradient_size {gradient_size}: nothing detected"
+        );
+        for &(x, y, _) in &pts {
+            let nearest = corners
+                .iter()
+                .map(|&c| dist(c, (x as f32, y as f32)))
+                .fold(f32::INFINITY, f32::min);
+            assert!(
+                nearest <= 5.0,
+                "gradient_size {gradient_size}: ({x}, {y}) is not a corner"
+            );
+        }
+    }
+}
+
+#[test]
+fn harris_finds_square_corners_and_exposes_response() {
+    let img = GrayImage::from_fn(120, 100, |x, y| {
+        let inside = (30..80).contains(&x) && (25..70).contains(&y);
+        Luma([if inside { 180 } else { 60 }])
+    });
+    let corners = [(30.0f32, 25.0f32), (79.0, 25.0), (30.0, 69.0), (79.0, 69.0)];
+
+    let pts = harris_corners(&img, 0.04, 3, 1000.0);
+    assert!(pts.len() >= 4, "expected the four corners, got {pts:?}");
+    assert!(
+        pts.windows(2).all(|w| w[0].2 >= w[1].2),
+        "sorted by response"
+    );
+    for &(x, y, _) in &pts {
+        let nearest = corners
+            .iter()
+            .map(|&c| dist(c, (x as f32, y as f32)))
+            .fold(f32::INFINITY, f32::min);
+        assert!(nearest <= 2.0, "({x}, {y}) is not a corner");
+    }
+    for c in corners {
+        assert!(
+            pts.iter()
+                .any(|&(x, y, _)| dist(c, (x as f32, y as f32)) <= 2.0),
+            "corner {c:?} missed"
+        );
+    }
+
+    // Edges respond negatively, flat areas with zero.
+    let (same, response) = harris_corners_with_response(&img, 0.04, 3, 1000.0);
+    assert_eq!(same, pts);
+    assert_eq!(response.dimensions(), img.dimensions());
+    assert!(response.get_pixel(55, 25)[0] < 0.0);
+    assert_eq!(response.get_pixel(10, 10)[0], 0.0);
+    for &(x, y, r) in &pts {
+        assert_eq!(response.get_pixel(x, y)[0], r);
+    }
+}
+
+#[test]
+fn quantized_gradient_storage_tracks_accurately() {
+    let prev = textured(320, 240);
+    let next = shift(&prev, 2.0, -1.5);
+    let pts = vec![(160.0f32, 120.0), (90.0, 80.0), (210.0, 160.0)];
+
+    let mut ctx = TrackerContext::new();
+    ctx.set_gradient_storage(GradientStorage::Quantized);
+    // Twice, so the second prepare runs over reused quantized storage.
+    for _ in 0..2 {
+        ctx.prepare(&prev, &next, 4);
+        let res = ctx.track(&pts, None, WIN, ITERS, DEFAULT_MIN_EIGEN_THRESHOLD);
+        for (p, r) in pts.iter().zip(res) {
+            assert_eq!(r.status, TrackStatus::Tracked);
+            assert!(dist(r.pos, (p.0 + 2.0, p.1 - 1.5)) < 0.2, "{:?}", r.pos);
+        }
+    }
+}
+
+#[test]
+fn context_reports_stage_timings() {
+    let prev = textured(320, 240);
+    let next = shift(&prev, 2.0, -1.5);
+    let pts = vec![(160.0f32, 120.0), (90.0, 80.0), (210.0, 160.0)];
+
+    let mut ctx = TrackerContext::new();
+    ctx.prepare(&prev, &next, 4);
+    ctx.track(&pts, None, WIN, ITERS, DEFAULT_MIN_EIGEN_THRESHOLD);
+    assert!(ctx.timing().is_none(), "timing is opt-in");
+
+    ctx.set_timing_clock(Some(system_clock_ms));
+    ctx.prepare(&prev, &next, 4);
+    ctx.track_fb(
+        &pts,
+        None,
+        WIN,
+        ITERS,
+        DEFAULT_MIN_EIGEN_THRESHOLD,
+        DEFAULT_FB_THRESHOLD,
+    );
+
+    let report = ctx.timing().expect("timing enabled");
+    assert_eq!(report.per_level_ms.len(), 4);
+    assert!(report.pyramid_ms >= 0.0);
+    assert!(report.per_level_ms.iter().all(|&ms| ms >= 0.0));
+    let level_sum: f64 = report.per_level_ms.iter().sum();
+    let stage_sum = report.gradients_ms + report.solve_ms;
+    assert!((level_sum - stage_sum).abs() < 1e-6 * level_sum.max(1.0));
+}
+
+#[test]
+fn split_prepare_steps_sum_their_pyramid_time() {
+    // Every reading advances the clock by 1 ms.
+    static TICKS: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+    fn ticking_clock() -> f64 {
+        TICKS.fetch_add(1, std::sync::atomic::Ordering::Relaxed) as f64
+    }
+
+    let prev = textured(160, 120);
+    let next = shift(&prev, 1.0, 0.5);
+    let pts = vec![(80.0f32, 60.0)];
+    let mut ctx = TrackerContext::new();
+    ctx.set_timing_clock(Some(ticking_clock));
+
+    ctx.prepare_prev(&prev, 3);
+    ctx.prepare_next(&next, 3);
+    ctx.track(&pts, None, WIN, ITERS, DEFAULT_MIN_EIGEN_THRESHOLD);
+    assert_eq!(ctx.timing().unwrap().pyramid_ms, 2.0);
+
+    // The next frame starts a new sum.
+    ctx.prepare_next(&next, 3);
+    ctx.track(&pts, None, WIN, ITERS, DEFAULT_MIN_EIGEN_THRESHOLD);
+    assert_eq!(ctx.timing().unwrap().pyramid_ms, 1.0);
+}
+
+#[cfg(feature = "debug-trace")]
+#[test]
+fn debug_trace_records_selected_points() {
+    let prev = textured(320, 240);
+    let next = shift(&prev, 3.0, 2.0);
+    let pts = vec![(160.0f32, 120.0), (90.0, 80.0), (210.0, 160.0)];
+
+    let mut ctx = TrackerContext::new();
+    ctx.set_debug_points(&[1]);
+    ctx.prepare(&prev, &next, 4);
+    let res = ctx
+        .track_fb(
+            &pts,
+            None,
+            WIN,
+            ITERS,
+            DEFAULT_MIN_EIGEN_THRESHOLD,
+            DEFAULT_FB_THRESHOLD,
+        )
+        .to_vec();
+
+    let trace = ctx.debug_trace();
+    assert_eq!(trace.len(), 1);
+    assert_eq!(trace[0].index, 1);
+    let levels: Vec<usize> = trace[0].levels.iter().map(|l| l.level).collect();
+    assert_eq!(
+        levels,
+        vec![3, 2, 1, 0],
+        "forward pass only, coarse to fine"
+    );
+
+    // The last recorded step at level 0 is the reported displacement.
+    let last = trace[0].levels[3].iterations.last().expect("iterated");
+    assert!((pts[1].0 + last.dx - res[1].pos.0).abs() < 1e-4);
+    assert!((pts[1].1 + last.dy - res[1].pos.1).abs() < 1e-4);
+    assert!(last.residual.is_finite());
+}
+
+#[test]
+fn grid_detection_is_uniform_and_respects_occupancy() {
+    let img = textured(320, 240);
+    let (cols, rows, max_per_cell, min_dist) = (4u32, 3u32, 3u32, 8u32);
+
+    let pts = good_features_to_track_grid(&img, cols, rows, max_per_cell, 0.05, min_dist, &[]);
+
+    // Spacing respected.
+    for i in 0..pts.len() {
+        for j in (i + 1)..pts.len() {
+            let d2 = (pts[i].0 as i32 - pts[j].0 as i32).pow(2)
+                + (pts[i].1 as i32 - pts[j].1 as i32).pow(2);
+            assert!(
+                d2 >= (min_dist * min_dist) as i32,
+                "points closer than min_distance"
+            );
+        }
+    }
+
+    // Per-cell budget respected and coverage is broad.
+    let mut counts = vec![0u32; (cols * rows) as usize];
+    for &(x, y, _) in &pts {
+        let c = (x * cols / 320).min(cols - 1);
+        let r = (y * rows / 240).min(rows - 1);
+        counts[(r * cols + c) as usize] += 1;
+    }
+    assert!(counts.iter().all(|&c| c <= max_per_cell));
+    assert!(
+        counts.iter().filter(|&&c| c > 0).count() >= 10,
+        "should cover most cells"
+    );
+
+    // A cell pre-filled by existing points yields no new detections there.
+    let existing: Vec<(f32, f32)> = vec![(20.0, 20.0), (40.0, 30.0), (60.0, 10.0)];
+    let pts2 =
+        good_features_to_track_grid(&img, cols, rows, max_per_cell, 0.05, min_dist, &existing);
+    let in_cell0 = pts2
+        .iter()
+        .filter(|&&(x, y, _)| x * cols / 320 == 0 && y * rows / 240 == 0)
+        .count();
+    assert_eq!(
+        in_cell0, 0,
+        "cell filled by existing_points must be skipped"
+    );
+}
+
+#[test]
+fn sparse_redetection_scans_only_under_populated_cells() {
+    let img = textured(320, 240);
+    let (cols, rows, budget, min_dist) = (4u32, 3u32, 3u32, 8u32);
+    // Cell (0, 0) is full, cell (1, 1) holds one track.
+    let existing = vec![(20.0, 20.0), (40.0, 30.0), (60.0, 10.0), (130.0, 110.0)];
+    let mut coverage = CoverageMap::new(320, 240, cols, rows);
+    coverage.set_points(&existing);
+    assert_eq!(coverage.count(0, 0), 3);
+    assert_eq!(coverage.count(1, 1), 1);
+    assert_eq!(coverage.sparse_cells(1).count(), 10);
+
+    // Cells below the budget are topped up to it, clear of the live tracks.
+    let sparse = good_features_to_track_sparse(&img, &coverage, budget, budget, 0.05, min_dist);
+    let mut counts = vec![0u32; (cols * rows) as usize];
+    for &(x, y, _) in &sparse {
+        let (col, row) = coverage.cell_of(x as f32, y as f32);
+        counts[(row * cols + col) as usize] += 1;
+        for &(ex, ey) in &existing {
+            assert!(dist((x as f32, y as f32), (ex, ey)) >= min_dist as f32);
+        }
+    }
+    assert_eq!(counts[0], 0);
+    assert_eq!(counts[(cols + 1) as usize], budget - 1);
+    assert_eq!(counts.iter().sum::<u32>(), 10 * budget + budget - 1);
+
+    // Only empty cells are topped up, each to its budget.
+    let empty = good_features_to_track_sparse(&img, &coverage, 1, budget, 0.05, min_dist);
+    let mut counts = vec![0u32; (cols * rows) as usize];
+    for &(x, y, _) in &empty {
+        let (col, row) = coverage.cell_of(x as f32, y as f32);
+        assert_eq!(coverage.count(col, row), 0, "({x}, {y}) in a covered cell");
+        counts[(row * cols + col) as usize] += 1;
+    }
+    assert_eq!(counts.iter().filter(|&&c| c == budget).count(), 10);
+}
+
+#[test]
+fn large_block_size_finds_square_corners() {
+    // A low-contrast square on a flat background: its four corners are the only
+    // corner-like structures.
+    let img = GrayImage::from_fn(120, 100, |x, y| {
+        let inside = (30..80).contains(&x) && (25..70).contains(&y);
+        Luma([if inside { 120 } else { 100 }])
+    });
+    let corners = [(30.0f32, 25.0f32), (79.0, 25.0), (30.0, 69.0), (79.0, 69.0)];
+
+    // Larger blocks place the response peak further inside the corner.
+    for block_size in [7, 15] {
+        let params = FeatureParams {
+            quality_level: 0.3,
+            min_distance: 10,
+            block_size,
+            ..FeatureParams::default()
+        };
+        let pts = good_features_to_track_with(&img, &params);
+
+        assert!(!pts.is_empty(), "block {block_size}: nothing detected");
+        assert!(
+            pts.windows(2).all(|w| w[0].2 >= w[1].2),
+            "sorted by quality"
+        );
+        for &(x, y, _) in &pts {
+            let nearest = corners
+                .iter()
+                .map(|&c| dist(c, (x as f32, y as f32)))
+                .fold(f32::INFINITY, f32::min);
+            assert!(
+                nearest <= block_size as f32 * 0.6 + 1.0,
+                "block {block_size}: ({x}, {y}) is not a corner"
+            );
+        }
+    }
+}
+
+#[test]
+fn contrast_normalized_responses_ignore_exposure() {
+    // Random 8x8 blocks: strong corners, so gradient quantization stays
+    // small next to the contrast change.
+    let bright = GrayImage::from_fn(200, 150, |x, y| {
+        let s = ((x / 8) * 31 + (y / 8) * 17).wrapping_mul(2654435761);
+        Luma([64 + (s >> 25) as u8])
+    });
+    let mut dim = bright.clone();
+    for Luma([v]) in dim.pixels_mut() {
+        *v = (*v as f32 * 0.5 + 64.0).round() as u8;
+    }
+    let strongest = |img: &GrayImage, normalization| {
+        let params = FeatureParams {
+            quality_level: 0.3,
+            normalization,
+            ..FeatureParams::default()
+        };
+        good_features_to_track_with(img, &params)[0].2
+    };
+
+    // Halving the contrast quarters the raw eigenvalues...
+    let raw = strongest(&bright, ResponseNormalization::BlockMean)
+        / strongest(&dim, ResponseNormalization::BlockMean);
+    assert!(raw > 3.0, "{raw}");
+    // ...but not their ratio to the intensity variance.
+    let normalized = strongest(&bright, ResponseNormalization::Contrast)
+        / strongest(&dim, ResponseNormalization::Contrast);
+    assert!((normalized - 1.0).abs() < 0.25, "{normalized}");
+}
+
+#[test]
+fn max_corners_keeps_the_strongest_spaced_corners() {
+    let img = textured(320, 240);
+    let params = FeatureParams {
+        quality_level: 0.01,
+        min_distance: 8,
+        ..FeatureParams::default()
+    };
+    let all = good_features_to_track_with(&img, &params);
+    assert!(all.len() > 100, "{}", all.len());
+
+    for max_corners in [0, 1, 37, 100, all.len() + 5, usize::MAX] {
+        let limited = good_features_to_track_with(
+            &img,
+            &FeatureParams {
+                max_corners: Some(max_corners),
+                ..params
+            },
+        );
+        assert_eq!(limited, all[..max_corners.min(all.len())]);
+    }
+}
+
+#[test]
+fn pyramid_detection_reports_level_zero_coordinates() {
+    let prev = textured(320, 240);
+    let next = shift(&prev, 1.5, -2.0);
+    let (pp, np) = (build_pyramid(&prev, 3), build_pyramid(&next, 3));
+    let params = FeatureParams {
+        quality_level: 0.1,
+        min_distance: 12,
+        ..FeatureParams::default()
+    };
+    assert_eq!(
+        good_features_to_track_pyramid(&pp, 0, &params),
+        good_features_to_track_with(&prev, &params)
+    );
+
+    // Level-1 features land on even level-0 pixels, spaced in level-0 units,
+    // and track with the same pyramids.
+    let pts = good_features_to_track_pyramid(&pp, 1, &params);
+    assert!(pts.len() > 10, "{}", pts.len());
+    for (i, &(x, y, _)) in pts.iter().enumerate() {
+        assert!(x % 2 == 0 && y % 2 == 0 && x < 320 && y < 240);
+        for &(ox, oy, _) in &pts[..i] {
+            assert!(dist((x as f32, y as f32), (ox as f32, oy as f32)) >= 12.0);
+        }
+    }
+    let points: Vec<(f32, f32)> = pts.iter().map(|&(x, y, _)| (x as f32, y as f32)).collect();
+    let results = calc_optical_flow_ex(&pp, &np, &points, None, WIN, ITERS, 1e-4);
+    let interior = points
+        .iter()
+        .zip(&results)
+        .filter(|(p, _)| p.0 > 20.0 && p.0 < 300.0 && p.1 > 20.0 && p.1 < 220.0);
+    for (&(x, y), r) in interior {
+        assert_eq!(r.status, TrackStatus::Tracked, "({x}, {y})");
+        assert!(
+            dist(r.pos, (x + 1.5, y - 2.0)) < 0.2,
+            "({x}, {y}) -> {:?}",
+            r.pos
+        );
+    }
+}
+
+#[test]
+fn color_detection_finds_isoluminant_corners() {
+    // A red square on a green background of the same brightness: flat in
+    // grayscale, four corners in color.
+    let (red, green) = (Rgb([40u8, 0, 0]), Rgb([0u8, 20, 0]));
+    let img = RgbImage::from_fn(120, 100, |x, y| {
+        let inside = (30..80).contains(&x) && (25..70).contains(&y);
+        if inside { red } else { green }
+    });
+    let gray = GrayImage::from_fn(120, 100, |x, y| {
+        let [r, g, b] = img.get_pixel(x, y).0;
+        Luma([(0.299 * r as f32 + 0.587 * g as f32 + 0.114 * b as f32).round() as u8])
+    });
+    let params = FeatureParams {
+        quality_level: 0.3,
+        min_distance: 10,
+        ..FeatureParams::default()
+    };
+    let gray_pts = good_features_to_track_with(&gray, &params);
+    assert!(gray_pts.iter().all(|&(_, _, q)| q == 0.0), "{gray_pts:?}");
+
+    let pts = good_features_to_track_rgb(&img, &params);
+    let corners = [(30.0f32, 25.0f32), (79.0, 25.0), (30.0, 69.0), (79.0, 69.0)];
+    assert_eq!(pts.len(), 4, "{pts:?}");
+    for &(x, y, _) in &pts {
+        let nearest = corners
+            .iter()
+            .map(|&c| dist((x as f32, y as f32), c))
+            .fold(f32::INFINITY, f32::min);
+        assert!(nearest <= 3.0, "({x}, {y}) is not at a corner");
+    }
+
+    // A gray color image responds exactly like its grayscale version.
+    let textured_gray = textured(96, 80);
+    let as_rgb = RgbImage::from_fn(96, 80, |x, y| {
+        let v = textured_gray.get_pixel(x, y)[0];
+        Rgb([v, v, v])
+    });
+    assert_eq!(
+        good_features_to_track_rgb(&as_rgb, &FeatureParams::default()),
+        good_features_to_track_with(&textured_gray, &FeatureParams::default())
+    );
+}
+
+#[test]
+fn segment_test_finds_square_corners() {
+    let img = GrayImage::from_fn(120, 100, |x, y| {
+        let inside = (30..80).contains(&x) && (25..70).contains(&y);
+        Luma([if inside { 160 } else { 60 }])
+    });
+    let corners = [(30.0f32, 25.0f32), (79.0, 25.0), (30.0, 69.0), (79.0, 69.0)];
+
+    let pts = agast_corners(&img, 20, true);
+    assert_eq!(pts.len(), 4, "{pts:?}");
+    for &(x, y, _) in &pts {
+        let nearest = corners
+            .iter()
+            .map(|&c| dist((x as f32, y as f32), c))
+            .fold(f32::INFINITY, f32::min);
+        assert!(nearest <= 2.0, "({x}, {y}) is not at a corner");
+    }
+
+    // Suppression keeps the best of each corner's cluster.
+    let all = agast_corners(&img, 20, false);
+    assert!(all.len() > pts.len());
+    assert_eq!(all[0].2, pts[0].2);
+    assert!(agast_corners(&img, 120, true).is_empty());
+}
+
+#[test]
+fn keypoint_orientation_follows_edge_normal() {
+    let angle = 0.6f32;
+    let (c, s) = (angle.cos(), angle.sin());
+    // Step edge whose gradient points along `angle`, next to a flat margin.
+    let img = GrayImage::from_fn(160, 120, |x, y| {
+        let d = (x as f32 - 60.0) * c + (y as f32 - 60.0) * s;
+        Luma([if x >= 80 || d < 0.0 { 60 } else { 180 }])
+    });
+    let pts = [(60.0f32, 60.0), (80.0, 31.0), (140.0, 60.0)];
+    let res = keypoint_orientations(&img, &pts, 7);
+
+    assert!((res[0].angle - angle).abs() < 0.05, "{:?}", res[0]);
+    assert!(res[0].coherence > 0.9, "{:?}", res[0]);
+    // Corner where the step meets the vertical border of the margin.
+    assert!(res[1].coherence < 0.5, "{:?}", res[1]);
+    assert_eq!(res[2].coherence, 0.0);
+}
+
+#[test]
+#[should_panic(expected = "pyramid level 0 differs in size: 320x240 (prev) vs 160x120 (next)")]
+fn mismatched_pyramids_are_rejected() {
+    let pp = build_pyramid(&textured(320, 240), 3);
+    let np = build_pyramid(&textured(160, 120), 3);
+    calc_optical_flow_ex(
+        &pp,
+        &np,
+        &[(100.0, 100.0)],
+        None,
+        WIN,
+        ITERS,
+        DEFAULT_MIN_EIGEN_THRESHOLD,
+    );
+}
+
+#[test]
+fn bidirectional_flow_matches_two_single_direction_calls() {
+    let prev = textured(320, 240);
+    let next = shift(&prev, 3.5, -2.0);
+    let pp = build_pyramid(&prev, 4);
+    let np = build_pyramid(&next, 4);
+    let forward_pts = vec![(160.0f32, 120.0), (90.0, 80.0), (210.0, 160.0)];
+    let backward_pts = vec![(163.5f32, 118.0), (60.0, 200.0)];
+
+    let params = FlowParams {
+        window_size: WIN,
+        max_iterations: ITERS,
+        ..FlowParams::default()
+    };
+    let (forward, backward) =
+        calc_optical_flow_bidirectional(&pp, &np, &forward_pts, &backward_pts, &params);
+
+    let single = |a: &[GrayImage], b: &[GrayImage], pts: &[(f32, f32)]| {
+        calc_optical_flow_ex(a, b, pts, None, WIN, ITERS, DEFAULT_MIN_EIGEN_THRESHOLD)
+    };
+    assert_eq!(forward, single(&pp, &np, &forward_pts));
+    assert_eq!(backward, single(&np, &pp, &backward_pts));
+    assert!(dist(backward[0].pos, (160.0, 120.0)) < 0.1);
+
+    // The settings reach both directions, with the brightness change undone
+    // backwards.
+    let brighter = GrayImage::from_fn(320, 240, |x, y| {
+        Luma([(next.get_pixel(x, y)[0] as f32 * 0.8 + 30.0) as u8])
+    });
+    let bp = build_pyramid(&brighter, 4);
+    let exposure = estimate_exposure_change(&prev, &brighter, &ExposureParams::default());
+    let params = FlowParams {
+        max_level: Some(2),
+        exposure: Some(exposure),
+        ..params
+    };
+    let (forward, backward) =
+        calc_optical_flow_bidirectional(&pp, &bp, &forward_pts, &backward_pts, &params);
+    assert_eq!(
+        forward,
+        calc_optical_flow_with(&pp, &bp, &forward_pts, None, &params)
+    );
+    let backward_params = FlowParams {
+        exposure: Some(exposure.inverse()),
+        ..params
+    };
+    assert_eq!(
+        backward,
+        calc_optical_flow_with(&bp, &pp, &backward_pts, None, &backward_params)
+    );
+    assert!(dist(backward[0].pos, (160.0, 120.0)) < 0.1);
+
+    // The pre-alignment only estimates the forward shift.
+    let params = FlowParams {
+        flags: LkFlags::PREALIGN,
+        ..FlowParams::default()
+    };
+    let (forward, backward) =
+        calc_optical_flow_bidirectional(&pp, &np, &forward_pts, &backward_pts, &params);
+    assert_eq!(
+        forward,
+        calc_optical_flow_with(&pp, &np, &forward_pts, None, &params)
+    );
+    assert_eq!(
+        backward,
+        calc_optical_flow_with(&np, &pp, &backward_pts, None, &FlowParams::default())
+    );
+}
+
+#[test]
+#[should_panic(expected = "USE_INITIAL_FLOW requires predicted positions")]
+fn bidirectional_flow_rejects_initial_flow() {
+    let pp = build_pyramid(&textured(64, 48), 2);
+    let params = FlowParams {
+        flags: LkFlags::USE_INITIAL_FLOW,
+        ..FlowParams::default()
+    };
+    calc_optical_flow_bidirectional(&pp, &pp, &[(32.0, 24.0)], &[], &params);
+}
+
+#[test]
+fn batch_tracks_each_pair_like_its_own_context() {
+    let frames = [
+        textured(320, 240),
+        shift(&textured(320, 240), 2.0, 1.0),
+        textured(200, 150),
+        shift(&textured(200, 150), -1.5, 2.5),
+    ];
+    let points_a = vec![(160.0f32, 120.0), (90.0, 80.0), (210.0, 160.0)];
+    let points_b = vec![(100.0f32, 75.0), (60.0, 50.0)];
+    let pairs = [
+        FramePair {
+            prev: &frames[0],
+            next: &frames[1],
+            points: &points_a,
+            predicted: None,
+        },
+        FramePair {
+            prev: &frames[2],
+            next: &frames[3],
+            points: &points_b,
+            predicted: None,
+        },
+    ];
+
+    let mut batch = BatchTracker::new();
+    batch.track(&pairs, 3, WIN, ITERS, DEFAULT_MIN_EIGEN_THRESHOLD);
+    assert_eq!(batch.len(), 2);
+
+    for (i, pair) in pairs.iter().enumerate() {
+        let mut ctx = TrackerContext::new();
+        ctx.prepare(pair.prev, pair.next, 3);
+        let single = ctx.track(pair.points, None, WIN, ITERS, DEFAULT_MIN_EIGEN_THRESHOLD);
+        assert_eq!(batch.results(i), single, "pair {i}");
+    }
+    assert!(dist(batch.results(1)[0].pos, (98.5, 77.5)) < 0.1);
+
+    // A smaller batch reuses the leading contexts.
+    batch.track(&pairs[1..], 3, WIN, ITERS, DEFAULT_MIN_EIGEN_THRESHOLD);
+    assert_eq!(batch.len(), 1);
+    assert_eq!(batch.results(0).len(), points_b.len());
+}
+
+#[test]
+fn rigid_transform_recovers_roll_zoom_and_shift() {
+    let prev = textured(320, 240);
+    let (angle, zoom) = (0.05f32, 1.04f32);
+    let (cx, cy) = (150.0f32, 130.0f32);
+    let next = rotate_scale(&prev, angle, zoom, cx, cy);
+
+    let fit = estimate_rigid_transform(&prev, &next, &RigidParams::default()).unwrap();
+
+    assert!(fit.inliers * 10 >= fit.tracked * 9, "{fit:?}");
+    assert!(fit.rms_error < 0.3, "{fit:?}");
+    assert!((fit.rotation() - angle).abs() < 0.005, "{fit:?}");
+    assert!((fit.scale() - zoom).abs() < 0.005, "{fit:?}");
+    // The rotation center maps onto itself.
+    assert!(dist(fit.apply((cx, cy)), (cx, cy)) < 0.5, "{fit:?}");
+}
+
+#[test]
+fn stabilize_pair_aligns_shaky_frame_to_reference() {
+    let reference = textured(320, 240);
+    let shaky = rotate_scale(&shift(&reference, 3.0, -2.0), -0.03, 1.0, 160.0, 120.0);
+
+    let (aligned, transform) = stabilize_pair(&reference, &shaky).unwrap();
+    assert!((transform.rotation() + 0.03).abs() < 0.005, "{transform:?}");
+
+    let mean_diff = |img: &GrayImage| {
+        let mut sum = 0.0;
+        let mut n = 0.0;
+        for y in 30..210 {
+            for x in 30..290 {
+                sum += (img.get_pixel(x, y)[0] as f32 - reference.get_pixel(x, y)[0] as f32).abs();
+                n += 1.0;
+            }
+        }
+        sum / n
+    };
+    let (before, after) = (mean_diff(&shaky), mean_diff(&aligned));
+    assert!(after < 1.0 && after * 5.0 < before, "{before} -> {after}");
+}
+
+#[test]
+fn registration_quality_separates_good_and_bad_alignments() {
+    let reference = textured(320, 240);
+    let target = shift(&reference, 6.0, -4.0);
+
+    let transform = estimate_rigid_transform(&reference, &target, &RigidParams::default()).unwrap();
+    let good = registration_quality(&reference, &target, &transform);
+    assert!(
+        good.inlier_ratio > 0.9 && good.inlier_rmse < 0.3,
+        "{transform:?}"
+    );
+    // The shifted reference covers all but a 6 px column band and a 4 px row
+    // band of the target.
+    let expected_overlap = (314.0 * 236.0) / (320.0 * 240.0);
+    assert!(
+        (good.overlap - expected_overlap).abs() < 0.01,
+        "{}",
+        good.overlap
+    );
+    assert!(good.photometric_rmse < 2.0, "{}", good.photometric_rmse);
+
+    // Off by three pixels: geometrically plausible, photometrically wrong.
+    let mut wrong = transform;
+    wrong.matrix[0][2] += 3.0;
+    let bad = registration_quality(&reference, &target, &wrong);
+    assert!(
+        bad.photometric_rmse > 5.0 * good.photometric_rmse,
+        "{}",
+        bad.photometric_rmse
+    );
+    let mean_residual = |q: &RegistrationQuality| {
+        q.residual.pixels().map(|p| p[0] as f32).sum::<f32>() / (320.0 * 240.0)
+    };
+    assert!(mean_residual(&bad) > 3.0 * mean_residual(&good));
+}
+
+#[test]
+fn staged_pipeline_reports_every_stage_in_original_coordinates() {
+    let prev = textured(640, 480);
+    let (sx, sy) = (6.0f32, -4.0f32);
+    let next = shift(&prev, sx, sy);
+    let pipeline = StagedPipeline::new(640, 480, StageResolutions::default());
+
+    let corners = pipeline.detect(
+        &prev,
+        &FeatureParams {
+            quality_level: 0.1,
+            ..FeatureParams::default()
+        },
+    );
+    assert!(corners.len() > 20, "{}", corners.len());
+    let points: Vec<(f32, f32)> = corners
+        .iter()
+        .filter(|&&(x, y, _)| x > 40.0 && y > 40.0 && x < 600.0 && y < 440.0)
+        .take(30)
+        .map(|&(x, y, _)| (x, y))
+        .collect();
+    // Detected at half resolution, the corners spread over the whole frame.
+    assert!(points.iter().any(|p| p.0 > 320.0 && p.1 > 240.0));
+
+    let results = pipeline.track(
+        &prev,
+        &next,
+        &points,
+        3,
+        WIN,
+        ITERS,
+        DEFAULT_MIN_EIGEN_THRESHOLD,
+    );
+    for (p, r) in points.iter().zip(&results) {
+        assert_eq!(r.status, TrackStatus::Tracked);
+        assert!(dist(r.pos, (p.0 + sx, p.1 + sy)) < 0.1, "{p:?} -> {r:?}");
+    }
+
+    let motion = pipeline
+        .global_motion(&prev, &next, &RigidParams::default())
+        .unwrap();
+    let (tx, ty) = motion.translation();
+    assert!((tx - sx).abs() < 0.3 && (ty - sy).abs() < 0.3, "{motion:?}");
+    assert!((motion.scale() - 1.0).abs() < 0.005, "{motion:?}");
+}
+
+#[test]
+fn budget_tracking_reports_original_coordinates() {
+    let prev = textured(960, 720);
+    let (sx, sy) = (6.0f32, -4.0f32);
+    let next = shift(&prev, sx, sy);
+    let pts = vec![(480.0f32, 360.0), (300.0, 250.0), (650.0, 500.0)];
+
+    // A quarter of the pixels: tracked at 480x360.
+    let res = calc_optical_flow_budget(
+        &prev,
+        &next,
+        &pts,
+        480 * 360,
+        3,
+        WIN,
+        ITERS,
+        DEFAULT_MIN_EIGEN_THRESHOLD,
+    );
+
+    for (r, &(x, y)) in res.iter().zip(&pts) {
+        assert_eq!(r.status, TrackStatus::Tracked);
+        assert!(dist(r.pos, (x + sx, y + sy)) < 0.3, "{r:?}");
+    }
+}
+
+#[test]
+fn image_pair_tracking_builds_its_own_pyramids() {
+    let prev = textured(320, 240);
+    let (sx, sy) = (9.5f32, -6.25f32);
+    let next = shift(&prev, sx, sy);
+    let pts = vec![(160.0f32, 120.0), (100.0, 90.0), (220.0, 150.0)];
+    let params = FlowParams::default();
+
+    let results = calc_optical_flow_images(&prev, &next, &pts, &params);
+    for (r, &(x, y)) in results.iter().zip(&pts) {
+        assert_eq!(r.status, TrackStatus::Tracked);
+        assert!(dist(r.pos, (x + sx, y + sy)) < 0.1, "{r:?}");
+    }
+
+    // The same as tracking on pyramids of the automatic depth.
+    let levels = auto_pyramid_levels(320, 240, params.window_size);
+    assert_eq!(levels, 4);
+    let explicit = calc_optical_flow_with(
+        &build_pyramid(&prev, levels),
+        &build_pyramid(&next, levels),
+        &pts,
+        None,
+        &params,
+    );
+    assert_eq!(results, explicit);
+}
+
+#[test]
+fn gain_bias_forward_backward_survives_exposure_change() {
+    let prev = textured(320, 240);
+    let (sx, sy) = (3.4f32, -2.2f32);
+    let moved = shift(&prev, sx, sy);
+    let next = GrayImage::from_fn(320, 240, |x, y| {
+        Luma([(moved.get_pixel(x, y)[0] as f32 * 0.6 + 40.0) as u8])
+    });
+    let pts: Vec<(f32, f32)> = (0..4)
+        .flat_map(|j| (0..6).map(move |i| (50.0 + 44.0 * i as f32, 50.0 + 45.0 * j as f32)))
+        .collect();
+
+    let mut ctx = TrackerContext::new();
+    ctx.set_flags(LkFlags::GAIN_BIAS);
+    ctx.prepare(&prev, &next, 3);
+    let results = ctx.track_fb(
+        &pts,
+        None,
+        WIN,
+        ITERS,
+        DEFAULT_MIN_EIGEN_THRESHOLD,
+        DEFAULT_FB_THRESHOLD,
+    );
+    for (r, p) in results.iter().zip(&pts) {
+        assert_eq!(r.status, TrackStatus::Tracked, "{p:?} -> {r:?}");
+        assert!(dist(r.pos, (p.0 + sx, p.1 + sy)) < 0.2, "{p:?} -> {r:?}");
+    }
+}
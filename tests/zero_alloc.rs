@@ -2,19 +2,25 @@
 //!
 //! Uses a counting global allocator (scoped to this test binary only, so it
 //! does not affect the library or other tests) to count allocations across a
-//! warmed-up `prepare` + `track` step.
+//! warmed-up `prepare` + `track` / `track_fb` step.
 
 use std::alloc::{GlobalAlloc, Layout, System};
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
 use image::{GrayImage, Luma};
-use optical_flow_lk::{DEFAULT_MIN_EIGEN_THRESHOLD, TrackerContext};
+use optical_flow_lk::{
+    BatchTracker, DEFAULT_FB_THRESHOLD, DEFAULT_MIN_EIGEN_THRESHOLD, FramePair, KeyframeParams,
+    KeyframeTracker, Preprocess, TrackWindow, TrackerContext,
+};
 
 struct CountingAllocator;
 
 static ALLOCS: AtomicUsize = AtomicUsize::new(0);
 static COUNTING: AtomicBool = AtomicBool::new(false);
 
+/// The counters are process-global, so tests that measure must not overlap.
+static SERIAL: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
 unsafe impl GlobalAlloc for CountingAllocator {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         if COUNTING.load(Ordering::Relaxed) {
@@ -56,6 +62,7 @@ fn textured(w: u32, h: u32, seed: u32) -> GrayImage {
 
 #[test]
 fn steady_state_track_is_allocation_free() {
+    let _guard = SERIAL.lock().unwrap();
     let prev = textured(640, 480, 1);
     let next = textured(640, 480, 2);
     let points: Vec<(f32, f32)> = (0..150)
@@ -78,6 +85,7 @@ fn steady_state_track_is_allocation_free() {
     }
 
     // Measured steady-state step.
+    ALLOCS.store(0, Ordering::Relaxed);
     COUNTING.store(true, Ordering::Relaxed);
     ctx.prepare(&prev, &next, 4);
     let results = ctx.track(
@@ -97,3 +105,190 @@ fn steady_state_track_is_allocation_free() {
         "steady-state prepare+track allocated {allocs} times"
     );
 }
+
+#[test]
+fn steady_state_track_fb_is_allocation_free() {
+    let _guard = SERIAL.lock().unwrap();
+    let prev = textured(320, 240, 3);
+    let next = textured(320, 240, 4);
+    let points: Vec<(f32, f32)> = (0..100)
+        .map(|i| (30.0 + (i % 10) as f32 * 26.0, 30.0 + (i / 10) as f32 * 18.0))
+        .collect();
+
+    let mut ctx = TrackerContext::new();
+    let step = |ctx: &mut TrackerContext| {
+        ctx.prepare(&prev, &next, 3);
+        ctx.track_fb(
+            &points,
+            None,
+            15,
+            30,
+            DEFAULT_MIN_EIGEN_THRESHOLD,
+            DEFAULT_FB_THRESHOLD,
+        )
+        .len()
+    };
+    for _ in 0..3 {
+        step(&mut ctx);
+    }
+
+    // Every point runs both passes and up to 30 iterations per level; a single
+    // per-iteration allocation in the Lucas-Kanade loop would show up here
+    // thousands of times.
+    ALLOCS.store(0, Ordering::Relaxed);
+    COUNTING.store(true, Ordering::Relaxed);
+    let n = step(&mut ctx);
+    COUNTING.store(false, Ordering::Relaxed);
+
+    let allocs = ALLOCS.load(Ordering::Relaxed);
+    assert_eq!(n, points.len());
+    assert_eq!(
+        allocs, 0,
+        "steady-state prepare+track_fb allocated {allocs} times"
+    );
+}
+
+#[test]
+fn steady_state_track_windows_is_allocation_free() {
+    let _guard = SERIAL.lock().unwrap();
+    let prev = textured(320, 240, 5);
+    let next = textured(320, 240, 6);
+    let points: Vec<(f32, f32)> = (0..60)
+        .map(|i| (40.0 + (i % 10) as f32 * 24.0, 40.0 + (i / 10) as f32 * 26.0))
+        .collect();
+    let weights = vec![0.5f32; 21 * 21];
+    // Mixed sizes make the window buffers shrink and grow between points.
+    let windows: Vec<TrackWindow> = (0..points.len())
+        .map(|i| match i % 3 {
+            0 => TrackWindow::new(9),
+            1 => TrackWindow {
+                width: 21,
+                height: 21,
+                weights: Some(&weights),
+            },
+            _ => TrackWindow::new(15),
+        })
+        .collect();
+
+    let mut ctx = TrackerContext::new();
+    let step = |ctx: &mut TrackerContext| {
+        ctx.prepare(&prev, &next, 3);
+        ctx.track_windows(&points, None, &windows, 30, DEFAULT_MIN_EIGEN_THRESHOLD)
+            .len()
+    };
+    for _ in 0..3 {
+        step(&mut ctx);
+    }
+
+    ALLOCS.store(0, Ordering::Relaxed);
+    COUNTING.store(true, Ordering::Relaxed);
+    let n = step(&mut ctx);
+    COUNTING.store(false, Ordering::Relaxed);
+
+    let allocs = ALLOCS.load(Ordering::Relaxed);
+    assert_eq!(n, points.len());
+    assert_eq!(
+        allocs, 0,
+        "steady-state prepare+track_windows allocated {allocs} times"
+    );
+}
+
+#[test]
+fn steady_state_keyframe_track_is_allocation_free() {
+    let _guard = SERIAL.lock().unwrap();
+    let keyframe = textured(320, 240, 7);
+    let next = textured(320, 240, 8);
+    let points: Vec<(f32, f32)> = (0..80)
+        .map(|i| (30.0 + (i % 10) as f32 * 26.0, 30.0 + (i / 10) as f32 * 24.0))
+        .collect();
+
+    // The frames are unrelated, so promotion is disabled to keep the keyframe.
+    let mut tracker = KeyframeTracker::new(KeyframeParams {
+        levels: 3,
+        min_overlap: 0.0,
+        ..KeyframeParams::default()
+    });
+    tracker.set_keyframe(&keyframe, &points);
+    for _ in 0..3 {
+        tracker.track(&next);
+    }
+
+    ALLOCS.store(0, Ordering::Relaxed);
+    COUNTING.store(true, Ordering::Relaxed);
+    let n = tracker.track(&next).len();
+    COUNTING.store(false, Ordering::Relaxed);
+
+    let allocs = ALLOCS.load(Ordering::Relaxed);
+    assert_eq!(n, points.len());
+    assert!(!tracker.promoted());
+    assert_eq!(
+        allocs, 0,
+        "steady-state keyframe track allocated {allocs} times"
+    );
+}
+
+#[test]
+fn steady_state_batch_track_is_allocation_free() {
+    let _guard = SERIAL.lock().unwrap();
+    let frames: Vec<GrayImage> = (0..4).map(|seed| textured(160, 120, 10 + seed)).collect();
+    let points: Vec<(f32, f32)> = (0..40)
+        .map(|i| (20.0 + (i % 8) as f32 * 16.0, 20.0 + (i / 8) as f32 * 18.0))
+        .collect();
+    let pairs = [
+        FramePair {
+            prev: &frames[0],
+            next: &frames[1],
+            points: &points,
+            predicted: None,
+        },
+        FramePair {
+            prev: &frames[2],
+            next: &frames[3],
+            points: &points[..25],
+            predicted: None,
+        },
+    ];
+
+    let mut batch = BatchTracker::new();
+    for _ in 0..3 {
+        batch.track(&pairs, 3, 15, 30, DEFAULT_MIN_EIGEN_THRESHOLD);
+    }
+
+    ALLOCS.store(0, Ordering::Relaxed);
+    COUNTING.store(true, Ordering::Relaxed);
+    batch.track(&pairs, 3, 15, 30, DEFAULT_MIN_EIGEN_THRESHOLD);
+    COUNTING.store(false, Ordering::Relaxed);
+
+    let allocs = ALLOCS.load(Ordering::Relaxed);
+    assert_eq!(batch.results(1).len(), 25);
+    assert_eq!(
+        allocs, 0,
+        "steady-state batch track allocated {allocs} times"
+    );
+}
+
+#[test]
+fn steady_state_preprocess_is_allocation_free() {
+    let _guard = SERIAL.lock().unwrap();
+    let frame = textured(320, 240, 20);
+    let mut preprocess = Preprocess::new()
+        .resize(160, 120)
+        .gamma(0.8)
+        .clahe(2.0, 4, 4)
+        .blur(1.0);
+    for _ in 0..2 {
+        preprocess.apply(&frame);
+    }
+
+    ALLOCS.store(0, Ordering::Relaxed);
+    COUNTING.store(true, Ordering::Relaxed);
+    let dimensions = preprocess.apply(&frame).dimensions();
+    COUNTING.store(false, Ordering::Relaxed);
+
+    let allocs = ALLOCS.load(Ordering::Relaxed);
+    assert_eq!(dimensions, (160, 120));
+    assert_eq!(
+        allocs, 0,
+        "steady-state preprocess allocated {allocs} times"
+    );
+}